@@ -1,13 +1,72 @@
+use std::str;
+
+use builders::notify::EventGroup;
+use builders::search::{SearchCriteria, SearchReturnOption};
 use quoted_string;
-use types::{AttrMacro, Attribute, State};
+use sequence::SequenceSet;
+use types::{AttrMacro, Attribute, ListReturn, MetadataDepth, State, StoreType};
+use utf7;
 
 pub struct CommandBuilder {}
 
 impl CommandBuilder {
+    /// Builds an `APPEND` command up to and including the literal marker.
+    ///
+    /// The `len` bytes of message data must be sent after the command line,
+    /// followed by CRLF. Unless `literal_plus` is set (which requires server
+    /// support for RFC 7888), the client must wait for a continuation request
+    /// before sending the message data.
+    pub fn append(
+        mailbox: &str, flags: &[&str], date: Option<&str>, len: u64, literal_plus: bool
+    ) -> Command {
+        append_command(mailbox, flags, date, len, literal_plus, false)
+    }
+
+    /// Like `append()`, but sends the message in a UTF-8 literal (RFC 6855,
+    /// section 4), so that it may have UTF-8 header fields. This is only
+    /// allowed once UTF8=ACCEPT has been enabled. The message data must be
+    /// followed by `)` and CRLF.
+    pub fn append_utf8(
+        mailbox: &str, flags: &[&str], date: Option<&str>, len: u64, literal_plus: bool
+    ) -> Command {
+        append_command(mailbox, flags, date, len, literal_plus, true)
+    }
+
+    /// Builds the arguments that add another message to an `APPEND`
+    /// command, up to and including its literal marker (RFC 3502,
+    /// MULTIAPPEND).
+    ///
+    /// They are sent right after the data of the previous message (and its
+    /// `)` if it was a UTF-8 literal), in place of the CRLF that would end
+    /// the command, and followed by CRLF themselves. The message data is then
+    /// sent as for `append()`.
+    pub fn append_next(
+        flags: &[&str], date: Option<&str>, len: u64, literal_plus: bool
+    ) -> Vec<u8> {
+        append_message(flags, date, len, literal_plus, false).into_bytes()
+    }
+
+    /// Like `append_next()`, for a message sent in a UTF-8 literal.
+    pub fn append_next_utf8(
+        flags: &[&str], date: Option<&str>, len: u64, literal_plus: bool
+    ) -> Vec<u8> {
+        append_message(flags, date, len, literal_plus, true).into_bytes()
+    }
+
+    pub fn capability() -> Command {
+        let args = b"CAPABILITY".to_vec();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
     pub fn check() -> Command {
         let args = b"CHECK".to_vec();
         Command {
             args,
+            utf8_args: None,
             next_state: None,
         }
     }
@@ -16,31 +75,307 @@ impl CommandBuilder {
         let args = b"CLOSE".to_vec();
         Command {
             args,
+            utf8_args: None,
             next_state: Some(State::Authenticated),
         }
     }
 
+    pub fn compress() -> Command {
+        let args = b"COMPRESS DEFLATE".to_vec();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    pub fn copy(set: &SequenceSet, mailbox: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!("COPY {} \"{}\"", set, quote(mailbox))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Creates `mailbox`.
+    pub fn create(mailbox: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| format!("CREATE \"{}\"", quote(mailbox)));
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Deletes `mailbox`.
+    pub fn delete(mailbox: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| format!("DELETE \"{}\"", quote(mailbox)));
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Removes the rights of `identifier` from the access control list of
+    /// `mailbox` (RFC 4314).
+    pub fn delete_acl(mailbox: &str, identifier: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!(
+                "DELETEACL \"{}\" \"{}\"",
+                quote(mailbox),
+                quoted_string(identifier).unwrap()
+            )
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Enables the given extensions (RFC 5161).
+    pub fn enable(capabilities: &[&str]) -> Command {
+        let args = format!("ENABLE {}", capabilities.join(" ")).into_bytes();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
     pub fn examine(mailbox: &str) -> Command {
-        let args = format!("EXAMINE \"{}\"", quoted_string(mailbox).unwrap()).into_bytes();
+        let (args, utf8_args) = mailbox_args(|quote| format!("EXAMINE \"{}\"", quote(mailbox)));
         Command {
             args,
+            utf8_args,
             next_state: Some(State::Selected),
         }
     }
 
+    pub fn expunge() -> Command {
+        let args = b"EXPUNGE".to_vec();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
     pub fn fetch() -> FetchCommandEmpty {
         let args = b"FETCH ".to_vec();
         FetchCommandEmpty { args }
     }
 
+    /// Asks for the access control list of `mailbox` (RFC 4314).
+    pub fn get_acl(mailbox: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| format!("GETACL \"{}\"", quote(mailbox)));
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Asks for the values of the annotation `entries`, like
+    /// `/private/comment`, of `mailbox`, or of the server if `mailbox` is
+    /// empty (RFC 5464). `depth` also asks for the entries below the given
+    /// ones; values larger than `max_size` bytes are left out.
+    pub fn get_metadata(
+        mailbox: &str, entries: &[&str], depth: Option<MetadataDepth>, max_size: Option<u32>
+    ) -> Command {
+        let mut options = Vec::new();
+        if let Some(max_size) = max_size {
+            options.push(format!("MAXSIZE {}", max_size));
+        }
+        if let Some(depth) = depth {
+            options.push(format!("DEPTH {}", match depth {
+                MetadataDepth::Zero => "0",
+                MetadataDepth::One => "1",
+                MetadataDepth::Infinity => "infinity",
+            }));
+        }
+        let options = if options.is_empty() {
+            String::new()
+        } else {
+            format!("({}) ", options.join(" "))
+        };
+        let entries = entries
+            .iter()
+            .map(|entry| format!("\"{}\"", quoted_string(entry).unwrap()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!("GETMETADATA {}\"{}\" ({})", options, quote(mailbox), entries)
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Sets annotation entries of `mailbox`, or of the server if `mailbox`
+    /// is empty (RFC 5464). An entry without a value is removed.
+    ///
+    /// Values that cannot be sent as quoted strings, because they are not
+    /// ASCII or contain line breaks, are sent as non-synchronizing literals,
+    /// which requires LITERAL+ (RFC 7888).
+    pub fn set_metadata(mailbox: &str, values: &[(&str, Option<&[u8]>)]) -> Command {
+        let mut entries = Vec::new();
+        for (i, &(entry, value)) in values.iter().enumerate() {
+            if i > 0 {
+                entries.push(b' ');
+            }
+            entries.extend(format!("\"{}\" ", quoted_string(entry).unwrap()).as_bytes());
+            match value {
+                None => entries.extend(b"NIL"),
+                Some(value) => {
+                    let quoted = str::from_utf8(value)
+                        .ok()
+                        .and_then(|text| if text.is_ascii() && !text.contains('\0') {
+                            quoted_string(text).ok()
+                        } else {
+                            None
+                        });
+                    match quoted {
+                        Some(quoted) => entries.extend(format!("\"{}\"", quoted).as_bytes()),
+                        None => {
+                            entries.extend(format!("{{{}+}}\r\n", value.len()).as_bytes());
+                            entries.extend(value);
+                        },
+                    }
+                },
+            }
+        }
+        let (mut args, utf8_args) =
+            mailbox_args(|quote| format!("SETMETADATA \"{}\" (", quote(mailbox)));
+        let utf8_args = utf8_args.map(|mut utf8_args| {
+            utf8_args.extend(&entries);
+            utf8_args.push(b')');
+            utf8_args
+        });
+        args.extend(entries);
+        args.push(b')');
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Asks for the usage and limits of quota root `root` (RFC 2087).
+    pub fn get_quota(root: &str) -> Command {
+        let args = format!("GETQUOTA \"{}\"", quoted_string(root).unwrap()).into_bytes();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    /// Asks for the quota roots of `mailbox`, and their usage and limits
+    /// (RFC 2087).
+    pub fn get_quota_root(mailbox: &str) -> Command {
+        let (args, utf8_args) =
+            mailbox_args(|quote| format!("GETQUOTAROOT \"{}\"", quote(mailbox)));
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
     pub fn list(reference: &str, glob: &str) -> Command {
-        let args = format!(
-            "LIST \"{}\" \"{}\"",
-            quoted_string(reference).unwrap(),
-            quoted_string(glob).unwrap()
-        ).into_bytes();
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!("LIST \"{}\" \"{}\"", quote(reference), quote(glob))
+        });
         Command {
             args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// A `LIST` command with selection and return options (RFC 5258), such
+    /// as `SUBSCRIBED` or `STATUS (MESSAGES UNSEEN)` (RFC 5819).
+    pub fn list_extended(
+        reference: &str, glob: &str, selection: &[&str], returns: &[&str]
+    ) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            let mut args = "LIST ".to_string();
+            if !selection.is_empty() {
+                args.push_str(&format!("({}) ", selection.join(" ")));
+            }
+            args.push_str(&format!("\"{}\" \"{}\"", quote(reference), quote(glob)));
+            if !returns.is_empty() {
+                args.push_str(&format!(" RETURN ({})", returns.join(" ")));
+            }
+            args
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// A `LIST` command with typed return options, such as `CHILDREN` to
+    /// learn which mailboxes have child mailboxes without listing them.
+    pub fn list_returning(reference: &str, glob: &str, returns: &[ListReturn]) -> Command {
+        let returns = returns
+            .iter()
+            .map(|ret| match *ret {
+                ListReturn::Children => "CHILDREN".to_string(),
+                ListReturn::Subscribed => "SUBSCRIBED".to_string(),
+                ListReturn::Status(items) => format!("STATUS ({})", items.join(" ")),
+            })
+            .collect::<Vec<_>>();
+        let returns = returns.iter().map(|ret| ret.as_str()).collect::<Vec<_>>();
+        Self::list_extended(reference, glob, &[], &returns)
+    }
+
+    /// Asks which rights can be granted to `identifier` on `mailbox`
+    /// (RFC 4314).
+    pub fn list_rights(mailbox: &str, identifier: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!(
+                "LISTRIGHTS \"{}\" \"{}\"",
+                quote(mailbox),
+                quoted_string(identifier).unwrap()
+            )
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Sends the client's identification, such as `name` and `version`
+    /// (RFC 2971).
+    pub fn id(params: &[(&str, &str)]) -> Command {
+        let args = if params.is_empty() {
+            "ID NIL".to_string()
+        } else {
+            let params = params
+                .iter()
+                .map(|&(key, value)| {
+                    format!(
+                        "\"{}\" \"{}\"",
+                        quoted_string(key).unwrap(),
+                        quoted_string(value).unwrap()
+                    )
+                })
+                .collect::<Vec<_>>();
+            format!("ID ({})", params.join(" "))
+        };
+        Command {
+            args: args.into_bytes(),
+            utf8_args: None,
             next_state: None,
         }
     }
@@ -53,34 +388,461 @@ impl CommandBuilder {
         ).into_bytes();
         Command {
             args,
+            utf8_args: None,
             next_state: Some(State::Authenticated),
         }
     }
 
+    /// Asks for the rights the user has on `mailbox` (RFC 4314).
+    pub fn my_rights(mailbox: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| format!("MYRIGHTS \"{}\"", quote(mailbox)));
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Asks for the server's mailbox namespaces (RFC 2342).
+    pub fn namespace() -> Command {
+        let args = b"NAMESPACE".to_vec();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    /// Does nothing, but lets the server send updates and resets its
+    /// inactivity timer.
+    pub fn noop() -> Command {
+        let args = b"NOOP".to_vec();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    /// Asks the server to report the events in `groups` as they happen
+    /// (RFC 5465). With `status`, the server first sends the `STATUS` of
+    /// each mailbox that message events are requested for.
+    pub fn notify(groups: &[EventGroup], status: bool) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            let groups = groups
+                .iter()
+                .map(|group| group.format(quote))
+                .collect::<Vec<_>>();
+            let status = if status { " STATUS" } else { "" };
+            format!("NOTIFY SET{} {}", status, groups.join(" "))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Turns off all notifications requested with `notify()`.
+    pub fn notify_none() -> Command {
+        let args = b"NOTIFY NONE".to_vec();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    pub fn logout() -> Command {
+        let args = b"LOGOUT".to_vec();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: Some(State::Logout),
+        }
+    }
+
+    pub fn lsub(reference: &str, glob: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!("LSUB \"{}\" \"{}\"", quote(reference), quote(glob))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Moves the messages in `set` to `mailbox` (RFC 6851).
+    pub fn move_messages(set: &SequenceSet, mailbox: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!("MOVE {} \"{}\"", set, quote(mailbox))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Renames the mailbox `from` to `to`.
+    pub fn rename(from: &str, to: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!("RENAME \"{}\" \"{}\"", quote(from), quote(to))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    pub fn search(criteria: &str) -> Command {
+        let args = format!("SEARCH {}", criteria).into_bytes();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    /// Fails if the criteria cannot be sent; see `SearchCriteria::to_args()`.
+    pub fn search_criteria(criteria: &SearchCriteria) -> Result<Command, &'static str> {
+        search_command("SEARCH", criteria)
+    }
+
+    /// A `SEARCH` answered with an `ESEARCH` response holding only what
+    /// `returns` asks for (RFC 4731).
+    pub fn search_returning(
+        returns: &[SearchReturnOption], criteria: &SearchCriteria
+    ) -> Result<Command, &'static str> {
+        search_command(&format!("SEARCH {}", return_options(returns)), criteria)
+    }
+
     pub fn select(mailbox: &str) -> Command {
-        let args = format!("SELECT \"{}\"", quoted_string(mailbox).unwrap()).into_bytes();
+        let (args, utf8_args) = mailbox_args(|quote| format!("SELECT \"{}\"", quote(mailbox)));
+        Command {
+            args,
+            utf8_args,
+            next_state: Some(State::Selected),
+        }
+    }
+
+    /// Selects `mailbox`, asking for the changes since `mod_seq` to be
+    /// reported (RFC 7162, QRESYNC). `known_uids` limits the report to the
+    /// given UID set.
+    pub fn select_qresync(
+        mailbox: &str, uid_validity: u32, mod_seq: u64, known_uids: Option<&str>
+    ) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            let mut args = format!(
+                "SELECT \"{}\" (QRESYNC ({} {}",
+                quote(mailbox),
+                uid_validity,
+                mod_seq
+            );
+            if let Some(known_uids) = known_uids {
+                args.push(' ');
+                args.push_str(known_uids);
+            }
+            args.push_str("))");
+            args
+        });
         Command {
             args,
+            utf8_args,
             next_state: Some(State::Selected),
         }
     }
 
+    /// Changes the rights of `identifier` on `mailbox` (RFC 4314): `rights`
+    /// such as `lrs` replace its rights, while `+lrs` adds to them and `-w`
+    /// takes rights away.
+    pub fn set_acl(mailbox: &str, identifier: &str, rights: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!(
+                "SETACL \"{}\" \"{}\" \"{}\"",
+                quote(mailbox),
+                quoted_string(identifier).unwrap(),
+                quoted_string(rights).unwrap()
+            )
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Sets the limits of quota root `root`, as resource names such as
+    /// `STORAGE` with their limits (RFC 2087). Resources that are left out
+    /// have no limit afterwards.
+    pub fn set_quota(root: &str, limits: &[(&str, u64)]) -> Command {
+        let limits = limits
+            .iter()
+            .map(|&(name, limit)| format!("{} {}", name, limit))
+            .collect::<Vec<_>>();
+        let args = format!(
+            "SETQUOTA \"{}\" ({})",
+            quoted_string(root).unwrap(),
+            limits.join(" ")
+        ).into_bytes();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    /// Requests the given status items, such as `MESSAGES` or `UNSEEN`, for
+    /// `mailbox` without selecting it.
+    pub fn status(mailbox: &str, items: &[&str]) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!("STATUS \"{}\" ({})", quote(mailbox), items.join(" "))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    pub fn store(set: &SequenceSet, kind: StoreType, flags: &[&str]) -> Command {
+        let args = store_args("STORE", set, kind, "FLAGS", &flags.join(" "));
+        Command {
+            args: args.into_bytes(),
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    /// Changes the Gmail labels of the messages in `set`. System labels
+    /// such as `\Inbox` are sent as they are, others as label names.
+    pub fn store_labels(set: &SequenceSet, kind: StoreType, labels: &[&str]) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            store_args("STORE", set, kind, "X-GM-LABELS", &gmail_labels(quote, labels))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    pub fn uid_copy(set: &SequenceSet, mailbox: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!("UID COPY {} \"{}\"", set, quote(mailbox))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    /// Expunges only the messages in `set` (RFC 4315, UIDPLUS).
+    pub fn uid_expunge(set: &SequenceSet) -> Command {
+        let args = format!("UID EXPUNGE {}", set).into_bytes();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
     pub fn uid_fetch() -> FetchCommandEmpty {
         let args = b"UID FETCH ".to_vec();
         FetchCommandEmpty { args }
     }
+
+    pub fn uid_move(set: &SequenceSet, mailbox: &str) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            format!("UID MOVE {} \"{}\"", set, quote(mailbox))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+
+    pub fn uid_search(criteria: &str) -> Command {
+        let args = format!("UID SEARCH {}", criteria).into_bytes();
+        Command {
+            args,
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    /// Like `search_criteria()`, but finding UIDs.
+    pub fn uid_search_criteria(criteria: &SearchCriteria) -> Result<Command, &'static str> {
+        search_command("UID SEARCH", criteria)
+    }
+
+    pub fn uid_search_returning(
+        returns: &[SearchReturnOption], criteria: &SearchCriteria
+    ) -> Result<Command, &'static str> {
+        search_command(&format!("UID SEARCH {}", return_options(returns)), criteria)
+    }
+
+    pub fn uid_store(set: &SequenceSet, kind: StoreType, flags: &[&str]) -> Command {
+        let args = store_args("UID STORE", set, kind, "FLAGS", &flags.join(" "));
+        Command {
+            args: args.into_bytes(),
+            utf8_args: None,
+            next_state: None,
+        }
+    }
+
+    pub fn uid_store_labels(set: &SequenceSet, kind: StoreType, labels: &[&str]) -> Command {
+        let (args, utf8_args) = mailbox_args(|quote| {
+            store_args("UID STORE", set, kind, "X-GM-LABELS", &gmail_labels(quote, labels))
+        });
+        Command {
+            args,
+            utf8_args,
+            next_state: None,
+        }
+    }
+}
+
+fn append_command(
+    mailbox: &str, flags: &[&str], date: Option<&str>, len: u64, literal_plus: bool, utf8: bool
+) -> Command {
+    let (args, utf8_args) = mailbox_args(|quote| {
+        let message = append_message(flags, date, len, literal_plus, utf8);
+        format!("APPEND \"{}\"{}", quote(mailbox), message)
+    });
+    Command {
+        args,
+        utf8_args,
+        next_state: None,
+    }
+}
+
+// The flags, date and literal marker of a message to append, each preceded
+// by a space.
+fn append_message(
+    flags: &[&str], date: Option<&str>, len: u64, literal_plus: bool, utf8: bool
+) -> String {
+    let mut args = String::new();
+    if !flags.is_empty() {
+        args.push_str(&format!(" ({})", flags.join(" ")));
+    }
+    if let Some(date) = date {
+        args.push_str(&format!(" \"{}\"", quoted_string(date).unwrap()));
+    }
+    let plus = if literal_plus { "+" } else { "" };
+    if utf8 {
+        args.push_str(&format!(" UTF8 (~{{{}{}}}", len, plus));
+    } else {
+        args.push_str(&format!(" {{{}{}}}", len, plus));
+    }
+    args
+}
+
+// Builds the arguments of a command with mailbox names or patterns, which
+// `build` quotes with the function it is passed. Besides the arguments with
+// the names encoded in modified UTF-7, returns those with the names as UTF-8
+// if they differ, for use once UTF8=ACCEPT is enabled (RFC 6855).
+fn mailbox_args<F>(build: F) -> (Vec<u8>, Option<Vec<u8>>)
+where
+    F: Fn(&Fn(&str) -> String) -> String, {
+    let args = build(&|name| quoted_string(&utf7::encode(name)).unwrap().into_owned());
+    let utf8 = build(&|name| quoted_string(name).unwrap().into_owned());
+    let utf8_args = if utf8 != args {
+        Some(utf8.into_bytes())
+    } else {
+        None
+    };
+    (args.into_bytes(), utf8_args)
+}
+
+// Builds a search with `criteria`. Strings that are not ASCII are sent in
+// literals with `CHARSET UTF-8`, or, once UTF8=ACCEPT is enabled, as quoted
+// strings without a charset, as clients must then not specify one (RFC 6855,
+// section 3).
+fn search_command(verb: &str, criteria: &SearchCriteria) -> Result<Command, &'static str> {
+    if criteria.is_ascii() {
+        return Ok(Command {
+            args: format!("{} {}", verb, criteria.to_args(false)?).into_bytes(),
+            utf8_args: None,
+            next_state: None,
+        });
+    }
+    let args = format!("{} CHARSET UTF-8 {}", verb, criteria.to_args(false)?);
+    let utf8_args = format!("{} {}", verb, criteria.to_args(true)?);
+    Ok(Command {
+        args: args.into_bytes(),
+        utf8_args: Some(utf8_args.into_bytes()),
+        next_state: None,
+    })
+}
+
+fn return_options(returns: &[SearchReturnOption]) -> String {
+    let returns = returns.iter().map(|ret| ret.to_string()).collect::<Vec<_>>();
+    format!("RETURN ({})", returns.join(" "))
+}
+
+fn store_args(
+    verb: &str, set: &SequenceSet, kind: StoreType, item: &str, values: &str
+) -> String {
+    let sign = match kind {
+        StoreType::Replace => "",
+        StoreType::Add => "+",
+        StoreType::Remove => "-",
+    };
+    format!("{} {} {}{} ({})", verb, set, sign, item, values)
 }
 
+// Gmail label names are encoded like mailbox names, while system labels
+// are sent as flags.
+fn gmail_labels(quote: &Fn(&str) -> String, labels: &[&str]) -> String {
+    labels
+        .iter()
+        .map(|label| {
+            if label.starts_with('\\') {
+                label.to_string()
+            } else {
+                format!("\"{}\"", quote(label))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Clone)]
 pub struct Command {
     args: Vec<u8>,
+    // The arguments with mailbox names in UTF-8 rather than modified UTF-7,
+    // if there are any non-ASCII names.
+    utf8_args: Option<Vec<u8>>,
     next_state: Option<State>,
 }
 
 impl Command {
+    /// The state the connection changes to when the command succeeds, if
+    /// it changes.
+    pub fn next_state(&self) -> Option<State> {
+        self.next_state
+    }
+
     pub fn into_parts(self) -> (Vec<u8>, Option<State>) {
-        let Command { args, next_state } = self;
+        let Command { args, next_state, .. } = self;
         (args, next_state)
     }
+
+    /// Like `into_parts()`, but with mailbox names in UTF-8, as they must be
+    /// sent once UTF8=ACCEPT is enabled (RFC 6855).
+    pub fn into_utf8_parts(self) -> (Vec<u8>, Option<State>) {
+        let Command {
+            args,
+            utf8_args,
+            next_state,
+        } = self;
+        (utf8_args.unwrap_or(args), next_state)
+    }
 }
 
 pub struct FetchCommandEmpty {
@@ -145,6 +907,12 @@ where
         args.extend(b":*");
         FetchCommandMessages { args }
     }
+
+    fn set(self, set: &SequenceSet) -> FetchCommandMessages {
+        let FetchCommandMessages { mut args } = self.prepare();
+        args.extend(set.to_string().as_bytes());
+        FetchCommandMessages { args }
+    }
 }
 
 pub struct FetchCommandAttributes {
@@ -173,22 +941,48 @@ where
     fn prepare(self) -> FetchCommandAttributes;
     fn attr(self, attr: Attribute) -> FetchCommandAttributes {
         let FetchCommandAttributes { mut args } = self.prepare();
-        args.extend(
-            match attr {
-                Attribute::Body => "BODY",
-                Attribute::Envelope => "ENVELOPE",
-                Attribute::Flags => "FLAGS",
-                Attribute::InternalDate => "INTERNALDATE",
-                Attribute::ModSeq => "MODSEQ",
-                Attribute::Rfc822 => "RFC822",
-                Attribute::Rfc822Size => "RFC822.SIZE",
-                Attribute::Uid => "UID",
-            }.as_bytes(),
-        );
+        args.extend(attribute_name(attr).as_bytes());
+        FetchCommandAttributes { args }
+    }
+    /// Asks for the header fields named in `fields`, or the whole header if
+    /// `fields` is empty, without setting the `\Seen` flag.
+    fn header_fields(self, fields: &[&str]) -> FetchCommandAttributes {
+        let FetchCommandAttributes { mut args } = self.prepare();
+        if fields.is_empty() {
+            args.extend(b"BODY.PEEK[HEADER]");
+        } else {
+            args.extend(b"BODY.PEEK[HEADER.FIELDS (");
+            args.extend(fields.join(" ").as_bytes());
+            args.extend(b")]");
+        }
+        FetchCommandAttributes { args }
+    }
+    /// Asks for the whole message, like `RFC822`, but without setting the
+    /// `\Seen` flag.
+    fn body_peek(self) -> FetchCommandAttributes {
+        let FetchCommandAttributes { mut args } = self.prepare();
+        args.extend(b"BODY.PEEK[]");
         FetchCommandAttributes { args }
     }
 }
 
+pub(crate) fn attribute_name(attr: Attribute) -> &'static str {
+    match attr {
+        Attribute::Body => "BODY",
+        Attribute::BodyStructure => "BODYSTRUCTURE",
+        Attribute::Envelope => "ENVELOPE",
+        Attribute::Flags => "FLAGS",
+        Attribute::GmailLabels => "X-GM-LABELS",
+        Attribute::GmailMsgId => "X-GM-MSGID",
+        Attribute::GmailThrId => "X-GM-THRID",
+        Attribute::InternalDate => "INTERNALDATE",
+        Attribute::ModSeq => "MODSEQ",
+        Attribute::Rfc822 => "RFC822",
+        Attribute::Rfc822Size => "RFC822.SIZE",
+        Attribute::Uid => "UID",
+    }
+}
+
 pub struct FetchCommand {
     args: Vec<u8>,
 }
@@ -201,6 +995,7 @@ where
         let FetchCommand { args } = self.prepare();
         Command {
             args,
+            utf8_args: None,
             next_state: None,
         }
     }
@@ -211,6 +1006,15 @@ where
         args.push(b')');
         FetchCommand { args }
     }
+    /// Like `changed_since()`, but also asks for the UIDs of messages
+    /// expunged since `seq` (RFC 7162, QRESYNC; only valid for `UID FETCH`).
+    fn changed_since_vanished(self, seq: u64) -> FetchCommand {
+        let FetchCommand { mut args } = self.prepare();
+        args.extend(b" (CHANGEDSINCE ");
+        args.extend(seq.to_string().as_bytes());
+        args.extend(b" VANISHED)");
+        FetchCommand { args }
+    }
 }
 
 impl FetchBuilderModifiers for FetchCommandAttributes {
@@ -229,7 +1033,12 @@ impl FetchBuilderModifiers for FetchCommand {
 
 #[cfg(test)]
 mod tests {
-    use super::CommandBuilder;
+    use super::{CommandBuilder, FetchBuilderAttributes, FetchBuilderMessages,
+                FetchBuilderModifiers};
+    use builders::notify::{EventGroup, NotifyEvent, NotifyFilter};
+    use builders::search::{SearchCriteria, SearchReturnOption};
+    use sequence::SequenceSet;
+    use types::{Attribute, ListReturn, MetadataDepth, StoreType};
     #[test]
     fn login() {
         assert_eq!(
@@ -243,4 +1052,309 @@ mod tests {
             b"LOGIN \"djc\" \"domain\\\\password\""
         );
     }
+
+    #[test]
+    fn acl() {
+        assert_eq!(
+            CommandBuilder::set_acl("INBOX", "fred", "+lrs").into_parts().0,
+            &b"SETACL \"INBOX\" \"fred\" \"+lrs\""[..]
+        );
+        assert_eq!(
+            CommandBuilder::delete_acl("Entwürfe", "-anyone").into_parts().0,
+            &b"DELETEACL \"Entw&APw-rfe\" \"-anyone\""[..]
+        );
+        assert_eq!(CommandBuilder::get_acl("INBOX").into_parts().0, b"GETACL \"INBOX\"");
+        assert_eq!(
+            CommandBuilder::list_rights("INBOX", "smith").into_parts().0,
+            &b"LISTRIGHTS \"INBOX\" \"smith\""[..]
+        );
+    }
+
+    #[test]
+    fn append() {
+        assert_eq!(
+            CommandBuilder::append("INBOX", &[], None, 310, false)
+                .into_parts()
+                .0,
+            b"APPEND \"INBOX\" {310}"
+        );
+        assert_eq!(
+            CommandBuilder::append(
+                "Sent",
+                &["\\Seen"],
+                Some("07-Feb-1994 21:52:25 -0800"),
+                42,
+                true
+            ).into_parts()
+                .0,
+            b"APPEND \"Sent\" (\\Seen) \"07-Feb-1994 21:52:25 -0800\" {42+}"
+        );
+        assert_eq!(
+            CommandBuilder::append_utf8("INBOX", &[], None, 310, false)
+                .into_parts()
+                .0,
+            &b"APPEND \"INBOX\" UTF8 (~{310}"[..]
+        );
+        assert_eq!(CommandBuilder::append_next(&[], None, 17, true), b" {17+}");
+        assert_eq!(
+            CommandBuilder::append_next(&["\\Seen", "\\Draft"], None, 17, false),
+            &b" (\\Seen \\Draft) {17}"[..]
+        );
+        assert_eq!(
+            CommandBuilder::append_next_utf8(&[], None, 17, false),
+            b" UTF8 (~{17}"
+        );
+    }
+
+    #[test]
+    fn id() {
+        assert_eq!(CommandBuilder::id(&[]).into_parts().0, b"ID NIL");
+        assert_eq!(
+            CommandBuilder::id(&[("name", "sodr"), ("version", "19.34")])
+                .into_parts()
+                .0,
+            &b"ID (\"name\" \"sodr\" \"version\" \"19.34\")"[..]
+        );
+    }
+
+    #[test]
+    fn list_extended() {
+        assert_eq!(
+            CommandBuilder::list_extended("", "*", &["SUBSCRIBED"], &["STATUS (UNSEEN)"])
+                .into_parts()
+                .0,
+            b"LIST (SUBSCRIBED) \"\" \"*\" RETURN (STATUS (UNSEEN))"
+        );
+        assert_eq!(
+            CommandBuilder::list_extended("", "%", &[], &[]).into_parts().0,
+            b"LIST \"\" \"%\""
+        );
+    }
+
+    #[test]
+    fn list_returning() {
+        let returns = [
+            ListReturn::Children,
+            ListReturn::Subscribed,
+            ListReturn::Status(&["MESSAGES", "UNSEEN"]),
+        ];
+        assert_eq!(
+            CommandBuilder::list_returning("", "%", &returns).into_parts().0,
+            &b"LIST \"\" \"%\" RETURN (CHILDREN SUBSCRIBED STATUS (MESSAGES UNSEEN))"[..]
+        );
+        assert_eq!(
+            CommandBuilder::list_returning("", "%", &[]).into_parts().0,
+            b"LIST \"\" \"%\""
+        );
+    }
+
+    #[test]
+    fn mailbox_names() {
+        assert_eq!(
+            CommandBuilder::select("Entwürfe").into_parts().0,
+            b"SELECT \"Entw&APw-rfe\""
+        );
+        assert_eq!(
+            CommandBuilder::rename("Tom & Jerry", "Archiv/Entwürfe")
+                .into_parts()
+                .0,
+            &b"RENAME \"Tom &- Jerry\" \"Archiv/Entw&APw-rfe\""[..]
+        );
+        assert_eq!(
+            CommandBuilder::list("Entwürfe/", "%").into_parts().0,
+            b"LIST \"Entw&APw-rfe/\" \"%\""
+        );
+        assert_eq!(
+            CommandBuilder::select("Entwürfe").into_utf8_parts().0,
+            "SELECT \"Entwürfe\"".as_bytes()
+        );
+        assert_eq!(
+            CommandBuilder::select("INBOX").into_utf8_parts().0,
+            b"SELECT \"INBOX\""
+        );
+    }
+
+    #[test]
+    fn notify() {
+        let groups = [
+            EventGroup::new(
+                NotifyFilter::Selected,
+                vec![
+                    NotifyEvent::MessageNew(vec![Attribute::Uid, Attribute::Flags]),
+                    NotifyEvent::MessageExpunge,
+                    NotifyEvent::FlagChange,
+                ],
+            ),
+            EventGroup::new(
+                NotifyFilter::Subtree(vec!["Entwürfe".to_string()]),
+                vec![NotifyEvent::MessageNew(vec![])],
+            ),
+            EventGroup::new(NotifyFilter::Personal, vec![]),
+        ];
+        assert_eq!(
+            String::from_utf8(CommandBuilder::notify(&groups, true).into_parts().0).unwrap(),
+            "NOTIFY SET STATUS (selected (MessageNew (UID FLAGS) MessageExpunge FlagChange)) \
+             (subtree (\"Entw&APw-rfe\") (MessageNew)) (personal NONE)"
+        );
+        assert_eq!(CommandBuilder::notify_none().into_parts().0, b"NOTIFY NONE");
+    }
+
+    #[test]
+    fn quota() {
+        assert_eq!(CommandBuilder::get_quota("").into_parts().0, b"GETQUOTA \"\"");
+        assert_eq!(
+            CommandBuilder::get_quota_root("INBOX").into_parts().0,
+            b"GETQUOTAROOT \"INBOX\""
+        );
+        assert_eq!(
+            CommandBuilder::set_quota("", &[("STORAGE", 512), ("MESSAGE", 100)])
+                .into_parts()
+                .0,
+            &b"SETQUOTA \"\" (STORAGE 512 MESSAGE 100)"[..]
+        );
+    }
+
+    #[test]
+    fn qresync() {
+        assert_eq!(
+            CommandBuilder::select_qresync("INBOX", 67890007, 90060115194045000, Some("41:211"))
+                .into_parts()
+                .0,
+            b"SELECT \"INBOX\" (QRESYNC (67890007 90060115194045000 41:211))"
+        );
+        assert_eq!(
+            CommandBuilder::uid_fetch()
+                .range(300, 500)
+                .attr(Attribute::Flags)
+                .changed_since_vanished(12345)
+                .build()
+                .into_parts()
+                .0,
+            b"UID FETCH 300:500 (FLAGS) (CHANGEDSINCE 12345 VANISHED)"
+        );
+        let set: SequenceSet = vec![1, 2, 3, 7].into_iter().collect();
+        assert_eq!(
+            CommandBuilder::uid_fetch()
+                .set(&set)
+                .all_after(20)
+                .attr(Attribute::Uid)
+                .build()
+                .into_parts()
+                .0,
+            &b"UID FETCH 1:3,7,20:* (UID)"[..]
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        assert_eq!(
+            CommandBuilder::get_metadata("INBOX", &["/private/comment"], None, None)
+                .into_parts()
+                .0,
+            &b"GETMETADATA \"INBOX\" (\"/private/comment\")"[..]
+        );
+        assert_eq!(
+            CommandBuilder::get_metadata(
+                "",
+                &["/shared/a", "/shared/b"],
+                Some(MetadataDepth::One),
+                Some(1024)
+            ).into_parts()
+                .0,
+            &b"GETMETADATA (MAXSIZE 1024 DEPTH 1) \"\" (\"/shared/a\" \"/shared/b\")"[..]
+        );
+        let values = [
+            ("/private/comment", Some(&b"say \"hi\""[..])),
+            ("/private/x", None),
+            ("/private/y", Some(&b"a\r\nb"[..])),
+        ];
+        assert_eq!(
+            CommandBuilder::set_metadata("INBOX", &values).into_parts().0,
+            &b"SETMETADATA \"INBOX\" (\"/private/comment\" \"say \\\"hi\\\"\" \"/private/x\" NIL \
+               \"/private/y\" {4+}\r\na\r\nb)"[..]
+        );
+    }
+
+    #[test]
+    fn header_fields() {
+        assert_eq!(
+            CommandBuilder::fetch()
+                .range(1, 5)
+                .attr(Attribute::Uid)
+                .header_fields(&["FROM", "SUBJECT"])
+                .build()
+                .into_parts()
+                .0,
+            &b"FETCH 1:5 (UID BODY.PEEK[HEADER.FIELDS (FROM SUBJECT)])"[..]
+        );
+        assert_eq!(
+            CommandBuilder::fetch()
+                .num(3)
+                .header_fields(&[])
+                .build()
+                .into_parts()
+                .0,
+            &b"FETCH 3 (BODY.PEEK[HEADER])"[..]
+        );
+        assert_eq!(
+            CommandBuilder::uid_fetch()
+                .range(7, 9)
+                .attr(Attribute::Flags)
+                .body_peek()
+                .build()
+                .into_parts()
+                .0,
+            &b"UID FETCH 7:9 (FLAGS BODY.PEEK[])"[..]
+        );
+    }
+
+    #[test]
+    fn search_returning() {
+        let returns = [SearchReturnOption::Min, SearchReturnOption::Count];
+        let criteria = SearchCriteria::Subject("Grüße".to_string());
+        let cmd = CommandBuilder::search_returning(&returns, &criteria).unwrap();
+        assert_eq!(
+            cmd.into_parts().0,
+            "SEARCH RETURN (MIN COUNT) CHARSET UTF-8 SUBJECT {7+}\r\nGrüße".as_bytes()
+        );
+        let cmd = CommandBuilder::uid_search_returning(&[SearchReturnOption::Save], &criteria);
+        assert_eq!(
+            cmd.unwrap().into_utf8_parts().0,
+            "UID SEARCH RETURN (SAVE) SUBJECT \"Grüße\"".as_bytes()
+        );
+        let criteria = SearchCriteria::Subject("a\0b".to_string());
+        assert!(CommandBuilder::search_criteria(&criteria).is_err());
+        let criteria = SearchCriteria::Subject("ascii".to_string());
+        let cmd = CommandBuilder::uid_search_criteria(&criteria).unwrap();
+        assert_eq!(cmd.into_utf8_parts().0, b"UID SEARCH SUBJECT \"ascii\"");
+        let cmd = CommandBuilder::uid_store(&SequenceSet::saved(), StoreType::Add, &["\\Seen"]);
+        assert_eq!(cmd.into_parts().0, b"UID STORE $ +FLAGS (\\Seen)");
+    }
+
+    #[test]
+    fn store() {
+        let set = SequenceSet::from(1..=3);
+        assert_eq!(
+            CommandBuilder::store(&set, StoreType::Add, &["\\Seen", "\\Flagged"])
+                .into_parts()
+                .0,
+            b"STORE 1:3 +FLAGS (\\Seen \\Flagged)"
+        );
+        assert_eq!(
+            CommandBuilder::uid_store(&SequenceSet::from(42), StoreType::Replace, &[])
+                .into_parts()
+                .0,
+            b"UID STORE 42 FLAGS ()"
+        );
+        let labels = ["\\Important", "Muy Importante", "Entwürfe"];
+        let cmd = CommandBuilder::uid_store_labels(&set, StoreType::Remove, &labels);
+        assert_eq!(
+            cmd.clone().into_parts().0,
+            &b"UID STORE 1:3 -X-GM-LABELS (\\Important \"Muy Importante\" \"Entw&APw-rfe\")"[..]
+        );
+        assert_eq!(
+            cmd.into_utf8_parts().0,
+            "UID STORE 1:3 -X-GM-LABELS (\\Important \"Muy Importante\" \"Entwürfe\")".as_bytes()
+        );
+    }
 }
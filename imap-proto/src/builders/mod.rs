@@ -1 +1,3 @@
 pub mod command;
+pub mod notify;
+pub mod search;
@@ -0,0 +1,96 @@
+use builders::command::attribute_name;
+use types::Attribute;
+
+/// The mailboxes an event group for `NOTIFY` applies to (RFC 5465,
+/// section 6).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NotifyFilter {
+    /// The selected mailbox.
+    Selected,
+    /// The selected mailbox, with expunges only reported when the server
+    /// could also report them without `NOTIFY`.
+    SelectedDelayed,
+    /// All mailboxes that receive new mail.
+    Inboxes,
+    /// All mailboxes in the personal namespace.
+    Personal,
+    Subscribed,
+    /// The given mailboxes and all mailboxes below them.
+    Subtree(Vec<String>),
+    Mailboxes(Vec<String>),
+}
+
+/// An event to be notified of (RFC 5465, section 5).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NotifyEvent {
+    /// New messages. For the selected mailbox, the server sends `FETCH`
+    /// responses with the given attributes for each of them.
+    MessageNew(Vec<Attribute>),
+    MessageExpunge,
+    FlagChange,
+    AnnotationChange,
+    /// Mailboxes were created, deleted or renamed.
+    MailboxName,
+    SubscriptionChange,
+    MailboxMetadataChange,
+    ServerMetadataChange,
+}
+
+/// The events to be notified of for some mailboxes; no events at all turns
+/// notifications off for them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EventGroup {
+    pub filter: NotifyFilter,
+    pub events: Vec<NotifyEvent>,
+}
+
+impl EventGroup {
+    pub fn new(filter: NotifyFilter, events: Vec<NotifyEvent>) -> Self {
+        Self { filter, events }
+    }
+
+    pub(crate) fn format(&self, quote: &Fn(&str) -> String) -> String {
+        let mailboxes = |names: &[String]| {
+            let names = names
+                .iter()
+                .map(|name| format!("\"{}\"", quote(name)))
+                .collect::<Vec<_>>();
+            format!("({})", names.join(" "))
+        };
+        let filter = match self.filter {
+            NotifyFilter::Selected => "selected".to_string(),
+            NotifyFilter::SelectedDelayed => "selected-delayed".to_string(),
+            NotifyFilter::Inboxes => "inboxes".to_string(),
+            NotifyFilter::Personal => "personal".to_string(),
+            NotifyFilter::Subscribed => "subscribed".to_string(),
+            NotifyFilter::Subtree(ref names) => format!("subtree {}", mailboxes(names)),
+            NotifyFilter::Mailboxes(ref names) => format!("mailboxes {}", mailboxes(names)),
+        };
+        if self.events.is_empty() {
+            return format!("({} NONE)", filter);
+        }
+        let events = self.events.iter().map(format_event).collect::<Vec<_>>();
+        format!("({} ({}))", filter, events.join(" "))
+    }
+}
+
+fn format_event(event: &NotifyEvent) -> String {
+    let name = match *event {
+        NotifyEvent::MessageNew(ref attrs) if !attrs.is_empty() => {
+            let attrs = attrs
+                .iter()
+                .map(|&attr| attribute_name(attr))
+                .collect::<Vec<_>>();
+            return format!("MessageNew ({})", attrs.join(" "));
+        },
+        NotifyEvent::MessageNew(_) => "MessageNew",
+        NotifyEvent::MessageExpunge => "MessageExpunge",
+        NotifyEvent::FlagChange => "FlagChange",
+        NotifyEvent::AnnotationChange => "AnnotationChange",
+        NotifyEvent::MailboxName => "MailboxName",
+        NotifyEvent::SubscriptionChange => "SubscriptionChange",
+        NotifyEvent::MailboxMetadataChange => "MailboxMetadataChange",
+        NotifyEvent::ServerMetadataChange => "ServerMetadataChange",
+    };
+    name.to_string()
+}
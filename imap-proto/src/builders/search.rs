@@ -0,0 +1,379 @@
+use std::fmt;
+
+use quoted_string;
+use sequence::SequenceSet;
+use utf7;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A date for search keys such as `SINCE`, formatted like `1-Feb-1994`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SearchDate {
+    pub year: u16,
+    /// 1 for January to 12 for December.
+    pub month: u8,
+    pub day: u8,
+}
+
+impl SearchDate {
+    pub fn new(year: u16, month: u8, day: u8) -> Self {
+        assert!(month >= 1 && month <= 12, "invalid month {}", month);
+        Self { year, month, day }
+    }
+}
+
+impl fmt::Display for SearchDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let month = MONTHS[usize::from(self.month - 1)];
+        write!(f, "{}-{}-{}", self.day, month, self.year)
+    }
+}
+
+/// What `SEARCH RETURN (...)` reports about the messages found, in an
+/// `ESEARCH` response (RFC 4731), instead of listing every one of them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SearchReturnOption {
+    /// All messages found, as a compact sequence set.
+    All,
+    Count,
+    Max,
+    Min,
+    /// Saves the messages found on the server, for later commands to refer
+    /// to as `SequenceSet::saved()` (RFC 5182, SEARCHRES).
+    Save,
+}
+
+impl fmt::Display for SearchReturnOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SearchReturnOption::All => "ALL",
+            SearchReturnOption::Count => "COUNT",
+            SearchReturnOption::Max => "MAX",
+            SearchReturnOption::Min => "MIN",
+            SearchReturnOption::Save => "SAVE",
+        })
+    }
+}
+
+/// Search keys for `SEARCH` and `UID SEARCH` (RFC 3501, section 6.4.4).
+///
+/// Criteria are combined with `and()`, `or()` and `not()`:
+///
+/// ```
+/// use imap_proto::builders::search::{SearchCriteria, SearchDate};
+///
+/// let criteria = SearchCriteria::Unseen
+///     .and(SearchCriteria::Since(SearchDate::new(2018, 3, 1)))
+///     .and(SearchCriteria::From("me@example.com".to_string()).not());
+/// assert_eq!(
+///     criteria.to_args(false).unwrap(),
+///     "UNSEEN SINCE 1-Mar-2018 NOT FROM \"me@example.com\""
+/// );
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SearchCriteria {
+    All,
+    And(Vec<SearchCriteria>),
+    Answered,
+    Bcc(String),
+    Before(SearchDate),
+    Body(String),
+    Cc(String),
+    Deleted,
+    Draft,
+    Flagged,
+    From(String),
+    /// Messages with the given Gmail label, or system label like `\Inbox`.
+    GmailLabel(String),
+    GmailMsgId(u64),
+    /// Messages matching a Gmail web interface search, like `has:attachment
+    /// in:unread` (`X-GM-RAW`).
+    GmailRaw(String),
+    GmailThrId(u64),
+    Header(String, String),
+    Keyword(String),
+    Larger(u32),
+    New,
+    Not(Box<SearchCriteria>),
+    Old,
+    On(SearchDate),
+    Or(Box<SearchCriteria>, Box<SearchCriteria>),
+    Recent,
+    Seen,
+    SentBefore(SearchDate),
+    SentOn(SearchDate),
+    SentSince(SearchDate),
+    /// Messages in the given sequence set.
+    Sequence(SequenceSet),
+    Since(SearchDate),
+    Smaller(u32),
+    Subject(String),
+    Text(String),
+    To(String),
+    /// Messages in the given UID set.
+    Uid(SequenceSet),
+    Unanswered,
+    Undeleted,
+    Undraft,
+    Unflagged,
+    Unkeyword(String),
+    Unseen,
+}
+
+impl SearchCriteria {
+    /// Messages matching both `self` and `other`.
+    pub fn and(self, other: SearchCriteria) -> SearchCriteria {
+        match self {
+            SearchCriteria::And(mut criteria) => {
+                criteria.push(other);
+                SearchCriteria::And(criteria)
+            },
+            criteria => SearchCriteria::And(vec![criteria, other]),
+        }
+    }
+
+    /// Messages matching either `self` or `other`.
+    pub fn or(self, other: SearchCriteria) -> SearchCriteria {
+        SearchCriteria::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Messages not matching `self`.
+    pub fn not(self) -> SearchCriteria {
+        SearchCriteria::Not(Box::new(self))
+    }
+
+    /// Whether all strings in the criteria are ASCII. Otherwise, the command
+    /// must specify `CHARSET UTF-8`.
+    pub fn is_ascii(&self) -> bool {
+        use self::SearchCriteria::*;
+        match *self {
+            Keyword(ref s) | Unkeyword(ref s) => s.is_ascii(),
+            _ => !self.any_string(&|s| !s.is_ascii()),
+        }
+    }
+
+    /// Whether `to_args(utf8)` sends any strings as literals: those with
+    /// line breaks and, unless `utf8`, those that are not ASCII. They are
+    /// sent as non-synchronizing literals, which requires LITERAL+ (RFC
+    /// 7888).
+    pub fn has_literals(&self, utf8: bool) -> bool {
+        self.any_string(&|s| needs_literal(s, utf8))
+    }
+
+    /// The criteria as arguments of `SEARCH`.
+    ///
+    /// Strings are sent as quoted strings where possible. Quoted strings can
+    /// only hold 7-bit text (RFC 3501, section 4.3), so strings that are not
+    /// ASCII are sent as literals, unless `utf8` is set because UTF8=ACCEPT
+    /// is enabled (RFC 6855). Strings with line breaks are always sent as
+    /// literals. Fails for strings with NUL characters, which cannot be
+    /// sent at all, and keywords that are not atoms.
+    pub fn to_args(&self, utf8: bool) -> Result<String, &'static str> {
+        let mut args = String::new();
+        self.write(&mut args, utf8, false)?;
+        Ok(args)
+    }
+
+    // Whether `f` holds for any of the strings that are sent as quoted
+    // strings or literals.
+    fn any_string(&self, f: &Fn(&str) -> bool) -> bool {
+        use self::SearchCriteria::*;
+        match *self {
+            And(ref criteria) => criteria.iter().any(|c| c.any_string(f)),
+            Not(ref criteria) => criteria.any_string(f),
+            Or(ref a, ref b) => a.any_string(f) || b.any_string(f),
+            Header(ref name, ref value) => f(name) || f(value),
+            Bcc(ref s) | Body(ref s) | Cc(ref s) | From(ref s) | GmailRaw(ref s)
+            | Subject(ref s) | Text(ref s) | To(ref s) => f(s),
+            _ => false,
+        }
+    }
+
+    fn write(&self, out: &mut String, utf8: bool, nested: bool) -> Result<(), &'static str> {
+        use self::SearchCriteria::*;
+        let string = |key: &str, s: &str, out: &mut String| {
+            out.push_str(key);
+            out.push(' ');
+            write_string(out, s, utf8)
+        };
+        // Keywords, and system labels like flags.
+        let atom = |key: &str, s: &str, out: &mut String| {
+            let name = if s.starts_with('\\') { &s[1..] } else { s };
+            if name.is_empty() || !name.bytes().all(is_atom_char) {
+                return Err("keyword is not an atom");
+            }
+            out.push_str(&format!("{} {}", key, s));
+            Ok(())
+        };
+        match *self {
+            All => out.push_str("ALL"),
+            And(ref criteria) => {
+                if nested {
+                    out.push('(');
+                }
+                for (i, criteria) in criteria.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    criteria.write(out, utf8, true)?;
+                }
+                if nested {
+                    out.push(')');
+                }
+            },
+            Answered => out.push_str("ANSWERED"),
+            Bcc(ref s) => string("BCC", s, out)?,
+            Before(date) => out.push_str(&format!("BEFORE {}", date)),
+            Body(ref s) => string("BODY", s, out)?,
+            Cc(ref s) => string("CC", s, out)?,
+            Deleted => out.push_str("DELETED"),
+            Draft => out.push_str("DRAFT"),
+            Flagged => out.push_str("FLAGGED"),
+            From(ref s) => string("FROM", s, out)?,
+            // Label names are encoded like mailbox names.
+            GmailLabel(ref s) if s.starts_with('\\') => atom("X-GM-LABELS", s, out)?,
+            GmailLabel(ref s) => string("X-GM-LABELS", &utf7::encode(s), out)?,
+            GmailMsgId(id) => out.push_str(&format!("X-GM-MSGID {}", id)),
+            GmailRaw(ref s) => string("X-GM-RAW", s, out)?,
+            GmailThrId(id) => out.push_str(&format!("X-GM-THRID {}", id)),
+            Header(ref name, ref value) => {
+                string("HEADER", name, out)?;
+                out.push(' ');
+                write_string(out, value, utf8)?;
+            },
+            Keyword(ref s) => atom("KEYWORD", s, out)?,
+            Larger(n) => out.push_str(&format!("LARGER {}", n)),
+            New => out.push_str("NEW"),
+            Not(ref criteria) => {
+                out.push_str("NOT ");
+                criteria.write(out, utf8, true)?;
+            },
+            Old => out.push_str("OLD"),
+            On(date) => out.push_str(&format!("ON {}", date)),
+            Or(ref a, ref b) => {
+                out.push_str("OR ");
+                a.write(out, utf8, true)?;
+                out.push(' ');
+                b.write(out, utf8, true)?;
+            },
+            Recent => out.push_str("RECENT"),
+            Seen => out.push_str("SEEN"),
+            SentBefore(date) => out.push_str(&format!("SENTBEFORE {}", date)),
+            SentOn(date) => out.push_str(&format!("SENTON {}", date)),
+            SentSince(date) => out.push_str(&format!("SENTSINCE {}", date)),
+            Sequence(ref set) => out.push_str(&set.to_string()),
+            Since(date) => out.push_str(&format!("SINCE {}", date)),
+            Smaller(n) => out.push_str(&format!("SMALLER {}", n)),
+            Subject(ref s) => string("SUBJECT", s, out)?,
+            Text(ref s) => string("TEXT", s, out)?,
+            To(ref s) => string("TO", s, out)?,
+            Uid(ref set) => out.push_str(&format!("UID {}", set)),
+            Unanswered => out.push_str("UNANSWERED"),
+            Undeleted => out.push_str("UNDELETED"),
+            Undraft => out.push_str("UNDRAFT"),
+            Unflagged => out.push_str("UNFLAGGED"),
+            Unkeyword(ref s) => atom("UNKEYWORD", s, out)?,
+            Unseen => out.push_str("UNSEEN"),
+        }
+        Ok(())
+    }
+}
+
+fn needs_literal(s: &str, utf8: bool) -> bool {
+    (!utf8 && !s.is_ascii()) || s.contains(|c| c == '\r' || c == '\n')
+}
+
+// Writes `s` as a quoted string, or as a non-synchronizing literal if it
+// cannot be quoted.
+fn write_string(out: &mut String, s: &str, utf8: bool) -> Result<(), &'static str> {
+    if s.contains('\0') {
+        return Err("NUL not allowed in search strings");
+    }
+    if needs_literal(s, utf8) {
+        out.push_str(&format!("{{{}+}}\r\n{}", s.len(), s));
+    } else {
+        out.push_str(&format!("\"{}\"", quoted_string(s)?));
+    }
+    Ok(())
+}
+
+// `ATOM-CHAR` (RFC 3501, section 9), as keywords and flags are sent.
+fn is_atom_char(b: u8) -> bool {
+    match b {
+        b'(' | b')' | b'{' | b' ' | b'%' | b'*' | b'"' | b'\\' | b']' => false,
+        _ => b > 0x1f && b < 0x7f,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SearchCriteria, SearchDate};
+
+    #[test]
+    fn nesting() {
+        let criteria = SearchCriteria::Flagged
+            .or(SearchCriteria::Larger(1024).and(SearchCriteria::Deleted.not()))
+            .and(SearchCriteria::Header("X-Spam".to_string(), "\"yes\"".to_string()));
+        assert_eq!(
+            criteria.to_args(false).unwrap(),
+            "OR FLAGGED (LARGER 1024 NOT DELETED) HEADER \"X-Spam\" \"\\\"yes\\\"\""
+        );
+        assert!(criteria.is_ascii());
+    }
+
+    #[test]
+    fn gmail() {
+        let criteria = SearchCriteria::GmailRaw("has:attachment in:unread".to_string())
+            .and(SearchCriteria::GmailLabel("\\Important".to_string()).not())
+            .and(SearchCriteria::GmailThrId(1278455344230334865))
+            .and(SearchCriteria::GmailLabel("Entwürfe".to_string()));
+        assert_eq!(
+            criteria.to_args(false).unwrap(),
+            "X-GM-RAW \"has:attachment in:unread\" NOT X-GM-LABELS \\Important \
+             X-GM-THRID 1278455344230334865 X-GM-LABELS \"Entw&APw-rfe\""
+        );
+        assert!(criteria.is_ascii());
+    }
+
+    #[test]
+    fn dates() {
+        let criteria = SearchCriteria::SentBefore(SearchDate::new(1994, 2, 7));
+        assert_eq!(criteria.to_args(false).unwrap(), "SENTBEFORE 7-Feb-1994");
+    }
+
+    #[test]
+    fn literals() {
+        let criteria = SearchCriteria::Subject("Grüße".to_string())
+            .and(SearchCriteria::From("me@example.com".to_string()));
+        assert!(!criteria.is_ascii());
+        assert!(criteria.has_literals(false));
+        assert!(!criteria.has_literals(true));
+        assert_eq!(
+            criteria.to_args(false).unwrap(),
+            "SUBJECT {7+}\r\nGrüße FROM \"me@example.com\""
+        );
+        assert_eq!(
+            criteria.to_args(true).unwrap(),
+            "SUBJECT \"Grüße\" FROM \"me@example.com\""
+        );
+
+        let criteria = SearchCriteria::Body("two\r\nlines".to_string());
+        assert!(criteria.is_ascii());
+        assert!(criteria.has_literals(true));
+        assert_eq!(criteria.to_args(true).unwrap(), "BODY {10+}\r\ntwo\r\nlines");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(SearchCriteria::Text("a\0b".to_string()).to_args(false).is_err());
+        assert!(SearchCriteria::Keyword("two words".to_string()).to_args(false).is_err());
+        assert!(SearchCriteria::Unkeyword("".to_string()).to_args(false).is_err());
+        let criteria = SearchCriteria::Keyword("$Junk".to_string())
+            .and(SearchCriteria::GmailLabel("\\Inbox".to_string()));
+        assert_eq!(criteria.to_args(false).unwrap(), "KEYWORD $Junk X-GM-LABELS \\Inbox");
+        let criteria = SearchCriteria::GmailLabel("\\In box".to_string());
+        assert!(criteria.to_args(false).is_err());
+    }
+}
@@ -3,9 +3,12 @@ extern crate nom;
 
 pub mod builders;
 mod parser;
+pub mod sequence;
 pub mod types;
+pub mod utf7;
 
 pub use parser::{parse_response, ParseResult};
+pub use sequence::SequenceSet;
 pub use types::*;
 
 use std::borrow::Cow;
@@ -4,6 +4,8 @@
 
 use nom::{self, IResult};
 
+use std::cmp;
+use std::ops::RangeInclusive;
 use std::str;
 
 use types::*;
@@ -256,6 +258,11 @@ named!(resp_text_code_highest_mod_seq<ResponseCode>, do_parse!(
     (ResponseCode::HighestModSeq(num))
 ));
 
+named!(resp_text_code_no_mod_seq<ResponseCode>, do_parse!(
+    tag_s!("NOMODSEQ") >>
+    (ResponseCode::NoModSeq)
+));
+
 named!(resp_text_code_read_only<ResponseCode>, do_parse!(
     tag_s!("READ-ONLY") >>
     (ResponseCode::ReadOnly)
@@ -289,6 +296,73 @@ named!(resp_text_code_unseen<ResponseCode>, do_parse!(
     (ResponseCode::Unseen(num))
 ));
 
+named!(resp_text_code_capability<ResponseCode>, do_parse!(
+    tag_s!("CAPABILITY") >>
+    capabilities: many1!(capability) >>
+    (ResponseCode::Capabilities(capabilities))
+));
+
+named!(resp_text_code_append_uid<ResponseCode>, do_parse!(
+    tag_s!("APPENDUID ") >>
+    uid_validity: number >>
+    tag_s!(" ") >>
+    uids: uid_set >>
+    (ResponseCode::AppendUid(uid_validity, uids))
+));
+
+named!(resp_text_code_copy_uid<ResponseCode>, do_parse!(
+    tag_s!("COPYUID ") >>
+    uid_validity: number >>
+    tag_s!(" ") >>
+    source: uid_set >>
+    tag_s!(" ") >>
+    destination: uid_set >>
+    (ResponseCode::CopyUid(uid_validity, source, destination))
+));
+
+named!(resp_text_code_uid_not_sticky<ResponseCode>, do_parse!(
+    tag_s!("UIDNOTSTICKY") >>
+    (ResponseCode::UidNotSticky)
+));
+
+named!(resp_text_code_notification_overflow<ResponseCode>, do_parse!(
+    tag_s!("NOTIFICATIONOVERFLOW") >>
+    (ResponseCode::NotificationOverflow)
+));
+
+// RFC 5464, sections 4.2.1 and 4.3
+named!(resp_text_code_metadata<ResponseCode>, do_parse!(
+    tag_s!("METADATA ") >>
+    code: alt!(
+        map!(preceded!(tag_s!("LONGENTRIES "), number), MetadataCode::LongEntries) |
+        map!(preceded!(tag_s!("MAXSIZE "), number), MetadataCode::MaxSize) |
+        map!(tag_s!("TOOMANY"), |_| MetadataCode::TooMany) |
+        map!(tag_s!("NOPRIVATE"), |_| MetadataCode::NoPrivate)
+    ) >>
+    (ResponseCode::Metadata(code))
+));
+
+// RFC 5530, section 3
+named!(resp_text_code_condition<ResponseCode>, alt!(
+    map!(tag_s!("ALREADYEXISTS"), |_| ResponseCode::AlreadyExists) |
+    map!(tag_s!("AUTHENTICATIONFAILED"), |_| ResponseCode::AuthenticationFailed) |
+    map!(tag_s!("AUTHORIZATIONFAILED"), |_| ResponseCode::AuthorizationFailed) |
+    map!(tag_s!("CANNOT"), |_| ResponseCode::Cannot) |
+    map!(tag_s!("CLIENTBUG"), |_| ResponseCode::ClientBug) |
+    map!(tag_s!("CONTACTADMIN"), |_| ResponseCode::ContactAdmin) |
+    map!(tag_s!("CORRUPTION"), |_| ResponseCode::Corruption) |
+    map!(tag_s!("EXPIRED"), |_| ResponseCode::Expired) |
+    map!(tag_s!("EXPUNGEISSUED"), |_| ResponseCode::ExpungeIssued) |
+    map!(tag_s!("INUSE"), |_| ResponseCode::InUse) |
+    map!(tag_s!("LIMIT"), |_| ResponseCode::Limit) |
+    map!(tag_s!("NONEXISTENT"), |_| ResponseCode::NonExistent) |
+    map!(tag_s!("NOPERM"), |_| ResponseCode::NoPerm) |
+    map!(tag_s!("OVERQUOTA"), |_| ResponseCode::OverQuota) |
+    map!(tag_s!("PRIVACYREQUIRED"), |_| ResponseCode::PrivacyRequired) |
+    map!(tag_s!("SERVERBUG"), |_| ResponseCode::ServerBug) |
+    map!(tag_s!("UNAVAILABLE"), |_| ResponseCode::Unavailable)
+));
+
 named!(resp_text_code<ResponseCode>, do_parse!(
     tag_s!("[") >>
     coded: alt!(
@@ -299,7 +373,15 @@ named!(resp_text_code<ResponseCode>, do_parse!(
         resp_text_code_read_only |
         resp_text_code_read_write |
         resp_text_code_try_create |
-        resp_text_code_highest_mod_seq
+        resp_text_code_highest_mod_seq |
+        resp_text_code_no_mod_seq |
+        resp_text_code_capability |
+        resp_text_code_append_uid |
+        resp_text_code_copy_uid |
+        resp_text_code_uid_not_sticky |
+        resp_text_code_notification_overflow |
+        resp_text_code_metadata |
+        resp_text_code_condition
     ) >>
     // Per the spec, the closing tag should be "] ".
     // See `resp_text` for more on why this is done differently.
@@ -319,6 +401,72 @@ named!(capability_data<Response>, do_parse!(
     (Response::Capabilities(capabilities))
 ));
 
+named!(enabled_data<Response>, do_parse!(
+    tag_s!("ENABLED") >>
+    capabilities: many0!(capability) >>
+    (Response::Enabled(capabilities))
+));
+
+named!(uid_range<RangeInclusive<u32>>, do_parse!(
+    start: number >>
+    end: opt!(do_parse!(
+        tag_s!(":") >>
+        end: number >>
+        (end)
+    )) >> ({
+        let end = end.unwrap_or(start);
+        cmp::min(start, end)..=cmp::max(start, end)
+    })
+));
+
+named!(uid_set<Vec<RangeInclusive<u32>>>, do_parse!(
+    first: uid_range >>
+    rest: many0!(do_parse!(
+        tag_s!(",") >>
+        range: uid_range >>
+        (range)
+    )) >> ({
+        let mut ranges = vec![first];
+        ranges.extend(rest);
+        ranges
+    })
+));
+
+named!(vanished<Response>, do_parse!(
+    tag_s!("VANISHED ") >>
+    earlier: opt!(tag_s!("(EARLIER) ")) >>
+    uids: uid_set >>
+    (Response::Vanished {
+        earlier: earlier.is_some(),
+        uids,
+    })
+));
+
+named!(search_return<SearchReturn>, alt!(
+    do_parse!(tag_s!(" ALL ") >> uids: uid_set >> (SearchReturn::All(uids))) |
+    do_parse!(tag_s!(" COUNT ") >> num: number >> (SearchReturn::Count(num))) |
+    do_parse!(tag_s!(" MAX ") >> num: number >> (SearchReturn::Max(num))) |
+    do_parse!(tag_s!(" MIN ") >> num: number >> (SearchReturn::Min(num))) |
+    do_parse!(tag_s!(" MODSEQ ") >> num: number_64 >> (SearchReturn::ModSeq(num)))
+));
+
+named!(esearch<Response>, do_parse!(
+    tag_s!("ESEARCH") >>
+    tag: opt!(do_parse!(
+        tag_s!(" (TAG ") >>
+        tag: map_res!(string, str::from_utf8) >>
+        tag_s!(")") >>
+        (tag)
+    )) >>
+    uid: opt!(tag_s!(" UID")) >>
+    data: many0!(search_return) >>
+    (Response::ESearch {
+        tag,
+        uid: uid.is_some(),
+        data,
+    })
+));
+
 named!(mailbox_data_search<Response>, do_parse!(
     tag_s!("SEARCH") >>
     ids: many0!(do_parse!(
@@ -340,11 +488,16 @@ named!(mailbox_data_exists<Response>, do_parse!(
     (Response::MailboxData(MailboxDatum::Exists(num)))
 ));
 
+named!(delimiter<Option<&str>>, alt!(
+    map!(tag_s!("NIL"), |_| None) |
+    map!(map_res!(quoted, str::from_utf8), Some)
+));
+
 named!(mailbox_data_list<Response>, do_parse!(
     tag_s!("LIST ") >>
     flags: flag_list >>
     tag_s!(" ") >>
-    delimiter: map_res!(quoted, str::from_utf8) >>
+    delimiter: delimiter >>
     tag_s!(" ") >>
     name: mailbox >>
     (Response::MailboxData(MailboxDatum::List {
@@ -358,7 +511,7 @@ named!(mailbox_data_lsub<Response>, do_parse!(
     tag_s!("LSUB ") >>
     flags: flag_list >>
     tag_s!(" ") >>
-    delimiter: map_res!(quoted, str::from_utf8) >>
+    delimiter: delimiter >>
     tag_s!(" ") >>
     name: mailbox >>
     (Response::MailboxData(MailboxDatum::SubList {
@@ -370,24 +523,31 @@ named!(mailbox_data_lsub<Response>, do_parse!(
 
 // Unlike `status_att` in the RFC syntax, this includes the value,
 // so that it can return a valid enum object instead of just a key.
-named!(status_att<StatusAttribute>, do_parse!(
-    key: alt!(
-        tag_s!("MESSAGES") |
-        tag_s!("RECENT") |
-        tag_s!("UIDNEXT") |
-        tag_s!("UIDVALIDITY") |
-        tag_s!("UNSEEN")
-    ) >>
-    tag_s!(" ") >>
-    val: number >>
-    (match key {
-        b"MESSAGES" => StatusAttribute::Messages(val),
-        b"RECENT" => StatusAttribute::Recent(val),
-        b"UIDNEXT" => StatusAttribute::UidNext(val),
-        b"UIDVALIDITY" => StatusAttribute::UidValidity(val),
-        b"UNSEEN" => StatusAttribute::Unseen(val),
-        _ => panic!("invalid status key {}", str::from_utf8(key).unwrap()),
-    })
+named!(status_att<StatusAttribute>, alt!(
+    do_parse!(
+        tag_s!("HIGHESTMODSEQ ") >>
+        val: number_64 >>
+        (StatusAttribute::HighestModSeq(val))
+    ) |
+    do_parse!(
+        key: alt!(
+            tag_s!("MESSAGES") |
+            tag_s!("RECENT") |
+            tag_s!("UIDNEXT") |
+            tag_s!("UIDVALIDITY") |
+            tag_s!("UNSEEN")
+        ) >>
+        tag_s!(" ") >>
+        val: number >>
+        (match key {
+            b"MESSAGES" => StatusAttribute::Messages(val),
+            b"RECENT" => StatusAttribute::Recent(val),
+            b"UIDNEXT" => StatusAttribute::UidNext(val),
+            b"UIDVALIDITY" => StatusAttribute::UidValidity(val),
+            b"UNSEEN" => StatusAttribute::Unseen(val),
+            _ => panic!("invalid status key {}", str::from_utf8(key).unwrap()),
+        })
+    )
 ));
 
 named!(status_att_list<Vec<StatusAttribute>>, do_parse!(
@@ -479,11 +639,16 @@ named!(msg_att_body_section<AttributeValue>, do_parse!(
     (AttributeValue::BodySection { section, index, data })
 ));
 
-named!(msg_att_envelope<AttributeValue>, do_parse!(
-    tag_s!("ENVELOPE (") >>
-    date: nstring >>
+named!(nstr<Option<&str>>, map!(
+    nstring,
+    |s: Option<&[u8]>| s.map(|s| str::from_utf8(s).unwrap())
+));
+
+named!(envelope<Envelope>, do_parse!(
+    tag_s!("(") >>
+    date: nstr >>
     tag_s!(" ") >>
-    subject: nstring >>
+    subject: nstr >>
     tag_s!(" ") >>
     from: opt_addresses >>
     tag_s!(" ") >>
@@ -497,25 +662,273 @@ named!(msg_att_envelope<AttributeValue>, do_parse!(
     tag_s!(" ") >>
     bcc: opt_addresses >>
     tag_s!(" ") >>
-    in_reply_to: nstring >>
+    in_reply_to: nstr >>
     tag_s!(" ") >>
-    message_id: nstring >>
-    tag_s!(")") >> ({
-        AttributeValue::Envelope(Box::new(Envelope {
-            date: date.map(|s| str::from_utf8(s).unwrap()),
-            subject: subject.map(|s| str::from_utf8(s).unwrap()),
-            from,
-            sender,
-            reply_to,
-            to,
-            cc,
-            bcc,
-            in_reply_to: in_reply_to.map(|s| str::from_utf8(s).unwrap()),
-            message_id: message_id.map(|s| str::from_utf8(s).unwrap()),
-        }))
+    message_id: nstr >>
+    tag_s!(")") >>
+    (Envelope {
+        date,
+        subject,
+        from,
+        sender,
+        reply_to,
+        to,
+        cc,
+        bcc,
+        in_reply_to,
+        message_id,
     })
 ));
 
+named!(msg_att_envelope<AttributeValue>, do_parse!(
+    tag_s!("ENVELOPE ") >>
+    envelope: envelope >>
+    (AttributeValue::Envelope(Box::new(envelope)))
+));
+
+named!(str_utf8<&str>, map_res!(string, str::from_utf8));
+
+named!(body_param<(&str, &str)>, do_parse!(
+    key: str_utf8 >>
+    tag_s!(" ") >>
+    val: str_utf8 >>
+    ((key, val))
+));
+
+named!(body_fld_param<BodyParams>, alt!(
+    map!(tag_s!("NIL"), |_| None) |
+    do_parse!(
+        tag_s!("(") >>
+        first: body_param >>
+        rest: many0!(preceded!(tag_s!(" "), body_param)) >>
+        tag_s!(")") >> ({
+            let mut params = vec![first];
+            params.extend(rest);
+            Some(params)
+        })
+    )
+));
+
+named!(body_fld_dsp<Option<ContentDisposition>>, alt!(
+    map!(tag_s!("NIL"), |_| None) |
+    do_parse!(
+        tag_s!("(") >>
+        ty: str_utf8 >>
+        tag_s!(" ") >>
+        params: body_fld_param >>
+        tag_s!(")") >>
+        (Some(ContentDisposition { ty, params }))
+    )
+));
+
+named!(body_fld_lang<Option<Vec<&str>>>, alt!(
+    map!(nstr, |lang: Option<&str>| lang.map(|lang| vec![lang])) |
+    do_parse!(
+        tag_s!("(") >>
+        first: str_utf8 >>
+        rest: many0!(preceded!(tag_s!(" "), str_utf8)) >>
+        tag_s!(")") >> ({
+            let mut langs = vec![first];
+            langs.extend(rest);
+            Some(langs)
+        })
+    )
+));
+
+// Extension data defined by future RFCs, which is skipped.
+named!(body_extension<()>, alt!(
+    map!(nstring, |_| ()) |
+    map!(number, |_| ()) |
+    do_parse!(
+        tag_s!("(") >>
+        body_extension >>
+        many0!(preceded!(tag_s!(" "), body_extension)) >>
+        tag_s!(")") >>
+        (())
+    )
+));
+
+// The data following `body-fld-md5` or `body-fld-param`, respectively, for
+// single part and multipart bodies. Each item is only sent if all previous
+// ones are, so parsing them one after the other is unambiguous.
+named!(body_ext_tail<(Option<ContentDisposition>, Option<Vec<&str>>, Option<&str>)>, do_parse!(
+    disposition: opt!(preceded!(tag_s!(" "), body_fld_dsp)) >>
+    language: opt!(preceded!(tag_s!(" "), body_fld_lang)) >>
+    location: opt!(preceded!(tag_s!(" "), nstr)) >>
+    many0!(preceded!(tag_s!(" "), body_extension)) >>
+    ((disposition.and_then(|d| d), language.and_then(|l| l), location.and_then(|l| l)))
+));
+
+named!(body_ext_1part<BodyExtension>, do_parse!(
+    md5: nstr >>
+    tail: body_ext_tail >>
+    (BodyExtension {
+        md5,
+        disposition: tail.0,
+        language: tail.1,
+        location: tail.2,
+    })
+));
+
+named!(body_fields<(BodyParams, BodyFields)>, do_parse!(
+    params: body_fld_param >>
+    tag_s!(" ") >>
+    id: nstr >>
+    tag_s!(" ") >>
+    description: nstr >>
+    tag_s!(" ") >>
+    transfer_encoding: str_utf8 >>
+    tag_s!(" ") >>
+    octets: number >>
+    ((params, BodyFields { id, description, transfer_encoding, octets }))
+));
+
+named!(media_message<(&str, &str)>, do_parse!(
+    ty: map_res!(tag_no_case!("\"MESSAGE\""), str::from_utf8) >>
+    tag_s!(" ") >>
+    subtype: map_res!(tag_no_case!("\"RFC822\""), str::from_utf8) >>
+    ((&ty[1..ty.len() - 1], &subtype[1..subtype.len() - 1]))
+));
+
+named!(media_text<(&str, &str)>, do_parse!(
+    ty: map_res!(tag_no_case!("\"TEXT\""), str::from_utf8) >>
+    tag_s!(" ") >>
+    subtype: str_utf8 >>
+    ((&ty[1..ty.len() - 1], subtype))
+));
+
+named!(media_basic<(&str, &str)>, do_parse!(
+    ty: str_utf8 >>
+    tag_s!(" ") >>
+    subtype: str_utf8 >>
+    ((ty, subtype))
+));
+
+named!(body_type_msg<BodyStructure>, do_parse!(
+    media: media_message >>
+    tag_s!(" ") >>
+    fields: body_fields >>
+    tag_s!(" ") >>
+    envelope: envelope >>
+    tag_s!(" ") >>
+    body: body >>
+    tag_s!(" ") >>
+    lines: number >>
+    extension: opt!(preceded!(tag_s!(" "), body_ext_1part)) >>
+    (BodyStructure::Message {
+        content_type: ContentType { ty: media.0, subtype: media.1, params: fields.0 },
+        fields: fields.1,
+        envelope: Box::new(envelope),
+        body: Box::new(body),
+        lines,
+        extension,
+    })
+));
+
+named!(body_type_text<BodyStructure>, do_parse!(
+    media: media_text >>
+    tag_s!(" ") >>
+    fields: body_fields >>
+    tag_s!(" ") >>
+    lines: number >>
+    extension: opt!(preceded!(tag_s!(" "), body_ext_1part)) >>
+    (BodyStructure::Text {
+        content_type: ContentType { ty: media.0, subtype: media.1, params: fields.0 },
+        fields: fields.1,
+        lines,
+        extension,
+    })
+));
+
+named!(body_type_basic<BodyStructure>, do_parse!(
+    media: media_basic >>
+    tag_s!(" ") >>
+    fields: body_fields >>
+    extension: opt!(preceded!(tag_s!(" "), body_ext_1part)) >>
+    (BodyStructure::Basic {
+        content_type: ContentType { ty: media.0, subtype: media.1, params: fields.0 },
+        fields: fields.1,
+        extension,
+    })
+));
+
+named!(body_type_mpart<BodyStructure>, do_parse!(
+    parts: many1!(body) >>
+    tag_s!(" ") >>
+    subtype: str_utf8 >>
+    extension: opt!(do_parse!(
+        tag_s!(" ") >>
+        params: body_fld_param >>
+        tail: body_ext_tail >>
+        ((params, tail))
+    )) >> ({
+        let (params, extension) = match extension {
+            Some((params, tail)) => (params, Some(BodyExtension {
+                md5: None,
+                disposition: tail.0,
+                language: tail.1,
+                location: tail.2,
+            })),
+            None => (None, None),
+        };
+        BodyStructure::Multipart {
+            content_type: ContentType { ty: "multipart", subtype, params },
+            parts,
+            extension,
+        }
+    })
+));
+
+named!(body<BodyStructure>, do_parse!(
+    tag_s!("(") >>
+    body: alt!(body_type_mpart | body_type_msg | body_type_text | body_type_basic) >>
+    tag_s!(")") >>
+    (body)
+));
+
+named!(msg_att_body_structure<AttributeValue>, do_parse!(
+    alt!(tag_s!("BODYSTRUCTURE ") | tag_s!("BODY ")) >>
+    body: body >>
+    (AttributeValue::BodyStructure(body))
+));
+
+// Labels are quoted when they contain spaces, and system labels look like
+// flags.
+named!(gmail_label<&str>, alt!(
+    flag_extension |
+    map_res!(astring, str::from_utf8)
+));
+
+named!(msg_att_gmail_labels<AttributeValue>, do_parse!(
+    tag_s!("X-GM-LABELS (") >>
+    labels: opt!(do_parse!(
+        label0: gmail_label >>
+        labels: many0!(do_parse!(
+            tag_s!(" ") >>
+            label: gmail_label >>
+            (label)
+        )) >> ({
+            let mut res = vec![label0];
+            res.extend(labels);
+            res
+        })
+    )) >>
+    tag_s!(")") >>
+    (AttributeValue::GmailLabels(labels.unwrap_or_default()))
+));
+
+named!(msg_att_gmail_msg_id<AttributeValue>, do_parse!(
+    tag_s!("X-GM-MSGID ") >>
+    id: number_64 >>
+    (AttributeValue::GmailMsgId(id))
+));
+
+named!(msg_att_gmail_thr_id<AttributeValue>, do_parse!(
+    tag_s!("X-GM-THRID ") >>
+    id: number_64 >>
+    (AttributeValue::GmailThrId(id))
+));
+
 named!(msg_att_internal_date<AttributeValue>, do_parse!(
     tag_s!("INTERNALDATE ") >>
     date: nstring >>
@@ -561,9 +974,13 @@ named!(msg_att_uid<AttributeValue>, do_parse!(
 
 named!(msg_att<AttributeValue>, alt!(
     msg_att_body_section |
+    msg_att_body_structure |
     msg_att_envelope |
     msg_att_internal_date |
     msg_att_flags |
+    msg_att_gmail_labels |
+    msg_att_gmail_msg_id |
+    msg_att_gmail_thr_id |
     msg_att_mod_seq |
     msg_att_rfc822 |
     msg_att_rfc822_header |
@@ -626,9 +1043,11 @@ named!(resp_text<(Option<ResponseCode>, Option<&str>)>, do_parse!(
     })
 ));
 
+// Some servers send a bare `+` without the space required by the spec.
 named!(continue_req<Response>, do_parse!(
-    tag_s!("+ ") >>
-    text: resp_text >> // TODO: base64
+    tag_s!("+") >>
+    opt!(tag_s!(" ")) >>
+    text: resp_text >>
     tag_s!("\r\n") >>
     (Response::Continue {
         code: text.0,
@@ -662,6 +1081,213 @@ named!(resp_cond<Response>, do_parse!(
     })
 ));
 
+// Verbs of unrecognized responses must start with a letter, so that
+// malformed numeric responses (like `* 42 EXISTZ`) still fail to parse.
+fn unknown_verb(i: &[u8]) -> IResult<&[u8], &str> {
+    match i.first() {
+        Some(c) if c.is_ascii_alphabetic() => {},
+        Some(_) => return IResult::Error(error_position!(nom::ErrorKind::Alpha, i)),
+        None => return IResult::Incomplete(nom::Needed::Size(1)),
+    }
+    map_res!(i, take_while1_s!(atom_char), str::from_utf8)
+}
+
+named!(response_unknown<Response>, do_parse!(
+    verb: unknown_verb >>
+    raw: opt!(do_parse!(
+        tag_s!(" ") >>
+        raw: take_till_s!(crlf) >>
+        (raw)
+    )) >>
+    (Response::Unknown {
+        verb,
+        raw: raw.unwrap_or(b""),
+    })
+));
+
+// Namespace response extensions (RFC 2342, section 5) are skipped.
+named!(namespace<Namespace>, do_parse!(
+    tag_s!("(") >>
+    prefix: map_res!(string, str::from_utf8) >>
+    tag_s!(" ") >>
+    delimiter: delimiter >>
+    many0!(do_parse!(
+        tag_s!(" ") >>
+        string >>
+        tag_s!(" (") >>
+        string >>
+        many0!(preceded!(tag_s!(" "), string)) >>
+        tag_s!(")") >>
+        (())
+    )) >>
+    tag_s!(")") >>
+    (Namespace { prefix, delimiter })
+));
+
+named!(namespaces<Option<Vec<Namespace>>>, alt!(
+    map!(tag_s!("NIL"), |_| None) |
+    do_parse!(
+        tag_s!("(") >>
+        list: many1!(namespace) >>
+        tag_s!(")") >>
+        (Some(list))
+    )
+));
+
+named!(namespace_data<Response>, do_parse!(
+    tag_s!("NAMESPACE ") >>
+    personal: namespaces >>
+    tag_s!(" ") >>
+    other_users: namespaces >>
+    tag_s!(" ") >>
+    shared: namespaces >>
+    (Response::Namespace {
+        personal,
+        other_users,
+        shared,
+    })
+));
+
+named!(id_param<(&str, Option<&str>)>, do_parse!(
+    key: map_res!(string, str::from_utf8) >>
+    tag_s!(" ") >>
+    value: nstr >>
+    (key, value)
+));
+
+named!(id_data<Response>, do_parse!(
+    tag_s!("ID ") >>
+    params: alt!(
+        map!(tag_s!("NIL"), |_| None) |
+        do_parse!(
+            tag_s!("(") >>
+            first: id_param >>
+            rest: many0!(preceded!(tag_s!(" "), id_param)) >>
+            tag_s!(")") >> ({
+                let mut params = vec![first];
+                params.extend(rest);
+                Some(params)
+            })
+        )
+    ) >>
+    (Response::Id(params))
+));
+
+named!(myrights_data<Response>, do_parse!(
+    tag_s!("MYRIGHTS ") >>
+    mailbox: mailbox >>
+    tag_s!(" ") >>
+    rights: map_res!(astring, str::from_utf8) >>
+    (Response::MyRights { mailbox, rights })
+));
+
+named!(quota_resource<QuotaResource>, do_parse!(
+    name: atom >>
+    tag_s!(" ") >>
+    usage: number_64 >>
+    tag_s!(" ") >>
+    limit: number_64 >>
+    (QuotaResource { name, usage, limit })
+));
+
+named!(quota_response<Response>, do_parse!(
+    tag_s!("QUOTA ") >>
+    root: map_res!(astring, str::from_utf8) >>
+    tag_s!(" (") >>
+    resources: opt!(do_parse!(
+        first: quota_resource >>
+        rest: many0!(preceded!(tag_s!(" "), quota_resource)) >> ({
+            let mut resources = vec![first];
+            resources.extend(rest);
+            resources
+        })
+    )) >>
+    tag_s!(")") >>
+    (Response::Quota {
+        root,
+        resources: resources.unwrap_or_default(),
+    })
+));
+
+named!(quotaroot_response<Response>, do_parse!(
+    tag_s!("QUOTAROOT ") >>
+    mailbox: mailbox >>
+    roots: many0!(preceded!(tag_s!(" "), map_res!(astring, str::from_utf8))) >>
+    (Response::QuotaRoot(QuotaRoot { mailbox, roots }))
+));
+
+// RFC 5464, section 5. Values may also be sent as binary literals
+// (RFC 3516).
+named!(metadata_value<Option<&[u8]>>, alt!(
+    nstring |
+    map!(preceded!(tag_s!("~"), literal), Some)
+));
+
+named!(metadata_entry_value<(&str, Option<&[u8]>)>, do_parse!(
+    entry: map_res!(astring, str::from_utf8) >>
+    tag_s!(" ") >>
+    value: metadata_value >>
+    (entry, value)
+));
+
+named!(metadata_response<Response>, do_parse!(
+    tag_s!("METADATA ") >>
+    mailbox: mailbox >>
+    tag_s!(" ") >>
+    response: alt!(
+        do_parse!(
+            tag_s!("(") >>
+            first: metadata_entry_value >>
+            rest: many0!(preceded!(tag_s!(" "), metadata_entry_value)) >>
+            tag_s!(")") >> ({
+                let mut values = vec![first];
+                values.extend(rest);
+                Response::Metadata { mailbox, values }
+            })
+        ) |
+        do_parse!(
+            first: map_res!(astring, str::from_utf8) >>
+            rest: many0!(preceded!(tag_s!(" "), map_res!(astring, str::from_utf8))) >> ({
+                let mut entries = vec![first];
+                entries.extend(rest);
+                Response::MetadataChanged { mailbox, entries }
+            })
+        )
+    ) >>
+    (response)
+));
+
+named!(acl_entry<AclEntry>, do_parse!(
+    tag_s!(" ") >>
+    identifier: map_res!(astring, str::from_utf8) >>
+    tag_s!(" ") >>
+    rights: map_res!(astring, str::from_utf8) >>
+    (AclEntry { identifier, rights })
+));
+
+named!(acl_data<Response>, do_parse!(
+    tag_s!("ACL ") >>
+    mailbox: mailbox >>
+    entries: many0!(acl_entry) >>
+    (Response::Acl { mailbox, entries })
+));
+
+named!(listrights_data<Response>, do_parse!(
+    tag_s!("LISTRIGHTS ") >>
+    mailbox: mailbox >>
+    tag_s!(" ") >>
+    identifier: map_res!(astring, str::from_utf8) >>
+    tag_s!(" ") >>
+    required: map_res!(astring, str::from_utf8) >>
+    optional: many0!(preceded!(tag_s!(" "), map_res!(astring, str::from_utf8))) >>
+    (Response::ListRights {
+        mailbox,
+        identifier,
+        required,
+        optional,
+    })
+));
+
 named!(response_data<Response>, do_parse!(
     tag_s!("* ") >>
     contents: alt!(
@@ -669,7 +1295,19 @@ named!(response_data<Response>, do_parse!(
         mailbox_data |
         message_data_expunge |
         message_data_fetch |
-        capability_data
+        capability_data |
+        enabled_data |
+        esearch |
+        namespace_data |
+        myrights_data |
+        listrights_data |
+        acl_data |
+        quotaroot_response |
+        quota_response |
+        metadata_response |
+        id_data |
+        vanished |
+        response_unknown
     ) >>
     tag_s!("\r\n") >>
     (contents)
@@ -713,6 +1351,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_capability_code() {
+        match parse_response(b"* OK [CAPABILITY IMAP4rev1 IDLE LITERAL+] ready\r\n").unwrap() {
+            (_, Response::Data {
+                status: Status::Ok,
+                code: Some(ResponseCode::Capabilities(caps)),
+                information: Some("ready"),
+            }) => assert_eq!(caps, ["IMAP4rev1", "IDLE", "LITERAL+"]),
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_uidplus_codes() {
+        match parse_response(b"A003 OK [APPENDUID 38505 3955] APPEND completed\r\n") {
+            IResult::Done(_, Response::Done {
+                code: Some(ResponseCode::AppendUid(38505, uids)),
+                ..
+            }) => assert_eq!(uids, [3955..=3955]),
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"A004 OK [COPYUID 38505 304,319:320 3956:3958] Done\r\n") {
+            IResult::Done(_, Response::Done {
+                code: Some(ResponseCode::CopyUid(38505, source, destination)),
+                ..
+            }) => {
+                assert_eq!(source, [304..=304, 319..=320]);
+                assert_eq!(destination, [3956..=3958]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_condition_codes() {
+        match parse_response(b"a OK [CONTACTADMIN] Your account needs attention\r\n") {
+            IResult::Done(_, Response::Done {
+                status: Status::Ok,
+                code: Some(ResponseCode::ContactAdmin),
+                information: Some("Your account needs attention"),
+                ..
+            }) => {},
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"b NO [INUSE] Mailbox in use\r\n") {
+            IResult::Done(_, Response::Done {
+                status: Status::No,
+                code: Some(ResponseCode::InUse),
+                ..
+            }) => {},
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
     #[test]
     fn test_body_text() {
         match parse_response(b"* 2 FETCH (BODY[TEXT] {3}\r\nfoo)\r\n") {
@@ -745,6 +1437,205 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_body_structure() {
+        let rsp = b"* 12 FETCH (BODYSTRUCTURE ((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"US-ASCII\") \
+            NIL NIL \"7BIT\" 1152 23)(\"APPLICATION\" \"PDF\" (\"NAME\" \"a.pdf\") \
+            \"<960723163407.20117h@cac.washington.edu>\" \"Compiled\" \"BASE64\" 4554 NIL \
+            (\"attachment\" (\"FILENAME\" \"a.pdf\")) NIL) \"MIXED\" (\"BOUNDARY\" \"x\") \
+            NIL NIL))\r\n";
+        let attrs = match parse_response(rsp) {
+            IResult::Done(_, Response::Fetch(12, attrs)) => attrs,
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        };
+        let body = match attrs[0] {
+            AttributeValue::BodyStructure(ref body) => body,
+            ref attr @ _ => panic!("unexpected attribute {:?}", attr),
+        };
+        assert_eq!(body.content_type().subtype, "MIXED");
+        assert_eq!(body.content_type().params, Some(vec![("BOUNDARY", "x")]));
+        let parts = body.parts();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0, [1]);
+        match *parts[0].1 {
+            BodyStructure::Text { ref fields, lines, ref extension, .. } => {
+                assert_eq!(fields.transfer_encoding, "7BIT");
+                assert_eq!(fields.octets, 1152);
+                assert_eq!(lines, 23);
+                assert_eq!(*extension, None);
+            },
+            ref part @ _ => panic!("unexpected part {:?}", part),
+        }
+        assert_eq!(parts[1].0, [2]);
+        let disposition = parts[1].1.extension().unwrap().disposition.as_ref().unwrap();
+        assert_eq!(disposition.ty, "attachment");
+        assert_eq!(disposition.params, Some(vec![("FILENAME", "a.pdf")]));
+    }
+
+    #[test]
+    fn test_body_message() {
+        let rsp = b"* 1 FETCH (BODY (\"MESSAGE\" \"RFC822\" NIL NIL NIL \"7BIT\" 342 \
+            (NIL \"Hi\" NIL NIL NIL NIL NIL NIL NIL NIL) \
+            (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 12 1) 10))\r\n";
+        match parse_response(rsp) {
+            IResult::Done(_, Response::Fetch(1, attrs)) => {
+                let parts = match attrs[0] {
+                    AttributeValue::BodyStructure(ref body) => body.parts(),
+                    ref attr @ _ => panic!("unexpected attribute {:?}", attr),
+                };
+                assert_eq!(parts.len(), 2);
+                assert_eq!(parts[1].0, [1, 1]);
+                assert_eq!(parts[1].1.content_type().ty, "TEXT");
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_namespace() {
+        let rsp = b"* NAMESPACE ((\"\" \"/\")) NIL \
+            ((\"Public Folders/\" \"/\" \"X-PARAM\" (\"FLAG1\" \"FLAG2\")))\r\n";
+        match parse_response(rsp) {
+            IResult::Done(_, Response::Namespace { personal, other_users, shared }) => {
+                assert_eq!(personal, Some(vec![Namespace { prefix: "", delimiter: Some("/") }]));
+                assert_eq!(other_users, None);
+                assert_eq!(shared, Some(vec![Namespace {
+                    prefix: "Public Folders/",
+                    delimiter: Some("/"),
+                }]));
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_id() {
+        match parse_response(b"* ID (\"name\" \"Cyrus\" \"version\" \"1.5\" \"os\" NIL)\r\n") {
+            IResult::Done(_, Response::Id(Some(params))) => {
+                assert_eq!(params, [
+                    ("name", Some("Cyrus")),
+                    ("version", Some("1.5")),
+                    ("os", None),
+                ]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* ID NIL\r\n") {
+            IResult::Done(_, Response::Id(None)) => {},
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_acl() {
+        match parse_response(b"* ACL INBOX Fred rwipslxetad \"-anyone\" w\r\n") {
+            IResult::Done(_, Response::Acl { mailbox, entries }) => {
+                assert_eq!(mailbox, "INBOX");
+                assert_eq!(entries, [
+                    AclEntry { identifier: "Fred", rights: "rwipslxetad" },
+                    AclEntry { identifier: "-anyone", rights: "w" },
+                ]);
+                let rights = Rights::from(entries[0].rights);
+                assert!(rights.contains(Rights::READ | Rights::ADMINISTER));
+                assert!(!rights.contains(Rights::CREATE_MAILBOX));
+                assert_eq!(rights.to_string(), "adeilprstwx");
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* LISTRIGHTS ~/Mail/saved smith la r swicdkxte\r\n") {
+            IResult::Done(_, Response::ListRights { mailbox, identifier, required, optional }) => {
+                assert_eq!(mailbox, "~/Mail/saved");
+                assert_eq!(identifier, "smith");
+                assert_eq!(required, "la");
+                assert_eq!(optional, ["r", "swicdkxte"]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_myrights() {
+        match parse_response(b"* MYRIGHTS \"Other Users/fred\" lrswi\r\n") {
+            IResult::Done(_, Response::MyRights { mailbox, rights }) => {
+                assert_eq!(mailbox, "Other Users/fred");
+                assert_eq!(rights, "lrswi");
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_quota() {
+        match parse_response(b"* QUOTA \"\" (STORAGE 10 512 MESSAGE 3 100)\r\n") {
+            IResult::Done(_, Response::Quota { root, resources }) => {
+                assert_eq!(root, "");
+                assert_eq!(resources, [
+                    QuotaResource { name: "STORAGE", usage: 10, limit: 512 },
+                    QuotaResource { name: "MESSAGE", usage: 3, limit: 100 },
+                ]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* QUOTA user.fred ()\r\n") {
+            IResult::Done(_, Response::Quota { root, resources }) => {
+                assert_eq!(root, "user.fred");
+                assert!(resources.is_empty());
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* QUOTAROOT INBOX \"\"\r\n") {
+            IResult::Done(_, Response::QuotaRoot(root)) => {
+                assert_eq!(root, QuotaRoot { mailbox: "INBOX", roots: vec![""] });
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* QUOTAROOT comp.mail.mime\r\n") {
+            IResult::Done(_, Response::QuotaRoot(root)) => {
+                assert_eq!(root.mailbox, "comp.mail.mime");
+                assert!(root.roots.is_empty());
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_metadata() {
+        let rsp = b"* METADATA \"INBOX\" (/private/comment {9}\r\nline\r\none /shared/x NIL)\r\n";
+        match parse_response(rsp) {
+            IResult::Done(_, Response::Metadata { mailbox, values }) => {
+                assert_eq!(mailbox, "INBOX");
+                assert_eq!(values, [
+                    ("/private/comment", Some(&b"line\r\none"[..])),
+                    ("/shared/x", None),
+                ]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* METADATA \"\" (/shared/admin \"mailto:a@b\" /x ~{1}\r\n\0)\r\n") {
+            IResult::Done(_, Response::Metadata { mailbox, values }) => {
+                assert_eq!(mailbox, "");
+                assert_eq!(values, [
+                    ("/shared/admin", Some(&b"mailto:a@b"[..])),
+                    ("/x", Some(&b"\0"[..])),
+                ]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* METADATA INBOX /shared/comment /private/comment\r\n") {
+            IResult::Done(_, Response::MetadataChanged { mailbox, entries }) => {
+                assert_eq!(mailbox, "INBOX");
+                assert_eq!(entries, ["/shared/comment", "/private/comment"]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"A1 OK [METADATA LONGENTRIES 2199] done\r\n") {
+            IResult::Done(_, Response::Done { code, .. }) => {
+                assert_eq!(code, Some(ResponseCode::Metadata(MetadataCode::LongEntries(2199))));
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
     #[test]
     fn test_status() {
         match parse_response(b"* STATUS blurdybloop (MESSAGES 231 UIDNEXT 44292)\r\n") {
@@ -757,6 +1648,15 @@ mod tests {
             },
             rsp @ _ => panic!("unexpected response {:?}", rsp),
         }
+        match parse_response(b"* STATUS INBOX (HIGHESTMODSEQ 7011231777 UNSEEN 2)\r\n") {
+            IResult::Done(_, Response::MailboxData(MailboxDatum::Status { status, .. })) => {
+                assert_eq!(status, [
+                    StatusAttribute::HighestModSeq(7011231777),
+                    StatusAttribute::Unseen(2),
+                ]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
     }
 
     #[test]
@@ -773,6 +1673,96 @@ mod tests {
             IResult::Done(_, Response::Continue { code: None, information: Some("idling") }) => {},
             rsp @ _ => panic!("unexpected response {:?}", rsp),
         }
+        match parse_response(b"* OK [NOTIFICATIONOVERFLOW] Notifications disabled\r\n") {
+            IResult::Done(_, Response::Data {
+                code: Some(ResponseCode::NotificationOverflow),
+                ..
+            }) => {},
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_unknown() {
+        match parse_response(b"* X-VENDOR-THING 1 (foo bar)\r\n") {
+            IResult::Done(_, Response::Unknown { verb, raw }) => {
+                assert_eq!(verb, "X-VENDOR-THING");
+                assert_eq!(raw, b"1 (foo bar)");
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* XSTATE\r\n") {
+            IResult::Done(_, Response::Unknown { verb: "XSTATE", raw: b"" }) => {},
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* X-VENDOR-THING 1 (foo") {
+            IResult::Incomplete(_) => {},
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_vanished() {
+        match parse_response(b"* VANISHED (EARLIER) 41,43:116,118,120:211,214:540\r\n") {
+            IResult::Done(_, Response::Vanished { earlier, uids }) => {
+                assert!(earlier);
+                assert_eq!(uids, [41..=41, 43..=116, 118..=118, 120..=211, 214..=540]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* VANISHED 405,407\r\n") {
+            IResult::Done(_, Response::Vanished { earlier, uids }) => {
+                assert!(!earlier);
+                assert_eq!(uids, [405..=405, 407..=407]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_enabled() {
+        match parse_response(b"* ENABLED CONDSTORE QRESYNC\r\n") {
+            IResult::Done(_, Response::Enabled(caps)) => {
+                assert_eq!(caps, ["CONDSTORE", "QRESYNC"]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_continue() {
+        match parse_response(b"+ YGgGCSqGSIb3EgECAgIAb1kwV6ADAgEFoQMCAQ+i\r\n") {
+            IResult::Done(_, Response::Continue { code: None, information }) => {
+                assert_eq!(information, Some("YGgGCSqGSIb3EgECAgIAb1kwV6ADAgEFoQMCAQ+i"));
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"+\r\n") {
+            IResult::Done(_, Response::Continue { code: None, information: None }) => {},
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
+    #[test]
+    fn test_esearch() {
+        match parse_response(b"* ESEARCH (TAG \"A282\") UID MIN 2 COUNT 3 ALL 2,10:11\r\n") {
+            IResult::Done(_, Response::ESearch { tag, uid, data }) => {
+                assert_eq!(tag, Some("A282"));
+                assert!(uid);
+                assert_eq!(data, [
+                    SearchReturn::Min(2),
+                    SearchReturn::Count(3),
+                    SearchReturn::All(vec![2..=2, 10..=11]),
+                ]);
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
+        match parse_response(b"* ESEARCH (TAG \"A283\")\r\n") {
+            IResult::Done(_, Response::ESearch { tag: Some("A283"), uid: false, data }) => {
+                assert!(data.is_empty());
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
     }
 
     #[test]
@@ -800,6 +1790,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_gmail_fetch() {
+        let rsp = b"* 1 FETCH (X-GM-THRID 1278455344230334865 X-GM-MSGID 1278455344230334865 \
+                    X-GM-LABELS (\\Inbox \\Sent Important \"Muy Importante\") UID 4)\r\n";
+        match parse_response(rsp) {
+            IResult::Done(_, Response::Fetch(1, attrs)) => {
+                assert_eq!(
+                    attrs,
+                    vec![
+                        AttributeValue::GmailThrId(1278455344230334865),
+                        AttributeValue::GmailMsgId(1278455344230334865),
+                        AttributeValue::GmailLabels(vec![
+                            "\\Inbox",
+                            "\\Sent",
+                            "Important",
+                            "Muy Importante",
+                        ]),
+                        AttributeValue::Uid(4),
+                    ]
+                );
+            },
+            rsp => panic!("unexpected response {:?}", rsp),
+        }
+
+        match parse_response(b"* 2 FETCH (X-GM-LABELS ())\r\n") {
+            IResult::Done(_, Response::Fetch(2, attrs)) => {
+                assert_eq!(attrs, vec![AttributeValue::GmailLabels(vec![])]);
+            },
+            rsp => panic!("unexpected response {:?}", rsp),
+        }
+    }
+
     #[test]
     fn test_list() {
         match ::parser::mailbox(b"iNboX") {
@@ -813,5 +1835,18 @@ mod tests {
             IResult::Done(_, Response::MailboxData(_)) => {},
             rsp @ _ => panic!("unexpected response {:?}", rsp),
         }
+
+        match parse_response(b"* LIST (\\HasNoChildren \\Sent) NIL Sent\r\n") {
+            IResult::Done(_, Response::MailboxData(MailboxDatum::List {
+                flags,
+                delimiter,
+                name,
+            })) => {
+                assert_eq!(flags, ["\\HasNoChildren", "\\Sent"]);
+                assert_eq!(delimiter, None);
+                assert_eq!(name, "Sent");
+            },
+            rsp @ _ => panic!("unexpected response {:?}", rsp),
+        }
     }
 }
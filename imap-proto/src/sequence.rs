@@ -0,0 +1,480 @@
+//! Sets of message sequence numbers or UIDs, as used in commands like
+//! `FETCH`, `STORE` and `COPY` (RFC 3501, section 9, `sequence-set`).
+//!
+//! Numbers and ranges can be added in any order; the set keeps them sorted
+//! and coalesced, so that it is sent in its most compact form, like
+//! `1:5,9,20:*`.
+//!
+//! `SequenceSet::saved()` stands for `$`, the result of an earlier search
+//! saved on the server (RFC 5182).
+
+use std::cmp;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{RangeFrom, RangeInclusive};
+use std::slice;
+use std::str::FromStr;
+use std::vec;
+
+// `*`, the largest number in use in the mailbox. It is kept as the largest
+// possible number, so that `20:*` includes all numbers from 20.
+const STAR: u32 = u32::max_value();
+
+/// A set of message sequence numbers or UIDs.
+///
+/// Sets are built from numbers (`SequenceSet::from(5)`), ranges
+/// (`SequenceSet::from(1..=5)`), open ranges up to `*` (`SequenceSet::from(
+/// 20..)`) or iterators of these, or parsed from their IMAP syntax with
+/// `"1:5,9".parse()`. The `Display` implementation formats the set in IMAP
+/// syntax.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SequenceSet {
+    // Sorted, disjoint and non-adjacent.
+    ranges: Vec<(u32, u32)>,
+    // `$`, in which case `ranges` is empty.
+    saved: bool,
+}
+
+impl SequenceSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `1:*`, all messages in the mailbox.
+    pub fn all() -> Self {
+        SequenceSet::from(1..)
+    }
+
+    /// `$`, the messages found by the last `SEARCH RETURN (SAVE)` (RFC
+    /// 5182). Only the server knows which messages these are, so the set
+    /// contains no numbers here, and adding numbers to it replaces it.
+    pub fn saved() -> Self {
+        SequenceSet {
+            ranges: Vec::new(),
+            saved: true,
+        }
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.saved
+    }
+
+    /// Whether the set is empty, which is not valid in commands.
+    pub fn is_empty(&self) -> bool {
+        !self.saved && self.ranges.is_empty()
+    }
+
+    /// Whether `num` is in the set, taking `*` to be larger than any number.
+    pub fn contains(&self, num: u32) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(start, end)| start <= num && num <= end)
+    }
+
+    pub fn insert(&mut self, num: u32) {
+        self.insert_range(num..=num);
+    }
+
+    /// Adds the numbers in `range`, which may be given in either order.
+    pub fn insert_range(&mut self, range: RangeInclusive<u32>) {
+        let (a, b) = (*range.start(), *range.end());
+        self.ranges.push((cmp::min(a, b), cmp::max(a, b)));
+        self.coalesce();
+    }
+
+    /// Adds the numbers from `start` up to `*`.
+    pub fn insert_from(&mut self, start: u32) {
+        self.insert_range(start..=STAR);
+    }
+
+    /// Adds the numbers in `other`.
+    pub fn extend_set(&mut self, other: &SequenceSet) {
+        self.ranges.extend_from_slice(&other.ranges);
+        self.coalesce();
+    }
+
+    /// The ranges in the set, in ascending order. A range up to `*` ends at
+    /// `u32::max_value()`.
+    pub fn ranges(&self) -> Vec<RangeInclusive<u32>> {
+        self.ranges.iter().map(|&(start, end)| start..=end).collect()
+    }
+
+    /// The number of numbers in the set, with a range up to `*` ending at
+    /// `u32::max_value()`.
+    pub fn len(&self) -> usize {
+        self.ranges
+            .iter()
+            .map(|&(start, end)| (end - start) as usize + 1)
+            .sum()
+    }
+
+    /// The numbers in the set, in ascending order. A range up to `*` goes on
+    /// up to `u32::max_value()`.
+    pub fn iter(&self) -> Iter {
+        Iter {
+            ranges: self.ranges.iter(),
+            current: None,
+        }
+    }
+
+    /// The numbers in either set.
+    pub fn union(&self, other: &SequenceSet) -> SequenceSet {
+        let mut union = self.clone();
+        union.extend_set(other);
+        union
+    }
+
+    /// The numbers in both sets.
+    pub fn intersection(&self, other: &SequenceSet) -> SequenceSet {
+        let mut ranges = Vec::new();
+        let (mut a, mut b) = (self.ranges.iter().peekable(), other.ranges.iter().peekable());
+        while let (Some(&&(a_start, a_end)), Some(&&(b_start, b_end))) = (a.peek(), b.peek()) {
+            let (start, end) = (cmp::max(a_start, b_start), cmp::min(a_end, b_end));
+            if start <= end {
+                ranges.push((start, end));
+            }
+            // The range ending first cannot overlap any later ones.
+            if a_end < b_end {
+                a.next();
+            } else {
+                b.next();
+            }
+        }
+        SequenceSet {
+            ranges,
+            saved: false,
+        }
+    }
+
+    /// The numbers in this set but not in `other`.
+    pub fn difference(&self, other: &SequenceSet) -> SequenceSet {
+        let mut ranges = Vec::new();
+        let mut others = other.ranges.iter().peekable();
+        for &(start, end) in &self.ranges {
+            let mut start = Some(start);
+            while let (Some(from), Some(&&(o_start, o_end))) = (start, others.peek()) {
+                if o_end < from {
+                    others.next();
+                    continue;
+                }
+                if o_start > end {
+                    break;
+                }
+                if o_start > from {
+                    ranges.push((from, o_start - 1));
+                }
+                start = o_end.checked_add(1).filter(|&next| next <= end);
+                if o_end > end {
+                    break;
+                }
+                others.next();
+            }
+            if let Some(from) = start {
+                ranges.push((from, end));
+            }
+        }
+        SequenceSet {
+            ranges,
+            saved: false,
+        }
+    }
+
+    fn coalesce(&mut self) {
+        // `$` cannot be combined with numbers.
+        self.saved = false;
+        self.ranges.sort();
+        let mut coalesced: Vec<(u32, u32)> = Vec::with_capacity(self.ranges.len());
+        for &(start, end) in &self.ranges {
+            if let Some(last) = coalesced.last_mut() {
+                if start <= last.1.saturating_add(1) {
+                    last.1 = cmp::max(last.1, end);
+                    continue;
+                }
+            }
+            coalesced.push((start, end));
+        }
+        self.ranges = coalesced;
+    }
+}
+
+impl fmt::Display for SequenceSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.saved {
+            return write!(f, "$");
+        }
+        for (i, &(start, end)) in self.ranges.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write_number(f, start)?;
+            if end != start {
+                write!(f, ":")?;
+                write_number(f, end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_number(f: &mut fmt::Formatter, num: u32) -> fmt::Result {
+    if num == STAR {
+        write!(f, "*")
+    } else {
+        write!(f, "{}", num)
+    }
+}
+
+impl FromStr for SequenceSet {
+    type Err = &'static str;
+
+    /// Parses a set in IMAP syntax, like `1:5,9,20:*` or `$`.
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        if s == "$" {
+            return Ok(SequenceSet::saved());
+        }
+        let mut set = SequenceSet::new();
+        for range in s.split(',') {
+            let mut bounds = range.splitn(2, ':');
+            let start = parse_number(bounds.next().unwrap_or(""))?;
+            let end = match bounds.next() {
+                Some(end) => parse_number(end)?,
+                None => start,
+            };
+            set.ranges.push((cmp::min(start, end), cmp::max(start, end)));
+        }
+        set.coalesce();
+        Ok(set)
+    }
+}
+
+fn parse_number(s: &str) -> Result<u32, &'static str> {
+    if s == "*" {
+        return Ok(STAR);
+    }
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err("invalid number in sequence set");
+    }
+    match s.parse() {
+        Ok(0) => Err("zero in sequence set"),
+        Ok(num) => Ok(num),
+        Err(_) => Err("number too large in sequence set"),
+    }
+}
+
+impl From<u32> for SequenceSet {
+    fn from(num: u32) -> Self {
+        SequenceSet {
+            ranges: vec![(num, num)],
+            saved: false,
+        }
+    }
+}
+
+impl From<RangeInclusive<u32>> for SequenceSet {
+    fn from(range: RangeInclusive<u32>) -> Self {
+        let mut set = SequenceSet::new();
+        set.insert_range(range);
+        set
+    }
+}
+
+impl From<RangeFrom<u32>> for SequenceSet {
+    fn from(range: RangeFrom<u32>) -> Self {
+        let mut set = SequenceSet::new();
+        set.insert_from(range.start);
+        set
+    }
+}
+
+/// Builds a set from the ranges in a server response, such as the UIDs of
+/// an `ESEARCH` or `COPYUID` response.
+impl<'a> From<&'a [RangeInclusive<u32>]> for SequenceSet {
+    fn from(ranges: &'a [RangeInclusive<u32>]) -> Self {
+        ranges.iter().cloned().collect()
+    }
+}
+
+impl FromIterator<u32> for SequenceSet {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        iter.into_iter().map(|num| num..=num).collect()
+    }
+}
+
+impl FromIterator<RangeInclusive<u32>> for SequenceSet {
+    fn from_iter<I: IntoIterator<Item = RangeInclusive<u32>>>(iter: I) -> Self {
+        let mut set = SequenceSet {
+            ranges: iter.into_iter()
+                .map(|range| {
+                    let (a, b) = (*range.start(), *range.end());
+                    (cmp::min(a, b), cmp::max(a, b))
+                })
+                .collect(),
+            saved: false,
+        };
+        set.coalesce();
+        set
+    }
+}
+
+impl Extend<u32> for SequenceSet {
+    fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+        self.ranges
+            .extend(iter.into_iter().map(|num| (num, num)));
+        self.coalesce();
+    }
+}
+
+impl<'a> IntoIterator for &'a SequenceSet {
+    type Item = u32;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl IntoIterator for SequenceSet {
+    type Item = u32;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter {
+            ranges: self.ranges.into_iter(),
+            current: None,
+        }
+    }
+}
+
+/// The numbers in a `SequenceSet`, returned by `SequenceSet::iter()`.
+pub struct Iter<'a> {
+    ranges: slice::Iter<'a, (u32, u32)>,
+    current: Option<(u32, u32)>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let ranges = &mut self.ranges;
+        next_number(&mut self.current, || ranges.next().cloned())
+    }
+}
+
+/// The numbers in a `SequenceSet`, in ascending order.
+pub struct IntoIter {
+    ranges: vec::IntoIter<(u32, u32)>,
+    current: Option<(u32, u32)>,
+}
+
+impl Iterator for IntoIter {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        let ranges = &mut self.ranges;
+        next_number(&mut self.current, || ranges.next())
+    }
+}
+
+// Takes the next number from the range being iterated over, moving on to
+// the next range once it is used up.
+fn next_number<F>(current: &mut Option<(u32, u32)>, mut next_range: F) -> Option<u32>
+where
+    F: FnMut() -> Option<(u32, u32)>, {
+    let (start, end) = match current.take() {
+        Some(range) => range,
+        None => next_range()?,
+    };
+    if start < end {
+        *current = Some((start + 1, end));
+    }
+    Some(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce() {
+        let set: SequenceSet = vec![9, 3, 1, 2, 4, 5, 20, 3].into_iter().collect();
+        assert_eq!(set.to_string(), "1:5,9,20");
+        let mut set = SequenceSet::from(20..);
+        set.insert_range(7..=5);
+        set.insert(25);
+        set.insert(8);
+        assert_eq!(set.to_string(), "5:8,20:*");
+        assert!(set.contains(1000));
+        assert!(!set.contains(9));
+        assert_eq!(SequenceSet::all().to_string(), "1:*");
+        let ranges = [1..=3, 4..=4, 10..=12];
+        assert_eq!(SequenceSet::from(&ranges[..]).to_string(), "1:4,10:12");
+    }
+
+    #[test]
+    fn parse() {
+        let set: SequenceSet = "20:*,9,5:1,4".parse().unwrap();
+        assert_eq!(set.to_string(), "1:5,9,20:*");
+        assert_eq!(set.ranges(), vec![1..=5, 9..=9, 20..=STAR]);
+        assert_eq!("*:4".parse::<SequenceSet>().unwrap().to_string(), "4:*");
+        assert!("".parse::<SequenceSet>().is_err());
+        assert!("1,,2".parse::<SequenceSet>().is_err());
+        assert!("0:3".parse::<SequenceSet>().is_err());
+        assert!("1:2:3".parse::<SequenceSet>().is_err());
+        assert!("4294967296".parse::<SequenceSet>().is_err());
+    }
+
+    #[test]
+    fn saved() {
+        let mut set: SequenceSet = "$".parse().unwrap();
+        assert_eq!(set, SequenceSet::saved());
+        assert_eq!(set.to_string(), "$");
+        assert!(!set.is_empty());
+        set.insert(3);
+        assert!(!set.is_saved());
+        assert_eq!(set.to_string(), "3");
+    }
+
+    #[test]
+    fn iterate() {
+        let set: SequenceSet = "7,1:3".parse().unwrap();
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![1, 2, 3, 7]);
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.clone().into_iter().collect::<Vec<_>>(), vec![1, 2, 3, 7]);
+        let top = SequenceSet::from(STAR - 1..);
+        assert_eq!((&top).into_iter().collect::<Vec<_>>(), vec![STAR - 1, STAR]);
+        assert_eq!(SequenceSet::all().len(), STAR as usize);
+        assert_eq!(SequenceSet::new().iter().next(), None);
+    }
+
+    #[test]
+    fn union() {
+        let a: SequenceSet = "1:3,10".parse().unwrap();
+        let b: SequenceSet = "4,9,12:*".parse().unwrap();
+        assert_eq!(a.union(&b).to_string(), "1:4,9:10,12:*");
+        assert_eq!(a.union(&SequenceSet::new()), a);
+        assert_eq!(SequenceSet::saved().union(&a), a);
+    }
+
+    #[test]
+    fn intersection() {
+        let a: SequenceSet = "1:10,20:30,40".parse().unwrap();
+        let b: SequenceSet = "5:25,28,35:*".parse().unwrap();
+        assert_eq!(a.intersection(&b).to_string(), "5:10,20:25,28,40");
+        assert_eq!(b.intersection(&a), a.intersection(&b));
+        assert!(a.intersection(&SequenceSet::new()).is_empty());
+        let c: SequenceSet = "11:19".parse().unwrap();
+        assert!(a.intersection(&c).is_empty());
+    }
+
+    #[test]
+    fn difference() {
+        let a: SequenceSet = "1:10,20:30,40".parse().unwrap();
+        let b: SequenceSet = "3,5:7,10:22,30:*".parse().unwrap();
+        assert_eq!(a.difference(&b).to_string(), "1:2,4,8:9,23:29");
+        assert_eq!(b.difference(&a).to_string(), "11:19,31:39,41:*");
+        assert_eq!(a.difference(&SequenceSet::new()), a);
+        assert!(a.difference(&SequenceSet::all()).is_empty());
+        let top = SequenceSet::from(10..);
+        assert_eq!(top.difference(&SequenceSet::from(STAR)).to_string(), "10:4294967294");
+    }
+}
@@ -1,3 +1,6 @@
+use std::fmt;
+use std::ops::{BitOr, RangeInclusive};
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct Request(pub RequestId, pub Vec<u8>);
 
@@ -10,8 +13,22 @@ pub enum AttrMacro {
 
 impl Copy for AttrMacro {}
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StoreType {
+    Replace,
+    Add,
+    Remove,
+}
+
+impl Copy for StoreType {}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum Response<'a> {
+    // RFC 4314, section 3.6. The access control list of `mailbox`.
+    Acl {
+        mailbox: &'a str,
+        entries: Vec<AclEntry<'a>>,
+    },
     Capabilities(Vec<&'a str>),
     Continue {
         code: Option<ResponseCode<'a>>,
@@ -28,10 +45,74 @@ pub enum Response<'a> {
         code: Option<ResponseCode<'a>>,
         information: Option<&'a str>,
     },
+    Enabled(Vec<&'a str>), // RFC 5161
+    // RFC 4731, section 3.1. `tag` is the tag of the command the results
+    // are for, and `uid` is set if they are UIDs.
+    ESearch {
+        tag: Option<&'a str>,
+        uid: bool,
+        data: Vec<SearchReturn>,
+    },
     Expunge(u32),
     Fetch(u32, Vec<AttributeValue<'a>>),
+    // RFC 2971, section 3.2. The server's fields and their values, or
+    // `None` for `ID NIL`.
+    Id(Option<Vec<(&'a str, Option<&'a str>)>>),
     MailboxData(MailboxDatum<'a>),
+    // RFC 5464, section 4.4.1. The values of annotation entries of
+    // `mailbox`, which is empty for server annotations, with `None` for
+    // entries that have no value.
+    Metadata {
+        mailbox: &'a str,
+        values: Vec<(&'a str, Option<&'a [u8]>)>,
+    },
+    // RFC 5464, section 4.4.2. Annotation entries of `mailbox` that were
+    // changed.
+    MetadataChanged {
+        mailbox: &'a str,
+        entries: Vec<&'a str>,
+    },
     IDs(Vec<u32>),
+    // RFC 4314, section 3.7. The rights that are always granted to
+    // `identifier` on `mailbox`, and the groups of rights that can be
+    // granted to it in addition.
+    ListRights {
+        mailbox: &'a str,
+        identifier: &'a str,
+        required: &'a str,
+        optional: Vec<&'a str>,
+    },
+    // RFC 4314, section 3.8. The rights the user has on `mailbox`.
+    MyRights {
+        mailbox: &'a str,
+        rights: &'a str,
+    },
+    // RFC 2342, section 5. Each list is `None` if there are no namespaces
+    // of that kind.
+    Namespace {
+        personal: Option<Vec<Namespace<'a>>>,
+        other_users: Option<Vec<Namespace<'a>>>,
+        shared: Option<Vec<Namespace<'a>>>,
+    },
+    // RFC 2087, section 5.1. The usage and limits of quota root `root`.
+    Quota {
+        root: &'a str,
+        resources: Vec<QuotaResource<'a>>,
+    },
+    // RFC 2087, section 5.2.
+    QuotaRoot(QuotaRoot<'a>),
+    // RFC 7162, section 3.2.10. `earlier` is set for `VANISHED (EARLIER)`,
+    // which reports messages expunged before the command was issued.
+    Vanished {
+        earlier: bool,
+        uids: Vec<RangeInclusive<u32>>,
+    },
+    // Untagged responses not otherwise recognized (e.g. vendor X-* extensions),
+    // with `raw` holding the rest of the line following the verb.
+    Unknown {
+        verb: &'a str,
+        raw: &'a [u8],
+    },
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -45,18 +126,84 @@ pub enum Status {
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum ResponseCode<'a> {
+    AlreadyExists, // RFC 5530, section 3
+    AppendUid(u32, Vec<RangeInclusive<u32>>), // RFC 4315, section 3
+    AuthenticationFailed, // RFC 5530, section 3
+    AuthorizationFailed, // RFC 5530, section 3
+    Cannot, // RFC 5530, section 3
+    Capabilities(Vec<&'a str>),
+    ClientBug, // RFC 5530, section 3
+    ContactAdmin, // RFC 5530, section 3
+    CopyUid(u32, Vec<RangeInclusive<u32>>, Vec<RangeInclusive<u32>>), // RFC 4315, section 3
+    Corruption, // RFC 5530, section 3
+    Expired, // RFC 5530, section 3
+    ExpungeIssued, // RFC 5530, section 3
     HighestModSeq(u64), // RFC 4551, section 3.1.1
+    InUse, // RFC 5530, section 3
+    Limit, // RFC 5530, section 3
+    Metadata(MetadataCode), // RFC 5464, sections 4.2.1 and 4.3
+    NoModSeq, // RFC 4551, section 3.1.2
+    NonExistent, // RFC 5530, section 3
+    NoPerm, // RFC 5530, section 3
+    NotificationOverflow, // RFC 5465, section 5.8
+    OverQuota, // RFC 5530, section 3
     PermanentFlags(Vec<&'a str>),
+    PrivacyRequired, // RFC 5530, section 3
     ReadOnly,
     ReadWrite,
+    ServerBug, // RFC 5530, section 3
     TryCreate,
     UidNext(u32),
+    UidNotSticky, // RFC 4315, section 3
     UidValidity(u32),
+    Unavailable, // RFC 5530, section 3
     Unseen(u32),
 }
 
+// RFC 5464, sections 4.2.1 and 4.3
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MetadataCode {
+    // `GETMETADATA` left out values larger than `MAXSIZE`; the number is
+    // the size of the largest.
+    LongEntries(u32),
+    // `SETMETADATA` failed because a value is larger than this.
+    MaxSize(u32),
+    TooMany,
+    NoPrivate,
+}
+
+// RFC 5258, section 6. The additional data requested for each mailbox by
+// `LIST ... RETURN (...)`. `Status` takes the `STATUS` items to return
+// (RFC 5819).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ListReturn<'a> {
+    Children,
+    Subscribed,
+    Status(&'a [&'a str]),
+}
+
+// RFC 5464, section 4.2.2. How far below the given entries `GETMETADATA`
+// returns entries.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MetadataDepth {
+    Zero,
+    One,
+    Infinity,
+}
+
+// RFC 4731, section 3.1
 #[derive(Debug, Eq, PartialEq)]
+pub enum SearchReturn {
+    All(Vec<RangeInclusive<u32>>),
+    Count(u32),
+    Max(u32),
+    Min(u32),
+    ModSeq(u64), // RFC 7162, section 3.1.5
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum StatusAttribute {
+    HighestModSeq(u64), // RFC 7162, section 3.1.6
     Messages(u32),
     Recent(u32),
     UidNext(u32),
@@ -68,9 +215,10 @@ pub enum StatusAttribute {
 pub enum MailboxDatum<'a> {
     Exists(u32),
     Flags(Vec<&'a str>),
+    // `delimiter` is `None` for flat mailbox hierarchies.
     List {
         flags: Vec<&'a str>,
-        delimiter: &'a str,
+        delimiter: Option<&'a str>,
         name: &'a str,
     },
     Status {
@@ -79,17 +227,22 @@ pub enum MailboxDatum<'a> {
     },
     SubList {
         flags: Vec<&'a str>,
-        delimiter: &'a str,
+        delimiter: Option<&'a str>,
         name: &'a str,
     },
     Recent(u32),
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Attribute {
     Body,
+    BodyStructure,
     Envelope,
     Flags,
+    // Gmail IMAP extensions (X-GM-EXT-1)
+    GmailLabels,
+    GmailMsgId,
+    GmailThrId,
     InternalDate,
     ModSeq, // RFC 4551, section 3.3.2
     Rfc822,
@@ -97,6 +250,8 @@ pub enum Attribute {
     Uid,
 }
 
+impl Copy for Attribute {}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum MessageSection<'a> {
     Header,
@@ -119,8 +274,16 @@ pub enum AttributeValue<'a> {
         index: Option<u32>,
         data: Option<&'a [u8]>,
     },
+    // Both `BODY` and `BODYSTRUCTURE`; only the latter includes extension
+    // data.
+    BodyStructure(BodyStructure<'a>),
     Envelope(Box<Envelope<'a>>),
     Flags(Vec<&'a str>),
+    // Gmail IMAP extensions (X-GM-EXT-1). Labels are system labels such as
+    // `\Inbox`, or label names in modified UTF-7.
+    GmailLabels(Vec<&'a str>),
+    GmailMsgId(u64),
+    GmailThrId(u64),
     InternalDate(&'a str),
     ModSeq(u64), // RFC 4551, section 3.3.2
     Rfc822(Option<&'a [u8]>),
@@ -151,6 +314,249 @@ pub struct Address<'a> {
     pub host: Option<&'a str>,
 }
 
+#[derive(Debug, Eq, PartialEq)]
+pub struct Namespace<'a> {
+    pub prefix: &'a str,
+    pub delimiter: Option<&'a str>,
+}
+
+/// The rights granted to an identifier, such as a user name or `anyone`;
+/// identifiers starting with `-` have these rights revoked.
+#[derive(Debug, Eq, PartialEq)]
+pub struct AclEntry<'a> {
+    pub identifier: &'a str,
+    pub rights: &'a str,
+}
+
+/// A set of access rights (RFC 4314, section 2.1), converted from and
+/// displayed as a string of rights such as `lrswi`.
+///
+/// Besides the standard rights, the obsolete `c` and `d` rights and the
+/// digits that servers may use for rights of their own are kept; other
+/// characters are dropped.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Rights(u64);
+
+impl Rights {
+    pub const LOOKUP: Rights = Rights(1 << (b'l' - b'a'));
+    pub const READ: Rights = Rights(1 << (b'r' - b'a'));
+    pub const SEEN: Rights = Rights(1 << (b's' - b'a'));
+    pub const WRITE: Rights = Rights(1 << (b'w' - b'a'));
+    pub const INSERT: Rights = Rights(1 << (b'i' - b'a'));
+    pub const POST: Rights = Rights(1 << (b'p' - b'a'));
+    pub const CREATE_MAILBOX: Rights = Rights(1 << (b'k' - b'a'));
+    pub const DELETE_MAILBOX: Rights = Rights(1 << (b'x' - b'a'));
+    pub const DELETE_MESSAGES: Rights = Rights(1 << (b't' - b'a'));
+    pub const EXPUNGE: Rights = Rights(1 << (b'e' - b'a'));
+    pub const ADMINISTER: Rights = Rights(1 << (b'a' - b'a'));
+
+    pub fn empty() -> Self {
+        Rights(0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether all of `rights` are in this set.
+    pub fn contains(self, rights: Rights) -> bool {
+        self.0 & rights.0 == rights.0
+    }
+
+    pub fn insert(&mut self, rights: Rights) {
+        self.0 |= rights.0;
+    }
+
+    pub fn remove(&mut self, rights: Rights) {
+        self.0 &= !rights.0;
+    }
+
+    // The bit for right `c`: letters first, then digits.
+    fn bit(c: char) -> Option<u64> {
+        match c {
+            'a'..='z' => Some(1 << (c as u8 - b'a')),
+            '0'..='9' => Some(1 << (26 + c as u8 - b'0')),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Rights {
+    fn from(rights: &'a str) -> Self {
+        Rights(rights.chars().filter_map(Rights::bit).fold(0, |acc, bit| acc | bit))
+    }
+}
+
+impl BitOr for Rights {
+    type Output = Rights;
+
+    fn bitor(self, rights: Rights) -> Rights {
+        Rights(self.0 | rights.0)
+    }
+}
+
+impl fmt::Display for Rights {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in (b'a'..=b'z').chain(b'0'..=b'9') {
+            let c = c as char;
+            if Rights::bit(c).map_or(false, |bit| self.0 & bit != 0) {
+                write!(f, "{}", c)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The usage and limit of one resource of a quota root, such as `STORAGE`
+/// (in units of 1024 octets) or `MESSAGE` (RFC 2087, section 3).
+#[derive(Debug, Eq, PartialEq)]
+pub struct QuotaResource<'a> {
+    pub name: &'a str,
+    pub usage: u64,
+    pub limit: u64,
+}
+
+/// The quota roots a mailbox's usage counts against.
+#[derive(Debug, Eq, PartialEq)]
+pub struct QuotaRoot<'a> {
+    pub mailbox: &'a str,
+    pub roots: Vec<&'a str>,
+}
+
+pub type BodyParams<'a> = Option<Vec<(&'a str, &'a str)>>;
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ContentType<'a> {
+    pub ty: &'a str,
+    pub subtype: &'a str,
+    pub params: BodyParams<'a>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ContentDisposition<'a> {
+    pub ty: &'a str,
+    pub params: BodyParams<'a>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct BodyFields<'a> {
+    pub id: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub transfer_encoding: &'a str,
+    pub octets: u32,
+}
+
+// RFC 3501, section 7.4.2. `md5` is always `None` for multipart bodies.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct BodyExtension<'a> {
+    pub md5: Option<&'a str>,
+    pub disposition: Option<ContentDisposition<'a>>,
+    pub language: Option<Vec<&'a str>>,
+    pub location: Option<&'a str>,
+}
+
+// RFC 3501, section 7.4.2. `extension` is only sent for `BODYSTRUCTURE`.
+#[derive(Debug, Eq, PartialEq)]
+pub enum BodyStructure<'a> {
+    Basic {
+        content_type: ContentType<'a>,
+        fields: BodyFields<'a>,
+        extension: Option<BodyExtension<'a>>,
+    },
+    Text {
+        content_type: ContentType<'a>,
+        fields: BodyFields<'a>,
+        lines: u32,
+        extension: Option<BodyExtension<'a>>,
+    },
+    Message {
+        content_type: ContentType<'a>,
+        fields: BodyFields<'a>,
+        envelope: Box<Envelope<'a>>,
+        body: Box<BodyStructure<'a>>,
+        lines: u32,
+        extension: Option<BodyExtension<'a>>,
+    },
+    Multipart {
+        content_type: ContentType<'a>,
+        parts: Vec<BodyStructure<'a>>,
+        extension: Option<BodyExtension<'a>>,
+    },
+}
+
+impl<'a> BodyStructure<'a> {
+    pub fn content_type(&self) -> &ContentType<'a> {
+        match *self {
+            BodyStructure::Basic {
+                ref content_type, ..
+            }
+            | BodyStructure::Text {
+                ref content_type, ..
+            }
+            | BodyStructure::Message {
+                ref content_type, ..
+            }
+            | BodyStructure::Multipart {
+                ref content_type, ..
+            } => content_type,
+        }
+    }
+
+    /// The fields of a single part; `None` for multipart bodies.
+    pub fn fields(&self) -> Option<&BodyFields<'a>> {
+        match *self {
+            BodyStructure::Basic { ref fields, .. }
+            | BodyStructure::Text { ref fields, .. }
+            | BodyStructure::Message { ref fields, .. } => Some(fields),
+            BodyStructure::Multipart { .. } => None,
+        }
+    }
+
+    pub fn extension(&self) -> Option<&BodyExtension<'a>> {
+        match *self {
+            BodyStructure::Basic { ref extension, .. }
+            | BodyStructure::Text { ref extension, .. }
+            | BodyStructure::Message { ref extension, .. }
+            | BodyStructure::Multipart { ref extension, .. } => extension.as_ref(),
+        }
+    }
+
+    /// All single parts with their part numbers, as used in `BODY[1.2]`,
+    /// in depth-first order. Parts of attached messages are included.
+    pub fn parts(&self) -> Vec<(Vec<u32>, &BodyStructure<'a>)> {
+        let mut parts = Vec::new();
+        match *self {
+            BodyStructure::Multipart { .. } => self.collect_parts(Vec::new(), &mut parts),
+            _ => self.collect_parts(vec![1], &mut parts),
+        }
+        parts
+    }
+
+    fn collect_parts<'b>(&'b self, path: Vec<u32>, parts: &mut Vec<(Vec<u32>, &'b Self)>) {
+        match *self {
+            BodyStructure::Multipart { parts: ref children, .. } => {
+                for (i, child) in children.iter().enumerate() {
+                    let mut child_path = path.clone();
+                    child_path.push(i as u32 + 1);
+                    child.collect_parts(child_path, parts);
+                }
+            },
+            BodyStructure::Message { ref body, .. } => {
+                parts.push((path.clone(), self));
+                match **body {
+                    BodyStructure::Multipart { .. } => body.collect_parts(path, parts),
+                    _ => {
+                        let mut body_path = path;
+                        body_path.push(1);
+                        body.collect_parts(body_path, parts);
+                    },
+                }
+            },
+            _ => parts.push((path, self)),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct RequestId(pub String);
 
@@ -160,7 +566,7 @@ impl RequestId {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum State {
     NotAuthenticated,
     Authenticated,
@@ -0,0 +1,152 @@
+//! Modified UTF-7, the encoding of international mailbox names (RFC 3501,
+//! section 5.1.3).
+//!
+//! Printable ASCII characters other than `&` represent themselves, `&` is
+//! written as `&-`, and runs of other characters are written as their UTF-16
+//! code units in a variant of base64 (with `,` instead of `/`) between `&`
+//! and `-`, so that `Entwürfe` becomes `Entw&APw-rfe`.
+
+use std::borrow::Cow;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+,";
+
+/// Encodes a mailbox name in modified UTF-7.
+pub fn encode(name: &str) -> Cow<str> {
+    if name.bytes().all(|b| is_direct(b) && b != b'&') {
+        return Cow::Borrowed(name);
+    }
+    let mut encoded = String::with_capacity(name.len() + 8);
+    let mut units = Vec::new();
+    for c in name.chars() {
+        if c.is_ascii() && is_direct(c as u8) {
+            if !units.is_empty() {
+                push_base64(&mut encoded, &units);
+                units.clear();
+            }
+            encoded.push(c);
+            if c == '&' {
+                encoded.push('-');
+            }
+        } else {
+            let mut buf = [0; 2];
+            units.extend_from_slice(c.encode_utf16(&mut buf));
+        }
+    }
+    if !units.is_empty() {
+        push_base64(&mut encoded, &units);
+    }
+    Cow::Owned(encoded)
+}
+
+/// Decodes a mailbox name from modified UTF-7.
+///
+/// Fails if `name` contains characters that are not printable ASCII or a
+/// base64 run that does not decode to UTF-16.
+pub fn decode(name: &str) -> Result<Cow<str>, &'static str> {
+    if !name.bytes().all(is_direct) {
+        return Err("non-ASCII character in modified UTF-7");
+    }
+    if !name.contains('&') {
+        return Ok(Cow::Borrowed(name));
+    }
+    let mut decoded = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = match rest.find('-') {
+            Some(end) => end,
+            None => return Err("unterminated base64 run in modified UTF-7"),
+        };
+        if end == 0 {
+            decoded.push('&');
+        } else {
+            let units = decode_base64(&rest[..end])?;
+            match String::from_utf16(&units) {
+                Ok(s) => decoded.push_str(&s),
+                Err(_) => return Err("invalid UTF-16 in modified UTF-7"),
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    decoded.push_str(rest);
+    Ok(Cow::Owned(decoded))
+}
+
+// Printable US-ASCII characters stand for themselves.
+fn is_direct(b: u8) -> bool {
+    b >= 0x20 && b <= 0x7e
+}
+
+// Appends `&`, the base64 of the big-endian code units, and `-`.
+fn push_base64(out: &mut String, units: &[u16]) {
+    let bytes = units
+        .iter()
+        .flat_map(|unit| vec![(unit >> 8) as u8, *unit as u8])
+        .collect::<Vec<_>>();
+    out.push('&');
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..chunk.len() + 1 {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    out.push('-');
+}
+
+fn decode_base64(run: &str) -> Result<Vec<u16>, &'static str> {
+    let mut bytes = Vec::with_capacity(run.len() * 3 / 4);
+    let (mut bits, mut n) = (0, 0u32);
+    for b in run.bytes() {
+        let value = match ALPHABET.iter().position(|&a| a == b) {
+            Some(value) => value as u32,
+            None => return Err("invalid base64 character in modified UTF-7"),
+        };
+        n = n << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+            n &= (1 << bits) - 1;
+        }
+    }
+    if n != 0 || bytes.len() % 2 != 0 {
+        return Err("invalid base64 run in modified UTF-7");
+    }
+    Ok(bytes
+        .chunks(2)
+        .map(|pair| u16::from(pair[0]) << 8 | u16::from(pair[1]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode};
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("INBOX"), "INBOX");
+        assert_eq!(encode("Entwürfe"), "Entw&APw-rfe");
+        assert_eq!(encode("Tom & Jerry"), "Tom &- Jerry");
+        assert_eq!(encode("~peter/mail/台北/日本語"), "~peter/mail/&U,BTFw-/&ZeVnLIqe-");
+        assert_eq!(encode("😀"), "&2D3eAA-");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("INBOX").unwrap(), "INBOX");
+        assert_eq!(decode("Entw&APw-rfe").unwrap(), "Entwürfe");
+        assert_eq!(decode("Tom &- Jerry").unwrap(), "Tom & Jerry");
+        assert_eq!(
+            decode("~peter/mail/&U,BTFw-/&ZeVnLIqe-").unwrap(),
+            "~peter/mail/台北/日本語"
+        );
+        assert!(decode("Entwürfe").is_err());
+        assert!(decode("&APw").is_err());
+        assert!(decode("&AP-").is_err());
+        assert!(decode("&2D0-").is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Decodes FETCH responses with large bodies and keeps the bodies, either
+//! by sharing the decoded buffer or by copying them, as a client keeping
+//! messages for later processing would.
+//!
+//! Run with `cargo bench --bench decode`.
+
+extern crate bytes;
+extern crate imap_proto;
+extern crate tokio_codec;
+extern crate tokio_imap;
+
+use bytes::{Bytes, BytesMut};
+use imap_proto::{AttributeValue, Response};
+use tokio_codec::Decoder;
+use tokio_imap::proto::{ImapCodec, ResponseData};
+
+use std::time::{Duration, Instant};
+
+const MESSAGES: usize = 2000;
+const BODY_LEN: usize = 64 * 1024;
+const ROUNDS: u32 = 5;
+
+fn main() {
+    let input = responses();
+    let shared = run(&input, |rsp, body| rsp.slice_bytes(body).unwrap());
+    let copied = run(&input, |_, body| Bytes::from(body.to_vec()));
+    report("shared", shared);
+    report("copied", copied);
+}
+
+fn responses() -> Vec<u8> {
+    let body = vec![b'x'; BODY_LEN];
+    let mut input = Vec::with_capacity(MESSAGES * (BODY_LEN + 64));
+    for i in 1..=MESSAGES {
+        let line = format!("* {} FETCH (UID {} BODY[] {{{}}}\r\n", i, i, BODY_LEN);
+        input.extend(line.as_bytes());
+        input.extend(&body);
+        input.extend(b")\r\n");
+    }
+    input
+}
+
+// Decodes all responses `ROUNDS` times, keeping the bodies with `keep`.
+fn run<F>(input: &[u8], keep: F) -> Duration
+where
+    F: Fn(&ResponseData, &[u8]) -> Bytes, {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let mut codec = ImapCodec::default();
+        let mut buf = BytesMut::from(input);
+        let mut bodies = Vec::with_capacity(MESSAGES);
+        while let Some(rsp) = codec.decode(&mut buf).unwrap() {
+            let body = match *rsp.parsed() {
+                Response::Fetch(_, ref attrs) => attrs.iter().filter_map(body).next(),
+                _ => None,
+            };
+            bodies.push(keep(&rsp, body.unwrap()));
+        }
+        assert_eq!(bodies.len(), MESSAGES);
+    }
+    start.elapsed() / ROUNDS
+}
+
+fn body<'a>(attr: &AttributeValue<'a>) -> Option<&'a [u8]> {
+    match *attr {
+        AttributeValue::BodySection { data, .. } => data,
+        _ => None,
+    }
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+    let mib = (MESSAGES * BODY_LEN) as f64 / (1024.0 * 1024.0);
+    println!("{}: {:.1} ms per round, {:.0} MiB/s", name, secs * 1e3, mib / secs);
+}
@@ -7,7 +7,6 @@ use futures::future::Future;
 use futures_state_stream::StateStream;
 use std::error::Error;
 use std::fmt::{self, Display, Formatter};
-use std::io;
 use tokio::executor::current_thread;
 use tokio_imap::{ImapClient, TlsClient};
 use tokio_imap::client::builder::{CommandBuilder, FetchBuilderAttributes, FetchBuilderMessages,
@@ -80,10 +79,10 @@ fn process_email(response_data: &ResponseData) {
 
 #[derive(Debug)]
 pub enum ImapError {
-    Connect { cause: io::Error },
-    Login { cause: io::Error },
-    Select { cause: io::Error },
-    UidFetch { cause: io::Error },
+    Connect { cause: tokio_imap::Error },
+    Login { cause: tokio_imap::Error },
+    Select { cause: tokio_imap::Error },
+    UidFetch { cause: tokio_imap::Error },
 }
 
 impl Error for ImapError {
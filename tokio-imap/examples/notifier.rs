@@ -0,0 +1,174 @@
+//! Prints a line for each message arriving in INBOX, ready to be shown as a
+//! desktop notification: the sender and the subject, separated by a tab.
+//!
+//! ```text
+//! notifier imap.example.com me@example.com secret |
+//!     while IFS=$'\t' read -r from subject; do notify-send "$from" "$subject"; done
+//! ```
+//!
+//! The connection is re-established whenever it is lost. As tokio-imap has
+//! no support for `IDLE` yet, the mailbox is polled with `NOOP`; the client's
+//! `MailboxWatch` tells whether the message count changed, so that new
+//! messages are only fetched when there may be any.
+
+extern crate futures;
+extern crate tokio;
+extern crate tokio_imap;
+
+use futures::future::{self, Future, Loop};
+use std::cmp;
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+use tokio_imap::{Error, ReconnectingClient, TlsClient};
+use tokio_imap::client::builder::{CommandBuilder, FetchBuilderAttributes, FetchBuilderMessages,
+                                  FetchBuilderModifiers};
+use tokio_imap::proto::ResponseData;
+use tokio_imap::reconnect::{Login, ReconnectFuture};
+use tokio_imap::session::Mailbox;
+use tokio_imap::types::{Address, Attribute, AttributeValue, Response};
+
+type Step = Box<Future<Item = Loop<(), (Seen, ReconnectingClient)>, Error = Error> + Send>;
+
+fn main() {
+    let mut args = std::env::args();
+    let _ = args.next();
+    let server = args.next().expect("no server provided");
+    let login = args.next().expect("no login provided");
+    let password = args.next().expect("no password provided");
+    let interval = args.next()
+        .map(|secs| secs.parse().expect("invalid poll interval"))
+        .unwrap_or(60);
+    let interval = Duration::from_secs(interval);
+
+    let client = ReconnectingClient::new(
+        move || TlsClient::connect(&server),
+        Login::new(&login, &password),
+    );
+    let notifier = select_inbox(client).and_then(move |(seen, client)| {
+        future::loop_fn((seen, client), move |(seen, client)| -> Step {
+            Box::new(poll(client, seen, interval).map(Loop::Continue))
+        })
+    });
+    tokio::run(notifier.map_err(|cause| eprintln!("Giving up: {}", cause)));
+}
+
+// What has been reported so far.
+struct Seen {
+    // New messages have at least this UID.
+    next_uid: u32,
+    // The message count in the mailbox watch, when last checked.
+    exists: Option<u32>,
+}
+
+fn select_inbox(client: ReconnectingClient) -> ReconnectFuture<Seen> {
+    Box::new(
+        client
+            .call(CommandBuilder::select("INBOX"))
+            .and_then(|(result, client)| {
+                let responses = result?;
+                let mailbox = Mailbox::from_responses(&responses);
+                let seen = Seen {
+                    next_uid: mailbox.uid_next.unwrap_or(1),
+                    exists: None,
+                };
+                Ok((seen, client))
+            }),
+    )
+}
+
+// Waits for `interval`, then asks the server for changes and fetches the
+// new messages if there are any.
+fn poll(client: ReconnectingClient, seen: Seen, interval: Duration) -> ReconnectFuture<Seen> {
+    let wait = Delay::new(Instant::now() + interval)
+        .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::Other, e)));
+    Box::new(
+        wait.and_then(move |()| client.call(CommandBuilder::noop()))
+            .and_then(move |(result, mut client)| -> ReconnectFuture<Seen> {
+                match result {
+                    Ok(_) => {},
+                    Err(Error::UidValidityChanged { .. }) => {
+                        eprintln!("INBOX was recreated, starting over");
+                        return select_inbox(client);
+                    },
+                    Err(cause) => {
+                        eprintln!("Polling failed: {}", cause);
+                        return Box::new(future::ok((seen, client)));
+                    },
+                }
+                // The watch belongs to the connection, so it starts out empty
+                // after reconnecting; the first check then always fetches.
+                let exists = client
+                    .client_mut()
+                    .map(|client| client.mailbox_watch().get().exists);
+                if exists.is_some() && exists == seen.exists {
+                    return Box::new(future::ok((seen, client)));
+                }
+                let seen = Seen {
+                    next_uid: seen.next_uid,
+                    exists,
+                };
+                fetch_new(client, seen)
+            }),
+    )
+}
+
+fn fetch_new(client: ReconnectingClient, mut seen: Seen) -> ReconnectFuture<Seen> {
+    let cmd = CommandBuilder::uid_fetch()
+        .all_after(seen.next_uid)
+        .attr(Attribute::Uid)
+        .attr(Attribute::Envelope);
+    Box::new(client.call(cmd.build()).map(move |(result, client)| {
+        match result {
+            Ok(responses) => for rsp in &responses {
+                // `UID n:*` includes the last message even if its UID is
+                // lower than `n`.
+                match summarize(rsp) {
+                    Some((uid, summary)) if uid >= seen.next_uid => {
+                        println!("{}", summary);
+                        seen.next_uid = cmp::max(seen.next_uid, uid + 1);
+                    },
+                    _ => {},
+                }
+            },
+            Err(cause) => {
+                eprintln!("Fetching new messages failed: {}", cause);
+                seen.exists = None;
+            },
+        }
+        (seen, client)
+    }))
+}
+
+fn summarize(rsp: &ResponseData) -> Option<(u32, String)> {
+    let attrs = match *rsp.parsed() {
+        Response::Fetch(_, ref attrs) => attrs,
+        _ => return None,
+    };
+    let mut uid = None;
+    let mut envelope = None;
+    for attr in attrs {
+        match *attr {
+            AttributeValue::Uid(u) => uid = Some(u),
+            AttributeValue::Envelope(ref e) => envelope = Some(e),
+            _ => {},
+        }
+    }
+    let envelope = envelope?;
+    let from = envelope
+        .from
+        .as_ref()
+        .and_then(|from| from.first())
+        .and_then(sender)
+        .unwrap_or_else(|| "Unknown sender".to_string());
+    let subject = envelope.subject.unwrap_or("(no subject)");
+    Some((uid?, format!("{}\t{}", from, subject)))
+}
+
+fn sender(address: &Address) -> Option<String> {
+    match (address.name, address.mailbox, address.host) {
+        (Some(name), _, _) => Some(name.to_string()),
+        (None, Some(mailbox), Some(host)) => Some(format!("{}@{}", mailbox, host)),
+        _ => None,
+    }
+}
@@ -0,0 +1,302 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use imap_proto::builders::search::{SearchCriteria, SearchDate};
+
+/// The date a backup window applies to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BackupDate {
+    /// The date the server received the message (`SINCE`).
+    Received,
+    /// The date in the message's `Date:` header (`SENTSINCE`).
+    Sent,
+}
+
+impl BackupDate {
+    // The messages dated `since` or later.
+    pub(crate) fn since(&self, since: SearchDate) -> SearchCriteria {
+        match *self {
+            BackupDate::Received => SearchCriteria::Since(since),
+            BackupDate::Sent => SearchCriteria::SentSince(since),
+        }
+    }
+}
+
+/// Where an incremental backup left off.
+///
+/// Formatted as `<uid validity>:<last uid>`, which can be parsed back with
+/// `str::parse()` before the next run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Checkpoint {
+    pub uid_validity: u32,
+    pub last_uid: u32,
+}
+
+impl fmt::Display for Checkpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.uid_validity, self.last_uid)
+    }
+}
+
+impl FromStr for Checkpoint {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid checkpoint");
+        let mut parts = s.trim().splitn(2, ':');
+        let uid_validity = parts.next().ok_or_else(invalid)?;
+        let last_uid = parts.next().ok_or_else(invalid)?;
+        Ok(Checkpoint {
+            uid_validity: uid_validity.parse().map_err(|_| invalid())?,
+            last_uid: last_uid.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A message written to a backup.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ManifestEntry {
+    pub uid: u32,
+    pub flags: Vec<String>,
+    pub internal_date: Option<String>,
+    /// Position of the message's `From ` line in the mbox output.
+    pub offset: u64,
+    /// Length of the message in the mbox output, including separators.
+    pub len: u64,
+}
+
+/// The result of `Session::backup_since()`.
+pub struct Backup<W> {
+    pub writer: W,
+    pub manifest: Vec<ManifestEntry>,
+    /// Checkpoint to pass to the next run; `None` if the mailbox has no
+    /// `UIDVALIDITY`, in which case incremental backups are not possible.
+    pub checkpoint: Option<Checkpoint>,
+}
+
+impl<W> Backup<W> {
+    /// Writes the manifest as tab-separated lines of UID, offset, length,
+    /// internal date and space-separated flags.
+    pub fn write_manifest<M: Write>(&self, mut out: M) -> io::Result<()> {
+        for entry in &self.manifest {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                entry.uid,
+                entry.offset,
+                entry.len,
+                entry.internal_date.as_ref().map(|s| &s[..]).unwrap_or(""),
+                entry.flags.join(" ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a manifest written by `Backup::write_manifest()`.
+pub fn read_manifest<R: BufRead>(input: R) -> io::Result<Vec<ManifestEntry>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid manifest line");
+    let mut manifest = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 5 {
+            return Err(invalid());
+        }
+        manifest.push(ManifestEntry {
+            uid: fields[0].parse().map_err(|_| invalid())?,
+            offset: fields[1].parse().map_err(|_| invalid())?,
+            len: fields[2].parse().map_err(|_| invalid())?,
+            internal_date: if fields[3].is_empty() {
+                None
+            } else {
+                Some(fields[3].to_string())
+            },
+            flags: fields[4].split_whitespace().map(|f| f.to_string()).collect(),
+        });
+    }
+    Ok(manifest)
+}
+
+/// How `Session::restore()` deals with messages already in the target mailbox.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CollisionPolicy {
+    /// Skip messages whose `Message-ID` is found in the target mailbox.
+    Skip,
+    /// Append all messages, even if that creates duplicates.
+    Duplicate,
+}
+
+/// What happened to a message during a restore.
+#[derive(Debug, Eq, PartialEq)]
+pub enum RestoreOutcome {
+    /// The message was appended. If it has a `Message-ID`, `verified` tells
+    /// whether it was found in the target mailbox afterwards.
+    Restored { verified: bool },
+    /// A message with the same `Message-ID` was already present.
+    Skipped,
+    /// The server refused the message or it could not be read from the archive.
+    Failed(String),
+}
+
+/// Per-message result of `Session::restore()`.
+#[derive(Debug)]
+pub struct RestoreResult {
+    /// The message's UID in the original mailbox.
+    pub uid: u32,
+    pub message_id: Option<String>,
+    pub outcome: RestoreOutcome,
+}
+
+/// Extracts the message at `entry` from an mbox archive written by a backup,
+/// undoing the mbox quoting and restoring CRLF line endings.
+pub(crate) fn read_message(archive: &[u8], entry: &ManifestEntry) -> Option<Vec<u8>> {
+    let start = entry.offset as usize;
+    let end = start.checked_add(entry.len as usize)?;
+    if end > archive.len() {
+        return None;
+    }
+    let mbox = &archive[start..end];
+    if !mbox.starts_with(b"From ") {
+        return None;
+    }
+    // Skip the `From ` line and the trailing separator line.
+    let from_end = mbox.iter().position(|&b| b == b'\n')? + 1;
+    if !mbox.ends_with(b"\n\n") {
+        return None;
+    }
+    let content = &mbox[from_end..mbox.len() - 2];
+    let mut message = Vec::with_capacity(content.len() + content.len() / 32);
+    for line in content.split(|&b| b == b'\n') {
+        let quoted = line.iter().take_while(|&&b| b == b'>').count();
+        if quoted > 0 && line[quoted..].starts_with(b"From ") {
+            message.extend(&line[1..]);
+        } else {
+            message.extend(line);
+        }
+        message.extend(b"\r\n");
+    }
+    Some(message)
+}
+
+/// Finds the value of the `Message-ID` header in a message.
+pub(crate) fn message_id(message: &[u8]) -> Option<String> {
+    let mut lines = message.split(|&b| b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        let line = trim_cr(line);
+        if line.is_empty() {
+            break;
+        }
+        if line.len() < 11 || !line[..11].eq_ignore_ascii_case(b"message-id:") {
+            continue;
+        }
+        let mut value = String::from_utf8_lossy(&line[11..]).into_owned();
+        // The value may be folded onto continuation lines.
+        while let Some(next) = lines.peek().map(|next| trim_cr(next)) {
+            if !next.starts_with(b" ") && !next.starts_with(b"\t") {
+                break;
+            }
+            value.push_str(&String::from_utf8_lossy(next));
+            lines.next();
+        }
+        let value = value.trim();
+        return if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        };
+    }
+    None
+}
+
+fn trim_cr(line: &[u8]) -> &[u8] {
+    if line.ends_with(b"\r") {
+        &line[..line.len() - 1]
+    } else {
+        line
+    }
+}
+
+/// Writes messages in mboxrd format, keeping track of the output position.
+pub(crate) struct MboxWriter<W> {
+    writer: W,
+    offset: u64,
+}
+
+impl<W: Write> MboxWriter<W> {
+    pub(crate) fn new(writer: W) -> Self {
+        Self { writer, offset: 0 }
+    }
+
+    pub(crate) fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    // Writes a message, returning its offset and length in the output.
+    pub(crate) fn write_message(
+        &mut self, internal_date: Option<&str>, message: &[u8]
+    ) -> io::Result<(u64, u64)> {
+        let start = self.offset;
+        let date = internal_date
+            .and_then(asctime)
+            .unwrap_or_else(|| "Thu Jan  1 00:00:00 1970".to_string());
+        self.put(format!("From MAILER-DAEMON {}\n", date).as_bytes())?;
+        let message = if message.ends_with(b"\n") {
+            &message[..message.len() - 1]
+        } else {
+            message
+        };
+        for line in message.split(|&b| b == b'\n') {
+            let line = trim_cr(line);
+            let unquoted = line.iter().skip_while(|&&b| b == b'>').cloned();
+            if unquoted.take(5).eq(b"From ".iter().cloned()) {
+                self.put(b">")?;
+            }
+            self.put(line)?;
+            self.put(b"\n")?;
+        }
+        self.put(b"\n")?;
+        Ok((start, self.offset - start))
+    }
+
+    fn put(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+// Converts an IMAP date-time like `17-Jul-1996 02:44:25 -0700` to the
+// asctime() format used in mbox `From ` lines.
+fn asctime(date: &str) -> Option<String> {
+    let mut parts = date.trim().split(' ');
+    let mut day = parts.next()?.split('-');
+    let time = parts.next()?;
+    let (dd, mon, year) = (day.next()?, day.next()?, day.next()?);
+    let dd: u32 = dd.trim().parse().ok()?;
+    let year: i64 = year.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == mon)? as i64 + 1;
+    // Day of week using Sakamoto's method.
+    let offsets = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+    let y = if month < 3 { year - 1 } else { year };
+    let dow = (y + y / 4 - y / 100 + y / 400 + offsets[month as usize - 1] + i64::from(dd)) % 7;
+    Some(format!(
+        "{} {} {:2} {} {}",
+        DAYS[dow as usize],
+        mon,
+        dd,
+        time,
+        year
+    ))
+}
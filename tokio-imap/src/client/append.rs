@@ -0,0 +1,491 @@
+use futures::{Async, Future, Poll, Sink, Stream};
+use futures::sink;
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_codec::Framed;
+
+use imap_proto::{Request, RequestId, Response};
+use imap_proto::builders::command::Command;
+
+use super::{Client, ClientState};
+use super::throttle::{Throttle, ThrottleState, TokenBucket};
+use error::Error;
+use proto::{ImapCodec, ResponseData};
+
+const COPY_BUFFER_SIZE: usize = 8 * 1024;
+
+type ProgressHandler = Box<FnMut(&AppendProgress) + Send>;
+
+/// How far an upload has got, as reported to `AppendFuture::on_progress()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AppendProgress {
+    /// The bytes of message data sent so far.
+    pub sent: u64,
+    pub total: u64,
+    /// The state of the throttle, if the upload is throttled.
+    pub throttle: Option<ThrottleState>,
+}
+
+/// A message to upload with `Client::multi_append()`.
+pub struct AppendMessage<R> {
+    pub flags: Vec<String>,
+    pub date: Option<String>,
+    pub body: R,
+    pub len: u64,
+}
+
+impl<R> AppendMessage<R> {
+    /// A message of `len` bytes, read from `body`, without flags or date.
+    pub fn new(body: R, len: u64) -> Self {
+        Self {
+            flags: Vec::new(),
+            date: None,
+            body,
+            len,
+        }
+    }
+
+    pub fn flags(mut self, flags: &[&str]) -> Self {
+        self.flags = flags.iter().map(|flag| flag.to_string()).collect();
+        self
+    }
+
+    pub fn date(mut self, date: &str) -> Self {
+        self.date = Some(date.to_string());
+        self
+    }
+}
+
+/// Uploads messages with `APPEND`, streaming their bodies from `AsyncRead`s.
+///
+/// Resolves to the responses received for the command (the last of which
+/// is the tagged response) together with the client. If the server rejects
+/// the command before the data of a message was sent, no more data is sent
+/// at all.
+pub struct AppendFuture<T, R>
+where
+    T: AsyncRead + AsyncWrite, {
+    step: Step<T>,
+    state: Option<ClientState>,
+    request_id: RequestId,
+    literal_plus: bool,
+    utf8: bool,
+    body: Option<R>,
+    // The messages after the current one, with the arguments announcing
+    // them (MULTIAPPEND).
+    next: VecDeque<(Vec<u8>, R, u64)>,
+    total: u64,
+    remaining: u64,
+    sent: u64,
+    throttle: Option<TokenBucket>,
+    progress: Option<ProgressHandler>,
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+    responses: Vec<ResponseData>,
+}
+
+enum Step<T>
+where
+    T: AsyncRead + AsyncWrite, {
+    // Waiting for the scheduler, if any, to let the command be sent.
+    Scheduling(sink::Send<Framed<T, ImapCodec>>),
+    Sending(sink::Send<Framed<T, ImapCodec>>),
+    Continuation(Framed<T, ImapCodec>),
+    Literal(Framed<T, ImapCodec>),
+    // Writing what follows the message data, of which the given number of
+    // bytes were written already.
+    Finishing(Framed<T, ImapCodec>, Vec<u8>, usize),
+    Responses(Framed<T, ImapCodec>),
+    Failed(Error),
+    Done,
+}
+
+impl<T, R> AppendFuture<T, R>
+where
+    T: AsyncRead + AsyncWrite,
+    R: AsyncRead,
+{
+    // Appends the message announced by `cmd`, followed by those in `next`.
+    // The client must be in a state that allows `APPEND`.
+    pub(crate) fn new(
+        client: Client<T>, cmd: Command, body: R, len: u64, next: VecDeque<(Vec<u8>, R, u64)>,
+        utf8: bool,
+    ) -> Self {
+        let Client {
+            transport,
+            mut state,
+        } = client;
+        let request_id = state.request_ids.next().unwrap(); // safe: never returns Err
+        let (cmd_bytes, _) = state.command_parts(cmd);
+        state.started(&request_id, "APPEND", &cmd_bytes);
+        let step = Step::Scheduling(transport.send(Request(request_id.clone(), cmd_bytes)));
+        let mut future = Self::with_step(step, state, request_id, utf8);
+        future.total = len + next.iter().map(|&(_, _, len)| len).sum::<u64>();
+        future.remaining = len;
+        future.body = Some(body);
+        future.next = next;
+        future
+    }
+
+    // Fails without sending anything, for `Client::append()` and
+    // `Client::multi_append()`; their `try_` variants keep the client.
+    pub(crate) fn failed(client: Client<T>, e: Error) -> Self {
+        let mut state = client.state;
+        let request_id = state.request_ids.next().unwrap(); // safe: never returns Err
+        Self::with_step(Step::Failed(e), state, request_id, false)
+    }
+
+    fn with_step(step: Step<T>, state: ClientState, request_id: RequestId, utf8: bool) -> Self {
+        let literal_plus = state.has_capability("LITERAL+");
+        Self {
+            step,
+            state: Some(state),
+            request_id,
+            literal_plus,
+            utf8,
+            body: None,
+            next: VecDeque::new(),
+            total: 0,
+            remaining: 0,
+            sent: 0,
+            throttle: None,
+            progress: None,
+            buf: vec![0; COPY_BUFFER_SIZE].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            responses: Vec::new(),
+        }
+    }
+
+    /// Limits the rate at which the message data is sent, for example so
+    /// that a background migration does not saturate a slow uplink.
+    pub fn throttle(mut self, limit: Throttle) -> Self {
+        if let Some(ref state) = self.state {
+            self.throttle = Some(TokenBucket::new(limit, state.clock.clone()));
+        }
+        self
+    }
+
+    /// Calls `handler` whenever message data has been sent, and when the
+    /// upload starts waiting for the throttle.
+    pub fn on_progress<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(&AppendProgress) + Send + 'static, {
+        self.progress = Some(Box::new(handler));
+        self
+    }
+
+    fn report(&mut self) {
+        let progress = AppendProgress {
+            sent: self.sent,
+            total: self.total,
+            throttle: self.throttle.as_ref().map(|throttle| throttle.state()),
+        };
+        if let Some(ref mut handler) = self.progress {
+            handler(&progress);
+        }
+    }
+
+    fn finish(&mut self, transport: Framed<T, ImapCodec>) -> (Vec<ResponseData>, Client<T>) {
+        let client = Client {
+            transport,
+            state: self.state.take().unwrap(), // safe: only taken once
+        };
+        (mem::replace(&mut self.responses, Vec::new()), client)
+    }
+
+    // What follows the data of the current message: CRLF, preceded by `)`
+    // for a UTF-8 literal and by the arguments of the next message, if any.
+    fn trailer(&self) -> Vec<u8> {
+        let mut trailer = Vec::new();
+        if self.utf8 {
+            trailer.push(b')');
+        }
+        if let Some(&(ref args, _, _)) = self.next.front() {
+            trailer.extend_from_slice(args);
+        }
+        trailer.extend_from_slice(b"\r\n");
+        trailer
+    }
+
+    // The step that sends the data of the current message.
+    fn send_literal(&self, transport: Framed<T, ImapCodec>) -> Step<T> {
+        if self.literal_plus {
+            Step::Literal(transport)
+        } else {
+            Step::Continuation(transport)
+        }
+    }
+
+    fn is_done(&self, rsp: &ResponseData) -> bool {
+        rsp.request_id() == Some(&self.request_id)
+    }
+
+    fn route(&mut self, rsp: ResponseData) -> Result<Option<ResponseData>, Error> {
+        let state = self.state.as_mut().unwrap(); // safe: only taken when done
+        state.observe(&rsp);
+        state.route("APPEND", None, rsp)
+    }
+
+    // Copies message data to the stream, returning `Ready` once all of it
+    // has been written.
+    fn poll_literal(&mut self, transport: &mut Framed<T, ImapCodec>) -> Poll<(), Error> {
+        loop {
+            if self.pos == self.cap {
+                if self.remaining == 0 {
+                    return Ok(Async::Ready(()));
+                }
+                let max = if self.remaining < self.buf.len() as u64 {
+                    self.remaining as usize
+                } else {
+                    self.buf.len()
+                };
+                let body = self.body.as_mut().unwrap(); // safe: set while sending data
+                let read = try_ready!(body.poll_read(&mut self.buf[..max]));
+                if read == 0 {
+                    return Err(Error::Io(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "message body shorter than announced",
+                    )));
+                }
+                self.pos = 0;
+                self.cap = read;
+                self.remaining -= read as u64;
+            }
+            let mut end = self.cap;
+            if let Some(ref mut throttle) = self.throttle {
+                let was_waiting = throttle.state().waiting;
+                match throttle.poll_allowance(end - self.pos)? {
+                    Async::Ready(allowed) => end = self.pos + allowed,
+                    Async::NotReady => {
+                        if !was_waiting {
+                            self.report();
+                        }
+                        return Ok(Async::NotReady);
+                    },
+                }
+            }
+            let written = try_ready!(transport.get_mut().poll_write(&self.buf[self.pos..end]));
+            if written == 0 {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write message body",
+                )));
+            }
+            if let Some(ref mut throttle) = self.throttle {
+                throttle.consume(written);
+            }
+            self.pos += written;
+            self.sent += written as u64;
+            self.report();
+        }
+    }
+}
+
+impl<T, R> Future for AppendFuture<T, R>
+where
+    T: AsyncRead + AsyncWrite,
+    R: AsyncRead,
+{
+    type Item = (Vec<ResponseData>, Client<T>);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.step = match mem::replace(&mut self.step, Step::Done) {
+                Step::Scheduling(future) => {
+                    let state = self.state.as_mut().unwrap(); // safe: only taken when done
+                    if let Some(ref mut scheduler) = state.scheduler {
+                        if scheduler.poll_send()?.is_not_ready() {
+                            self.step = Step::Scheduling(future);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                    Step::Sending(future)
+                },
+                Step::Sending(mut future) => match future.poll()? {
+                    Async::Ready(transport) => self.send_literal(transport),
+                    Async::NotReady => {
+                        self.step = Step::Sending(future);
+                        return Ok(Async::NotReady);
+                    },
+                },
+                Step::Continuation(mut transport) => match transport.poll()? {
+                    Async::Ready(Some(rsp)) => {
+                        if let Response::Continue { .. } = *rsp.parsed() {
+                            Step::Literal(transport)
+                        } else if self.is_done(&rsp) {
+                            self.responses.push(rsp);
+                            return Ok(Async::Ready(self.finish(transport)));
+                        } else {
+                            if let Some(rsp) = self.route(rsp)? {
+                                self.responses.push(rsp);
+                            }
+                            Step::Continuation(transport)
+                        }
+                    },
+                    Async::Ready(None) => return Err(Error::ConnectionClosed),
+                    Async::NotReady => {
+                        self.step = Step::Continuation(transport);
+                        return Ok(Async::NotReady);
+                    },
+                },
+                Step::Literal(mut transport) => match self.poll_literal(&mut transport)? {
+                    Async::Ready(()) => {
+                        let trailer = self.trailer();
+                        Step::Finishing(transport, trailer, 0)
+                    },
+                    Async::NotReady => {
+                        self.step = Step::Literal(transport);
+                        return Ok(Async::NotReady);
+                    },
+                },
+                Step::Finishing(mut transport, trailer, mut written) => {
+                    while written < trailer.len() {
+                        match transport.get_mut().poll_write(&trailer[written..])? {
+                            Async::Ready(0) => {
+                                return Err(Error::Io(io::Error::new(
+                                    io::ErrorKind::WriteZero,
+                                    "failed to write end of command",
+                                )));
+                            },
+                            Async::Ready(n) => written += n,
+                            Async::NotReady => {
+                                self.step = Step::Finishing(transport, trailer, written);
+                                return Ok(Async::NotReady);
+                            },
+                        }
+                    }
+                    if let Async::NotReady = transport.get_mut().poll_flush()? {
+                        self.step = Step::Finishing(transport, trailer, written);
+                        return Ok(Async::NotReady);
+                    }
+                    match self.next.pop_front() {
+                        Some((_, body, len)) => {
+                            self.body = Some(body);
+                            self.remaining = len;
+                            self.send_literal(transport)
+                        },
+                        None => Step::Responses(transport),
+                    }
+                },
+                Step::Responses(mut transport) => match transport.poll()? {
+                    Async::Ready(Some(rsp)) => {
+                        if self.is_done(&rsp) {
+                            self.responses.push(rsp);
+                            return Ok(Async::Ready(self.finish(transport)));
+                        }
+                        if let Some(rsp) = self.route(rsp)? {
+                            self.responses.push(rsp);
+                        }
+                        Step::Responses(transport)
+                    },
+                    Async::Ready(None) => return Err(Error::ConnectionClosed),
+                    Async::NotReady => {
+                        self.step = Step::Responses(transport);
+                        return Ok(Async::NotReady);
+                    },
+                },
+                Step::Failed(e) => return Err(e),
+                Step::Done => panic!("AppendFuture polled after completion"),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fault::{FaultSchedule, FaultyStream};
+    use imap_proto::State;
+
+    // A connection that reads what the server sent from `input`, and keeps
+    // what the client wrote in `output`.
+    struct Memory {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl io::Read for Memory {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl io::Write for Memory {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl AsyncRead for Memory {}
+
+    impl AsyncWrite for Memory {
+        fn shutdown(&mut self) -> Poll<(), io::Error> {
+            Ok(Async::Ready(()))
+        }
+    }
+
+    fn client(schedule: FaultSchedule) -> Client<FaultyStream<Memory>> {
+        let memory = Memory {
+            input: io::Cursor::new(b"A0001 OK appended\r\n".to_vec()),
+            output: Vec::new(),
+        };
+        let mut state = ClientState::new();
+        state.set_state(State::Authenticated);
+        let caps = b"* CAPABILITY IMAP4rev1 LITERAL+\r\n".to_vec();
+        state.observe(&ResponseData::parse(caps, None).unwrap());
+        Client {
+            transport: Framed::new(FaultyStream::new(memory, schedule), ImapCodec::default()),
+            state,
+        }
+    }
+
+    #[test]
+    fn write_zero_in_trailer() {
+        let body = &b"hello"[..];
+        let (_, sent) = client(FaultSchedule::default())
+            .append("INBOX", &[], None, body, 5)
+            .wait()
+            .unwrap();
+        let written = sent.transport.get_ref().get_ref().output.len();
+        // The connection stops taking data right before the final CRLF.
+        let schedule = FaultSchedule {
+            write_limit: Some(written - 2),
+            ..FaultSchedule::default()
+        };
+        match client(schedule).append("INBOX", &[], None, body, 5).wait() {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::WriteZero => {},
+            other => panic!("expected WriteZero, got {:?}", other.map(|(rsp, _)| rsp)),
+        }
+    }
+
+    #[test]
+    fn rejected_append_keeps_client() {
+        let body = &b"hello"[..];
+        let mut client = client(FaultSchedule::default());
+        client.state.set_state(State::NotAuthenticated);
+        let mut client = match client.try_append("INBOX", &[], None, body, 5) {
+            Err((Error::BadState { .. }, client)) => client,
+            Err((e, _)) => panic!("expected BadState, got {:?}", e),
+            Ok(_) => panic!("APPEND must not be sent before logging in"),
+        };
+        client.state.set_state(State::Authenticated);
+        let messages = vec![AppendMessage::new(body, 5)];
+        let client = match client.try_multi_append("INBOX", messages) {
+            Err((Error::MissingCapability(_), client)) => client,
+            Err((e, _)) => panic!("expected MissingCapability, got {:?}", e),
+            Ok(_) => panic!("MULTIAPPEND was not advertised"),
+        };
+        assert!(client.transport.get_ref().get_ref().output.is_empty());
+    }
+}
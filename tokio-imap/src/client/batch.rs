@@ -0,0 +1,103 @@
+use futures::{Async, Future, Poll, Stream};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use imap_proto::builders::command::{Command, CommandBuilder};
+use imap_proto::SequenceSet;
+use imap_proto::types::StoreType;
+
+use super::{Client, Pipeline, PipelineResponses};
+use error::Error;
+use proto::ResponseData;
+use store::FlagChanges;
+
+/// Collects the commands for `Client::batch()`.
+#[derive(Default)]
+pub struct Batch {
+    commands: Vec<Command>,
+}
+
+impl Batch {
+    pub fn call(&mut self, cmd: Command) -> &mut Self {
+        self.commands.push(cmd);
+        self
+    }
+
+    pub fn copy(&mut self, set: &SequenceSet, mailbox: &str) -> &mut Self {
+        self.call(CommandBuilder::copy(set, mailbox))
+    }
+
+    pub fn store(&mut self, set: &SequenceSet, kind: StoreType, flags: &[&str]) -> &mut Self {
+        self.call(CommandBuilder::store(set, kind, flags))
+    }
+
+    pub fn uid_copy(&mut self, set: &SequenceSet, mailbox: &str) -> &mut Self {
+        self.call(CommandBuilder::uid_copy(set, mailbox))
+    }
+
+    pub fn uid_store(&mut self, set: &SequenceSet, kind: StoreType, flags: &[&str]) -> &mut Self {
+        self.call(CommandBuilder::uid_store(set, kind, flags))
+    }
+
+    /// Adds the `UID STORE` commands that make `changes`.
+    pub fn store_changes(&mut self, changes: &FlagChanges) -> &mut Self {
+        self.commands.extend(changes.commands());
+        self
+    }
+}
+
+/// Sends a batch of commands, created by `Client::batch()`.
+///
+/// Resolves to the client and, for each command in the order they were
+/// added, either the responses belonging to it (ending with its tagged
+/// response) or the error that kept it from being sent.
+pub struct BatchFuture<T> {
+    pipeline: Pipeline<T>,
+    responses: Vec<PipelineResponses>,
+}
+
+impl<T> BatchFuture<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    pub(crate) fn new(client: Client<T>, batch: Batch) -> Self {
+        let (pipeline, handle) = client.pipeline();
+        let responses = batch
+            .commands
+            .into_iter()
+            .map(|cmd| handle.call(cmd))
+            .collect();
+        // Dropping the handle lets the pipeline resolve once all commands
+        // have completed.
+        Self {
+            pipeline,
+            responses,
+        }
+    }
+}
+
+// Takes the responses already delivered for a completed command.
+fn drain(responses: &mut PipelineResponses) -> Result<Vec<ResponseData>, Error> {
+    let mut collected = Vec::new();
+    loop {
+        match responses.poll()? {
+            Async::Ready(Some(rsp)) => collected.push(rsp),
+            Async::Ready(None) => return Ok(collected),
+            Async::NotReady => return Err(Error::ConnectionClosed),
+        }
+    }
+}
+
+impl<T> Future for BatchFuture<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    type Item = (Client<T>, Vec<Result<Vec<ResponseData>, Error>>);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let client = try_ready!(self.pipeline.poll());
+        let results = self.responses.iter_mut().map(drain).collect();
+        Ok(Async::Ready((client, results)))
+    }
+}
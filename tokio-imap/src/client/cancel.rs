@@ -0,0 +1,68 @@
+use futures::{Async, Future};
+use futures::sync::oneshot;
+
+use clock::Sleep;
+use error::Error;
+
+/// Aborts the command of a `ResponseStream`, created by
+/// `ResponseStream::cancel_handle()`.
+///
+/// Dropping the handle without calling `cancel()` has no effect.
+pub struct CancelHandle {
+    sender: oneshot::Sender<()>,
+}
+
+impl CancelHandle {
+    /// Ends the stream of the command as soon as it is polled, handing back
+    /// the client. Nothing happens if the command already completed.
+    pub fn cancel(self) {
+        let _ = self.sender.send(());
+    }
+}
+
+// What may abort a command before it completes.
+#[derive(Default)]
+pub(crate) struct Abort {
+    deadline: Option<Sleep>,
+    cancel: Option<oneshot::Receiver<()>>,
+}
+
+impl Abort {
+    pub(crate) fn set_deadline(&mut self, deadline: Sleep) {
+        self.deadline = Some(deadline);
+    }
+
+    pub(crate) fn handle(&mut self) -> CancelHandle {
+        let (sender, receiver) = oneshot::channel();
+        self.cancel = Some(receiver);
+        CancelHandle { sender }
+    }
+
+    pub(crate) fn timed_out(&mut self) -> Result<bool, Error> {
+        match self.deadline {
+            Some(ref mut deadline) => Ok(deadline.poll()?.is_ready()),
+            None => Ok(false),
+        }
+    }
+
+    // Whether the command timed out or was cancelled, registering the
+    // current task to be woken when either happens.
+    pub(crate) fn requested(&mut self) -> Result<bool, Error> {
+        if self.timed_out()? {
+            return Ok(true);
+        }
+        let cancelled = match self.cancel {
+            Some(ref mut cancel) => match cancel.poll() {
+                Ok(Async::Ready(())) => true,
+                Ok(Async::NotReady) => false,
+                // The handle was dropped.
+                Err(_) => {
+                    self.cancel = None;
+                    false
+                },
+            },
+            None => false,
+        };
+        Ok(cancelled)
+    }
+}
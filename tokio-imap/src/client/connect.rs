@@ -0,0 +1,490 @@
+use futures::{Async, Future, Poll, Stream};
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_codec::{Decoder, Framed};
+
+use imap_proto::{Response, ResponseCode, State, Status};
+
+use super::{Client, ClientState, TlsClient};
+use super::proxy::{Proxy, ProxyHandshake};
+use super::schedule::Schedule;
+use clock::{Clock, Sleep, SystemClock};
+use error::Error;
+use proto::{ImapCodec, ResponseData};
+use timeout::TimeoutStream;
+use tls::{self, TlsBackend, TlsConnector, TlsStream};
+
+// Delay before trying the next address while earlier attempts are pending,
+// as recommended by RFC 8305 (Happy Eyeballs).
+const CONNECTION_ATTEMPT_DELAY: u64 = 250;
+
+/// Configures how a `TlsClient` connects to a server.
+///
+/// ```ignore
+/// let connect = ClientBuilder::new("imap.example.com")
+///     .port(1993)
+///     .connect_timeout(Duration::from_secs(10))
+///     .connect()?;
+/// ```
+pub struct ClientBuilder {
+    server: String,
+    port: u16,
+    backend: TlsBackend,
+    connector: Option<TlsConnector>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    proxy: Option<Proxy>,
+    schedule: Option<Schedule>,
+    clock: Arc<Clock>,
+}
+
+impl ClientBuilder {
+    pub fn new(server: &str) -> Self {
+        Self {
+            server: server.to_string(),
+            port: 993,
+            backend: TlsBackend::default(),
+            connector: None,
+            connect_timeout: None,
+            read_timeout: None,
+            proxy: None,
+            schedule: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Selects the TLS implementation, using its default configuration.
+    pub fn tls_backend(mut self, backend: TlsBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Uses a pre-configured connector, for example to add root certificates
+    /// or client certificates.
+    pub fn tls_connector(mut self, connector: TlsConnector) -> Self {
+        self.connector = Some(connector);
+        self
+    }
+
+    /// Limits the time to establish the connection, including the TLS
+    /// handshake and the server greeting.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Fails reads when the server does not send any data for `timeout`.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Connects through `proxy` instead of directly.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Limits how fast the client sends commands; see
+    /// `Client::set_schedule()`.
+    pub fn schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
+    /// Measures the timeouts and the delays between connection attempts with
+    /// `clock`.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Resolves the server's addresses and starts connecting.
+    ///
+    /// All addresses are tried, alternating between address families, with
+    /// a new attempt started every 250 milliseconds while earlier ones are
+    /// still pending, and right away when one fails. The first connection to
+    /// succeed is used. With a proxy, the proxy's addresses are tried
+    /// instead, and the server's name is left to the proxy to resolve.
+    pub fn connect(self) -> Result<ImapConnectFuture, Error> {
+        let (host, port) = match self.proxy {
+            Some(ref proxy) => (proxy.host(), proxy.port()),
+            None => (&self.server[..], self.port),
+        };
+        let addrs = (host, port).to_socket_addrs()?.collect();
+        let addrs = interleave(addrs);
+        if addrs.is_empty() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::Other,
+                format!("no IP addresses found for {}", host),
+            )));
+        }
+        let connector = match self.connector {
+            Some(connector) => connector,
+            None => TlsConnector::new(self.backend)?,
+        };
+        let clock = self.clock;
+        Ok(ImapConnectFuture {
+            state: ConnectState::TcpConnecting(HappyEyeballs::new(addrs, clock.clone())),
+            domain: self.server,
+            port: self.port,
+            proxy: self.proxy,
+            connector,
+            read_timeout: self.read_timeout,
+            schedule: self.schedule,
+            deadline: self.connect_timeout
+                .map(|timeout| clock.delay_for(timeout)),
+            clock,
+        })
+    }
+}
+
+// Orders addresses so that address families alternate, starting with the
+// family of the first address.
+fn interleave(addrs: Vec<SocketAddr>) -> VecDeque<SocketAddr> {
+    let first_v6 = addrs.first().map(|addr| addr.is_ipv6()).unwrap_or(false);
+    let (mut first, mut second): (VecDeque<_>, VecDeque<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let mut ordered = VecDeque::new();
+    loop {
+        match (first.pop_front(), second.pop_front()) {
+            (None, None) => return ordered,
+            (a, b) => {
+                ordered.extend(a);
+                ordered.extend(b);
+            },
+        }
+    }
+}
+
+type Attempt = Box<Future<Item = TcpStream, Error = io::Error> + Send>;
+
+struct HappyEyeballs {
+    addrs: VecDeque<SocketAddr>,
+    attempts: Vec<Attempt>,
+    next_attempt: Option<Sleep>,
+    error: Option<io::Error>,
+    clock: Arc<Clock>,
+    // Starts a connection attempt; replaced in tests.
+    connect: fn(&SocketAddr) -> Attempt,
+}
+
+impl HappyEyeballs {
+    fn new(addrs: VecDeque<SocketAddr>, clock: Arc<Clock>) -> Self {
+        Self {
+            addrs,
+            attempts: Vec::new(),
+            next_attempt: None,
+            error: None,
+            clock,
+            connect: |addr| Box::new(TcpStream::connect(addr)),
+        }
+    }
+
+    fn start_next(&mut self) {
+        if let Some(addr) = self.addrs.pop_front() {
+            debug!("connecting to {}", addr);
+            self.attempts.push((self.connect)(&addr));
+            let delay = Duration::from_millis(CONNECTION_ATTEMPT_DELAY);
+            self.next_attempt = Some(self.clock.delay_for(delay));
+        }
+    }
+}
+
+impl Future for HappyEyeballs {
+    type Item = TcpStream;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<TcpStream, io::Error> {
+        loop {
+            if self.attempts.is_empty() {
+                self.start_next();
+            }
+            let mut i = 0;
+            while i < self.attempts.len() {
+                match self.attempts[i].poll() {
+                    Ok(Async::Ready(stream)) => return Ok(Async::Ready(stream)),
+                    Ok(Async::NotReady) => i += 1,
+                    Err(e) => {
+                        debug!("connection attempt failed: {}", e);
+                        drop(self.attempts.remove(i));
+                        self.error = Some(e);
+                        // Don't wait for the delay to try the next address
+                        // (RFC 8305, section 5). It is polled in this loop.
+                        self.start_next();
+                    },
+                }
+            }
+            if self.attempts.is_empty() {
+                if self.addrs.is_empty() {
+                    return Err(self.error.take().unwrap_or_else(|| {
+                        io::Error::new(io::ErrorKind::Other, "no addresses to connect to")
+                    }));
+                }
+                continue;
+            }
+            let ready = match self.next_attempt {
+                Some(ref mut delay) if !self.addrs.is_empty() => delay.poll()?.is_ready(),
+                _ => false,
+            };
+            if !ready {
+                return Ok(Async::NotReady);
+            }
+            self.start_next();
+        }
+    }
+}
+
+/// Connects to a server, resolving to its greeting and a `TlsClient`.
+pub struct ImapConnectFuture {
+    state: ConnectState,
+    domain: String,
+    port: u16,
+    proxy: Option<Proxy>,
+    connector: TlsConnector,
+    read_timeout: Option<Duration>,
+    schedule: Option<Schedule>,
+    deadline: Option<Sleep>,
+    clock: Arc<Clock>,
+}
+
+enum ConnectState {
+    TcpConnecting(HappyEyeballs),
+    ProxyHandshake(ProxyHandshake),
+    TlsHandshake(tls::Handshake),
+    ServerGreeting(GreetingFuture<TlsStream>),
+    Done,
+}
+
+impl ImapConnectFuture {
+    fn tls_handshake(&self, stream: TcpStream) -> ConnectState {
+        let stream = TimeoutStream::with_clock(stream, self.read_timeout, self.clock.clone());
+        ConnectState::TlsHandshake(self.connector.handshake(&self.domain, stream))
+    }
+
+    fn poll_connect(&mut self) -> Poll<(Greeting, TlsClient), Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, ConnectState::Done) {
+                ConnectState::TcpConnecting(mut future) => match future.poll()? {
+                    Async::Ready(stream) => match self.proxy.take() {
+                        Some(proxy) => ConnectState::ProxyHandshake(proxy.handshake(
+                            stream,
+                            &self.domain,
+                            self.port,
+                        )),
+                        None => self.tls_handshake(stream),
+                    },
+                    Async::NotReady => {
+                        self.state = ConnectState::TcpConnecting(future);
+                        return Ok(Async::NotReady);
+                    },
+                },
+                ConnectState::ProxyHandshake(mut future) => match future.poll()? {
+                    Async::Ready(stream) => self.tls_handshake(stream),
+                    Async::NotReady => {
+                        self.state = ConnectState::ProxyHandshake(future);
+                        return Ok(Async::NotReady);
+                    },
+                },
+                ConnectState::TlsHandshake(mut future) => match future.poll()? {
+                    Async::Ready(stream) => {
+                        ConnectState::ServerGreeting(GreetingFuture::new(stream))
+                    },
+                    Async::NotReady => {
+                        self.state = ConnectState::TlsHandshake(future);
+                        return Ok(Async::NotReady);
+                    },
+                },
+                ConnectState::ServerGreeting(mut future) => match future.poll()? {
+                    Async::Ready((greeting, mut client)) => {
+                        client.state.clock = self.clock.clone();
+                        client.set_schedule(self.schedule);
+                        return Ok(Async::Ready((greeting, client)));
+                    },
+                    Async::NotReady => {
+                        self.state = ConnectState::ServerGreeting(future);
+                        return Ok(Async::NotReady);
+                    },
+                },
+                ConnectState::Done => panic!("ImapConnectFuture polled after completion"),
+            };
+        }
+    }
+}
+
+impl Future for ImapConnectFuture {
+    type Item = (Greeting, TlsClient);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(done) = self.poll_connect()? {
+            return Ok(Async::Ready(done));
+        }
+        if let Some(ref mut deadline) = self.deadline {
+            if deadline.poll()?.is_ready() {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "connection timed out",
+                )));
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+/// The server's greeting on a new connection.
+#[derive(Debug)]
+pub struct Greeting {
+    /// Whether the server greeted with `PREAUTH`: the connection is already
+    /// authenticated, and logging in is neither needed nor allowed.
+    pub preauth: bool,
+    /// The capabilities from a `[CAPABILITY ...]` response code, which the
+    /// client also takes as its known capabilities.
+    pub capabilities: Option<Vec<String>>,
+    pub text: Option<String>,
+    response: ResponseData,
+}
+
+impl Greeting {
+    // Fails with `Error::Bye` if the server refused the connection.
+    fn from_response(response: ResponseData) -> Result<Self, Error> {
+        let (preauth, capabilities, text) = match *response.parsed() {
+            Response::Data {
+                status: Status::Bye,
+                information,
+                ..
+            } => return Err(Error::Bye(information.unwrap_or("").to_string())),
+            Response::Data {
+                ref status,
+                ref code,
+                information,
+            } => {
+                let capabilities = match *code {
+                    Some(ResponseCode::Capabilities(ref caps)) => {
+                        Some(caps.iter().map(|cap| cap.to_string()).collect())
+                    },
+                    _ => None,
+                };
+                let text = information.map(|text| text.to_string());
+                (*status == Status::PreAuth, capabilities, text)
+            },
+            _ => (false, None, None),
+        };
+        Ok(Self {
+            preauth,
+            capabilities,
+            text,
+            response,
+        })
+    }
+
+    /// The greeting as it was received.
+    pub fn response(&self) -> &ResponseData {
+        &self.response
+    }
+}
+
+/// Waits for the server greeting on a new connection, resolving to it and
+/// a `Client`. A `BYE` greeting fails with `Error::Bye`.
+pub struct GreetingFuture<T> {
+    transport: Option<Framed<T, ImapCodec>>,
+}
+
+impl<T> GreetingFuture<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    pub(crate) fn new(stream: T) -> Self {
+        Self {
+            transport: Some(ImapCodec::default().framed(stream)),
+        }
+    }
+}
+
+impl<T> Future for GreetingFuture<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    type Item = (Greeting, Client<T>);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let polled = self.transport.as_mut().unwrap().poll()?; // safe: only taken here
+        match polled {
+            Async::Ready(Some(msg)) => {
+                let mut state = ClientState::new();
+                state.observe(&msg);
+                let greeting = Greeting::from_response(msg)?;
+                if greeting.preauth {
+                    state.set_state(State::Authenticated);
+                }
+                let client = Client {
+                    transport: self.transport.take().unwrap(),
+                    state,
+                };
+                Ok(Async::Ready((greeting, client)))
+            },
+            Async::Ready(None) => Err(Error::ConnectionClosed),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    use clock::ManualClock;
+
+    // Port 1 never answers and port 2 refuses connections.
+    fn scripted(addr: &SocketAddr) -> Attempt {
+        match addr.port() {
+            1 => Box::new(future::empty()),
+            _ => Box::new(future::err(io::ErrorKind::ConnectionRefused.into())),
+        }
+    }
+
+    #[test]
+    fn next_attempt_after_failure() {
+        let addrs = [1, 2, 1, 2]
+            .iter()
+            .map(|&port| SocketAddr::from(([127, 0, 0, 1], port)))
+            .collect();
+        let clock = ManualClock::new();
+        let mut eyeballs = HappyEyeballs::new(addrs, Arc::new(clock.clone()));
+        eyeballs.connect = scripted;
+        let checked = future::lazy(|| {
+            assert!(eyeballs.poll().unwrap().is_not_ready());
+            assert_eq!(eyeballs.attempts.len(), 1);
+            assert_eq!(eyeballs.addrs.len(), 3);
+            // The second attempt fails, and the third starts without waiting
+            // for another delay.
+            clock.advance(Duration::from_millis(CONNECTION_ATTEMPT_DELAY));
+            assert!(eyeballs.poll().unwrap().is_not_ready());
+            assert_eq!(eyeballs.attempts.len(), 2);
+            assert_eq!(eyeballs.addrs.len(), 1);
+            // The last one fails as well, leaving the first two pending.
+            clock.advance(Duration::from_millis(CONNECTION_ATTEMPT_DELAY));
+            assert!(eyeballs.poll().unwrap().is_not_ready());
+            assert_eq!(eyeballs.attempts.len(), 2);
+            assert!(eyeballs.addrs.is_empty());
+            Ok::<(), ()>(())
+        });
+        checked.wait().unwrap();
+    }
+}
@@ -1,26 +1,66 @@
-use futures::{Async, Future, Poll, Sink};
+use futures::{future, Async, AsyncSink, Future, Poll, Sink};
 use futures::stream::Stream;
-use futures::sink::Send;
+use futures::sync::mpsc;
 use futures_state_stream::{StateStream, StreamEvent};
 
-use native_tls::TlsConnector;
-
+use std::collections::VecDeque;
 use std::io;
-use std::net::ToSocketAddrs;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_codec::{Decoder, Framed};
 
-use tokio::net::{ConnectFuture, TcpStream};
-use tokio_codec::Decoder;
-use tokio_tls::{ConnectAsync, TlsConnectorExt};
+use imap_proto::{MailboxDatum, Request, RequestId, Response, ResponseCode, State, Status};
+use imap_proto::builders::command::{Command, CommandBuilder};
+use clock::{Clock, SystemClock};
+use compress::DeflateStream;
+use diagnostics::Warning;
+use error::{Error, Limit};
+use events::{ConnectionClosed, Event, EventLog};
+use notify::Notifications;
+use proto::{ImapCodec, ImapTransport, ResponseData};
+use tls::{TlsBackend, TlsStream};
+use watch::{MailboxWatch, WatchHandle};
 
-use imap_proto::{Request, RequestId, State};
-use imap_proto::builders::command::Command;
-use proto::{ImapCodec, ImapTls, ImapTransport, ResponseData};
+mod append;
+pub use self::append::{AppendFuture, AppendMessage, AppendProgress};
+mod batch;
+pub use self::batch::{Batch, BatchFuture};
+mod cancel;
+pub use self::cancel::CancelHandle;
+use self::cancel::Abort;
+mod connect;
+pub use self::connect::{ClientBuilder, Greeting, GreetingFuture, ImapConnectFuture};
+mod outcome;
+pub use self::outcome::{CollectOutcome, CommandOutcome};
+mod pipeline;
+pub use self::pipeline::{Cork, Pipeline, PipelineHandle, PipelineResponses};
+mod proxy;
+pub use self::proxy::Proxy;
+mod role;
+pub use self::role::Role;
+mod routing;
+pub use self::routing::FetchRouting;
+use self::routing::FetchScope;
+mod schedule;
+pub use self::schedule::Schedule;
+use self::schedule::Scheduler;
+mod throttle;
+pub use self::throttle::{Throttle, ThrottleState};
+mod unsolicited;
+pub use self::unsolicited::UnsolicitedResponses;
+use self::unsolicited::UnsolicitedSender;
 
 pub mod builder {
     pub use imap_proto::builders::command::{CommandBuilder, FetchBuilderAttributes,
                                             FetchBuilderMessages, FetchBuilderModifiers,
                                             FetchCommand, FetchCommandAttributes,
                                             FetchCommandMessages};
+    pub use imap_proto::builders::notify::{EventGroup, NotifyEvent, NotifyFilter};
+    pub use imap_proto::builders::search::{SearchCriteria, SearchDate};
 }
 
 pub trait ImapClient {
@@ -28,77 +68,619 @@ pub trait ImapClient {
     fn into_parts(self) -> (Self::Transport, ClientState);
     fn rebuild(transport: Self::Transport, state: ClientState) -> Self;
 
+    /// Sends `cmd`, returning a stream of the responses to it that ends with
+    /// the client once the command completes.
+    ///
+    /// A command that is not valid in the current state, not allowed on a
+    /// monitor connection, or has to wait for `Client::resync()`, is not
+    /// sent; the stream fails right away and the client is dropped. Use
+    /// `try_call()` to get the client back in that case.
     fn call(self, cmd: Command) -> ResponseStream<Self>
+    where
+        Self: ImapClient + Sized, {
+        match self.try_call(cmd) {
+            Ok(stream) => stream,
+            Err((e, _)) => ResponseStream::failed(e),
+        }
+    }
+
+    /// Sends `cmd` like `call()`, but hands the client back together with
+    /// the error if the command cannot be sent in the current state.
+    fn try_call(self, cmd: Command) -> Result<ResponseStream<Self>, (Error, Self)>
     where
         Self: ImapClient + Sized, {
         let (transport, mut state) = self.into_parts();
+        let selecting = match cmd.next_state() {
+            Some(State::Selected) => Some(cmd.clone()),
+            _ => None,
+        };
+        let (cmd_bytes, next_state) = state.command_parts(cmd);
+        let verb = command_verb(&cmd_bytes);
+        if let Err(e) = state.check_state(&verb) {
+            return Err((e, Self::rebuild(transport, state)));
+        }
+        state.selecting = selecting.map(|cmd| (cmd, None));
         let request_id = state.request_ids.next().unwrap(); // safe: never returns Err
-        let (cmd_bytes, next_state) = cmd.into_parts();
-        let future = transport.send(Request(request_id.clone(), cmd_bytes));
-        ResponseStream::new(future, state, request_id, next_state)
+        state.started(&request_id, &verb, &cmd_bytes);
+        let scope = FetchScope::of(&verb, &cmd_bytes);
+        let request = Request(request_id, cmd_bytes);
+        let mut stream = ResponseStream::new(transport, request, state, next_state);
+        stream.verb = verb;
+        stream.scope = scope;
+        Ok(stream)
+    }
+
+    /// Sends `cmd` like `call()`, aborting it if it does not complete within
+    /// `timeout`; see `ResponseStream::timeout()`.
+    fn call_with_timeout(self, cmd: Command, timeout: Duration) -> ResponseStream<Self>
+    where
+        Self: ImapClient + Sized, {
+        self.call(cmd).timeout(timeout)
     }
 }
 
-pub struct TlsClient {
-    transport: ImapTls,
+pub struct Client<T> {
+    transport: Framed<T, ImapCodec>,
     state: ClientState,
 }
 
+pub type TlsClient = Client<TlsStream>;
+
 impl TlsClient {
-    pub fn connect(server: &str) -> io::Result<ImapConnectFuture> {
-        let addr = (server, 993).to_socket_addrs()?.next().ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("no IP addresses found for {}", server),
-            )
-        })?;
-        Ok(ImapConnectFuture::TcpConnecting(
-            TcpStream::connect(&addr),
-            server.to_string(),
-        ))
+    pub fn connect(server: &str) -> Result<ImapConnectFuture, Error> {
+        Self::connect_with(server, TlsBackend::default())
+    }
+
+    /// Connects using a specific TLS implementation.
+    pub fn connect_with(server: &str, backend: TlsBackend) -> Result<ImapConnectFuture, Error> {
+        ClientBuilder::new(server).tls_backend(backend).connect()
+    }
+}
+
+pub type InsecureConnectFuture =
+    Box<Future<Item = (Greeting, Client<TcpStream>), Error = Error> + Send>;
+
+impl Client<TcpStream> {
+    /// Connects without TLS, for example to a test server on localhost.
+    ///
+    /// Everything, including passwords, is sent in the clear, so this must
+    /// not be used over networks that are not trusted.
+    pub fn connect_insecure(addr: &SocketAddr) -> InsecureConnectFuture {
+        Box::new(
+            TcpStream::connect(addr)
+                .map_err(Error::Io)
+                .and_then(Client::from_stream),
+        )
+    }
+}
+
+impl<T> Client<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    /// Starts a client on an established connection, such as a Unix domain
+    /// socket or an in-memory stream in tests, by waiting for the server
+    /// greeting. Any TLS must already have been set up on `stream`.
+    pub fn from_stream(stream: T) -> GreetingFuture<T> {
+        GreetingFuture::new(stream)
+    }
+
+    pub fn codec(&self) -> &ImapCodec {
+        self.transport.codec()
+    }
+
+    pub fn codec_mut(&mut self) -> &mut ImapCodec {
+        self.transport.codec_mut()
+    }
+
+    /// The state of the connection, such as `State::Selected` once a
+    /// mailbox is selected.
+    pub fn state(&self) -> State {
+        self.state.state()
+    }
+
+    /// The capabilities most recently advertised by the server.
+    ///
+    /// These are taken from the greeting and from any `CAPABILITY` response
+    /// or response code since; `None` if the server has not sent any yet, or
+    /// if they may have changed after login.
+    pub fn capabilities(&self) -> Option<&[String]> {
+        self.state.capabilities()
+    }
+
+    /// The extensions enabled with `ENABLE`.
+    pub fn enabled(&self) -> &[String] {
+        self.state.enabled()
+    }
+
+    /// Whether the server advertised `capability`, compared without regard
+    /// to case.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.state.has_capability(capability)
+    }
+
+    /// Whether UTF8=ACCEPT (RFC 6855) was enabled, so that mailbox names and
+    /// other strings are sent and received as UTF-8.
+    pub fn utf8_enabled(&self) -> bool {
+        self.state.utf8_enabled()
+    }
+
+    /// Fails with `Error::MissingCapability` if the server is known not to
+    /// support `capability`.
+    ///
+    /// Methods such as `compress()` make this check before sending anything
+    /// and drop the client if it fails; checking first keeps it.
+    pub fn require(&self, capability: &str) -> Result<(), Error> {
+        self.state.require(capability)
+    }
+
+    // The command that selected the current mailbox, and the mailbox's
+    // UIDVALIDITY, if the mailbox was selected with `call()`.
+    pub(crate) fn selected(&self) -> Option<(Command, Option<u32>)> {
+        self.state.selected.clone()
+    }
+
+    /// Routes unsolicited responses to the returned channel.
+    ///
+    /// Servers may send untagged responses such as `EXISTS`, `EXPUNGE` or
+    /// `BYE` at any time. Once this has been called, untagged responses that
+    /// do not belong to the command in flight are sent to the channel instead
+    /// of being yielded from the `ResponseStream`. Calling this again replaces
+    /// the previous channel; if the receiver is dropped, such responses are
+    /// yielded from the stream again.
+    pub fn unsolicited_responses(&mut self) -> UnsolicitedResponses {
+        let (tx, rx) = unsolicited::channel();
+        self.state.unsolicited = Some(tx);
+        rx
+    }
+
+    /// Limits the number of responses sent to `unsolicited_responses()`
+    /// that have not been received yet. A server that sends more fails the
+    /// command in flight with `Error::LimitExceeded`, ending the connection.
+    pub fn set_max_unsolicited(&mut self, max: Option<usize>) {
+        self.state.max_unsolicited = max;
+    }
+
+    pub fn max_unsolicited(&self) -> Option<usize> {
+        self.state.max_unsolicited
+    }
+
+    /// Yields the changes reported by the server after `NOTIFY`, in place of
+    /// `unsolicited_responses()`; see `notify`.
+    pub fn notifications(&mut self) -> Notifications {
+        let utf8 = self.state.utf8_enabled();
+        Notifications::new(self.unsolicited_responses(), utf8)
+    }
+
+    /// Reports recoverable protocol anomalies, such as unknown response
+    /// codes, to the returned channel; see `diagnostics`.
+    pub fn warnings(&mut self) -> mpsc::UnboundedReceiver<Warning> {
+        self.codec_mut().warnings()
+    }
+
+    /// A view of the selected mailbox's counters that can be shared with
+    /// other threads.
+    ///
+    /// The view is updated as responses are received, until the client is
+    /// dropped. All calls return views of the same state. A connection that
+    /// is only used for watching is best marked as `Role::Monitor`.
+    pub fn mailbox_watch(&mut self) -> MailboxWatch {
+        let handle = self.state
+            .watch
+            .get_or_insert_with(|| WatchHandle(MailboxWatch::new()));
+        handle.0.clone()
+    }
+
+    /// Marks the connection as interactive (the default) or as a read-only
+    /// monitor; see `Role`.
+    pub fn set_role(&mut self, role: Role) {
+        self.state.role = role;
+    }
+
+    pub fn role(&self) -> Role {
+        self.state.role
+    }
+
+    /// Sets how `FETCH` responses that may report changes made by other
+    /// clients are attributed; see `FetchRouting`.
+    pub fn set_fetch_routing(&mut self, routing: FetchRouting) {
+        self.state.fetch_routing = routing;
+    }
+
+    /// Starts recording the last `capacity` commands, responses and state
+    /// changes, to be retrieved with `recent_events()`, for example to
+    /// include them in bug reports. Recording again discards earlier events.
+    pub fn record_events(&mut self, capacity: usize) {
+        self.state.events = Some(EventLog::new(capacity));
+    }
+
+    /// The recorded events, oldest first; empty unless `record_events()`
+    /// was called.
+    pub fn recent_events(&self) -> Vec<Event> {
+        match self.state.events {
+            Some(ref events) => events.events(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Measures command timeouts and the `Schedule`, if any, with `clock`.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.state.clock = Arc::new(clock);
+        let schedule = self.schedule();
+        self.set_schedule(schedule);
+    }
+
+    /// Limits how fast commands are sent, holding them back until it is
+    /// their turn; `None` sends them right away.
+    ///
+    /// The limits apply to commands sent with `call()`, `append()` and
+    /// through a `Pipeline`.
+    pub fn set_schedule(&mut self, schedule: Option<Schedule>) {
+        let clock = self.state.clock.clone();
+        self.state.scheduler = schedule.map(|schedule| Scheduler::new(schedule, clock));
+    }
+
+    pub fn schedule(&self) -> Option<Schedule> {
+        self.state.scheduler.as_ref().map(|scheduler| scheduler.schedule())
+    }
+
+    /// Whether a command was aborted before it completed, so that the
+    /// connection must be resynchronized with `resync()` before it can be
+    /// used again.
+    pub fn needs_resync(&self) -> bool {
+        self.state.needs_resync()
+    }
+
+    /// Switches to pipelined mode, where commands do not have to wait for
+    /// earlier commands to complete.
+    ///
+    /// Returns the `Pipeline` driving the connection, which resolves to the
+    /// client again once all handles are dropped, and a handle to submit
+    /// commands through. The pipeline can also keep an idle connection alive
+    /// and report when it ends; see `Pipeline::keep_alive()`.
+    pub fn pipeline(self) -> (Pipeline<T>, PipelineHandle) {
+        Pipeline::new(self)
+    }
+
+    /// Sends the commands added by `build` together, flushing the connection
+    /// once, and collects the responses to each.
+    ///
+    /// Like with `pipeline()`, commands do not wait for earlier ones to
+    /// complete, so all of them must be valid in the current state; a batch
+    /// should not contain commands such as `SELECT` that change it.
+    ///
+    /// ```ignore
+    /// client.batch(|b| {
+    ///     b.uid_store(&SequenceSet::from(1..=4), StoreType::Add, &["\\Seen"]);
+    ///     b.uid_store(&SequenceSet::from(5), StoreType::Add, &["\\Flagged"]);
+    /// })
+    /// ```
+    pub fn batch<F>(self, build: F) -> BatchFuture<T>
+    where
+        F: FnOnce(&mut Batch), {
+        let mut batch = Batch::default();
+        build(&mut batch);
+        BatchFuture::new(self, batch)
+    }
+
+    /// Appends a message of `len` bytes, read from `body`, to `mailbox`.
+    ///
+    /// If the server advertised LITERAL+ in a `CAPABILITY` response, the
+    /// message data is sent right away; otherwise the client waits for the
+    /// server's continuation request first. Once UTF8=ACCEPT is enabled, the
+    /// message is sent in a UTF-8 literal, so it may have UTF-8 header
+    /// fields.
+    ///
+    /// The upload can be throttled and its progress followed; see
+    /// `AppendFuture::throttle()` and `AppendFuture::on_progress()`.
+    pub fn append<R>(
+        self, mailbox: &str, flags: &[&str], date: Option<&str>, body: R, len: u64
+    ) -> AppendFuture<T, R>
+    where
+        R: AsyncRead, {
+        match self.try_append(mailbox, flags, date, body, len) {
+            Ok(future) => future,
+            Err((e, client)) => AppendFuture::failed(client, e),
+        }
+    }
+
+    /// Like `append()`, but hands the client back together with the error if
+    /// the command cannot be sent in the current state.
+    pub fn try_append<R>(
+        self, mailbox: &str, flags: &[&str], date: Option<&str>, body: R, len: u64
+    ) -> Result<AppendFuture<T, R>, (Error, Self)>
+    where
+        R: AsyncRead, {
+        if let Err(e) = self.state.check_state("APPEND") {
+            return Err((e, self));
+        }
+        let literal_plus = self.state.has_capability("LITERAL+");
+        let utf8 = self.state.utf8_enabled();
+        let cmd = if utf8 {
+            CommandBuilder::append_utf8(mailbox, flags, date, len, literal_plus)
+        } else {
+            CommandBuilder::append(mailbox, flags, date, len, literal_plus)
+        };
+        Ok(AppendFuture::new(self, cmd, body, len, VecDeque::new(), utf8))
+    }
+
+    /// Appends `messages` to `mailbox` in a single `APPEND` command, which
+    /// requires the MULTIAPPEND capability (RFC 3502).
+    ///
+    /// The server adds either all of the messages or none of them. If it
+    /// supports UIDPLUS, the `APPENDUID` code of the tagged response holds
+    /// the UIDs of all messages. Progress is reported for the data of all
+    /// messages together.
+    pub fn multi_append<R>(
+        self, mailbox: &str, messages: Vec<AppendMessage<R>>
+    ) -> AppendFuture<T, R>
+    where
+        R: AsyncRead, {
+        match self.try_multi_append(mailbox, messages) {
+            Ok(future) => future,
+            Err((e, client)) => AppendFuture::failed(client, e),
+        }
+    }
+
+    /// Like `multi_append()`, but hands the client back together with the
+    /// error if the command cannot be sent, because of the current state, a
+    /// missing MULTIAPPEND capability or because `messages` is empty.
+    pub fn try_multi_append<R>(
+        self, mailbox: &str, messages: Vec<AppendMessage<R>>
+    ) -> Result<AppendFuture<T, R>, (Error, Self)>
+    where
+        R: AsyncRead, {
+        if let Err(e) = self.state.require("MULTIAPPEND") {
+            return Err((e, self));
+        }
+        if let Err(e) = self.state.check_state("APPEND") {
+            return Err((e, self));
+        }
+        let literal_plus = self.state.has_capability("LITERAL+");
+        let utf8 = self.state.utf8_enabled();
+        let mut messages = messages.into_iter();
+        let first = match messages.next() {
+            Some(first) => first,
+            None => {
+                let e = io::Error::new(io::ErrorKind::InvalidInput, "no messages to append");
+                return Err((Error::Io(e), self));
+            },
+        };
+        let next = messages
+            .map(|message| {
+                let flags: Vec<_> = message.flags.iter().map(|flag| &flag[..]).collect();
+                let date = message.date.as_ref().map(|date| &date[..]);
+                let args = if utf8 {
+                    CommandBuilder::append_next_utf8(&flags, date, message.len, literal_plus)
+                } else {
+                    CommandBuilder::append_next(&flags, date, message.len, literal_plus)
+                };
+                (args, message.body, message.len)
+            })
+            .collect();
+        let flags: Vec<_> = first.flags.iter().map(|flag| &flag[..]).collect();
+        let date = first.date.as_ref().map(|date| &date[..]);
+        let cmd = if utf8 {
+            CommandBuilder::append_utf8(mailbox, &flags, date, first.len, literal_plus)
+        } else {
+            CommandBuilder::append(mailbox, &flags, date, first.len, literal_plus)
+        };
+        Ok(AppendFuture::new(self, cmd, first.body, first.len, next, utf8))
+    }
+}
+
+impl<T> Client<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    /// Enables RFC 4978 compression for the rest of the connection.
+    ///
+    /// Issues `COMPRESS DEFLATE` and, if the server accepts it, wraps the
+    /// underlying stream in a `DeflateStream`. Any bytes the codec already
+    /// read past the tagged response are fed to the decompressor first.
+    pub fn compress(self) -> CompressFuture<T> {
+        if let Err(e) = self.state.require("COMPRESS=DEFLATE") {
+            return Box::new(future::err(e));
+        }
+        Box::new(
+            self.call(CommandBuilder::compress())
+                .collect()
+                .and_then(|(responses, client)| {
+                    Error::from_responses(&responses)?;
+                    let Client { transport, state } = client;
+                    let parts = transport.into_parts();
+                    let stream = DeflateStream::new(parts.io, parts.read_buf);
+                    Ok(Client {
+                        transport: parts.codec.framed(stream),
+                        state,
+                    })
+                }),
+        )
+    }
+
+    /// Resynchronizes the connection after a command was aborted, by sending
+    /// `NOOP` and discarding the responses until it completes. As commands
+    /// are answered in order, any responses still due for the aborted
+    /// command arrive first.
+    ///
+    /// If the server does not answer, for example because the aborted
+    /// command is still hanging, this does not complete either; it is best
+    /// given a timeout too, and the connection dropped if that expires. The
+    /// aborted command may have taken effect, including any change of state
+    /// such as a mailbox being selected, which the client does not track.
+    pub fn resync(self) -> ResyncFuture<T> {
+        Box::new(
+            self.call(CommandBuilder::noop())
+                .collect()
+                .and_then(|(responses, client)| {
+                    Error::from_responses(&responses)?;
+                    Ok(client)
+                }),
+        )
     }
 }
 
-impl ImapClient for TlsClient {
-    type Transport = ImapTls;
+pub type CompressFuture<T> =
+    Box<Future<Item = Client<DeflateStream<T>>, Error = Error> + Send>;
+
+pub type ResyncFuture<T> = Box<Future<Item = Client<T>, Error = Error> + Send>;
 
-    fn into_parts(self) -> (ImapTls, ClientState) {
+impl<T> ImapClient for Client<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    type Transport = Framed<T, ImapCodec>;
+
+    fn into_parts(self) -> (Framed<T, ImapCodec>, ClientState) {
         let Self { transport, state } = self;
         (transport, state)
     }
 
-    fn rebuild(transport: ImapTls, state: ClientState) -> TlsClient {
-        TlsClient { transport, state }
+    fn rebuild(transport: Framed<T, ImapCodec>, state: ClientState) -> Client<T> {
+        Client { transport, state }
     }
 }
 
 pub struct ResponseStream<E>
 where
     E: ImapClient, {
-    future: Option<Send<E::Transport>>,
     transport: Option<E::Transport>,
+    // The command, until it has been handed to the transport.
+    request: Option<Request>,
     state: Option<ClientState>,
     request_id: RequestId,
     next_state: Option<State>,
+    verb: String,
+    scope: Option<FetchScope>,
+    // Whether the scheduler, if any, has let the command be sent.
+    scheduled: bool,
     done: bool,
+    error: Option<Error>,
+    continuation: Option<ContinuationHandler>,
+    reply: Option<Request>,
+    abort: Abort,
 }
 
+type ContinuationHandler = Box<FnMut(&ResponseData) -> Vec<u8> + Send>;
+
 impl<E> ResponseStream<E>
 where
     E: ImapClient,
 {
     pub fn new(
-        future: Send<E::Transport>, state: ClientState, request_id: RequestId,
-        next_state: Option<State>,
+        transport: E::Transport, request: Request, state: ClientState, next_state: Option<State>
     ) -> Self {
         Self {
-            future: Some(future),
-            transport: None,
+            transport: Some(transport),
+            request_id: request.0.clone(),
+            request: Some(request),
             state: Some(state),
-            request_id,
             next_state,
+            verb: String::new(),
+            scope: None,
+            scheduled: false,
             done: false,
+            error: None,
+            continuation: None,
+            reply: None,
+            abort: Abort::default(),
+        }
+    }
+
+    /// Answers the server's continuation requests (`+`) with the line
+    /// returned by `handler`, which should not include the trailing CRLF.
+    ///
+    /// Continuation requests are passed to the handler instead of being
+    /// yielded from the stream. This is how multi-step commands such as
+    /// `AUTHENTICATE` are driven; `ResponseData::continuation_data()` gives
+    /// the decoded challenge.
+    pub fn on_continuation<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(&ResponseData) -> Vec<u8> + Send + 'static, {
+        self.continuation = Some(Box::new(handler));
+        self
+    }
+
+    /// Aborts the command if it has not completed within `timeout`, for
+    /// example because the server hangs.
+    ///
+    /// An aborted command's stream ends early, handing back the client,
+    /// which must then be resynchronized with `Client::resync()` or
+    /// dropped; `Client::needs_resync()` tells whether this happened. This
+    /// is so even if the command could not be written in time: whatever
+    /// part of it is still buffered is written before the next command.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        if let Some(ref state) = self.state {
+            self.abort.set_deadline(state.clock.delay_for(timeout));
         }
+        self
+    }
+
+    /// Returns a handle to abort the command from elsewhere, with the same
+    /// effect as a timeout.
+    pub fn cancel_handle(&mut self) -> CancelHandle {
+        self.abort.handle()
+    }
+
+    /// Collects the untagged responses to the command, resolving to them
+    /// together with the command's outcome and the client.
+    ///
+    /// Unlike with `collect()`, whether the command succeeded does not have
+    /// to be looked up in the last response.
+    pub fn collect_outcome(self) -> CollectOutcome<E> {
+        CollectOutcome::new(self)
+    }
+
+    // A stream that fails with `error` without sending anything.
+    fn failed(error: Error) -> Self {
+        Self {
+            transport: None,
+            request: None,
+            state: None,
+            request_id: RequestId(String::new()),
+            next_state: None,
+            verb: String::new(),
+            scope: None,
+            scheduled: false,
+            done: false,
+            error: Some(error),
+            continuation: None,
+            reply: None,
+            abort: Abort::default(),
+        }
+    }
+
+    // Waits until the scheduler, if any, lets the command be sent.
+    fn poll_schedule(&mut self) -> Poll<(), Error> {
+        if !self.scheduled {
+            let state = self.state.as_mut().unwrap(); // safe: initialized from start
+            if let Some(ref mut scheduler) = state.scheduler {
+                try_ready!(scheduler.poll_send());
+            }
+            self.scheduled = true;
+        }
+        Ok(Async::Ready(()))
+    }
+
+    // Ends the stream of a command that timed out or was cancelled, leaving
+    // the client to be resynchronized.
+    fn aborted(&mut self, transport: E::Transport) -> Poll<StreamEvent<ResponseData, E>, Error> {
+        let mut state = self.state.take().unwrap(); // safe: initialized from start
+        state.aborted = true;
+        // An aborted `SELECT` may or may not have taken effect.
+        state.selecting = None;
+        state.selected = None;
+        Ok(Async::Ready(StreamEvent::Done(E::rebuild(transport, state))))
+    }
+
+    // Ends the stream of a `LOGOUT` command whose connection was closed.
+    fn logged_out(
+        &mut self, transport: E::Transport
+    ) -> Poll<StreamEvent<ResponseData, E>, Error> {
+        let mut state = self.state.take().unwrap(); // safe: initialized from start
+        state.closed(&ConnectionClosed::Eof);
+        state.set_state(State::Logout);
+        Ok(Async::Ready(StreamEvent::Done(E::rebuild(transport, state))))
     }
 }
 
@@ -108,105 +690,136 @@ where
 {
     type Item = ResponseData;
     type State = E;
-    type Error = io::Error;
+    type Error = Error;
     fn poll(&mut self) -> Poll<StreamEvent<Self::Item, Self::State>, Self::Error> {
-        if let Some(mut future) = self.future.take() {
-            match future.poll() {
-                Ok(Async::Ready(transport)) => {
-                    self.transport = Some(transport);
-                },
-                Ok(Async::NotReady) => {
-                    self.future = Some(future);
-                    return Ok(Async::NotReady);
-                },
-                Err(e) => {
-                    return Err(e);
-                },
-            }
+        if let Some(e) = self.error.take() {
+            return Err(e);
         }
         let mut transport = match self.transport.take() {
             None => return Ok(Async::NotReady),
-            Some(mut transport) => transport,
+            Some(transport) => transport,
         };
         if self.done {
             let mut state = self.state.take().unwrap(); // safe: initialized from start
             if let Some(next_state) = self.next_state.take() {
-                state.state = next_state;
+                state.set_state(next_state);
             }
             return Ok(Async::Ready(StreamEvent::Done(E::rebuild(
                 transport,
                 state,
             ))));
         }
-        match transport.poll() {
-            Ok(Async::Ready(Some(rsp))) => {
-                if let Some(req_id) = rsp.request_id() {
-                    self.done = *req_id == self.request_id;
-                };
+        if let Some(request) = self.request.take() {
+            let sent = match self.poll_schedule()? {
+                Async::Ready(()) => transport.start_send(request)?,
+                Async::NotReady => AsyncSink::NotReady(request),
+            };
+            if let AsyncSink::NotReady(request) = sent {
+                if self.abort.requested()? {
+                    return self.aborted(transport);
+                }
+                self.request = Some(request);
                 self.transport = Some(transport);
-                return Ok(Async::Ready(StreamEvent::Next(rsp)));
-            },
-            Err(e) => {
-                return Err(e);
-            },
-            _ => (),
+                return Ok(Async::NotReady);
+            }
+        }
+        if self.abort.requested()? {
+            return self.aborted(transport);
+        }
+        if let Some(reply) = self.reply.take() {
+            if let AsyncSink::NotReady(reply) = transport.start_send(reply)? {
+                self.reply = Some(reply);
+                self.transport = Some(transport);
+                return Ok(Async::NotReady);
+            }
+        }
+        transport.poll_complete()?;
+        loop {
+            match transport.poll() {
+                Ok(Async::Ready(Some(rsp))) => {
+                    if let Some(ref mut handler) = self.continuation {
+                        if let Response::Continue { .. } = *rsp.parsed() {
+                            let reply = Request(RequestId(String::new()), handler(&rsp));
+                            if let AsyncSink::NotReady(reply) = transport.start_send(reply)? {
+                                self.reply = Some(reply);
+                                break;
+                            }
+                            transport.poll_complete()?;
+                            continue;
+                        }
+                    }
+                    let state = self.state.as_mut().unwrap(); // safe: initialized from start
+                    state.observe(&rsp);
+                    // Unsolicited responses are routed elsewhere; keep polling
+                    // until there is a response for the command in flight.
+                    let rsp = match state.route(&self.verb, self.scope.as_ref(), rsp)? {
+                        Some(rsp) => rsp,
+                        None => continue,
+                    };
+                    if let Some(req_id) = rsp.request_id() {
+                        self.done = *req_id == self.request_id;
+                    };
+                    if self.done {
+                        if !is_ok(&rsp) {
+                            self.next_state = None;
+                        }
+                        state.completed(&self.verb, &rsp);
+                    }
+                    self.transport = Some(transport);
+                    return Ok(Async::Ready(StreamEvent::Next(rsp)));
+                },
+                // Some servers close the connection right away on `LOGOUT`,
+                // without sending `BYE` or the tagged response.
+                Ok(Async::Ready(None)) if self.verb == "LOGOUT" => {
+                    return self.logged_out(transport);
+                },
+                Err(Error::Io(ref e)) if self.verb == "LOGOUT" && is_disconnect(e) => {
+                    return self.logged_out(transport);
+                },
+                Ok(Async::Ready(None)) => return Err(Error::ConnectionClosed),
+                Err(e) => {
+                    return Err(e);
+                },
+                Ok(Async::NotReady) => break,
+            }
         }
         self.transport = Some(transport);
         Ok(Async::NotReady)
     }
 }
 
-pub enum ImapConnectFuture {
-    #[doc(hidden)]
-    TcpConnecting(ConnectFuture, String),
-    #[doc(hidden)]
-    TlsHandshake(ConnectAsync<TcpStream>),
-    #[doc(hidden)]
-    ServerGreeting(Option<ImapTls>),
-}
-
-impl Future for ImapConnectFuture {
-    type Item = (ResponseData, TlsClient);
-    type Error = io::Error;
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        let mut new = None;
-        if let ImapConnectFuture::TcpConnecting(ref mut future, ref domain) = *self {
-            let stream = try_ready!(future.poll());
-            let ctx = TlsConnector::builder().unwrap().build().unwrap();
-            let future = ctx.connect_async(domain, stream);
-            new = Some(ImapConnectFuture::TlsHandshake(future));
-        }
-        if new.is_some() {
-            *self = new.take().unwrap();
-        }
-        if let ImapConnectFuture::TlsHandshake(ref mut future) = *self {
-            let transport = ImapCodec::default().framed(try_ready!(
-                future
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                    .poll()
-            ));
-            new = Some(ImapConnectFuture::ServerGreeting(Some(transport)));
-        }
-        if new.is_some() {
-            *self = new.take().unwrap();
-        }
-        if let ImapConnectFuture::ServerGreeting(ref mut wrapped) = *self {
-            let msg = try_ready!(wrapped.as_mut().unwrap().poll()).unwrap();
-            return Ok(Async::Ready((
-                msg,
-                TlsClient {
-                    transport: wrapped.take().unwrap(),
-                    state: ClientState::new(),
-                },
-            )));
-        }
-        Ok(Async::NotReady)
+fn is_disconnect(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::UnexpectedEof => true,
+        _ => false,
     }
 }
 
 pub struct ClientState {
     state: State,
     request_ids: IdGenerator,
+    capabilities: Option<Vec<String>>,
+    // Whether capabilities were received since the last command was sent.
+    capabilities_updated: bool,
+    enabled: Vec<String>,
+    unsolicited: Option<UnsolicitedSender>,
+    max_unsolicited: Option<usize>,
+    watch: Option<WatchHandle>,
+    events: Option<EventLog>,
+    fetch_routing: FetchRouting,
+    role: Role,
+    clock: Arc<Clock>,
+    scheduler: Option<Scheduler>,
+    // Whether a command was aborted, so that responses to it may still be
+    // pending.
+    aborted: bool,
+    // The `SELECT` or `EXAMINE` command in flight, if sent with `call()`,
+    // and the UIDVALIDITY reported for the mailbox so far.
+    selecting: Option<(Command, Option<u32>)>,
+    // The command that selected the current mailbox, and its UIDVALIDITY.
+    selected: Option<(Command, Option<u32>)>,
 }
 
 impl ClientState {
@@ -214,8 +827,338 @@ impl ClientState {
         Self {
             state: State::NotAuthenticated,
             request_ids: IdGenerator::new(),
+            capabilities: None,
+            capabilities_updated: false,
+            enabled: Vec::new(),
+            unsolicited: None,
+            max_unsolicited: None,
+            watch: None,
+            events: None,
+            fetch_routing: FetchRouting::default(),
+            role: Role::default(),
+            clock: Arc::new(SystemClock),
+            scheduler: None,
+            aborted: false,
+            selecting: None,
+            selected: None,
+        }
+    }
+
+    /// The capabilities most recently advertised by the server, or `None` if
+    /// they are not known (for example because they changed after login).
+    pub fn capabilities(&self) -> Option<&[String]> {
+        self.capabilities.as_ref().map(|caps| &caps[..])
+    }
+
+    /// The extensions enabled with `ENABLE`.
+    pub fn enabled(&self) -> &[String] {
+        &self.enabled
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        match self.capabilities {
+            Some(ref caps) => caps.iter().any(|cap| cap.eq_ignore_ascii_case(capability)),
+            None => false,
+        }
+    }
+
+    pub fn utf8_enabled(&self) -> bool {
+        self.enabled
+            .iter()
+            .any(|cap| cap.eq_ignore_ascii_case("UTF8=ACCEPT"))
+    }
+
+    // The arguments of `cmd`, with mailbox names in UTF-8 if UTF8=ACCEPT is
+    // enabled, and the state it leads to.
+    pub(crate) fn command_parts(&self, cmd: Command) -> (Vec<u8>, Option<State>) {
+        let (args, next_state) = if self.utf8_enabled() {
+            cmd.into_utf8_parts()
+        } else {
+            cmd.into_parts()
+        };
+        match self.role {
+            Role::Monitor => (role::examine_instead(args), next_state),
+            Role::Interactive => (args, next_state),
         }
     }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn needs_resync(&self) -> bool {
+        self.aborted
+    }
+
+    // Fails if a command of type `verb` is not valid in the current state,
+    // not allowed on a monitor connection, or has to wait for a resync.
+    pub(crate) fn check_state(&self, verb: &str) -> Result<(), Error> {
+        if self.aborted && verb != "NOOP" && verb != "LOGOUT" {
+            return Err(Error::Aborted);
+        }
+        if self.role == Role::Monitor && role::is_mutating(verb) {
+            return Err(Error::ReadOnly(verb.to_string()));
+        }
+        let required = match required_state(verb) {
+            Some(required) => required,
+            None => return Ok(()),
+        };
+        let valid = match (required, self.state) {
+            (State::Authenticated, State::Selected) => true,
+            (required, actual) => required == actual,
+        };
+        if !valid {
+            return Err(Error::BadState {
+                required,
+                actual: self.state,
+            });
+        }
+        Ok(())
+    }
+
+    // Fails if the server is known not to support `capability`. Commands are
+    // allowed while the capabilities are unknown, as the server may still
+    // support them.
+    pub(crate) fn require(&self, capability: &str) -> Result<(), Error> {
+        if self.capabilities.is_some() && !self.has_capability(capability) {
+            return Err(Error::MissingCapability(capability.to_string()));
+        }
+        Ok(())
+    }
+
+    // Updates the state before a command of type `verb` is sent.
+    pub(crate) fn started(&mut self, request_id: &RequestId, verb: &str, args: &[u8]) {
+        self.capabilities_updated = false;
+        if let Some(ref mut events) = self.events {
+            events.command_sent(&request_id.0, verb, args);
+        }
+        if let Some(ref watch) = self.watch {
+            if verb == "SELECT" || verb == "EXAMINE" {
+                watch.0.selecting();
+            }
+        }
+    }
+
+    pub(crate) fn set_state(&mut self, state: State) {
+        if let Some(ref mut events) = self.events {
+            events.state_changed(self.state, state);
+        }
+        self.state = state;
+    }
+
+    pub(crate) fn closed(&mut self, reason: &ConnectionClosed) {
+        if let Some(ref mut events) = self.events {
+            events.connection_closed(reason);
+        }
+    }
+
+    pub(crate) fn observe(&mut self, rsp: &ResponseData) {
+        if let Some(ref mut events) = self.events {
+            events.response_received(rsp);
+        }
+        if let Some(ref watch) = self.watch {
+            watch.0.observe(rsp);
+        }
+        if let Some((_, ref mut uid_validity)) = self.selecting {
+            if let Response::Data {
+                code: Some(ResponseCode::UidValidity(value)),
+                ..
+            } = *rsp.parsed()
+            {
+                *uid_validity = Some(value);
+            }
+        }
+        if let Response::Enabled(ref caps) = *rsp.parsed() {
+            self.enabled.extend(caps.iter().map(|cap| cap.to_string()));
+            return;
+        }
+        let caps = match *rsp.parsed() {
+            Response::Capabilities(ref caps) => caps,
+            Response::Data {
+                code: Some(ResponseCode::Capabilities(ref caps)),
+                ..
+            }
+            | Response::Done {
+                code: Some(ResponseCode::Capabilities(ref caps)),
+                ..
+            } => caps,
+            _ => return,
+        };
+        self.capabilities = Some(caps.iter().map(|cap| cap.to_string()).collect());
+        self.capabilities_updated = true;
+    }
+
+    // Updates the state after the tagged response `rsp` to a command of type
+    // `verb`. Servers may change their capabilities after authentication or
+    // STARTTLS; unless they sent the new list along, the old one is dropped
+    // so that it is not relied on.
+    pub(crate) fn completed(&mut self, verb: &str, rsp: &ResponseData) {
+        let changes_capabilities = match verb {
+            "AUTHENTICATE" | "LOGIN" | "STARTTLS" => true,
+            _ => false,
+        };
+        if changes_capabilities && is_ok(rsp) && !self.capabilities_updated {
+            self.capabilities = None;
+        }
+        // A completed `NOOP` follows all responses to an aborted command.
+        if verb == "NOOP" && is_ok(rsp) {
+            self.aborted = false;
+        }
+        // A failed SELECT or EXAMINE leaves no mailbox selected.
+        let deselects = match verb {
+            "CLOSE" | "UNSELECT" | "LOGOUT" => is_ok(rsp),
+            "SELECT" | "EXAMINE" => !is_ok(rsp),
+            _ => false,
+        };
+        if let Some(ref watch) = self.watch {
+            if deselects {
+                watch.0.reset();
+            }
+        }
+        match verb {
+            "SELECT" | "EXAMINE" => self.selected = self.selecting.take().filter(|_| !deselects),
+            _ if deselects => self.selected = None,
+            _ => {},
+        }
+        let reselects = verb == "SELECT" || verb == "EXAMINE";
+        if reselects && deselects && self.state == State::Selected {
+            self.set_state(State::Authenticated);
+        }
+    }
+
+    // Whether `rsp` is a response to a command of type `verb` that applies
+    // to the messages in `scope`.
+    fn belongs(&self, verb: &str, scope: Option<&FetchScope>, rsp: &Response) -> bool {
+        if !is_solicited(verb, rsp) {
+            return false;
+        }
+        match (self.fetch_routing, scope) {
+            (FetchRouting::ByMessageSet, Some(scope)) => scope.contains(rsp),
+            _ => true,
+        }
+    }
+
+    // Sends `rsp` to the unsolicited responses channel if it does not belong
+    // to a command of type `verb`, returning it otherwise. Fails if too many
+    // responses are queued on the channel.
+    fn route(
+        &mut self, verb: &str, scope: Option<&FetchScope>, rsp: ResponseData
+    ) -> Result<Option<ResponseData>, Error> {
+        if verb.is_empty() || self.belongs(verb, scope, rsp.parsed()) {
+            return Ok(Some(rsp));
+        }
+        let rsp = match self.unsolicited {
+            Some(ref tx) => {
+                if let Some(max) = self.max_unsolicited {
+                    if tx.queued() >= max {
+                        return Err(Error::LimitExceeded(Limit::UnsolicitedResponses(max)));
+                    }
+                }
+                match tx.send(rsp) {
+                    Ok(()) => return Ok(None),
+                    Err(rsp) => rsp,
+                }
+            },
+            None => return Ok(Some(rsp)),
+        };
+        self.unsolicited = None;
+        Ok(Some(rsp))
+    }
+}
+
+// Extracts the command name from the arguments to a command, including the
+// `UID` prefix where applicable.
+pub(crate) fn command_verb(args: &[u8]) -> String {
+    let mut words = args.split(|&b| b == b' ');
+    let mut verb = String::from_utf8_lossy(words.next().unwrap_or(b"")).to_uppercase();
+    if verb == "UID" {
+        verb.push(' ');
+        verb.push_str(&String::from_utf8_lossy(words.next().unwrap_or(b"")).to_uppercase());
+    }
+    verb
+}
+
+// The state a command of type `verb` requires, if any. Commands valid in
+// the authenticated state are also valid in the selected state.
+fn required_state(verb: &str) -> Option<State> {
+    if verb.starts_with("UID ") {
+        return Some(State::Selected);
+    }
+    match verb {
+        "AUTHENTICATE" | "LOGIN" | "STARTTLS" => Some(State::NotAuthenticated),
+        "APPEND" | "CREATE" | "DELETE" | "DELETEACL" | "ENABLE" | "EXAMINE" | "GETACL"
+        | "GETMETADATA" | "GETQUOTA" | "GETQUOTAROOT" | "IDLE" | "LIST" | "LISTRIGHTS" | "LSUB"
+        | "MYRIGHTS" | "NAMESPACE" | "NOTIFY" | "RENAME" | "SELECT" | "SETACL" | "SETMETADATA"
+        | "SETQUOTA" | "STATUS" | "SUBSCRIBE" | "UNSUBSCRIBE" => Some(State::Authenticated),
+        "CHECK" | "CLOSE" | "COPY" | "EXPUNGE" | "FETCH" | "MOVE" | "SEARCH" | "STORE"
+        | "UNSELECT" => Some(State::Selected),
+        _ => None,
+    }
+}
+
+// Whether `rsp` is a tagged response with status `OK`.
+fn is_ok(rsp: &ResponseData) -> bool {
+    match *rsp.parsed() {
+        Response::Done { ref status, .. } => *status == Status::Ok,
+        _ => false,
+    }
+}
+
+// Whether `rsp` is an expected response to a command of type `verb`.
+fn is_solicited(verb: &str, rsp: &Response) -> bool {
+    match *rsp {
+        Response::Done { .. } | Response::Continue { .. } | Response::Unknown { .. } => true,
+        Response::Acl { .. } => verb == "GETACL",
+        Response::Data {
+            status: Status::Bye,
+            ..
+        } => verb == "LOGOUT",
+        Response::Data {
+            code: Some(ResponseCode::CopyUid(..)),
+            ..
+        } => verb == "MOVE" || verb == "UID MOVE",
+        Response::Data { .. } => verb == "SELECT" || verb == "EXAMINE",
+        Response::Capabilities(_) => verb == "CAPABILITY",
+        Response::Enabled(_) => verb == "ENABLE",
+        Response::Expunge(_) => match verb {
+            "EXPUNGE" | "UID EXPUNGE" | "MOVE" | "UID MOVE" => true,
+            _ => false,
+        },
+        Response::Fetch(..) => match verb {
+            "FETCH" | "UID FETCH" | "STORE" | "UID STORE" => true,
+            _ => false,
+        },
+        Response::Id(_) => verb == "ID",
+        Response::ListRights { .. } => verb == "LISTRIGHTS",
+        Response::MyRights { .. } => verb == "MYRIGHTS",
+        Response::Metadata { .. } => verb == "GETMETADATA",
+        // Changes reported by NOTIFY.
+        Response::MetadataChanged { .. } => false,
+        Response::Namespace { .. } => verb == "NAMESPACE",
+        Response::Quota { .. } => match verb {
+            "GETQUOTA" | "GETQUOTAROOT" | "SETQUOTA" => true,
+            _ => false,
+        },
+        Response::QuotaRoot(_) => verb == "GETQUOTAROOT",
+        Response::IDs(_) | Response::ESearch { .. } => verb == "SEARCH" || verb == "UID SEARCH",
+        Response::Vanished { earlier: true, .. } => match verb {
+            "SELECT" | "EXAMINE" | "UID FETCH" => true,
+            _ => false,
+        },
+        Response::Vanished { earlier: false, .. } => match verb {
+            "EXPUNGE" | "UID EXPUNGE" | "MOVE" | "UID MOVE" => true,
+            _ => false,
+        },
+        Response::MailboxData(ref datum) => match *datum {
+            MailboxDatum::Exists(_) | MailboxDatum::Flags(_) | MailboxDatum::Recent(_) => {
+                verb == "SELECT" || verb == "EXAMINE"
+            },
+            MailboxDatum::List { .. } => verb == "LIST",
+            // LIST-STATUS (RFC 5819) returns STATUS responses for LIST.
+            MailboxDatum::Status { .. } => verb == "STATUS" || verb == "LIST",
+            MailboxDatum::SubList { .. } => verb == "LSUB",
+        },
+    }
 }
 
 impl Default for ClientState {
@@ -247,3 +1190,64 @@ impl Iterator for IdGenerator {
         Some(RequestId(format!("A{:04}", self.next % 10_000)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(line: &str) -> ResponseData {
+        ResponseData::parse(format!("{}\r\n", line).into_bytes(), None).unwrap()
+    }
+
+    #[test]
+    fn preconditions() {
+        let mut state = ClientState::new();
+        match state.check_state("SELECT") {
+            Err(Error::BadState { .. }) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        state.set_state(State::Selected);
+        state.check_state("SELECT").unwrap();
+        state.role = Role::Monitor;
+        match state.check_state("EXPUNGE") {
+            Err(Error::ReadOnly(ref verb)) => assert_eq!(verb, "EXPUNGE"),
+            other => panic!("unexpected {:?}", other),
+        }
+        state.aborted = true;
+        match state.check_state("FETCH") {
+            Err(Error::Aborted) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        state.check_state("NOOP").unwrap();
+    }
+
+    #[test]
+    fn failed_select_deselects() {
+        let mut state = ClientState::new();
+        state.set_state(State::Selected);
+        state.completed("SELECT", &response("A0001 OK [READ-WRITE] done"));
+        assert_eq!(state.state(), State::Selected);
+        state.completed("EXAMINE", &response("A0002 NO no such mailbox"));
+        assert_eq!(state.state(), State::Authenticated);
+        state.completed("SELECT", &response("A0003 NO no such mailbox"));
+        assert_eq!(state.state(), State::Authenticated);
+    }
+
+    #[test]
+    fn selected_mailbox() {
+        let uid_validity = |state: &ClientState| state.selected.as_ref().map(|&(_, v)| v);
+        let mut state = ClientState::new();
+        state.selecting = Some((CommandBuilder::select("INBOX"), None));
+        state.observe(&response("* OK [UIDVALIDITY 42] UIDs valid"));
+        state.completed("SELECT", &response("A0001 OK [READ-WRITE] done"));
+        assert_eq!(uid_validity(&state), Some(Some(42)));
+        // Selected without `call()`, as on a pipeline.
+        state.completed("EXAMINE", &response("A0002 OK [READ-ONLY] done"));
+        assert_eq!(uid_validity(&state), None);
+        state.selecting = Some((CommandBuilder::examine("Sent"), None));
+        state.completed("EXAMINE", &response("A0003 OK [READ-ONLY] done"));
+        assert_eq!(uid_validity(&state), Some(None));
+        state.completed("CLOSE", &response("A0004 OK closed"));
+        assert_eq!(uid_validity(&state), None);
+    }
+}
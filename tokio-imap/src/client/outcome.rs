@@ -0,0 +1,112 @@
+use futures::{Async, Future, Poll};
+use futures_state_stream::{StateStream, StreamEvent};
+
+use std::mem;
+use std::slice;
+
+use imap_proto::types::{Response, ResponseCode, Status};
+
+use super::{ImapClient, ResponseStream};
+use error::Error;
+use proto::ResponseData;
+
+/// How a command completed, as reported by its tagged response.
+#[derive(Debug)]
+pub struct CommandOutcome {
+    // `None` for a `LOGOUT` the server did not confirm before closing the
+    // connection.
+    response: Option<ResponseData>,
+}
+
+impl CommandOutcome {
+    /// `Ok`, `No` or `Bad`.
+    pub fn status(&self) -> &Status {
+        match self.response.as_ref().map(|rsp| rsp.parsed()) {
+            Some(&Response::Done { ref status, .. }) => status,
+            _ => &Status::Ok,
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        *self.status() == Status::Ok
+    }
+
+    pub fn code(&self) -> Option<&ResponseCode> {
+        match self.response.as_ref().map(|rsp| rsp.parsed()) {
+            Some(&Response::Done { ref code, .. }) => code.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The human-readable text of the tagged response.
+    pub fn text(&self) -> &str {
+        match self.response.as_ref().map(|rsp| rsp.parsed()) {
+            Some(&Response::Done { information, .. }) => information.unwrap_or(""),
+            _ => "",
+        }
+    }
+
+    /// The tagged response, unless the server closed the connection after
+    /// `LOGOUT` without sending it.
+    pub fn response(&self) -> Option<&ResponseData> {
+        self.response.as_ref()
+    }
+
+    /// Fails with `Error::No`, `Error::Bad` or `Error::Condition` if the
+    /// command failed.
+    pub fn into_result(self) -> Result<(), Error> {
+        match self.response {
+            Some(rsp) => Error::from_responses(slice::from_ref(&rsp)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Future returned by `ResponseStream::collect_outcome()`.
+pub struct CollectOutcome<E>
+where
+    E: ImapClient,
+{
+    stream: ResponseStream<E>,
+    responses: Vec<ResponseData>,
+    tagged: Option<ResponseData>,
+}
+
+impl<E> CollectOutcome<E>
+where
+    E: ImapClient,
+{
+    pub(crate) fn new(stream: ResponseStream<E>) -> Self {
+        Self {
+            stream,
+            responses: Vec::new(),
+            tagged: None,
+        }
+    }
+}
+
+impl<E> Future for CollectOutcome<E>
+where
+    E: ImapClient,
+{
+    type Item = (Vec<ResponseData>, CommandOutcome, E);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match try_ready!(self.stream.poll()) {
+                StreamEvent::Next(rsp) => match rsp.request_id() {
+                    Some(_) => self.tagged = Some(rsp),
+                    None => self.responses.push(rsp),
+                },
+                StreamEvent::Done(client) => {
+                    let outcome = CommandOutcome {
+                        response: self.tagged.take(),
+                    };
+                    let responses = mem::replace(&mut self.responses, Vec::new());
+                    return Ok(Async::Ready((responses, outcome, client)));
+                },
+            }
+        }
+    }
+}
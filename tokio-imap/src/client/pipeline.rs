@@ -0,0 +1,441 @@
+use futures::{Async, AsyncSink, Future, Poll, Sink, Stream};
+use futures::sync::{mpsc, oneshot};
+use futures::task;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use imap_proto::{Request, RequestId, Response, State, Status};
+use imap_proto::builders::command::{Command, CommandBuilder};
+
+use super::{command_verb, is_ok, Client};
+use super::routing::FetchScope;
+use super::schedule::has_literal;
+use clock::{Clock, Sleep, SystemClock};
+use error::Error;
+use events::ConnectionClosed;
+use proto::ResponseData;
+
+type ResponseSender = mpsc::UnboundedSender<Result<ResponseData, Error>>;
+
+enum Message {
+    Call(Command, ResponseSender),
+    // The last `Cork` was dropped.
+    Uncork,
+}
+
+/// Drives a pipelined connection, created by `Client::pipeline()`.
+///
+/// Commands submitted through a `PipelineHandle` are written as soon as they
+/// are submitted, without waiting for earlier commands to complete. Each
+/// tagged response is handed to the stream for the command it belongs to.
+/// Untagged responses go to the oldest pending command they are a valid
+/// response for; others go to the unsolicited responses channel, if any,
+/// or to the oldest pending command.
+///
+/// Commands submitted while a `Cork` is held are buffered, and written
+/// together once it is dropped.
+///
+/// The driver must be spawned or otherwise polled for commands to make
+/// progress. It resolves to the `Client` once all handles have been dropped
+/// and all pending commands have completed.
+pub struct Pipeline<T> {
+    client: Option<Client<T>>,
+    commands: mpsc::UnboundedReceiver<Message>,
+    // The number of `Cork`s held.
+    corks: Arc<AtomicUsize>,
+    closed: bool,
+    queued: Option<(Request, Pending)>,
+    // Whether the schedule has let the queued command be written.
+    scheduled: bool,
+    pending: VecDeque<Pending>,
+    // The interval, and the delay until the next `NOOP`, started when it is
+    // first polled.
+    keep_alive: Option<(Duration, Option<Sleep>)>,
+    clock: Arc<Clock>,
+    // The text of the `BYE` response, once one has been received.
+    bye: Option<Option<String>>,
+    on_close: Option<oneshot::Sender<ConnectionClosed>>,
+}
+
+struct Pending {
+    request_id: RequestId,
+    verb: String,
+    scope: Option<FetchScope>,
+    next_state: Option<State>,
+    sender: ResponseSender,
+    // Whether the command carries a literal, which counts against the
+    // schedule's `max_literals`.
+    literal: bool,
+}
+
+impl<T> Pipeline<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    pub(crate) fn new(client: Client<T>) -> (Self, PipelineHandle) {
+        let (tx, rx) = mpsc::unbounded();
+        let corks = Arc::new(AtomicUsize::new(0));
+        let pipeline = Self {
+            client: Some(client),
+            commands: rx,
+            corks: corks.clone(),
+            closed: false,
+            queued: None,
+            scheduled: false,
+            pending: VecDeque::new(),
+            keep_alive: None,
+            clock: Arc::new(SystemClock),
+            bye: None,
+            on_close: None,
+        };
+        let handle = PipelineHandle {
+            commands: tx,
+            corks,
+        };
+        (pipeline, handle)
+    }
+
+    /// Sends a `NOOP` whenever no command has been sent for `interval`, so
+    /// that servers and NAT devices do not drop the idle connection.
+    ///
+    /// With the default clock, this requires the pipeline to run on a tokio
+    /// runtime.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some((interval, None));
+        self
+    }
+
+    /// Measures the keep-alive interval with `clock`.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Returns a future that resolves with the reason when the connection
+    /// ends, either because the server sent `BYE` or because the connection
+    /// was closed or failed. It fails if the pipeline completes or is dropped
+    /// while the connection is still open.
+    pub fn on_close(&mut self) -> oneshot::Receiver<ConnectionClosed> {
+        let (tx, rx) = oneshot::channel();
+        self.on_close = Some(tx);
+        rx
+    }
+
+    // Queues a `NOOP` once the connection has been idle for the keep-alive
+    // interval.
+    fn poll_keep_alive(&mut self, client: &mut Client<T>) -> Result<(), Error> {
+        let idle = self.pending.is_empty() && self.queued.is_none() && !self.closed;
+        let fired = match self.keep_alive {
+            Some((interval, ref mut delay)) => {
+                let clock = &self.clock;
+                let fired = delay
+                    .get_or_insert_with(|| clock.delay_for(interval))
+                    .poll()?
+                    .is_ready();
+                if fired {
+                    *delay = None;
+                }
+                fired
+            },
+            None => false,
+        };
+        if fired && idle {
+            // Nobody listens for the responses to the NOOP.
+            let (sender, _) = mpsc::unbounded();
+            self.queue(client, CommandBuilder::noop(), sender);
+        }
+        Ok(())
+    }
+
+    // Restarts the keep-alive interval after a command has been sent.
+    fn touch(&mut self) {
+        if let Some((_, ref mut delay)) = self.keep_alive {
+            *delay = None;
+        }
+    }
+
+    // Prepares `cmd` to be written to the transport; the responses to it go
+    // to `sender`.
+    fn queue(&mut self, client: &mut Client<T>, cmd: Command, sender: ResponseSender) {
+        let (args, next_state) = client.state.command_parts(cmd);
+        let verb = command_verb(&args);
+        if let Err(e) = client.state.check_state(&verb) {
+            let _ = sender.unbounded_send(Err(e));
+            return;
+        }
+        let request_id = client.state.request_ids.next().unwrap(); // safe: never Err
+        client.state.started(&request_id, &verb, &args);
+        let pending = Pending {
+            request_id: request_id.clone(),
+            scope: FetchScope::of(&verb, &args),
+            verb,
+            next_state,
+            sender,
+            literal: has_literal(&args),
+        };
+        self.queued = Some((Request(request_id, args), pending));
+        self.scheduled = false;
+        self.touch();
+    }
+
+    // Waits until the client's schedule, if any, lets the queued command
+    // `pending` be written.
+    fn poll_schedule(&self, client: &mut Client<T>, pending: &Pending) -> Poll<(), Error> {
+        let scheduler = match client.state.scheduler {
+            Some(ref mut scheduler) => scheduler,
+            None => return Ok(Async::Ready(())),
+        };
+        if pending.literal {
+            // Woken up by `dispatch()` when a command with a literal completes.
+            let in_flight = self.pending.iter().filter(|p| p.literal).count();
+            if !scheduler.allows_literal(in_flight) {
+                return Ok(Async::NotReady);
+            }
+        }
+        scheduler.poll_send()
+    }
+
+    // Writes newly submitted commands to the transport buffer.
+    fn poll_commands(&mut self, client: &mut Client<T>) -> Poll<(), Error> {
+        loop {
+            if let Some((request, pending)) = self.queued.take() {
+                if !self.scheduled {
+                    if self.poll_schedule(client, &pending)?.is_not_ready() {
+                        self.queued = Some((request, pending));
+                        return Ok(Async::NotReady);
+                    }
+                    self.scheduled = true;
+                }
+                match client.transport.start_send(request)? {
+                    AsyncSink::Ready => self.pending.push_back(pending),
+                    AsyncSink::NotReady(request) => {
+                        self.queued = Some((request, pending));
+                        return Ok(Async::NotReady);
+                    },
+                }
+            }
+            if self.closed {
+                return Ok(Async::Ready(()));
+            }
+            match self.commands.poll() {
+                Ok(Async::Ready(Some(Message::Call(cmd, sender)))) => {
+                    self.queue(client, cmd, sender)
+                },
+                Ok(Async::Ready(Some(Message::Uncork))) => {},
+                Ok(Async::Ready(None)) | Err(()) => {
+                    self.closed = true;
+                },
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            }
+        }
+    }
+
+    fn dispatch(&mut self, client: &mut Client<T>, rsp: ResponseData) -> Result<(), Error> {
+        client.state.observe(&rsp);
+        if let Response::Data {
+            status: Status::Bye,
+            information,
+            ..
+        } = *rsp.parsed()
+        {
+            self.bye = Some(information.map(|text| text.to_string()));
+        }
+        if let Some(request_id) = rsp.request_id().cloned() {
+            match self.pending.iter().position(|p| p.request_id == request_id) {
+                Some(pos) => {
+                    let pending = self.pending.remove(pos).unwrap(); // safe: just found
+                    match pending.next_state {
+                        Some(next_state) if is_ok(&rsp) => client.state.set_state(next_state),
+                        _ => {},
+                    }
+                    client.state.completed(&pending.verb, &rsp);
+                    if pending.literal && self.queued.is_some() {
+                        // A command held back by `max_literals` may go now.
+                        task::current().notify();
+                    }
+                    let _ = pending.sender.unbounded_send(Ok(rsp));
+                },
+                None => warn!("dropping response for unknown request {:?}", request_id),
+            }
+            return Ok(());
+        }
+        let target = {
+            let state = &client.state;
+            self.pending
+                .iter()
+                .position(|p| state.belongs(&p.verb, p.scope.as_ref(), rsp.parsed()))
+        };
+        let rsp = match target {
+            Some(pos) => {
+                let _ = self.pending[pos].sender.unbounded_send(Ok(rsp));
+                return Ok(());
+            },
+            None => match client.state.route("", None, rsp)? {
+                Some(rsp) => rsp,
+                None => return Ok(()),
+            },
+        };
+        match self.pending.front() {
+            Some(pending) => {
+                let _ = pending.sender.unbounded_send(Ok(rsp));
+            },
+            None => debug!("dropping unsolicited response {:?}", rsp.parsed()),
+        }
+        Ok(())
+    }
+
+    fn fail(&mut self, client: &mut Client<T>, err: &Error) {
+        for pending in self.pending.drain(..) {
+            let _ = pending.sender.unbounded_send(Err(err.duplicate()));
+        }
+        let reason = match (self.bye.take(), err) {
+            (Some(text), _) => ConnectionClosed::Bye(text),
+            (None, &Error::ConnectionClosed) => ConnectionClosed::Eof,
+            (None, err) => ConnectionClosed::Failed(err.to_string()),
+        };
+        client.state.closed(&reason);
+        if let Some(on_close) = self.on_close.take() {
+            let _ = on_close.send(reason);
+        }
+    }
+
+    fn poll_client(&mut self, client: &mut Client<T>) -> Poll<(), Error> {
+        self.poll_keep_alive(client)?;
+        let commands = self.poll_commands(client)?;
+        if self.closed || self.corks.load(Ordering::SeqCst) == 0 {
+            client.transport.poll_complete()?;
+        }
+        loop {
+            match client.transport.poll()? {
+                Async::Ready(Some(rsp)) => self.dispatch(client, rsp)?,
+                // The server may close the connection right after the last
+                // tagged response, for example after `LOGOUT`.
+                Async::Ready(None) if commands.is_ready() && self.pending.is_empty() => {
+                    return Ok(Async::Ready(()));
+                },
+                Async::Ready(None) => return Err(Error::ConnectionClosed),
+                Async::NotReady => break,
+            }
+        }
+        if commands.is_ready() && self.pending.is_empty() {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl<T> Future for Pipeline<T>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    type Item = Client<T>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut client = self.client.take().expect("Pipeline polled after completion");
+        match self.poll_client(&mut client) {
+            Ok(Async::Ready(())) => Ok(Async::Ready(client)),
+            Ok(Async::NotReady) => {
+                self.client = Some(client);
+                Ok(Async::NotReady)
+            },
+            Err(e) => {
+                self.fail(&mut client, &e);
+                Err(e)
+            },
+        }
+    }
+}
+
+/// Submits commands to a `Pipeline`.
+#[derive(Clone)]
+pub struct PipelineHandle {
+    commands: mpsc::UnboundedSender<Message>,
+    corks: Arc<AtomicUsize>,
+}
+
+impl PipelineHandle {
+    /// Submits `cmd`, returning a stream of the responses that belong to it.
+    ///
+    /// The stream ends after the tagged response for the command.
+    pub fn call(&self, cmd: Command) -> PipelineResponses {
+        let (tx, rx) = mpsc::unbounded();
+        // If the pipeline is gone, dropping the sender makes the stream fail.
+        let _ = self.commands.unbounded_send(Message::Call(cmd, tx));
+        PipelineResponses {
+            responses: rx,
+            done: false,
+        }
+    }
+
+    /// Holds back the commands submitted from now on until the returned
+    /// `Cork` is dropped, so that they are written to the connection
+    /// together rather than one at a time.
+    ///
+    /// The commands are encoded into the connection's write buffer as they
+    /// are submitted; only the flush is delayed, unless the buffer fills up.
+    /// Responses to them cannot arrive before the cork is dropped.
+    pub fn cork(&self) -> Cork {
+        self.corks.fetch_add(1, Ordering::SeqCst);
+        Cork {
+            commands: self.commands.clone(),
+            corks: self.corks.clone(),
+        }
+    }
+}
+
+/// Delays writing the commands submitted to a `Pipeline`, created by
+/// `PipelineHandle::cork()`. While several are held, commands are written
+/// once all of them are dropped.
+pub struct Cork {
+    commands: mpsc::UnboundedSender<Message>,
+    corks: Arc<AtomicUsize>,
+}
+
+impl Drop for Cork {
+    fn drop(&mut self) {
+        if self.corks.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Wakes the pipeline to write the buffered commands.
+            let _ = self.commands.unbounded_send(Message::Uncork);
+        }
+    }
+}
+
+/// The responses to a command submitted through a `PipelineHandle`.
+pub struct PipelineResponses {
+    responses: mpsc::UnboundedReceiver<Result<ResponseData, Error>>,
+    done: bool,
+}
+
+impl Stream for PipelineResponses {
+    type Item = ResponseData;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+        match self.responses.poll() {
+            Ok(Async::Ready(Some(Ok(rsp)))) => {
+                self.done = rsp.request_id().is_some();
+                Ok(Async::Ready(Some(rsp)))
+            },
+            Ok(Async::Ready(Some(Err(e)))) => {
+                self.done = true;
+                Err(e)
+            },
+            Ok(Async::Ready(None)) | Err(()) => {
+                // The pipeline went away before the command completed.
+                self.done = true;
+                Err(Error::ConnectionClosed)
+            },
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use base64;
+
+use futures::{future, Future};
+use futures::future::Loop;
+
+use std::io;
+
+use tokio::io::{read_exact, write_all};
+use tokio::net::TcpStream;
+
+pub(crate) type ProxyHandshake = Box<Future<Item = TcpStream, Error = io::Error> + Send>;
+
+// The longest response header accepted from an HTTP proxy.
+const MAX_HTTP_RESPONSE: usize = 8192;
+
+/// A proxy to connect to the server through, set with
+/// `ClientBuilder::proxy()`.
+///
+/// The proxy is asked to connect to the server's host name, so that it is
+/// resolved by the proxy rather than locally, as needed for example for
+/// onion services through Tor. The TLS handshake still takes place with
+/// the server itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Proxy {
+    kind: ProxyKind,
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ProxyKind {
+    Socks5,
+    HttpConnect,
+}
+
+impl Proxy {
+    /// A SOCKS5 proxy (RFC 1928), such as Tor's SOCKS port.
+    pub fn socks5(host: &str, port: u16) -> Self {
+        Self::new(ProxyKind::Socks5, host, port)
+    }
+
+    /// An HTTP proxy that supports the `CONNECT` method (RFC 7231, section
+    /// 4.3.6).
+    pub fn http_connect(host: &str, port: u16) -> Self {
+        Self::new(ProxyKind::HttpConnect, host, port)
+    }
+
+    fn new(kind: ProxyKind, host: &str, port: u16) -> Self {
+        Self {
+            kind,
+            host: host.to_string(),
+            port,
+            credentials: None,
+        }
+    }
+
+    /// Authenticates with the proxy, using username/password authentication
+    /// (RFC 1929) for SOCKS5 and `Basic` authentication for HTTP.
+    pub fn credentials(mut self, user_name: &str, password: &str) -> Self {
+        self.credentials = Some((user_name.to_string(), password.to_string()));
+        self
+    }
+
+    pub(crate) fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    // Asks the proxy to connect `stream` to `host` and `port`.
+    pub(crate) fn handshake(self, stream: TcpStream, host: &str, port: u16) -> ProxyHandshake {
+        match self.kind {
+            ProxyKind::Socks5 => socks5(stream, host, port, self.credentials),
+            ProxyKind::HttpConnect => http_connect(stream, host, port, self.credentials),
+        }
+    }
+}
+
+fn proxy_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+fn socks5(
+    stream: TcpStream, host: &str, port: u16, credentials: Option<(String, String)>
+) -> ProxyHandshake {
+    if host.len() > 255 {
+        return Box::new(future::err(proxy_error(format!("host name too long: {}", host))));
+    }
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend(host.as_bytes());
+    request.extend(&[(port >> 8) as u8, port as u8]);
+
+    // Offer no authentication, and username/password if there are credentials.
+    let methods = match credentials {
+        Some(_) => vec![5, 2, 0, 2],
+        None => vec![5, 1, 0],
+    };
+    let negotiated = write_all(stream, methods)
+        .and_then(|(stream, _)| read_exact(stream, [0; 2]))
+        .and_then(move |(stream, reply)| -> ProxyHandshake {
+            match (reply, credentials) {
+                ([5, 0], _) => Box::new(future::ok(stream)),
+                ([5, 2], Some((user_name, password))) => {
+                    socks5_login(stream, &user_name, &password)
+                },
+                _ => Box::new(future::err(proxy_error(
+                    "SOCKS5 proxy accepts none of the offered authentication methods".into(),
+                ))),
+            }
+        });
+    Box::new(
+        negotiated
+            .and_then(move |stream| write_all(stream, request))
+            .and_then(|(stream, _)| read_exact(stream, [0; 4]))
+            .and_then(|(stream, reply)| {
+                if reply[1] != 0 {
+                    return Err(proxy_error(format!(
+                        "SOCKS5 proxy could not connect: {}",
+                        socks5_reply(reply[1])
+                    )));
+                }
+                // The address the proxy bound, followed by its port.
+                let len = match reply[3] {
+                    1 => 4 + 2,
+                    4 => 16 + 2,
+                    3 => return Ok(future::Either::A(socks5_domain(stream))),
+                    atyp => {
+                        return Err(proxy_error(format!("unknown SOCKS5 address type {}", atyp)))
+                    },
+                };
+                Ok(future::Either::B(
+                    read_exact(stream, vec![0; len]).map(|(stream, _)| stream),
+                ))
+            })
+            .flatten(),
+    )
+}
+
+// Authenticates with username and password (RFC 1929).
+fn socks5_login(stream: TcpStream, user_name: &str, password: &str) -> ProxyHandshake {
+    if user_name.len() > 255 || password.len() > 255 {
+        return Box::new(future::err(proxy_error("SOCKS5 credentials too long".into())));
+    }
+    let mut request = vec![1, user_name.len() as u8];
+    request.extend(user_name.as_bytes());
+    request.push(password.len() as u8);
+    request.extend(password.as_bytes());
+    Box::new(
+        write_all(stream, request)
+            .and_then(|(stream, _)| read_exact(stream, [0; 2]))
+            .and_then(|(stream, reply)| match reply[1] {
+                0 => Ok(stream),
+                _ => Err(proxy_error("SOCKS5 proxy rejected the credentials".into())),
+            }),
+    )
+}
+
+// Skips a bound address given as a domain name, and the port.
+fn socks5_domain(stream: TcpStream) -> ProxyHandshake {
+    Box::new(
+        read_exact(stream, [0; 1])
+            .and_then(|(stream, len)| read_exact(stream, vec![0; len[0] as usize + 2]))
+            .map(|(stream, _)| stream),
+    )
+}
+
+fn socks5_reply(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn http_connect(
+    stream: TcpStream, host: &str, port: u16, credentials: Option<(String, String)>
+) -> ProxyHandshake {
+    let target = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some((user_name, password)) = credentials {
+        let basic = base64::encode(&format!("{}:{}", user_name, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", basic));
+    }
+    request.push_str("\r\n");
+    Box::new(
+        write_all(stream, request.into_bytes())
+            .and_then(|(stream, _)| read_http_response(stream))
+            .and_then(|(stream, response)| {
+                let response = String::from_utf8_lossy(&response);
+                let status = response.lines().next().unwrap_or("");
+                let mut words = status.splitn(3, ' ');
+                let version = words.next().unwrap_or("");
+                let code = words.next().unwrap_or("");
+                if version.starts_with("HTTP/") && code.starts_with('2') {
+                    return Ok(stream);
+                }
+                Err(proxy_error(format!("HTTP proxy refused to connect: {}", status)))
+            }),
+    )
+}
+
+// Reads the response header up to the empty line, one byte at a time so
+// that nothing the server sends after it is consumed.
+fn read_http_response(
+    stream: TcpStream,
+) -> Box<Future<Item = (TcpStream, Vec<u8>), Error = io::Error> + Send> {
+    Box::new(future::loop_fn(
+        (stream, Vec::new()),
+        |(stream, mut response)| {
+            read_exact(stream, [0; 1]).and_then(move |(stream, byte)| {
+                response.push(byte[0]);
+                if response.ends_with(b"\r\n\r\n") {
+                    Ok(Loop::Break((stream, response)))
+                } else if response.len() > MAX_HTTP_RESPONSE {
+                    Err(proxy_error("HTTP proxy response too long".into()))
+                } else {
+                    Ok(Loop::Continue((stream, response)))
+                }
+            })
+        },
+    ))
+}
@@ -0,0 +1,41 @@
+/// What a connection is used for.
+///
+/// A `Monitor` connection only observes mailboxes, for example to keep a
+/// `MailboxWatch` up to date or to poll for new messages. Mailboxes are
+/// always opened with `EXAMINE` on it, even when `SELECT` is sent, so that
+/// fetching messages does not mark them `\Seen`, and commands that could
+/// change mailboxes or messages fail with `Error::ReadOnly` without being
+/// sent. This keeps a background monitor from changing flags by accident.
+///
+/// A `ClientPool` hands out monitor connections with `checkout_monitor()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Role {
+    Interactive,
+    Monitor,
+}
+
+impl Default for Role {
+    fn default() -> Self {
+        Role::Interactive
+    }
+}
+
+// Whether a command of type `verb` can change mailboxes or messages.
+pub(crate) fn is_mutating(verb: &str) -> bool {
+    match verb {
+        "APPEND" | "COPY" | "CREATE" | "DELETE" | "DELETEACL" | "EXPUNGE" | "MOVE" | "RENAME"
+        | "SETACL" | "SETMETADATA" | "SETQUOTA" | "STORE" | "SUBSCRIBE" | "UID COPY"
+        | "UID EXPUNGE" | "UID MOVE" | "UID STORE" | "UNSUBSCRIBE" => true,
+        _ => false,
+    }
+}
+
+// Turns a `SELECT` command into the equivalent `EXAMINE` command.
+pub(crate) fn examine_instead(args: Vec<u8>) -> Vec<u8> {
+    if !args.starts_with(b"SELECT ") {
+        return args;
+    }
+    let mut examine = b"EXAMINE".to_vec();
+    examine.extend(&args[6..]);
+    examine
+}
@@ -0,0 +1,83 @@
+use imap_proto::{AttributeValue, Response, SequenceSet};
+
+/// How `FETCH` responses received while a `FETCH` or `STORE` command is in
+/// flight are attributed.
+///
+/// Servers may send `FETCH` responses at any time to report flag changes
+/// made by other clients. With `ByCommand`, all of them are yielded as
+/// responses to the command; with `ByMessageSet`, only those for messages
+/// the command applies to are, and others go to the unsolicited responses
+/// channel.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FetchRouting {
+    ByCommand,
+    ByMessageSet,
+}
+
+impl Default for FetchRouting {
+    fn default() -> Self {
+        FetchRouting::ByCommand
+    }
+}
+
+// The messages a `FETCH` or `STORE` command applies to.
+#[derive(Clone, Debug)]
+pub(crate) struct FetchScope {
+    uid: bool,
+    // `STORE` with `.SILENT` does not ask for the new flags.
+    silent: bool,
+    set: SequenceSet,
+}
+
+impl FetchScope {
+    // The scope of a command with the given verb and arguments, if it is a
+    // `FETCH` or `STORE` with a message set that can be interpreted.
+    pub(crate) fn of(verb: &str, args: &[u8]) -> Option<Self> {
+        let uid = match verb {
+            "FETCH" | "STORE" => false,
+            "UID FETCH" | "UID STORE" => true,
+            _ => return None,
+        };
+        let args = String::from_utf8_lossy(args);
+        let mut words = args.split(' ').skip(if uid { 2 } else { 1 });
+        let set: SequenceSet = words.next()?.parse().ok()?;
+        // References to saved results (`$`) cannot be interpreted here.
+        if set.is_saved() {
+            return None;
+        }
+        let silent = verb.ends_with("STORE")
+            && words
+                .next()
+                .map(|item| item.to_uppercase().ends_with(".SILENT"))
+                .unwrap_or(false);
+        Some(FetchScope { uid, silent, set })
+    }
+
+    // Whether `rsp`, if it is a `FETCH` response, belongs to the command.
+    pub(crate) fn contains(&self, rsp: &Response) -> bool {
+        let attrs = match *rsp {
+            Response::Fetch(_, ref attrs) => attrs,
+            _ => return true,
+        };
+        let num = match *rsp {
+            Response::Fetch(num, _) if !self.uid => Some(num),
+            _ => attrs
+                .iter()
+                .filter_map(|attr| match *attr {
+                    AttributeValue::Uid(uid) => Some(uid),
+                    _ => None,
+                })
+                .next(),
+        };
+        let in_set = match num {
+            Some(num) => self.set.contains(num),
+            // Responses to UID commands always include the UID.
+            None => false,
+        };
+        let has_flags = attrs.iter().any(|attr| match *attr {
+            AttributeValue::Flags(_) => true,
+            _ => false,
+        });
+        in_set && !(self.silent && has_flags)
+    }
+}
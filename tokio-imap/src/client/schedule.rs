@@ -0,0 +1,103 @@
+use futures::{Async, Poll};
+
+use std::sync::Arc;
+
+use super::throttle::{Throttle, TokenBucket};
+use clock::Clock;
+use error::Error;
+
+/// Limits on how fast commands are sent on a connection, set with
+/// `Client::set_schedule()` or `ClientBuilder::schedule()`, for servers
+/// that throttle or disconnect clients sending commands too quickly.
+///
+/// Up to `burst` commands can be sent at once; after that, commands wait
+/// their turn, so that `commands_per_second` are sent on average. On a
+/// `Pipeline`, at most `max_literals` commands that carry literals, such as
+/// `SETMETADATA` with a binary value, are in flight at the same time; later
+/// ones wait until an earlier one has completed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Schedule {
+    pub commands_per_second: u64,
+    pub burst: u64,
+    pub max_literals: Option<usize>,
+}
+
+impl Schedule {
+    /// Limits commands to `commands_per_second`, allowing bursts of one
+    /// second's worth of commands.
+    pub fn new(commands_per_second: u64) -> Self {
+        Self {
+            commands_per_second,
+            burst: commands_per_second,
+            max_literals: None,
+        }
+    }
+
+    pub fn burst(mut self, burst: u64) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    pub fn max_literals(mut self, max_literals: usize) -> Self {
+        self.max_literals = Some(max_literals);
+        self
+    }
+}
+
+// Holds back commands according to a `Schedule`, with one token of the
+// bucket per command.
+pub(crate) struct Scheduler {
+    schedule: Schedule,
+    commands: TokenBucket,
+}
+
+impl Scheduler {
+    pub(crate) fn new(schedule: Schedule, clock: Arc<Clock>) -> Self {
+        let limit = Throttle::new(schedule.commands_per_second).burst(schedule.burst);
+        Self {
+            schedule,
+            commands: TokenBucket::new(limit, clock),
+        }
+    }
+
+    pub(crate) fn schedule(&self) -> Schedule {
+        self.schedule
+    }
+
+    // Waits until the next command may be sent, and counts it as sent.
+    pub(crate) fn poll_send(&mut self) -> Poll<(), Error> {
+        try_ready!(self.commands.poll_allowance(1));
+        self.commands.consume(1);
+        Ok(Async::Ready(()))
+    }
+
+    // Whether a command with a literal may be sent while `in_flight` others
+    // are.
+    pub(crate) fn allows_literal(&self, in_flight: usize) -> bool {
+        self.schedule
+            .max_literals
+            .map(|max| in_flight < max)
+            .unwrap_or(true)
+    }
+}
+
+// Whether the command `args` carry a literal, announced like `{12}` or
+// `{12+}` at the end of a line.
+pub(crate) fn has_literal(args: &[u8]) -> bool {
+    args.split(|&b| b == b'\n').any(|line| {
+        let line = match line.split_last() {
+            Some((&b'\r', line)) => line,
+            _ => line,
+        };
+        let line = match line.split_last() {
+            Some((&b'}', line)) => line,
+            _ => return false,
+        };
+        let line = match line.split_last() {
+            Some((&b'+', line)) => line,
+            _ => line,
+        };
+        let digits = line.iter().rev().take_while(|b| b.is_ascii_digit()).count();
+        digits > 0 && line.len() > digits && line[line.len() - digits - 1] == b'{'
+    })
+}
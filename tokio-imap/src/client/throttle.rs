@@ -0,0 +1,128 @@
+use futures::{Async, Future, Poll};
+
+use std::cmp;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clock::{Clock, Sleep};
+use error::Error;
+
+/// A limit on the rate at which message data is uploaded, set with
+/// `AppendFuture::throttle()`.
+///
+/// Up to `burst` bytes can be sent at once; after that, data is sent at
+/// `bytes_per_second` on average.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Throttle {
+    pub bytes_per_second: u64,
+    pub burst: u64,
+}
+
+impl Throttle {
+    /// Limits uploads to `bytes_per_second`, allowing bursts of one second's
+    /// worth of data.
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            burst: bytes_per_second,
+        }
+    }
+
+    pub fn burst(mut self, burst: u64) -> Self {
+        self.burst = burst;
+        self
+    }
+}
+
+/// The state of an upload's throttle, as reported to progress handlers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ThrottleState {
+    pub limit: Throttle,
+    /// How many bytes may be sent right away.
+    pub available: u64,
+    /// Whether the upload is waiting for the throttle.
+    pub waiting: bool,
+}
+
+// A token bucket: it holds up to `burst` bytes' worth of tokens, and is
+// refilled at `bytes_per_second`.
+pub(crate) struct TokenBucket {
+    limit: Throttle,
+    clock: Arc<Clock>,
+    available: u64,
+    refilled: Instant,
+    wait: Option<Sleep>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: Throttle, clock: Arc<Clock>) -> Self {
+        let limit = Throttle {
+            bytes_per_second: cmp::max(limit.bytes_per_second, 1),
+            burst: cmp::max(limit.burst, 1),
+        };
+        let refilled = clock.now();
+        Self {
+            limit,
+            clock,
+            available: limit.burst,
+            refilled,
+            wait: None,
+        }
+    }
+
+    pub(crate) fn state(&self) -> ThrottleState {
+        ThrottleState {
+            limit: self.limit,
+            available: self.available,
+            waiting: self.wait.is_some(),
+        }
+    }
+
+    // How many of `wanted` bytes may be sent now. Until there are any, waits
+    // for enough tokens to send `wanted` bytes, or a full burst.
+    pub(crate) fn poll_allowance(&mut self, wanted: usize) -> Poll<usize, Error> {
+        loop {
+            if let Some(ref mut wait) = self.wait {
+                try_ready!(wait.poll());
+            }
+            self.wait = None;
+            self.refill();
+            if self.available > 0 {
+                return Ok(Async::Ready(cmp::min(self.available, wanted as u64) as usize));
+            }
+            let needed = cmp::min(wanted as u64, self.limit.burst);
+            let deadline = self.refilled + self.time_for(needed);
+            self.wait = Some(self.clock.delay(deadline));
+        }
+    }
+
+    pub(crate) fn consume(&mut self, sent: usize) {
+        self.available = self.available.saturating_sub(sent as u64);
+    }
+
+    fn refill(&mut self) {
+        let now = self.clock.now();
+        if now <= self.refilled {
+            return;
+        }
+        let elapsed = now - self.refilled;
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        let tokens = (secs * self.limit.bytes_per_second as f64) as u64;
+        if tokens == 0 {
+            return;
+        }
+        if self.available + tokens >= self.limit.burst {
+            self.available = self.limit.burst;
+            self.refilled = now;
+        } else {
+            // Keep the time for the fraction of a token not handed out yet.
+            self.available += tokens;
+            self.refilled += self.time_for(tokens);
+        }
+    }
+
+    fn time_for(&self, tokens: u64) -> Duration {
+        let secs = tokens as f64 / self.limit.bytes_per_second as f64;
+        Duration::new(secs as u64, (secs.fract() * 1e9).ceil() as u32)
+    }
+}
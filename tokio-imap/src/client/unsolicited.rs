@@ -0,0 +1,64 @@
+use futures::{Async, Poll, Stream};
+use futures::sync::mpsc;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use proto::ResponseData;
+
+/// The unsolicited responses routed away from commands, created by
+/// `Client::unsolicited_responses()`. The stream ends when the client is
+/// dropped.
+pub struct UnsolicitedResponses {
+    responses: mpsc::UnboundedReceiver<ResponseData>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl Stream for UnsolicitedResponses {
+    type Item = ResponseData;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<ResponseData>, ()> {
+        let rsp = try_ready!(self.responses.poll());
+        if rsp.is_some() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        Ok(Async::Ready(rsp))
+    }
+}
+
+// Keeps count of the responses sent that have not been received yet, so
+// that the client can stop when too many pile up.
+pub(crate) struct UnsolicitedSender {
+    responses: mpsc::UnboundedSender<ResponseData>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl UnsolicitedSender {
+    // Gives `rsp` back if the receiver was dropped.
+    pub(crate) fn send(&self, rsp: ResponseData) -> Result<(), ResponseData> {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        self.responses.unbounded_send(rsp).map_err(|e| {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            e.into_inner()
+        })
+    }
+
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+}
+
+pub(crate) fn channel() -> (UnsolicitedSender, UnsolicitedResponses) {
+    let (tx, rx) = mpsc::unbounded();
+    let queued = Arc::new(AtomicUsize::new(0));
+    let sender = UnsolicitedSender {
+        responses: tx,
+        queued: queued.clone(),
+    };
+    let receiver = UnsolicitedResponses {
+        responses: rx,
+        queued,
+    };
+    (sender, receiver)
+}
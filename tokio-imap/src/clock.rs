@@ -0,0 +1,135 @@
+//! The source of time for timeouts, keep-alives and reconnect backoff.
+//!
+//! Everything time-based in the client waits through a `Clock`, which is
+//! `SystemClock` unless set otherwise. Tests can use a `ManualClock`
+//! instead, so that time only passes when they advance it:
+//!
+//! ```ignore
+//! let clock = ManualClock::new();
+//! let client = ReconnectingClient::new(connect, login).clock(clock.clone());
+//! // ... the first connection attempt fails ...
+//! clock.advance(Duration::from_secs(1));
+//! // ... the client tries again, without the test sleeping for a second.
+//! ```
+
+use futures::{Async, Future, Poll};
+use futures::task::{self, Task};
+
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::timer::Delay;
+
+/// A future that completes at a deadline, created by `Clock::delay()`.
+pub type Sleep = Box<Future<Item = (), Error = io::Error> + Send>;
+
+/// Tells the time and waits for deadlines.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Completes once `now()` has reached `deadline`.
+    fn delay(&self, deadline: Instant) -> Sleep;
+
+    /// Completes once `duration` has passed.
+    fn delay_for(&self, duration: Duration) -> Sleep {
+        self.delay(self.now() + duration)
+    }
+}
+
+/// The real time, with timers from the tokio runtime.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn delay(&self, deadline: Instant) -> Sleep {
+        Box::new(Delay::new(deadline).map_err(|e| io::Error::new(io::ErrorKind::Other, e)))
+    }
+}
+
+struct ManualState {
+    now: Instant,
+    // Tasks waiting for a deadline, woken when the clock passes it.
+    sleepers: Vec<(Instant, Task)>,
+}
+
+/// A clock that only moves when it is advanced, for deterministic tests.
+///
+/// Clones share the same time, so one clone can be handed to the client and
+/// another kept to advance it. Its delays do not need a tokio runtime.
+#[derive(Clone)]
+pub struct ManualClock {
+    state: Arc<Mutex<ManualState>>,
+}
+
+impl ManualClock {
+    /// Starts at the current time.
+    pub fn new() -> Self {
+        let state = ManualState {
+            now: Instant::now(),
+            sleepers: Vec::new(),
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Moves the clock forward by `duration`, completing the delays whose
+    /// deadline has passed.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now += duration;
+        let now = state.now;
+        let (due, waiting) = state
+            .sleepers
+            .drain(..)
+            .partition(|&(deadline, _)| deadline <= now);
+        state.sleepers = waiting;
+        drop(state);
+        for (_, task) in due {
+            task.notify();
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    fn delay(&self, deadline: Instant) -> Sleep {
+        Box::new(ManualDelay {
+            state: self.state.clone(),
+            deadline,
+        })
+    }
+}
+
+struct ManualDelay {
+    state: Arc<Mutex<ManualState>>,
+    deadline: Instant,
+}
+
+impl Future for ManualDelay {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        let mut state = self.state.lock().unwrap();
+        if state.now >= self.deadline {
+            return Ok(Async::Ready(()));
+        }
+        state.sleepers.push((self.deadline, task::current()));
+        Ok(Async::NotReady)
+    }
+}
@@ -0,0 +1,152 @@
+//! A futures 0.3 compatibility layer, for use from async/await code.
+//!
+//! The client itself is built on futures 0.1, futures-state-stream and
+//! tokio 0.1, and has not been ported to `std::future` or tokio 1.x. With
+//! the `compat` feature, its futures and response streams can be wrapped
+//! with futures 0.3's `compat` adapters into `std::future::Future`s and
+//! futures 0.3 `Stream`s:
+//!
+//! ```ignore
+//! let (_, client) = compat::std_future(Client::connect("imap.example.com")?).await?;
+//! let (responses, client) = compat::call(client, CommandBuilder::noop()).await?;
+//! let mut stream = compat::Responses::new(client.call(CommandBuilder::fetch()...));
+//! while let Some(rsp) = stream.next().await {
+//!     println!("{:?}", rsp?.parsed());
+//! }
+//! let client = stream.into_client();
+//! ```
+//!
+//! The connection is still driven by the tokio 0.1 reactor, which is started
+//! in the background when no runtime provides one. Timeouts and keep-alives
+//! wait through the client's `Clock`; on a runtime without a tokio 0.1
+//! timer, set one that does not rely on it.
+
+use futures::{Async, Poll, Stream};
+use futures_state_stream::{StateStream, StreamEvent};
+use futures03::compat::Compat01As03;
+
+use std::future::Future as StdFuture;
+use std::pin::Pin;
+use std::task::{self, Context};
+
+use imap_proto::builders::command::Command;
+
+use client::{ImapClient, ResponseStream};
+use error::Error;
+use proto::ResponseData;
+
+/// Turns a futures 0.1 future, such as `ImapConnectFuture` or one of the
+/// `Session` futures, into a `std::future::Future`.
+pub fn std_future<F>(future: F) -> Compat01As03<F>
+where
+    F: ::futures::Future, {
+    Compat01As03::new(future)
+}
+
+/// Sends `cmd`, resolving to its responses (the last of which is the tagged
+/// response) and the client.
+pub fn call<E>(
+    client: E, cmd: Command
+) -> impl StdFuture<Output = Result<(Vec<ResponseData>, E), Error>>
+where
+    E: ImapClient, {
+    Compat01As03::new(client.call(cmd).collect())
+}
+
+/// The responses of a `ResponseStream` as a futures 0.3 `Stream`.
+///
+/// Once the stream has ended, `into_client()` hands back the client.
+pub struct Responses<E>
+where
+    E: ImapClient, {
+    events: Compat01As03<Events<E>>,
+    client: Option<E>,
+    done: bool,
+}
+
+impl<E> Responses<E>
+where
+    E: ImapClient,
+{
+    pub fn new(stream: ResponseStream<E>) -> Self {
+        Self {
+            events: Compat01As03::new(Events(stream)),
+            client: None,
+            done: false,
+        }
+    }
+
+    /// The client, once all responses were yielded. `None` if the stream
+    /// has not ended yet or ended with an error.
+    pub fn into_client(self) -> Option<E> {
+        self.client
+    }
+}
+
+// The stream never relies on being pinned.
+impl<E> Unpin for Responses<E>
+where
+    E: ImapClient,
+{
+}
+
+impl<E> ::futures03::Stream for Responses<E>
+where
+    E: ImapClient,
+{
+    type Item = Result<ResponseData, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> task::Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return task::Poll::Ready(None);
+        }
+        let event = match Pin::new(&mut this.events).poll_next(cx) {
+            task::Poll::Ready(event) => event,
+            task::Poll::Pending => return task::Poll::Pending,
+        };
+        match event {
+            Some(Ok(Event::Response(rsp))) => task::Poll::Ready(Some(Ok(rsp))),
+            Some(Ok(Event::Done(client))) => {
+                this.client = Some(client);
+                this.done = true;
+                task::Poll::Ready(None)
+            },
+            Some(Err(e)) => {
+                this.done = true;
+                task::Poll::Ready(Some(Err(e)))
+            },
+            None => {
+                this.done = true;
+                task::Poll::Ready(None)
+            },
+        }
+    }
+}
+
+enum Event<E> {
+    Response(ResponseData),
+    Done(E),
+}
+
+// A `ResponseStream` as a futures 0.1 `Stream`, which yields the client as
+// its last item.
+struct Events<E>(ResponseStream<E>)
+where
+    E: ImapClient;
+
+impl<E> Stream for Events<E>
+where
+    E: ImapClient,
+{
+    type Item = Event<E>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Event<E>>, Error> {
+        let event = match try_ready!(self.0.poll()) {
+            StreamEvent::Next(rsp) => Event::Response(rsp),
+            StreamEvent::Done(client) => Event::Done(client),
+        };
+        Ok(Async::Ready(Some(event)))
+    }
+}
@@ -0,0 +1,166 @@
+use bytes::BytesMut;
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+
+use futures::{Async, Poll};
+
+use std::io::{self, Read, Write};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+const INPUT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// A stream wrapper implementing the raw DEFLATE layer from RFC 4978.
+///
+/// Outgoing data is compressed as it is written and sync-flushed whenever the
+/// stream is flushed, so that each command reaches the server in full.
+/// Incoming data is decompressed transparently, which means the `ImapCodec`
+/// can be layered on top without any changes.
+pub struct DeflateStream<S> {
+    inner: S,
+    compress: Compress,
+    decompress: Decompress,
+    input: Vec<u8>,
+    input_pos: usize,
+    input_len: usize,
+    output: Vec<u8>,
+    output_pos: usize,
+    needs_sync: bool,
+}
+
+impl<S> DeflateStream<S> {
+    /// Wraps `inner`, treating `pending` as compressed data that was already
+    /// read from it (for example, bytes still buffered by a previous codec).
+    pub fn new(inner: S, pending: BytesMut) -> Self {
+        let mut input = pending.to_vec();
+        let input_len = input.len();
+        if input.len() < INPUT_BUFFER_SIZE {
+            input.resize(INPUT_BUFFER_SIZE, 0);
+        }
+        Self {
+            inner,
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+            input,
+            input_pos: 0,
+            input_len,
+            output: Vec::new(),
+            output_pos: 0,
+            needs_sync: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    fn deflate(&mut self, mut data: &[u8], flush: FlushCompress) -> io::Result<()> {
+        loop {
+            if self.output.capacity() - self.output.len() < data.len() + 64 {
+                let extra = data.len() + 1024;
+                self.output.reserve(extra);
+            }
+            let before = self.compress.total_in();
+            self.compress
+                .compress_vec(data, &mut self.output, flush)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            let consumed = (self.compress.total_in() - before) as usize;
+            data = &data[consumed..];
+            if data.is_empty() && self.output.len() < self.output.capacity() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<S: Write> DeflateStream<S> {
+    fn write_output(&mut self) -> io::Result<()> {
+        while self.output_pos < self.output.len() {
+            let written = self.inner.write(&self.output[self.output_pos..])?;
+            if written == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write compressed data",
+                ));
+            }
+            self.output_pos += written;
+        }
+        self.output.clear();
+        self.output_pos = 0;
+        Ok(())
+    }
+}
+
+impl<S: Read> Read for DeflateStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // Always try the decompressor first: it may still hold output
+            // from input that was consumed during a previous call.
+            let before_in = self.decompress.total_in();
+            let before_out = self.decompress.total_out();
+            self.decompress
+                .decompress(
+                    &self.input[self.input_pos..self.input_len],
+                    buf,
+                    FlushDecompress::None,
+                )
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.input_pos += (self.decompress.total_in() - before_in) as usize;
+            let produced = (self.decompress.total_out() - before_out) as usize;
+            if produced > 0 {
+                return Ok(produced);
+            }
+            if self.input_pos < self.input_len {
+                continue;
+            }
+            let read = self.inner.read(&mut self.input)?;
+            if read == 0 {
+                return Ok(0);
+            }
+            self.input_pos = 0;
+            self.input_len = read;
+        }
+    }
+}
+
+impl<S: Write> Write for DeflateStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.deflate(buf, FlushCompress::None)?;
+        self.needs_sync = true;
+        match self.write_output() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            Err(e) => return Err(e),
+            Ok(()) => {},
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.needs_sync {
+            self.deflate(&[], FlushCompress::Sync)?;
+            self.needs_sync = false;
+        }
+        self.write_output()?;
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for DeflateStream<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for DeflateStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match self.flush() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+            Err(e) => return Err(e),
+            Ok(()) => {},
+        }
+        self.inner.shutdown()
+    }
+}
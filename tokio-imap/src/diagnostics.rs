@@ -0,0 +1,81 @@
+//! Reports of recoverable protocol anomalies: responses that the client
+//! could handle, but that suggest the server does not quite follow the
+//! protocol, or speaks extensions the client does not know.
+//!
+//! Such anomalies never fail commands. They are sent to the channel returned
+//! by `Client::warnings()`, for example to be logged or counted by operators
+//! keeping an eye on the servers their users connect to.
+
+use imap_proto::Response;
+
+use events::summarize;
+use proto::ResponseData;
+
+/// A recoverable anomaly, and the response it was found in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// The start of the response's first line.
+    pub context: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WarningKind {
+    /// An untagged response of a kind the client does not know, yielded as
+    /// `Response::Unknown`.
+    UnknownResponse { verb: String },
+    /// A response code the client does not know, which is left in the
+    /// response text.
+    UnknownResponseCode { code: String },
+    /// A continuation request without the space the grammar requires.
+    BareContinuation,
+    /// A response line longer than `ImapCodec::set_max_line_length()`
+    /// allows, of which `dropped` bytes were discarded.
+    TruncatedLine { dropped: usize },
+    /// A response that could not be parsed after it was truncated, and was
+    /// skipped.
+    SkippedResponse,
+}
+
+impl Warning {
+    pub(crate) fn new(kind: WarningKind, raw: &[u8]) -> Self {
+        Self {
+            kind,
+            context: summarize(raw),
+        }
+    }
+}
+
+// The anomalies in a parsed response.
+pub(crate) fn check(rsp: &ResponseData) -> Option<Warning> {
+    let kind = match *rsp.parsed() {
+        Response::Unknown { verb, .. } => WarningKind::UnknownResponse {
+            verb: verb.to_string(),
+        },
+        Response::Continue { .. } if !rsp.raw().starts_with(b"+ ") => {
+            WarningKind::BareContinuation
+        },
+        Response::Continue {
+            code: None,
+            information: Some(text),
+        }
+        | Response::Data {
+            code: None,
+            information: Some(text),
+            ..
+        }
+        | Response::Done {
+            code: None,
+            information: Some(text),
+            ..
+        } if text.starts_with('[') =>
+        {
+            let end = text.find(|c| c == ' ' || c == ']').unwrap_or(text.len());
+            WarningKind::UnknownResponseCode {
+                code: text[1..end].to_string(),
+            }
+        },
+        _ => return None,
+    };
+    Some(Warning::new(kind, rsp.raw()))
+}
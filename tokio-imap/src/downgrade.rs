@@ -0,0 +1,93 @@
+//! Downgrading of messages with UTF-8 header fields (RFC 6532) for servers
+//! that do not accept them, by writing non-ASCII text in header fields as
+//! encoded words (RFC 2047).
+
+use base64;
+
+use std::borrow::Cow;
+use std::str;
+
+// The most bytes of text in one encoded word, which keeps the word within
+// the limit of 75 characters (RFC 2047, section 2).
+const MAX_WORD_BYTES: usize = 45;
+
+/// Writes the non-ASCII text in the header fields of `message` as encoded
+/// words, leaving the body as it is.
+///
+/// Each run of words with non-ASCII characters is replaced by encoded words
+/// like `=?UTF-8?B?R3LDvMOfZQ==?=`, which mail readers display as the
+/// original text. This works for unstructured fields such as `Subject` and
+/// for display names; non-ASCII addresses cannot be downgraded and are left
+/// as they are, as are fields that are not valid UTF-8.
+pub fn downgrade_headers(message: &[u8]) -> Cow<[u8]> {
+    let header_len = match message.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(pos) => pos + 2,
+        None => message.len(),
+    };
+    let (header, body) = message.split_at(header_len);
+    if header.is_ascii() {
+        return Cow::Borrowed(message);
+    }
+    let mut downgraded = Vec::with_capacity(message.len() + 64);
+    for (i, line) in header.split(|&b| b == b'\n').enumerate() {
+        if i > 0 {
+            downgraded.push(b'\n');
+        }
+        let (line, eol): (&[u8], &[u8]) = match line.split_last() {
+            Some((&b'\r', line)) => (line, b"\r"),
+            _ => (line, b""),
+        };
+        let (field, value) = match line.iter().position(|&b| b == b':') {
+            // Continuation lines of folded fields consist of the value only.
+            Some(_) if line.starts_with(b" ") || line.starts_with(b"\t") => (&line[..0], line),
+            Some(colon) => line.split_at(colon + 1),
+            None => (&line[..0], line),
+        };
+        downgraded.extend(field);
+        match str::from_utf8(value) {
+            Ok(value) if !value.is_ascii() => downgraded.extend(encode_words(value).as_bytes()),
+            _ => downgraded.extend(value),
+        }
+        downgraded.extend(eol);
+    }
+    downgraded.extend(body);
+    Cow::Owned(downgraded)
+}
+
+// Replaces each run of space-separated words with non-ASCII characters by
+// encoded words.
+fn encode_words(value: &str) -> String {
+    let mut words = Vec::new();
+    let mut run: Vec<&str> = Vec::new();
+    for word in value.split(' ') {
+        if !word.is_ascii() {
+            run.push(word);
+            continue;
+        }
+        if !run.is_empty() {
+            words.push(encode_run(&run.join(" ")));
+            run.clear();
+        }
+        words.push(word.to_string());
+    }
+    if !run.is_empty() {
+        words.push(encode_run(&run.join(" ")));
+    }
+    words.join(" ")
+}
+
+// Encodes `text` as one or more encoded words. Whitespace between adjacent
+// encoded words is ignored when they are decoded.
+fn encode_run(text: &str) -> String {
+    let mut words = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut end = rest.len().min(MAX_WORD_BYTES);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        words.push(format!("=?UTF-8?B?{}?=", base64::encode(&rest[..end])));
+        rest = &rest[end..];
+    }
+    words.join(" ")
+}
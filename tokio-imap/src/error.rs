@@ -0,0 +1,275 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use imap_proto::{Response, ResponseCode, State, Status};
+
+use proto::ResponseData;
+
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Errors returned by the client.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to the connection failed.
+    Io(io::Error),
+    /// The TLS handshake failed.
+    Tls(Box<error::Error + Send + Sync>),
+    /// The server sent data that could not be parsed.
+    Parse(Vec<u8>),
+    /// The server answered a command with `NO`.
+    No(String),
+    /// The server answered a command with `BAD`.
+    Bad(String),
+    /// The server answered a command with `NO` or `BAD` and a response code
+    /// (RFC 5530) telling why.
+    Condition(ServerCondition, String),
+    /// The server closed the connection before the command completed.
+    ConnectionClosed,
+    /// The server refused the connection with a `BYE` greeting, with the
+    /// given text.
+    Bye(String),
+    /// The server does not advertise the capability a command requires.
+    MissingCapability(String),
+    /// The command is not valid in the connection's current state.
+    BadState { required: State, actual: State },
+    /// The command could change mailboxes, which a monitoring connection
+    /// (see `Role`) does not allow.
+    ReadOnly(String),
+    /// The connection was lost while the command was in flight, and it was
+    /// not sent again as it may already have taken effect.
+    Interrupted,
+    /// An earlier command was aborted before it completed, so responses to
+    /// it may still arrive; the connection must be resynchronized with
+    /// `Client::resync()` first.
+    Aborted,
+    /// The mailbox selected again after reconnecting has a different
+    /// UIDVALIDITY, so UIDs from before are no longer valid.
+    UidValidityChanged { old: u32, new: u32 },
+    /// The server exceeded a limit set with `ImapCodec::set_limits()` or
+    /// `Client::set_max_unsolicited()`, and the connection was given up.
+    LimitExceeded(Limit),
+}
+
+/// A limit on what is buffered from the server, with the value it was set
+/// to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Limit {
+    LineLength(usize),
+    LiteralSize(usize),
+    UnsolicitedResponses(usize),
+}
+
+impl Error {
+    /// Checks the tagged response that completes a command, which must be
+    /// the last of `responses`.
+    pub fn from_responses(responses: &[ResponseData]) -> Result<()> {
+        match responses.last().map(|rsp| rsp.parsed()) {
+            Some(&Response::Done {
+                ref status,
+                ref code,
+                ref information,
+                ..
+            }) => {
+                let information = information.unwrap_or("").to_string();
+                let condition = code.as_ref().and_then(ServerCondition::from_code);
+                match (status, condition) {
+                    (&Status::No, Some(condition)) | (&Status::Bad, Some(condition)) => {
+                        Err(Error::Condition(condition, information))
+                    },
+                    (&Status::No, None) => Err(Error::No(information)),
+                    (&Status::Bad, None) => Err(Error::Bad(information)),
+                    _ => Ok(()),
+                }
+            },
+            _ => Err(Error::ConnectionClosed),
+        }
+    }
+
+    /// Whether the command may succeed if it is retried later: the
+    /// connection failed, or the server reported a temporary condition.
+    pub fn is_temporary(&self) -> bool {
+        match *self {
+            Error::Io(_) | Error::ConnectionClosed | Error::Bye(_) => true,
+            Error::Condition(condition, _) => condition.is_temporary(),
+            _ => false,
+        }
+    }
+
+    // Makes a copy of this error, for reporting it to multiple receivers.
+    pub(crate) fn duplicate(&self) -> Error {
+        match *self {
+            Error::Io(ref e) => Error::Io(io::Error::new(e.kind(), e.to_string())),
+            Error::Tls(ref e) => Error::Io(io::Error::new(io::ErrorKind::Other, e.to_string())),
+            Error::Parse(ref raw) => Error::Parse(raw.clone()),
+            Error::No(ref information) => Error::No(information.clone()),
+            Error::Bad(ref information) => Error::Bad(information.clone()),
+            Error::Condition(condition, ref information) => {
+                Error::Condition(condition, information.clone())
+            },
+            Error::ConnectionClosed => Error::ConnectionClosed,
+            Error::Bye(ref information) => Error::Bye(information.clone()),
+            Error::MissingCapability(ref name) => Error::MissingCapability(name.clone()),
+            Error::BadState { required, actual } => Error::BadState { required, actual },
+            Error::ReadOnly(ref verb) => Error::ReadOnly(verb.clone()),
+            Error::Interrupted => Error::Interrupted,
+            Error::Aborted => Error::Aborted,
+            Error::UidValidityChanged { old, new } => Error::UidValidityChanged { old, new },
+            Error::LimitExceeded(limit) => Error::LimitExceeded(limit),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Tls(ref e) => write!(f, "TLS error: {}", e),
+            Error::Parse(ref raw) => {
+                write!(f, "failed to parse {:?}", String::from_utf8_lossy(raw))
+            },
+            Error::No(ref information) => write!(f, "command failed with NO: {}", information),
+            Error::Bad(ref information) => write!(f, "command failed with BAD: {}", information),
+            Error::Condition(condition, ref information) => {
+                write!(f, "command failed ({:?}): {}", condition, information)
+            },
+            Error::ConnectionClosed => write!(f, "connection closed"),
+            Error::Bye(ref information) => write!(f, "server refused connection: {}", information),
+            Error::MissingCapability(ref name) => {
+                write!(f, "server does not support {}", name)
+            },
+            Error::BadState { required, actual } => write!(
+                f,
+                "command requires {:?} state, connection is in {:?} state",
+                required, actual
+            ),
+            Error::ReadOnly(ref verb) => write!(f, "{} not allowed on a monitor connection", verb),
+            Error::Interrupted => write!(f, "connection lost while the command was in flight"),
+            Error::Aborted => write!(f, "connection needs resync after an aborted command"),
+            Error::UidValidityChanged { old, new } => {
+                write!(f, "UIDVALIDITY changed from {} to {}", old, new)
+            },
+            Error::LimitExceeded(limit) => match limit {
+                Limit::LineLength(max) => write!(f, "response line longer than {} bytes", max),
+                Limit::LiteralSize(max) => write!(f, "literal larger than {} bytes", max),
+                Limit::UnsolicitedResponses(max) => {
+                    write!(f, "more than {} unsolicited responses queued", max)
+                },
+            },
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Io(_) => "I/O error",
+            Error::Tls(_) => "TLS error",
+            Error::Parse(_) => "parse error",
+            Error::No(_) => "command failed with NO",
+            Error::Bad(_) => "command failed with BAD",
+            Error::Condition(..) => "command failed",
+            Error::ConnectionClosed => "connection closed",
+            Error::Bye(_) => "server refused connection",
+            Error::MissingCapability(_) => "missing capability",
+            Error::BadState { .. } => "command not valid in current state",
+            Error::ReadOnly(_) => "command not allowed on a monitor connection",
+            Error::Interrupted => "command interrupted",
+            Error::Aborted => "earlier command aborted",
+            Error::UidValidityChanged { .. } => "UIDVALIDITY changed",
+            Error::LimitExceeded(_) => "limit exceeded",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Tls(ref e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
+
+/// The reasons for a failed command that servers can give with RFC 5530
+/// response codes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ServerCondition {
+    /// A subsystem the server needs is down for now (`UNAVAILABLE`).
+    Unavailable,
+    AuthenticationFailed,
+    AuthorizationFailed,
+    /// The credentials or the account have expired.
+    Expired,
+    /// The operation requires an encrypted connection.
+    PrivacyRequired,
+    ContactAdmin,
+    /// The user lacks the rights for the operation (`NOPERM`).
+    NoPerm,
+    /// Another client holds a lock the operation needs (`INUSE`).
+    InUse,
+    ExpungeIssued,
+    Corruption,
+    ServerBug,
+    ClientBug,
+    /// The operation violates a server policy and will never succeed.
+    Cannot,
+    /// A server limit, like the number of connections, has been reached.
+    Limit,
+    OverQuota,
+    AlreadyExists,
+    NonExistent,
+}
+
+impl ServerCondition {
+    /// The condition for a response code, if it is one of those in RFC 5530
+    /// that explain failures.
+    pub fn from_code(code: &ResponseCode) -> Option<Self> {
+        Some(match *code {
+            ResponseCode::Unavailable => ServerCondition::Unavailable,
+            ResponseCode::AuthenticationFailed => ServerCondition::AuthenticationFailed,
+            ResponseCode::AuthorizationFailed => ServerCondition::AuthorizationFailed,
+            ResponseCode::Expired => ServerCondition::Expired,
+            ResponseCode::PrivacyRequired => ServerCondition::PrivacyRequired,
+            ResponseCode::ContactAdmin => ServerCondition::ContactAdmin,
+            ResponseCode::NoPerm => ServerCondition::NoPerm,
+            ResponseCode::InUse => ServerCondition::InUse,
+            ResponseCode::ExpungeIssued => ServerCondition::ExpungeIssued,
+            ResponseCode::Corruption => ServerCondition::Corruption,
+            ResponseCode::ServerBug => ServerCondition::ServerBug,
+            ResponseCode::ClientBug => ServerCondition::ClientBug,
+            ResponseCode::Cannot => ServerCondition::Cannot,
+            ResponseCode::Limit => ServerCondition::Limit,
+            ResponseCode::OverQuota => ServerCondition::OverQuota,
+            ResponseCode::AlreadyExists => ServerCondition::AlreadyExists,
+            ResponseCode::NonExistent => ServerCondition::NonExistent,
+            _ => return None,
+        })
+    }
+
+    /// Whether the condition is expected to go away, so that retrying the
+    /// command later may succeed.
+    pub fn is_temporary(&self) -> bool {
+        match *self {
+            ServerCondition::Unavailable | ServerCondition::InUse | ServerCondition::Limit => {
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            Error::ConnectionClosed => io::Error::new(io::ErrorKind::UnexpectedEof, e.to_string()),
+            e => io::Error::new(io::ErrorKind::Other, e.to_string()),
+        }
+    }
+}
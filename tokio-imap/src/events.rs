@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use imap_proto::State;
+
+use proto::ResponseData;
+
+// Responses are recorded up to this many bytes of their first line.
+const MAX_SUMMARY_LEN: usize = 200;
+
+/// Something that happened on a connection, as recorded by
+/// `Client::record_events()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EventKind {
+    /// A command was sent. The arguments of commands carrying credentials,
+    /// like `LOGIN`, are left out.
+    CommandSent { tag: String, command: String },
+    /// A response was received; `line` is the start of its first line,
+    /// without literal data.
+    ResponseReceived { line: String },
+    StateChanged { from: State, to: State },
+    ConnectionClosed(ConnectionClosed),
+}
+
+/// Why a connection ended.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConnectionClosed {
+    /// The server sent `BYE`, with the given text.
+    Bye(Option<String>),
+    /// The connection was closed without a `BYE`.
+    Eof,
+    /// Reading from or writing to the connection failed.
+    Failed(String),
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    pub time: SystemTime,
+    pub kind: EventKind,
+}
+
+// The most recent events on a connection, oldest first.
+pub(crate) struct EventLog {
+    events: VecDeque<Event>,
+    capacity: usize,
+}
+
+impl EventLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn events(&self) -> Vec<Event> {
+        self.events.iter().cloned().collect()
+    }
+
+    fn record(&mut self, kind: EventKind) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(Event {
+            time: SystemTime::now(),
+            kind,
+        });
+    }
+
+    pub(crate) fn command_sent(&mut self, tag: &str, verb: &str, args: &[u8]) {
+        let command = match verb {
+            "AUTHENTICATE" | "LOGIN" => verb.to_string(),
+            _ => summarize(args),
+        };
+        self.record(EventKind::CommandSent {
+            tag: tag.to_string(),
+            command,
+        });
+    }
+
+    pub(crate) fn response_received(&mut self, rsp: &ResponseData) {
+        let line = summarize(rsp.raw());
+        self.record(EventKind::ResponseReceived { line });
+    }
+
+    pub(crate) fn connection_closed(&mut self, reason: &ConnectionClosed) {
+        self.record(EventKind::ConnectionClosed(reason.clone()));
+    }
+
+    pub(crate) fn state_changed(&mut self, from: State, to: State) {
+        if from != to {
+            self.record(EventKind::StateChanged { from, to });
+        }
+    }
+}
+
+// The first line of `data`, cut off after `MAX_SUMMARY_LEN` bytes.
+pub(crate) fn summarize(data: &[u8]) -> String {
+    let end = data.iter()
+        .position(|&b| b == b'\r' || b == b'\n')
+        .unwrap_or(data.len());
+    let line = &data[..end];
+    if line.len() > MAX_SUMMARY_LEN {
+        format!("{}...", String::from_utf8_lossy(&line[..MAX_SUMMARY_LEN]))
+    } else {
+        String::from_utf8_lossy(line).into_owned()
+    }
+}
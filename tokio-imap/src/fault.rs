@@ -0,0 +1,250 @@
+use futures::{task, Async, Poll};
+
+use std::cmp;
+use std::io::{self, Read, Write};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Describes the faults a `FaultyStream` injects.
+///
+/// Probabilities are given per thousand I/O operations. All random choices
+/// are drawn from a generator seeded with `seed`, so that a given schedule
+/// injects the same faults every time it is replayed against the same
+/// sequence of reads and writes.
+#[derive(Clone, Debug, Default)]
+pub struct FaultSchedule {
+    pub seed: u64,
+    /// Reads and writes that return `NotReady` before being retried.
+    pub delay_per_mille: u32,
+    /// Writes that only accept part of the data they are given.
+    pub partial_write_per_mille: u32,
+    /// Reads in which one of the bytes is replaced by a random value.
+    pub garble_per_mille: u32,
+    /// Closes the connection once this many bytes have been read.
+    pub disconnect_after: Option<usize>,
+    /// Closes the connection halfway through the first literal received.
+    pub disconnect_in_literal: bool,
+    /// Writes return 0 once this many bytes have been written, as they do
+    /// on a stream that cannot take any more data.
+    pub write_limit: Option<usize>,
+}
+
+/// A stream wrapper injecting failures according to a `FaultSchedule`.
+///
+/// Meant for testing how code built on top of `ImapCodec` deals with slow
+/// servers, dropped connections and corrupted data. Once the connection has
+/// been closed, reads return EOF and writes fail with `BrokenPipe`.
+pub struct FaultyStream<S> {
+    inner: S,
+    schedule: FaultSchedule,
+    rng: XorShift,
+    read: usize,
+    written: usize,
+    line_end: Vec<u8>,
+    literal_remaining: usize,
+    disconnected: bool,
+}
+
+impl<S> FaultyStream<S> {
+    pub fn new(inner: S, schedule: FaultSchedule) -> Self {
+        let rng = XorShift::new(schedule.seed);
+        Self {
+            inner,
+            schedule,
+            rng,
+            read: 0,
+            written: 0,
+            line_end: Vec::new(),
+            literal_remaining: 0,
+            disconnected: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Whether an injected disconnect has happened.
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    fn chance(&mut self, per_mille: u32) -> bool {
+        per_mille > 0 && self.rng.next() % 1000 < u64::from(per_mille)
+    }
+
+    fn delay(&mut self) -> bool {
+        let per_mille = self.schedule.delay_per_mille;
+        if self.chance(per_mille) {
+            task::current().notify();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Limits a read of `len` bytes so that any scheduled disconnect happens
+    // at the right position, returning `None` if it has to happen right now.
+    fn read_limit(&self, len: usize) -> Option<usize> {
+        let mut limit = len;
+        if let Some(after) = self.schedule.disconnect_after {
+            if self.read >= after {
+                return None;
+            }
+            limit = cmp::min(limit, after - self.read);
+        }
+        if self.schedule.disconnect_in_literal && self.literal_remaining > 0 {
+            if self.literal_remaining == 1 {
+                return None;
+            }
+            limit = cmp::min(limit, self.literal_remaining / 2);
+        }
+        Some(limit)
+    }
+
+    // Keeps track of literals in the data read, so that a disconnect can be
+    // injected in the middle of one.
+    fn track(&mut self, data: &[u8]) {
+        for &b in data {
+            if self.literal_remaining > 0 {
+                self.literal_remaining -= 1;
+                if self.literal_remaining == 0 && self.schedule.disconnect_in_literal {
+                    // The first literal was read in full after all.
+                    self.schedule.disconnect_in_literal = false;
+                }
+                continue;
+            }
+            if self.line_end.len() == 32 {
+                self.line_end.remove(0);
+            }
+            self.line_end.push(b);
+            if self.line_end.ends_with(b"\r\n") {
+                self.literal_remaining = literal_len(&self.line_end).unwrap_or(0);
+                self.line_end.clear();
+            }
+        }
+    }
+}
+
+impl<S: Read> Read for FaultyStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.disconnected || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.delay() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let limit = match self.read_limit(buf.len()) {
+            Some(limit) => limit,
+            None => {
+                self.disconnected = true;
+                return Ok(0);
+            },
+        };
+        let read = self.inner.read(&mut buf[..limit])?;
+        let per_mille = self.schedule.garble_per_mille;
+        if read > 0 && self.chance(per_mille) {
+            let pos = self.rng.next() as usize % read;
+            buf[pos] = self.rng.next() as u8;
+        }
+        self.track(&buf[..read]);
+        self.read += read;
+        if self.schedule.disconnect_in_literal && self.literal_remaining > 0 {
+            // Cut the connection on the next read, while still inside the literal.
+            self.schedule.disconnect_after = Some(self.read);
+        }
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for FaultyStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.disconnected {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.delay() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let mut len = buf.len();
+        let per_mille = self.schedule.partial_write_per_mille;
+        if len > 1 && self.chance(per_mille) {
+            len = 1 + self.rng.next() as usize % (len - 1);
+        }
+        if let Some(limit) = self.schedule.write_limit {
+            len = cmp::min(len, limit.saturating_sub(self.written));
+            if len == 0 {
+                return Ok(0);
+            }
+        }
+        let written = self.inner.write(&buf[..len])?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.disconnected {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for FaultyStream<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for FaultyStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if self.disconnected {
+            return Ok(Async::Ready(()));
+        }
+        self.inner.shutdown()
+    }
+}
+
+// Extracts the length from a line ending in a literal marker like `{42}\r\n`.
+fn literal_len(line: &[u8]) -> Option<usize> {
+    let line = &line[..line.len() - 2];
+    if !line.ends_with(b"}") {
+        return None;
+    }
+    let start = line.iter().rposition(|&b| b == b'{')?;
+    let digits = &line[start + 1..line.len() - 1];
+    let digits = if digits.ends_with(b"+") {
+        &digits[..digits.len() - 1]
+    } else {
+        digits
+    };
+    if digits.is_empty() || !digits.iter().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    ::std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+// xorshift64*, which is plenty for picking faults.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // The state must never be zero.
+        XorShift(cmp::max(seed ^ 0x9E37_79B9_7F4A_7C15, 1))
+    }
+
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
@@ -0,0 +1,127 @@
+/// Known email providers, used to fill in details not covered by capabilities.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Provider {
+    Fastmail,
+    Gmail,
+    ICloud,
+    Outlook,
+    Yahoo,
+    Unknown,
+}
+
+impl Provider {
+    /// Guesses the provider from the server's host name.
+    pub fn from_host(host: &str) -> Provider {
+        let host = host.trim_right_matches('.').to_lowercase();
+        let matches = |domain: &str| host == domain || host.ends_with(&format!(".{}", domain));
+        if matches("gmail.com") || matches("googlemail.com") {
+            Provider::Gmail
+        } else if matches("office365.com") || matches("outlook.com") {
+            Provider::Outlook
+        } else if matches("yahoo.com") {
+            Provider::Yahoo
+        } else if matches("me.com") || matches("icloud.com") {
+            Provider::ICloud
+        } else if matches("fastmail.com") {
+            Provider::Fastmail
+        } else {
+            Provider::Unknown
+        }
+    }
+
+    fn from_capabilities(capabilities: &[String]) -> Provider {
+        let has = |name: &str| capabilities.iter().any(|c| c.eq_ignore_ascii_case(name));
+        if has("X-GM-EXT-1") {
+            Provider::Gmail
+        } else if has("XYMHIGHESTMODSEQ") {
+            Provider::Yahoo
+        } else {
+            Provider::Unknown
+        }
+    }
+
+    /// The number of simultaneous connections the provider is known to allow
+    /// per account.
+    pub fn max_connections(&self) -> Option<u32> {
+        match *self {
+            Provider::Gmail => Some(15),
+            Provider::Outlook => Some(20),
+            Provider::Yahoo => Some(5),
+            Provider::ICloud | Provider::Fastmail | Provider::Unknown => None,
+        }
+    }
+}
+
+/// What a server supports, derived from its capabilities.
+#[derive(Clone, Debug)]
+pub struct Features {
+    pub capabilities: Vec<String>,
+    pub provider: Provider,
+    pub supports_idle: bool,
+    pub supports_move: bool,
+    pub supports_uidplus: bool,
+    pub supports_condstore: bool,
+    pub supports_qresync: bool,
+    pub supports_literal_plus: bool,
+    pub supports_compress: bool,
+    pub supports_namespace: bool,
+    pub supports_enable: bool,
+    pub supports_utf8: bool,
+    /// How many connections per account can be used at the same time, if
+    /// known for the provider.
+    pub max_connections_hint: Option<u32>,
+}
+
+impl Features {
+    pub fn from_capabilities<S: AsRef<str>>(capabilities: &[S]) -> Features {
+        let capabilities: Vec<String> = capabilities
+            .iter()
+            .map(|c| c.as_ref().to_string())
+            .collect();
+        let provider = Provider::from_capabilities(&capabilities);
+        let mut features = Features {
+            provider,
+            supports_idle: false,
+            supports_move: false,
+            supports_uidplus: false,
+            supports_condstore: false,
+            supports_qresync: false,
+            supports_literal_plus: false,
+            supports_compress: false,
+            supports_namespace: false,
+            supports_enable: false,
+            supports_utf8: false,
+            max_connections_hint: provider.max_connections(),
+            capabilities,
+        };
+        features.supports_idle = features.has("IDLE");
+        features.supports_move = features.has("MOVE");
+        features.supports_uidplus = features.has("UIDPLUS");
+        // QRESYNC implies CONDSTORE (RFC 7162, section 3.2.3).
+        features.supports_qresync = features.has("QRESYNC");
+        features.supports_condstore = features.has("CONDSTORE") || features.supports_qresync;
+        features.supports_literal_plus = features.has("LITERAL+");
+        features.supports_compress = features.has("COMPRESS=DEFLATE");
+        features.supports_namespace = features.has("NAMESPACE");
+        features.supports_enable = features.has("ENABLE");
+        features.supports_utf8 = features.has("UTF8=ACCEPT") || features.has("UTF8=ONLY");
+        features
+    }
+
+    /// Refines the provider preset using the server's host name, for
+    /// providers that cannot be recognized from their capabilities.
+    pub fn for_host(mut self, host: &str) -> Features {
+        if self.provider == Provider::Unknown {
+            self.provider = Provider::from_host(host);
+            self.max_connections_hint = self.provider.max_connections();
+        }
+        self
+    }
+
+    /// Whether the server advertised the given capability.
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(capability))
+    }
+}
@@ -0,0 +1,227 @@
+//! Header fields of messages, as fetched for message listings, with the
+//! encoded words (RFC 2047) in their values decoded.
+
+use base64;
+
+use std::borrow::Cow;
+use std::slice;
+
+/// The header fields of a message, in the order in which they appear.
+///
+/// Field names are looked up without regard to case. Folded values are
+/// unfolded, and encoded words in them decoded with `decode_words()`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Parses the header of a message, up to the first empty line.
+    pub fn parse(header: &[u8]) -> Self {
+        let mut fields: Vec<(String, Vec<u8>)> = Vec::new();
+        for line in header.split(|&b| b == b'\n') {
+            let line = match line.split_last() {
+                Some((&b'\r', line)) => line,
+                _ => line,
+            };
+            if line.is_empty() {
+                break;
+            }
+            if line[0] == b' ' || line[0] == b'\t' {
+                // Unfolding removes the line break, keeping the whitespace.
+                if let Some(&mut (_, ref mut value)) = fields.last_mut() {
+                    value.extend_from_slice(line);
+                }
+                continue;
+            }
+            if let Some(colon) = line.iter().position(|&b| b == b':') {
+                let name = String::from_utf8_lossy(&line[..colon]).trim().to_string();
+                fields.push((name, line[colon + 1..].to_vec()));
+            }
+        }
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| {
+                let value = String::from_utf8_lossy(&value);
+                let value = decode_words(value.trim()).into_owned();
+                (name, value)
+            })
+            .collect();
+        Headers { fields }
+    }
+
+    /// The value of the first field named `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|&&(ref field, _)| field.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    /// The values of all fields named `name`, such as the `Received` fields.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|&&(ref field, _)| field.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// The fields as `(name, value)` pairs, with names as they were sent.
+    pub fn iter(&self) -> slice::Iter<(String, String)> {
+        self.fields.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// Decodes the encoded words, like `=?UTF-8?Q?Gr=C3=BC=C3=9Fe?=`, in a
+/// header field value (RFC 2047).
+///
+/// Whitespace between adjacent encoded words is dropped, as the standard
+/// requires. Words in charsets other than UTF-8, US-ASCII and ISO-8859-1,
+/// and words that are not valid, are left as they are.
+pub fn decode_words(value: &str) -> Cow<str> {
+    if !value.contains("=?") {
+        return Cow::Borrowed(value);
+    }
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, candidate) = rest.split_at(start);
+        match decode_word(candidate) {
+            Some((text, len)) => {
+                if !(after_word && before.trim().is_empty()) {
+                    decoded.push_str(before);
+                }
+                decoded.push_str(&text);
+                after_word = true;
+                rest = &candidate[len..];
+            },
+            None => {
+                decoded.push_str(&rest[..start + 2]);
+                after_word = false;
+                rest = &candidate[2..];
+            },
+        }
+    }
+    decoded.push_str(rest);
+    Cow::Owned(decoded)
+}
+
+// Decodes the encoded word at the start of `s`, giving its text and its
+// length in `s`.
+fn decode_word(s: &str) -> Option<(String, usize)> {
+    let inner = &s[2..];
+    let charset_end = inner.find('?')?;
+    let charset = &inner[..charset_end];
+    let encoding = inner[charset_end + 1..].chars().next()?;
+    let text_start = charset_end + 3;
+    if inner.as_bytes().get(charset_end + 2) != Some(&b'?') {
+        return None;
+    }
+    let text_len = inner[text_start..].find("?=")?;
+    let text = &inner[text_start..text_start + text_len];
+    if charset.contains(char::is_whitespace) || text.contains(char::is_whitespace) {
+        return None;
+    }
+    let bytes = match encoding {
+        'B' | 'b' => base64::decode(text).ok()?,
+        'Q' | 'q' => decode_q(text)?,
+        _ => return None,
+    };
+    // The charset may carry a language, like `UTF-8*en` (RFC 2231).
+    let charset = charset.split('*').next().unwrap_or("").to_ascii_lowercase();
+    let text = match &*charset {
+        "utf-8" | "utf8" | "us-ascii" => String::from_utf8_lossy(&bytes).into_owned(),
+        "iso-8859-1" | "latin1" => bytes.iter().map(|&b| b as char).collect(),
+        _ => return None,
+    };
+    Some((text, 2 + text_start + text_len + 2))
+}
+
+// Decodes the `Q` encoding: `_` for a space and `=XX` for a byte in hex.
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.bytes();
+    while let Some(b) = rest.next() {
+        match b {
+            b'_' => bytes.push(b' '),
+            b'=' => {
+                let hex = [rest.next()?, rest.next()?];
+                let hex = ::std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            },
+            _ => bytes.push(b),
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folded() {
+        let headers = Headers::parse(
+            b"Subject: a long\r\n\tsubject\r\nTo: a@example.com,\r\n b@example.com\r\n\
+              received: one\r\nReceived: two\r\n\r\nX-Body: not a header\r\n",
+        );
+        assert_eq!(headers.get("subject"), Some("a long\tsubject"));
+        assert_eq!(headers.get("TO"), Some("a@example.com, b@example.com"));
+        assert_eq!(headers.get_all("Received"), vec!["one", "two"]);
+        assert!(!headers.contains("X-Body"));
+        assert_eq!(headers.len(), 4);
+    }
+
+    #[test]
+    fn folded_encoded_words() {
+        let headers = Headers::parse(
+            b"Subject: =?UTF-8?Q?Gr=C3=BC?=\r\n =?UTF-8?Q?=C3=9Fe?= aus Berlin\n\n",
+        );
+        assert_eq!(headers.get("Subject"), Some("Grüße aus Berlin"));
+    }
+
+    #[test]
+    fn adjacent_words() {
+        assert_eq!(decode_words("=?UTF-8?B?R3LDvA==?= =?UTF-8?Q?=C3=9Fe?="), "Grüße");
+        assert_eq!(decode_words("=?ISO-8859-1?Q?a?=\t  =?us-ascii?Q?b?="), "ab");
+        // Whitespace between a word and plain text is kept.
+        assert_eq!(decode_words("=?UTF-8?Q?a?= b =?UTF-8?Q?c?="), "a b c");
+        assert_eq!(decode_words("x =?UTF-8?Q?a_b?="), "x a b");
+        assert_eq!(decode_words("=?utf-8*en?q?hi?="), "hi");
+    }
+
+    #[test]
+    fn malformed_words() {
+        for &value in &[
+            "=?UTF-8?Q?unterminated",
+            "=?UTF-8?X?unknown encoding?=",
+            "=?UTF-8?Q?bad=ZZhex?=",
+            "=?UTF-8?B?not*base64?=",
+            "=?KOI8-R?Q?=F0?=",
+            "=?UTF-8?Q?with space?=",
+            "=?",
+            "=?UTF-8",
+            "=?UTF-8?",
+            "=?UTF-8?Q",
+        ] {
+            assert_eq!(decode_words(value), value);
+        }
+        // A malformed word does not keep a valid one after it from decoding.
+        assert_eq!(decode_words("=?x =?UTF-8?Q?ok?="), "=?x ok");
+        assert_eq!(decode_words("plain"), "plain");
+    }
+}
@@ -3,21 +3,69 @@
 #![deny(future_incompatible)]
 #![deny(bad_style)]
 
+extern crate base64;
 extern crate bytes;
+extern crate flate2;
 #[macro_use]
 extern crate futures;
+#[cfg(feature = "compat")]
+extern crate futures03;
 extern crate futures_state_stream;
 extern crate imap_proto;
+#[macro_use]
+extern crate log;
+#[cfg(feature = "tls-native")]
 extern crate native_tls;
 extern crate nom;
+extern crate ring;
+#[cfg(feature = "tls-rustls")]
+extern crate rustls;
 extern crate tokio;
 extern crate tokio_codec;
+#[cfg(feature = "tls-rustls")]
+extern crate tokio_rustls;
+#[cfg(feature = "tls-native")]
 extern crate tokio_tls;
+#[cfg(feature = "tls-rustls")]
+extern crate webpki_roots;
 
+pub mod backup;
 pub mod client;
+pub mod clock;
+#[cfg(feature = "compat")]
+pub mod compat;
+pub mod compress;
+pub mod diagnostics;
+pub mod downgrade;
+pub mod error;
+pub mod events;
+#[cfg(any(test, feature = "mock"))]
+pub mod fault;
+pub mod features;
+pub mod headers;
+pub mod maildir;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod notify;
+pub mod pool;
 pub mod proto;
+pub mod reconnect;
+pub mod session;
+pub mod set;
+pub mod store;
+#[cfg(feature = "submit")]
+pub mod submit;
+pub mod timeout;
+pub mod tls;
+pub mod watch;
 
-pub use client::{ImapClient, TlsClient};
+pub use client::{Client, ClientBuilder, ImapClient, TlsClient};
+pub use error::{Error, Result, ServerCondition};
+pub use imap_proto::SequenceSet;
+pub use reconnect::ReconnectingClient;
+pub use session::Session;
+pub use set::UidSet;
+pub use watch::{MailboxSnapshot, MailboxWatch};
 
 pub mod types {
     pub use imap_proto::types::*;
@@ -0,0 +1,220 @@
+//! Mapping between IMAP flags and Maildir info suffixes.
+//!
+//! A Maildir message file name ends in an info suffix like `:2,FRS`, listing
+//! one letter per flag in ASCII order. The system flags have fixed upper-case
+//! letters; keywords are assigned the lower-case letters `a` to `z` in the
+//! order they are first seen, as in Dovecot's `dovecot-keywords` file, so the
+//! assignment has to be stored alongside the messages.
+//!
+//! These are building blocks for programs that export to or sync with a
+//! Maildir; the backups written by `Session::backup_since()` are mbox archives
+//! and do not use them.
+
+// Letters for the system flags, in ASCII order. `$Forwarded` is treated like
+// a system flag as it corresponds to the standard "passed" flag.
+const SYSTEM_FLAGS: [(char, &str); 6] = [
+    ('D', "\\Draft"),
+    ('F', "\\Flagged"),
+    ('P', "$Forwarded"),
+    ('R', "\\Answered"),
+    ('S', "\\Seen"),
+    ('T', "\\Deleted"),
+];
+
+// Keywords registered with IANA (RFC 5788) or in common use, with the
+// spellings some clients use instead.
+const KEYWORDS: [(&str, &[&str]); 8] = [
+    ("$Forwarded", &["Forwarded", "$Forward"]),
+    ("$Important", &["Important"]),
+    ("$Junk", &["Junk", "$Spam"]),
+    ("$MDNSent", &["MDNSent"]),
+    ("$NotJunk", &["NotJunk", "NonJunk", "$NonJunk", "$NotSpam"]),
+    ("$Phishing", &["Phishing"]),
+    ("$Submitted", &[]),
+    ("$SubmitPending", &[]),
+];
+
+const MAX_KEYWORDS: usize = 26;
+
+/// The canonical spelling of a well-known keyword, such as `$Forwarded` for
+/// `Forwarded`, or `keyword` itself if it is not known.
+pub fn canonical_keyword(keyword: &str) -> &str {
+    for &(canonical, aliases) in &KEYWORDS {
+        if canonical.eq_ignore_ascii_case(keyword)
+            || aliases.iter().any(|alias| alias.eq_ignore_ascii_case(keyword))
+        {
+            return canonical;
+        }
+    }
+    keyword
+}
+
+/// The Maildir letter for a system flag (or `$Forwarded`).
+pub fn flag_letter(flag: &str) -> Option<char> {
+    let flag = canonical_keyword(flag);
+    SYSTEM_FLAGS
+        .iter()
+        .find(|&&(_, name)| name.eq_ignore_ascii_case(flag))
+        .map(|&(letter, _)| letter)
+}
+
+/// The system flag (or `$Forwarded`) for a Maildir letter.
+pub fn letter_flag(letter: char) -> Option<&'static str> {
+    SYSTEM_FLAGS
+        .iter()
+        .find(|&&(l, _)| l == letter)
+        .map(|&(_, name)| name)
+}
+
+/// Assigns Maildir letters to keywords, and converts between flag lists and
+/// info suffixes.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct KeywordMap {
+    keywords: Vec<String>,
+}
+
+impl KeywordMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restores a map from its keywords, in letter order, as returned by
+    /// `keywords()`. Keywords beyond the 26th are ignored.
+    pub fn from_keywords<I, S>(keywords: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>, {
+        let keywords = keywords
+            .into_iter()
+            .map(|keyword| keyword.into())
+            .take(MAX_KEYWORDS)
+            .collect();
+        Self { keywords }
+    }
+
+    /// The keywords with letters assigned, starting with the one for `a`.
+    pub fn keywords(&self) -> &[String] {
+        &self.keywords
+    }
+
+    /// The letter for `keyword`, assigning the next free one if it has none
+    /// yet. Returns `None` if all 26 letters are taken.
+    pub fn letter(&mut self, keyword: &str) -> Option<char> {
+        let keyword = canonical_keyword(keyword);
+        let pos = match self.position(keyword) {
+            Some(pos) => pos,
+            None if self.keywords.len() < MAX_KEYWORDS => {
+                self.keywords.push(keyword.to_string());
+                self.keywords.len() - 1
+            },
+            None => return None,
+        };
+        Some((b'a' + pos as u8) as char)
+    }
+
+    fn position(&self, keyword: &str) -> Option<usize> {
+        self.keywords
+            .iter()
+            .position(|known| known.eq_ignore_ascii_case(keyword))
+    }
+
+    /// The info suffix for `flags`, like `2,FRSa`, without the leading colon.
+    ///
+    /// `\Recent` is not stored in Maildir and is left out, as are keywords
+    /// once all letters are taken.
+    pub fn info<S>(&mut self, flags: &[S]) -> String
+    where
+        S: AsRef<str>, {
+        let mut letters = Vec::new();
+        for flag in flags {
+            let flag = flag.as_ref();
+            let letter = match flag_letter(flag) {
+                Some(letter) => Some(letter),
+                None if flag.starts_with('\\') => None,
+                None => self.letter(flag),
+            };
+            if let Some(letter) = letter {
+                if !letters.contains(&letter) {
+                    letters.push(letter);
+                }
+            }
+        }
+        letters.sort();
+        let mut info = "2,".to_string();
+        info.extend(letters);
+        info
+    }
+
+    /// The flags listed in an info suffix, with or without the leading colon.
+    ///
+    /// Letters without a known meaning are skipped.
+    pub fn flags(&self, info: &str) -> Vec<String> {
+        let letters = info.trim_left_matches(':');
+        let letters = match letters.find(',') {
+            Some(pos) if letters.starts_with('2') => &letters[pos + 1..],
+            _ => return Vec::new(),
+        };
+        letters
+            .chars()
+            .filter_map(|letter| match letter {
+                'a'..='z' => self.keywords
+                    .get((letter as u8 - b'a') as usize)
+                    .cloned(),
+                _ => letter_flag(letter).map(|flag| flag.to_string()),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_flags() {
+        let mut map = KeywordMap::new();
+        let info = map.info(&["\\Seen", "\\Answered", "\\Recent", "\\Flagged", "\\Seen"]);
+        assert_eq!(info, "2,FRS");
+        assert_eq!(map.flags(&info), vec!["\\Flagged", "\\Answered", "\\Seen"]);
+        assert_eq!(map.flags(":2,DT"), vec!["\\Draft", "\\Deleted"]);
+        assert!(map.keywords().is_empty());
+    }
+
+    #[test]
+    fn keywords_round_trip() {
+        let mut map = KeywordMap::new();
+        let flags = ["Junk", "\\Seen", "Forwarded", "work", "$Important"];
+        let info = map.info(&flags);
+        assert_eq!(info, "2,PSabc");
+        assert_eq!(map.keywords(), &["$Junk", "work", "$Important"]);
+        assert_eq!(
+            map.flags(&info),
+            vec!["$Forwarded", "\\Seen", "$Junk", "work", "$Important"]
+        );
+
+        // The letters stay the same once restored from the stored keywords.
+        let mut restored = KeywordMap::from_keywords(map.keywords().to_vec());
+        assert_eq!(restored, map);
+        assert_eq!(restored.info(&["$Important", "$Spam"]), "2,ac");
+        assert_eq!(restored.flags("2,ac"), vec!["$Junk", "$Important"]);
+    }
+
+    #[test]
+    fn letters_run_out() {
+        let mut map = KeywordMap::new();
+        let keywords = (0..30).map(|i| format!("k{}", i)).collect::<Vec<_>>();
+        let info = map.info(&keywords);
+        assert_eq!(info, "2,abcdefghijklmnopqrstuvwxyz");
+        assert_eq!(map.letter("k29"), None);
+        assert_eq!(map.letter("K3"), Some('d'));
+        assert_eq!(KeywordMap::from_keywords(keywords).keywords().len(), 26);
+    }
+
+    #[test]
+    fn unknown_letters() {
+        let map = KeywordMap::new();
+        assert_eq!(map.flags("2,Sa"), vec!["\\Seen"]);
+        assert_eq!(map.flags("1,S"), Vec::<String>::new());
+        assert_eq!(map.flags("S"), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,302 @@
+//! A scripted IMAP server, for testing code that uses the client without a
+//! real server.
+//!
+//! The server accepts a single connection on localhost, sends the greeting
+//! and then works through its script: it checks that the client sends the
+//! expected commands, in order, and replies with the given responses.
+//!
+//! ```ignore
+//! let (addr, server) = MockServer::new()
+//!     .expect("LOGIN \"me\" \"secret\"")
+//!     .complete("OK [CAPABILITY IMAP4rev1] logged in")
+//!     .expect("SELECT \"INBOX\"")
+//!     .respond("* 3 EXISTS")
+//!     .respond("* OK [UIDVALIDITY 42] UIDs valid")
+//!     .complete("OK [READ-WRITE] done")
+//!     .bind()?;
+//! let client = Client::connect_insecure(&addr).and_then(|(_, client)| {
+//!     Session::new(client)
+//!         .login("me", "secret")
+//!         .and_then(|(_, session)| session.select("INBOX"))
+//! });
+//! // Run `server` and `client` together; `server` fails if the client did
+//! // not send the expected commands.
+//! ```
+
+use bytes::{BufMut, BytesMut};
+
+use futures::{future, Future, Sink, Stream};
+use futures::future::Loop;
+
+use std::io;
+use std::net::SocketAddr;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio_codec::{Decoder, Encoder, Framed};
+
+use error::Error;
+
+pub type MockFuture = Box<Future<Item = (), Error = Error> + Send>;
+
+type Transport = Framed<TcpStream, MockCodec>;
+type ReadFuture<T> = Box<Future<Item = T, Error = Error> + Send>;
+type StepFuture = ReadFuture<(Transport, Option<String>)>;
+
+enum Step {
+    // A command, without its tag.
+    Expect(String),
+    // A line that is not a command, such as `DONE` ending `IDLE`.
+    ExpectLine(String),
+    Respond(String),
+    // A tagged response to the last command.
+    Complete(String),
+    Close,
+}
+
+/// Builds the script of a mock server.
+pub struct MockServer {
+    greeting: String,
+    steps: Vec<Step>,
+}
+
+impl MockServer {
+    pub fn new() -> Self {
+        Self {
+            greeting: "* OK [CAPABILITY IMAP4rev1] mock server ready".to_string(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Replaces the greeting line, for example to greet with `PREAUTH`.
+    pub fn greeting(mut self, line: &str) -> Self {
+        self.greeting = line.to_string();
+        self
+    }
+
+    /// Expects the client to send `command`, which is compared without its
+    /// tag.
+    ///
+    /// Literals are part of the command as they are sent, as in `APPEND
+    /// "INBOX" {5}\r\nhello`; the server sends the continuation requests
+    /// for them.
+    pub fn expect(mut self, command: &str) -> Self {
+        self.steps.push(Step::Expect(command.to_string()));
+        self
+    }
+
+    /// Expects the client to send a line that is not a command, such as the
+    /// `DONE` that ends `IDLE` or an `AUTHENTICATE` response.
+    pub fn expect_line(mut self, line: &str) -> Self {
+        self.steps.push(Step::ExpectLine(line.to_string()));
+        self
+    }
+
+    /// Sends `line`, such as an untagged response or a continuation request.
+    pub fn respond(mut self, line: &str) -> Self {
+        self.steps.push(Step::Respond(line.to_string()));
+        self
+    }
+
+    /// Completes the last command by sending its tag followed by `status`,
+    /// as in `OK done`.
+    pub fn complete(mut self, status: &str) -> Self {
+        self.steps.push(Step::Complete(status.to_string()));
+        self
+    }
+
+    /// Closes the connection, ending the script.
+    pub fn close(mut self) -> Self {
+        self.steps.push(Step::Close);
+        self
+    }
+
+    /// Starts listening on a free port on localhost.
+    ///
+    /// Returns the address to connect to, and the future that serves the
+    /// first connection. It resolves once the script is done, closing the
+    /// connection, and fails when the client sends something else than the
+    /// script expects or closes the connection early.
+    pub fn bind(self) -> Result<(SocketAddr, MockFuture), Error> {
+        let listener = TcpListener::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))?;
+        let addr = listener.local_addr()?;
+        let MockServer { greeting, steps } = self;
+        let accepted = listener
+            .incoming()
+            .into_future()
+            .map_err(|(e, _)| Error::Io(e))
+            .and_then(|(stream, _)| stream.ok_or(Error::ConnectionClosed));
+        let served = accepted
+            .and_then(move |stream| {
+                MockCodec::default()
+                    .framed(stream)
+                    .send(greeting.into_bytes())
+            })
+            .and_then(move |transport| {
+                future::loop_fn(
+                    (transport, steps.into_iter(), None),
+                    |(transport, mut steps, tag)| match steps.next() {
+                        // Dropping the transport closes the connection.
+                        Some(Step::Close) | None => future::Either::B(future::ok(Loop::Break(()))),
+                        Some(step) => future::Either::A(
+                            run(transport, step, tag)
+                                .map(|(transport, tag)| Loop::Continue((transport, steps, tag))),
+                        ),
+                    },
+                )
+            });
+        Ok((addr, Box::new(served)))
+    }
+}
+
+impl Default for MockServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn run(transport: Transport, step: Step, tag: Option<String>) -> StepFuture {
+    match step {
+        Step::Expect(expected) => Box::new(read_command(transport).and_then(
+            move |(transport, received)| {
+                let received = String::from_utf8_lossy(&received).into_owned();
+                let mut parts = received.splitn(2, ' ');
+                let tag = parts.next().unwrap_or("").to_string();
+                let command = parts.next().unwrap_or("");
+                if command != expected {
+                    return Err(mismatch(&expected, &received));
+                }
+                Ok((transport, Some(tag)))
+            },
+        )),
+        Step::ExpectLine(expected) => Box::new(read_command(transport).and_then(
+            move |(transport, received)| {
+                if received != expected.as_bytes() {
+                    let received = String::from_utf8_lossy(&received);
+                    return Err(mismatch(&expected, &received));
+                }
+                Ok((transport, tag))
+            },
+        )),
+        Step::Respond(line) => Box::new(
+            transport
+                .send(line.into_bytes())
+                .map(move |transport| (transport, tag)),
+        ),
+        Step::Complete(status) => {
+            let line = format!("{} {}", tag.as_ref().map(|t| &t[..]).unwrap_or("*"), status);
+            Box::new(
+                transport
+                    .send(line.into_bytes())
+                    .map(move |transport| (transport, tag)),
+            )
+        },
+        Step::Close => unreachable!(),
+    }
+}
+
+fn mismatch(expected: &str, received: &str) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("mock server expected {:?}, received {:?}", expected, received),
+    ))
+}
+
+// Reads a line from the client, together with any literals it announces,
+// sending continuation requests for those that need them.
+fn read_command(
+    transport: Transport,
+) -> Box<Future<Item = (Transport, Vec<u8>), Error = Error> + Send> {
+    Box::new(future::loop_fn(
+        (transport, Vec::new()),
+        |(transport, mut received)| {
+            read(transport).and_then(move |(line, transport)| -> ReadFuture<Loop<_, _>> {
+                received.extend(&line);
+                let (len, plus) = match literal_marker(&line) {
+                    Some(marker) => marker,
+                    None => return Box::new(future::ok(Loop::Break((transport, received)))),
+                };
+                received.extend(b"\r\n");
+                let continued: ReadFuture<Transport> = if plus {
+                    Box::new(future::ok(transport))
+                } else {
+                    Box::new(transport.send(b"+ Ready for literal data".to_vec()))
+                };
+                Box::new(continued.and_then(move |mut transport| {
+                    transport.codec_mut().literal = Some(len);
+                    read(transport).map(move |(data, transport)| {
+                        received.extend(data);
+                        Loop::Continue((transport, received))
+                    })
+                }))
+            })
+        },
+    ))
+}
+
+// Reads the next line or literal.
+fn read(transport: Transport) -> ReadFuture<(Vec<u8>, Transport)> {
+    Box::new(
+        transport
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(|(data, transport)| match data {
+                Some(data) => Ok((data, transport)),
+                None => Err(Error::ConnectionClosed),
+            }),
+    )
+}
+
+// The length of the literal announced at the end of `line`, and whether it
+// is non-synchronizing (`{n+}`, RFC 7888).
+fn literal_marker(line: &[u8]) -> Option<(usize, bool)> {
+    if !line.ends_with(b"}") {
+        return None;
+    }
+    let start = line.iter().rposition(|&b| b == b'{')?;
+    let marker = String::from_utf8_lossy(&line[start + 1..line.len() - 1]).into_owned();
+    let (digits, plus) = if marker.ends_with('+') {
+        (&marker[..marker.len() - 1], true)
+    } else {
+        (&marker[..], false)
+    };
+    digits.parse().ok().map(|len| (len, plus))
+}
+
+// Frames lines ending in CRLF, or the next `literal` bytes when set.
+#[derive(Default)]
+struct MockCodec {
+    literal: Option<usize>,
+}
+
+impl Decoder for MockCodec {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Vec<u8>>, Error> {
+        if let Some(len) = self.literal {
+            if buf.len() < len {
+                return Ok(None);
+            }
+            self.literal = None;
+            return Ok(Some(buf.split_to(len).to_vec()));
+        }
+        let end = match buf.windows(2).position(|w| w == b"\r\n") {
+            Some(end) => end,
+            None => return Ok(None),
+        };
+        let line = buf.split_to(end + 2);
+        Ok(Some(line[..end].to_vec()))
+    }
+}
+
+impl Encoder for MockCodec {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn encode(&mut self, line: Vec<u8>, dst: &mut BytesMut) -> Result<(), Error> {
+        dst.reserve(line.len() + 2);
+        dst.put_slice(&line);
+        dst.put_slice(b"\r\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+//! Mailbox change events requested with `NOTIFY` (RFC 5465).
+//!
+//! After `CommandBuilder::notify()` succeeds, the server reports changes as
+//! untagged responses at any time. `Client::notifications()` turns them into
+//! a stream of `Notification`s:
+//!
+//! ```ignore
+//! let notifications = client.notifications();
+//! let groups = [
+//!     EventGroup::new(NotifyFilter::Selected, vec![
+//!         NotifyEvent::MessageNew(vec![Attribute::Uid]),
+//!         NotifyEvent::MessageExpunge,
+//!         NotifyEvent::FlagChange,
+//!     ]),
+//!     EventGroup::new(NotifyFilter::Personal, vec![
+//!         NotifyEvent::MessageNew(vec![]),
+//!         NotifyEvent::MessageExpunge,
+//!         NotifyEvent::MailboxName,
+//!     ]),
+//! ];
+//! let done = client.call(CommandBuilder::notify(&groups, true)).collect();
+//! ```
+
+use futures::{Async, Poll, Stream};
+
+use imap_proto::{MailboxDatum, Response, ResponseCode};
+
+use client::UnsolicitedResponses;
+use proto::ResponseData;
+use session::{Fetch, MailboxStatus};
+use set::UidSet;
+
+/// A change reported by the server.
+#[derive(Debug)]
+pub enum Notification {
+    /// The selected mailbox now holds this many messages.
+    Exists(u32),
+    /// A message was expunged from the selected mailbox.
+    Expunge(u32),
+    /// Messages were expunged from the selected mailbox, reported by UID
+    /// because QRESYNC is enabled.
+    Vanished(UidSet),
+    /// A message in the selected mailbox changed flags, or arrived and the
+    /// `MessageNew` event asked for its attributes.
+    Fetch(Fetch),
+    /// Messages arrived in or were expunged from another mailbox.
+    Status {
+        mailbox: String,
+        status: MailboxStatus,
+    },
+    /// A mailbox was created, renamed or deleted, or its subscription
+    /// changed. A deleted mailbox has the `\NonExistent` flag.
+    Mailbox { name: String, flags: Vec<String> },
+    /// Annotations of a mailbox, or of the server if `mailbox` is empty,
+    /// changed (RFC 5464); their new values can be asked for with
+    /// `GETMETADATA`.
+    Metadata {
+        mailbox: String,
+        entries: Vec<String>,
+    },
+    /// The server dropped events it could not keep up with, and stopped
+    /// sending them (RFC 5465, section 5.8). The client should resynchronize
+    /// and issue `NOTIFY` again.
+    Overflow,
+}
+
+impl Notification {
+    // The event a response reports, if any.
+    fn from_response(rsp: ResponseData, utf8: bool) -> Option<Self> {
+        let notification = match *rsp.parsed() {
+            Response::MailboxData(MailboxDatum::Exists(num)) => Notification::Exists(num),
+            Response::Expunge(num) => Notification::Expunge(num),
+            Response::Vanished {
+                earlier: false,
+                ref uids,
+            } => Notification::Vanished(uids.iter().cloned().collect()),
+            Response::Fetch(..) => {
+                return Some(Notification::Fetch(Fetch::from_response(rsp, utf8)))
+            },
+            Response::MailboxData(MailboxDatum::Status {
+                mailbox,
+                ref status,
+            }) => {
+                let mut counters = MailboxStatus::default();
+                counters.extend(status);
+                Notification::Status {
+                    mailbox: mailbox.to_string(),
+                    status: counters,
+                }
+            },
+            Response::MailboxData(MailboxDatum::List {
+                ref flags, name, ..
+            }) => Notification::Mailbox {
+                name: name.to_string(),
+                flags: flags.iter().map(|f| f.to_string()).collect(),
+            },
+            Response::MetadataChanged {
+                mailbox,
+                ref entries,
+            } => Notification::Metadata {
+                mailbox: mailbox.to_string(),
+                entries: entries.iter().map(|e| e.to_string()).collect(),
+            },
+            Response::Data {
+                code: Some(ResponseCode::NotificationOverflow),
+                ..
+            } => Notification::Overflow,
+            _ => return None,
+        };
+        Some(notification)
+    }
+}
+
+/// The stream of changes reported by the server, created by
+/// `Client::notifications()`.
+///
+/// Unsolicited responses that do not report a change are dropped. The
+/// stream ends when the client is dropped.
+pub struct Notifications {
+    responses: UnsolicitedResponses,
+    utf8: bool,
+}
+
+impl Notifications {
+    pub(crate) fn new(responses: UnsolicitedResponses, utf8: bool) -> Self {
+        Self { responses, utf8 }
+    }
+}
+
+impl Stream for Notifications {
+    type Item = Notification;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Notification>, ()> {
+        loop {
+            match try_ready!(self.responses.poll()) {
+                Some(rsp) => {
+                    if let Some(notification) = Notification::from_response(rsp, self.utf8) {
+                        return Ok(Async::Ready(Some(notification)));
+                    }
+                },
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
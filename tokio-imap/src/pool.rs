@@ -0,0 +1,508 @@
+//! A pool of authenticated connections, for working on several mailboxes
+//! at the same time.
+
+use futures::{future, Future};
+use futures::future::Loop;
+use futures::sync::oneshot;
+use futures_state_stream::StateStream;
+
+use std::collections::VecDeque;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use imap_proto::builders::command::CommandBuilder;
+
+use client::{Client, Greeting, ImapClient, Role};
+use error::Error;
+use reconnect::{connect_once, Authenticator, ConnectFuture};
+use session::Mailbox;
+use tls::TlsStream;
+
+pub type CheckoutFuture<T = TlsStream> =
+    Box<Future<Item = PooledClient<T>, Error = Error> + Send>;
+
+pub type PooledFuture<R, T = TlsStream> =
+    Box<Future<Item = (R, PooledClient<T>), Error = Error> + Send>;
+
+type StepFuture<T> = Box<Future<Item = Loop<PooledClient<T>, ()>, Error = Error> + Send>;
+
+// A checked out connection, and the UIDVALIDITY of the mailbox it selected.
+type SelectFuture<T> = PooledFuture<Result<Option<u32>, Error>, T>;
+
+type ValidateFuture<T> = Box<Future<Item = Client<T>, Error = Error> + Send>;
+
+/// Up to `size` authenticated connections to the same server, checked out
+/// by one task at a time.
+///
+/// Connections are made when they are first needed, with `connect` and the
+/// `Authenticator`, as for a `ReconnectingClient`, and are usually TLS
+/// connections made with a `ClientBuilder`. An idle connection is
+/// checked before it is handed out; one that fails is dropped, and a new
+/// one made in its place. When all connections are in use, `checkout()`
+/// waits until one is returned.
+///
+/// A connection keeps its selected mailbox when it is returned, so tasks
+/// should select the mailbox they work on, or check connections out with
+/// `checkout_for()`. If the mailbox was selected with
+/// `call()`, it is selected again when the connection is checked out, to
+/// see that it still exists; if its UIDVALIDITY has changed since, the
+/// connection is replaced by a new one, which has no mailbox selected.
+/// Otherwise, the connection is checked with `NOOP`.
+///
+/// ```ignore
+/// let pool = ClientPool::new(
+///     4,
+///     || ClientBuilder::new("imap.example.com").connect(),
+///     Login::new("me@example.com", "secret"),
+/// );
+/// let status = pool.checkout().and_then(|client| {
+///     client.with(|client| client.call(status_command).collect())
+/// });
+/// ```
+pub struct ClientPool<T = TlsStream> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T> {
+    connect: Arc<Fn() -> Result<ConnectFuture<T>, Error> + Send + Sync>,
+    authenticator: Arc<Authenticator<T>>,
+    size: usize,
+    state: Mutex<PoolState<T>>,
+}
+
+struct PoolState<T> {
+    idle: Vec<Client<T>>,
+    // Connections that are idle, checked out or being made.
+    open: usize,
+    waiting: VecDeque<oneshot::Sender<()>>,
+    // Bumped by `network_changed()`. Connections from an earlier generation
+    // are not taken back into the pool.
+    generation: usize,
+}
+
+enum Acquire<T> {
+    Idle(Client<T>, Slot<T>),
+    Connect(Slot<T>),
+    Wait(oneshot::Receiver<()>),
+}
+
+impl<T> Clone for ClientPool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> ClientPool<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    /// `connect` is called for every new connection. No connection is made
+    /// until the first checkout.
+    pub fn new<F, R, A>(size: usize, connect: F, authenticator: A) -> Self
+    where
+        F: Fn() -> Result<R, Error> + Send + Sync + 'static,
+        R: Future<Item = (Greeting, Client<T>), Error = Error> + Send + 'static,
+        A: Authenticator<T> + 'static, {
+        assert!(size > 0, "a connection pool needs at least one connection");
+        let connect = move || connect().map(|connecting| Box::new(connecting) as ConnectFuture<T>);
+        Self {
+            shared: Arc::new(Shared {
+                connect: Arc::new(connect),
+                authenticator: Arc::new(authenticator),
+                size,
+                state: Mutex::new(PoolState {
+                    idle: Vec::new(),
+                    open: 0,
+                    waiting: VecDeque::new(),
+                    generation: 0,
+                }),
+            }),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.shared.size
+    }
+
+    /// The number of connections that are idle, checked out or being made.
+    pub fn open(&self) -> usize {
+        self.shared.lock().open
+    }
+
+    pub fn idle(&self) -> usize {
+        self.shared.lock().idle.len()
+    }
+
+    /// Takes a connection that is known to work, making a new one if none
+    /// is idle and the pool is not full. The connection is handed out as an
+    /// `Role::Interactive` one, whatever role it had before.
+    pub fn checkout(&self) -> CheckoutFuture<T> {
+        self.checkout_as(Role::Interactive)
+    }
+
+    /// Takes a connection like `checkout()`, but hands it out as a
+    /// `Role::Monitor` one, for watching mailboxes or `IDLE`.
+    ///
+    /// Its mailbox, if any, is opened again with `EXAMINE`, and commands
+    /// that could change mailboxes fail without being sent, until it is
+    /// checked out again with `checkout()`.
+    pub fn checkout_monitor(&self) -> CheckoutFuture<T> {
+        self.checkout_as(Role::Monitor)
+    }
+
+    /// Takes a connection like `checkout()`, with `mailbox` selected, so that
+    /// it does not matter which mailbox the connection had selected before.
+    /// The mailbox is selected unless the connection has just selected it
+    /// again while being checked.
+    ///
+    /// If `uid_validity` is given, fails with `Error::UidValidityChanged`
+    /// when the mailbox has a different UIDVALIDITY, as the UIDs the caller
+    /// knows then refer to other messages. A mailbox that cannot be selected
+    /// fails with the server's response. Either way the connection is
+    /// returned to the pool.
+    pub fn checkout_for(&self, mailbox: &str, uid_validity: Option<u32>) -> CheckoutFuture<T> {
+        let select = CommandBuilder::select(mailbox);
+        let selected = self.checkout().and_then(move |client| -> SelectFuture<T> {
+            let args = select.clone().into_parts().0;
+            if let Some((cmd, current)) = client.selected() {
+                if cmd.into_parts().0 == args {
+                    return Box::new(future::ok((Ok(current), client)));
+                }
+            }
+            client.with(move |client| {
+                client.call(select).collect().map(|(responses, client)| {
+                    let selected = Error::from_responses(&responses)
+                        .map(|()| Mailbox::from_responses(&responses).uid_validity);
+                    (selected, client)
+                })
+            })
+        });
+        Box::new(selected.and_then(move |(selected, client)| {
+            match (uid_validity, selected?) {
+                (Some(old), Some(new)) if old != new => Err(Error::UidValidityChanged { old, new }),
+                _ => Ok(client),
+            }
+        }))
+    }
+
+    /// Closes the idle connections, for when the network has changed, for
+    /// example from Wi-Fi to a mobile network: connections made over the old
+    /// one may hang instead of failing. Connections that are checked out or
+    /// being made are closed when they are returned, rather than kept. New
+    /// connections are made as they are needed.
+    pub fn network_changed(&self) {
+        let mut state = self.shared.lock();
+        state.generation += 1;
+        let closed = state.idle.len();
+        state.idle.clear();
+        state.open -= closed;
+        for _ in 0..closed {
+            state.wake_one();
+        }
+    }
+
+    fn checkout_as(&self, role: Role) -> CheckoutFuture<T> {
+        let shared = self.shared.clone();
+        Box::new(future::loop_fn((), move |()| -> StepFuture<T> {
+            match shared.acquire() {
+                // Dropping the slot, also when the future is dropped, leaves
+                // room for a new connection in place of this one.
+                Acquire::Idle(mut client, slot) => {
+                    client.set_role(role);
+                    Box::new(validate(client).then(move |res| match res {
+                        Ok(client) => Ok(Loop::Break(PooledClient::new(client, slot))),
+                        Err(_) => Ok(Loop::Continue(())),
+                    }))
+                },
+                Acquire::Connect(slot) => {
+                    let connecting = {
+                        let shared = slot.shared();
+                        connect_once(&*shared.connect, shared.authenticator.clone(), None)
+                    };
+                    Box::new(connecting.map(move |(mut client, _)| {
+                        client.set_role(role);
+                        Loop::Break(PooledClient::new(client, slot))
+                    }))
+                },
+                Acquire::Wait(returned) => Box::new(returned.then(|_| Ok(Loop::Continue(())))),
+            }
+        }))
+    }
+}
+
+// Checks an idle connection before it is handed out, by selecting its
+// mailbox again or with `NOOP`. Fails if the connection does not work, or if
+// the mailbox's UIDVALIDITY changed. A mailbox that cannot be selected any
+// more leaves the connection without one.
+fn validate<T>(client: Client<T>) -> ValidateFuture<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (cmd, old) = match client.selected() {
+        Some(selected) => selected,
+        None => {
+            return Box::new(
+                client
+                    .call(CommandBuilder::noop())
+                    .collect()
+                    .and_then(|(responses, client)| {
+                        Error::from_responses(&responses).map(|()| client)
+                    }),
+            )
+        },
+    };
+    Box::new(
+        client
+            .call(cmd)
+            .collect()
+            .and_then(move |(responses, client)| {
+                if Error::from_responses(&responses).is_err() {
+                    return Ok(client);
+                }
+                match (old, Mailbox::from_responses(&responses).uid_validity) {
+                    (Some(old), Some(new)) if old != new => {
+                        Err(Error::UidValidityChanged { old, new })
+                    },
+                    _ => Ok(client),
+                }
+            }),
+    )
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> ::std::sync::MutexGuard<PoolState<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn acquire(self: &Arc<Self>) -> Acquire<T> {
+        let mut state = self.lock();
+        let generation = state.generation;
+        if let Some(client) = state.idle.pop() {
+            Acquire::Idle(client, Slot::new(self.clone(), generation))
+        } else if state.open < self.size {
+            state.open += 1;
+            Acquire::Connect(Slot::new(self.clone(), generation))
+        } else {
+            let (tx, rx) = oneshot::channel();
+            state.waiting.push_back(tx);
+            Acquire::Wait(rx)
+        }
+    }
+
+    // Connections from before the network changed are closed instead.
+    fn release(&self, client: Client<T>, generation: usize) {
+        let mut state = self.lock();
+        if generation == state.generation {
+            state.idle.push(client);
+        } else {
+            state.open -= 1;
+        }
+        state.wake_one();
+    }
+
+    // A connection failed or was taken out of the pool, leaving room for a
+    // new one.
+    fn lost(&self) {
+        let mut state = self.lock();
+        state.open -= 1;
+        state.wake_one();
+    }
+}
+
+impl<T> PoolState<T> {
+    fn wake_one(&mut self) {
+        // Waiters that have given up are skipped.
+        while let Some(tx) = self.waiting.pop_front() {
+            if tx.send(()).is_ok() {
+                break;
+            }
+        }
+    }
+}
+
+// The place in the pool of a connection that is checked out or being made.
+// Unless the connection is returned to the pool with `release()`, dropping
+// the slot leaves room for a new connection.
+struct Slot<T> {
+    shared: Option<Arc<Shared<T>>>,
+    generation: usize,
+}
+
+impl<T> Slot<T> {
+    fn new(shared: Arc<Shared<T>>, generation: usize) -> Self {
+        Self {
+            shared: Some(shared),
+            generation,
+        }
+    }
+
+    fn shared(&self) -> &Shared<T> {
+        self.shared.as_ref().expect("slot is taken")
+    }
+
+    fn release(mut self, client: Client<T>) {
+        if let Some(shared) = self.shared.take() {
+            shared.release(client, self.generation);
+        }
+    }
+}
+
+impl<T> Drop for Slot<T> {
+    fn drop(&mut self) {
+        if let Some(shared) = self.shared.take() {
+            shared.lost();
+        }
+    }
+}
+
+/// A connection checked out of a `ClientPool`, which returns it to the pool
+/// when dropped.
+pub struct PooledClient<T = TlsStream> {
+    client: Option<Client<T>>,
+    // Taken while the connection is lent out by `with()`.
+    slot: Option<Slot<T>>,
+}
+
+impl<T> PooledClient<T>
+where
+    T: Send + 'static,
+{
+    fn new(client: Client<T>, slot: Slot<T>) -> Self {
+        Self {
+            client: Some(client),
+            slot: Some(slot),
+        }
+    }
+
+    /// Runs `f`, which takes the connection and gives it back when done,
+    /// like `Client::call(...).collect()` or a `Session` method followed by
+    /// `into_client()`. If `f` fails, or the future is dropped before it
+    /// completes, the connection is given up and a new one made in its
+    /// place.
+    pub fn with<F, R, V>(mut self, f: F) -> PooledFuture<V, T>
+    where
+        F: FnOnce(Client<T>) -> R,
+        R: Future<Item = (V, Client<T>), Error = Error> + Send + 'static,
+        V: Send + 'static, {
+        let client = self.client.take().expect("pooled client is present");
+        let slot = self.slot.take().expect("pooled client is in a pool");
+        Box::new(f(client).map(move |(value, client)| (value, PooledClient::new(client, slot))))
+    }
+
+    /// Takes the connection out of the pool, leaving room for a new one.
+    pub fn detach(mut self) -> Client<T> {
+        self.client.take().expect("pooled client is present")
+    }
+}
+
+impl<T> Deref for PooledClient<T> {
+    type Target = Client<T>;
+
+    fn deref(&self) -> &Client<T> {
+        self.client.as_ref().expect("pooled client is present")
+    }
+}
+
+impl<T> DerefMut for PooledClient<T> {
+    fn deref_mut(&mut self) -> &mut Client<T> {
+        self.client.as_mut().expect("pooled client is present")
+    }
+}
+
+impl<T> Drop for PooledClient<T> {
+    // A detached connection's slot is dropped, leaving room for a new one.
+    fn drop(&mut self) {
+        if let (Some(slot), Some(client)) = (self.slot.take(), self.client.take()) {
+            slot.release(client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future;
+
+    use std::io;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::runtime::current_thread::Runtime;
+
+    use client::{ClientBuilder, GreetingFuture, ImapConnectFuture};
+    use reconnect::Login;
+
+    fn pool<F>(size: usize, connect: F) -> ClientPool
+    where
+        F: Fn() -> Result<ImapConnectFuture, Error> + Send + Sync + 'static, {
+        ClientPool::new(size, connect, Login::new("me", "secret"))
+    }
+
+    #[test]
+    fn slots() {
+        let pool = pool(2, || Err(Error::ConnectionClosed));
+        let first = pool.shared.acquire();
+        let _second = pool.shared.acquire();
+        let waiting = match pool.shared.acquire() {
+            Acquire::Wait(waiting) => waiting,
+            _ => panic!("pool is full"),
+        };
+        assert_eq!(pool.open(), 2);
+        drop(first);
+        assert_eq!(pool.open(), 1);
+        assert!(waiting.wait().is_ok());
+    }
+
+    #[test]
+    fn dropped_checkout() {
+        // A server that never greets.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let pool = pool(1, move || ClientBuilder::new("127.0.0.1").port(port).connect());
+        let mut checkout = pool.checkout();
+        let polled = future::lazy(|| checkout.poll().map(|connected| connected.is_ready()));
+        assert!(!Runtime::new().unwrap().block_on(polled).unwrap());
+        assert_eq!(pool.open(), 1);
+        drop(checkout);
+        assert_eq!(pool.open(), 0);
+    }
+
+    #[test]
+    fn failed_connect() {
+        let pool = pool(1, || Err(Error::ConnectionClosed));
+        assert!(pool.checkout().wait().is_err());
+        assert_eq!(pool.open(), 0);
+        assert_eq!(pool.idle(), 0);
+    }
+
+    #[test]
+    fn network_changed() {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counted = connects.clone();
+        let connect = move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            let greeting = io::Cursor::new(b"* PREAUTH ready\r\n".to_vec());
+            Ok(GreetingFuture::new(greeting))
+        };
+        let pool = ClientPool::new(2, connect, Login::new("me", "secret"));
+        let first = pool.checkout().wait().unwrap();
+        let second = pool.checkout().wait().unwrap();
+        drop(first);
+        assert_eq!((pool.open(), pool.idle()), (2, 1));
+
+        pool.network_changed();
+        assert_eq!((pool.open(), pool.idle()), (1, 0));
+        // Connections from before the change are not taken back.
+        drop(second);
+        assert_eq!((pool.open(), pool.idle()), (0, 0));
+
+        drop(pool.checkout().wait().unwrap());
+        assert_eq!((pool.open(), pool.idle()), (1, 1));
+        assert_eq!(connects.load(Ordering::SeqCst), 3);
+    }
+}
@@ -1,38 +1,409 @@
+use base64;
 use bytes::{BufMut, Bytes, BytesMut};
 
 use futures;
+use futures::sync::mpsc;
 
 use nom::{IResult, Needed};
 
 use imap_proto;
 use imap_proto::types::{Request, RequestId, Response};
 
-use std::io;
+use std::cmp;
 use std::mem;
 
-use tokio::net::TcpStream;
+use log::Level;
+
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_codec::{Decoder, Encoder, Framed};
-use tokio_tls::TlsStream;
+
+use client::command_verb;
+use diagnostics::{self, Warning, WarningKind};
+use error::{Error, Limit};
+use events::summarize;
+use tls::TlsStream;
 
 pub struct ImapCodec {
     decode_need_message_bytes: usize,
+    max_line_length: Option<usize>,
+    truncate_at: Option<usize>,
+    truncated_bytes: usize,
+    truncated_response: bool,
+    stream_literals: Option<usize>,
+    streaming: Option<StreamedLiteral>,
+    warnings: Option<mpsc::UnboundedSender<Warning>>,
+    limits: Limits,
+    // The tag of a `LOGIN` or `AUTHENTICATE` command that has been sent,
+    // until its tagged response, while the data sent carries credentials.
+    redacting: Option<RequestId>,
+    // The tags of the commands sent and not completed yet, oldest first,
+    // which the wire log gives as the context of untagged data.
+    in_flight: Vec<RequestId>,
+}
+
+/// Hard limits on the responses the codec buffers, to protect against
+/// servers that send without bound. When one is exceeded, decoding fails
+/// with `Error::LimitExceeded`, which ends the connection.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Limits {
+    /// The longest response line, not counting literals. Lines longer than
+    /// `ImapCodec::set_max_line_length()` are truncated before this applies.
+    pub line_length: Option<usize>,
+    /// The largest literal. `FETCH` body literals that are streamed (see
+    /// `ImapCodec::set_literal_streaming()`) are not buffered, so they are
+    /// not limited.
+    pub literal_size: Option<usize>,
+}
+
+/// The log target of the commands sent and responses received, at debug
+/// level. Credentials, in `LOGIN` and `AUTHENTICATE` commands and the
+/// data sent in answer to continuation requests while they run, are
+/// replaced by `[redacted]`. Only the first line of a response is logged.
+///
+/// Each line carries the tags of the requests it belongs to: commands and
+/// tagged responses start with their own tag, while untagged responses and
+/// continuation data are preceded by the tags of the commands in flight,
+/// in brackets, like `S: [A0003 A0004] * 12 EXISTS`.
+pub const WIRE_LOG_TARGET: &str = "tokio_imap::wire";
+
+struct StreamedLiteral {
+    prefix: Vec<u8>,
+    offset: usize,
+    total: usize,
+}
+
+impl ImapCodec {
+    /// Limits the length of a single response line (not counting literals).
+    ///
+    /// Servers occasionally send absurdly long lines, for example huge
+    /// `[PERMANENTFLAGS]` lists. Instead of buffering those without bound,
+    /// the codec keeps the first `max` bytes of such a line, discards the
+    /// rest and logs a warning, which is also sent to `warnings()`.
+    /// Truncated response codes end up as plain response text, so the
+    /// connection stays usable.
+    pub fn set_max_line_length(&mut self, max: Option<usize>) {
+        self.max_line_length = max;
+    }
+
+    pub fn max_line_length(&self) -> Option<usize> {
+        self.max_line_length
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Emits `FETCH` body literals larger than `threshold` bytes in chunks.
+    ///
+    /// Each chunk is yielded as soon as it is received, as a `FETCH` response
+    /// of its own in which the body section holds just that chunk; `chunk()`
+    /// on the `ResponseData` gives its position in the full literal. Once the
+    /// literal is complete, the `FETCH` response itself follows as usual,
+    /// with an empty body section. This allows writing large message bodies
+    /// to disk without holding them in memory.
+    pub fn set_literal_streaming(&mut self, threshold: Option<usize>) {
+        self.stream_literals = threshold;
+    }
+
+    pub fn literal_streaming(&self) -> Option<usize> {
+        self.stream_literals
+    }
+
+    /// Reports recoverable anomalies in the responses received to the
+    /// returned channel; see `diagnostics`. Calling this again replaces the
+    /// previous channel.
+    pub fn warnings(&mut self) -> mpsc::UnboundedReceiver<Warning> {
+        let (tx, rx) = mpsc::unbounded();
+        self.warnings = Some(tx);
+        rx
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        let sent = match self.warnings {
+            Some(ref warnings) => warnings.unbounded_send(warning).is_ok(),
+            None => return,
+        };
+        if !sent {
+            self.warnings = None;
+        }
+    }
+
+    fn decode_literal_chunk(
+        &mut self, buf: &mut BytesMut
+    ) -> Result<Option<ResponseData>, Error> {
+        let complete = match self.streaming {
+            Some(ref literal) => literal.offset == literal.total,
+            None => return Ok(None),
+        };
+        if complete {
+            // Put the rest of the response back behind an empty literal,
+            // so that it can be parsed as a regular response.
+            let literal = self.streaming.take().unwrap(); // safe: checked above
+            let mut rest = BytesMut::from(literal.prefix);
+            rest.extend_from_slice(b"{0}\r\n");
+            rest.extend_from_slice(buf);
+            *buf = rest;
+            return self.decode(buf);
+        }
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let literal = self.streaming.as_mut().unwrap(); // safe: checked above
+        let len = cmp::min(buf.len(), literal.total - literal.offset);
+        let mut raw = literal.prefix.clone();
+        raw.extend(format!("{{{}}}\r\n", len).as_bytes());
+        raw.extend(&buf.split_to(len));
+        raw.extend(b")\r\n");
+        let chunk = LiteralChunk {
+            offset: literal.offset,
+            total: literal.total,
+        };
+        literal.offset += len;
+        ResponseData::parse(raw, Some(chunk)).map(Some)
+    }
+
+    // Fails if the response at the start of `buf` exceeds the limits, as
+    // far as it has been received.
+    fn check_limits(&self, buf: &[u8]) -> Result<(), Error> {
+        if let Some(max) = self.limits.line_length {
+            if find_overlong_line(buf, max).is_some() {
+                return Err(Error::LimitExceeded(Limit::LineLength(max)));
+            }
+        }
+        if let Some(max) = self.limits.literal_size {
+            if find_oversized_literal(buf, max) {
+                return Err(Error::LimitExceeded(Limit::LiteralSize(max)));
+            }
+        }
+        Ok(())
+    }
+
+    // Drops bytes from `truncate_at` until the end of the current line,
+    // returning `false` if the end of the line has not been received yet.
+    fn discard_line_rest(&mut self, buf: &mut BytesMut) -> bool {
+        let start = match self.truncate_at {
+            Some(start) => start,
+            None => return true,
+        };
+        match find_crlf(&buf[start..]) {
+            Some(end) => {
+                // Retain a literal announcement at the end of the line, since
+                // the literal data that follows would otherwise be misparsed.
+                let end = start + end;
+                let keep = literal_marker_start(&buf[start..end]).map_or(end, |pos| start + pos);
+                self.truncated_bytes += keep - start;
+                let line = buf[..start].iter().rposition(|&b| b == b'\n').map_or(0, |pos| pos + 1);
+                let kind = WarningKind::TruncatedLine {
+                    dropped: self.truncated_bytes,
+                };
+                let warning = Warning::new(kind, &buf[line..start]);
+                self.warn(warning);
+                let tail = buf.split_off(keep);
+                buf.truncate(start);
+                buf.extend_from_slice(&tail);
+                warn!(
+                    "truncated overlong response line by {} bytes",
+                    self.truncated_bytes
+                );
+                self.truncate_at = None;
+                self.truncated_bytes = 0;
+                self.truncated_response = true;
+                true
+            },
+            None => {
+                // Keep enough bytes to recognize a literal marker later on.
+                if buf.len() - start > MAX_LITERAL_MARKER {
+                    let keep = buf.len() - MAX_LITERAL_MARKER;
+                    self.truncated_bytes += keep - start;
+                    let tail = buf.split_off(keep);
+                    buf.truncate(start);
+                    buf.extend_from_slice(&tail);
+                }
+                false
+            },
+        }
+    }
 }
 
 impl Default for ImapCodec {
     fn default() -> Self {
         Self {
             decode_need_message_bytes: 0,
+            max_line_length: None,
+            truncate_at: None,
+            truncated_bytes: 0,
+            truncated_response: false,
+            stream_literals: None,
+            streaming: None,
+            warnings: None,
+            limits: Limits::default(),
+            redacting: None,
+            in_flight: Vec::new(),
+        }
+    }
+}
+
+// Length of the longest literal marker, `{4294967295+}`.
+const MAX_LITERAL_MARKER: usize = 13;
+
+fn find_crlf(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|w| w == b"\r\n")
+}
+
+// Returns the position of a trailing `{n}` or `{n+}` literal marker in `line`.
+fn literal_marker_start(line: &[u8]) -> Option<usize> {
+    literal_marker(line).map(|(start, _)| start)
+}
+
+// Returns the position and announced length of a trailing literal marker.
+fn literal_marker(line: &[u8]) -> Option<(usize, usize)> {
+    if !line.ends_with(b"}") {
+        return None;
+    }
+    let start = line.iter().rposition(|b| *b == b'{')?;
+    let digits = &line[start + 1..line.len() - 1];
+    let digits = if digits.ends_with(b"+") {
+        &digits[..digits.len() - 1]
+    } else {
+        digits
+    };
+    if digits.is_empty() || !digits.iter().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let len = digits.iter().fold(0usize, |acc, b| {
+        acc.saturating_mul(10).saturating_add((b - b'0') as usize)
+    });
+    Some((start, len))
+}
+
+// Finds the position at which the first line exceeding `max` bytes should be
+// truncated, skipping over the data of any literals announced along the way.
+// A literal marker at the end of a line does not count towards its length,
+// as truncating keeps it.
+fn find_overlong_line(buf: &[u8], max: usize) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        let rest = &buf[pos..];
+        let end = match find_crlf(rest) {
+            Some(end) => end,
+            None if rest.len() > max => return Some(pos + max),
+            None => return None,
+        };
+        if literal_marker_start(&rest[..end]).unwrap_or(end) > max {
+            return Some(pos + max);
+        }
+        let (_, len) = literal_marker(&rest[..end])?;
+        pos = pos.saturating_add(end + 2).saturating_add(len);
+        if pos >= buf.len() {
+            return None;
+        }
+    }
+}
+
+// Whether the response at the start of `buf` announces a literal of more
+// than `max` bytes.
+fn find_oversized_literal(buf: &[u8], max: usize) -> bool {
+    let mut pos = 0;
+    while let Some(end) = find_crlf(&buf[pos..]) {
+        let end = pos + end;
+        match literal_marker(&buf[pos..end]) {
+            Some((_, len)) if len > max => return true,
+            Some((_, len)) => pos = end.saturating_add(2).saturating_add(len),
+            None => return false,
+        }
+        if pos > buf.len() {
+            return false;
+        }
+    }
+    false
+}
+
+// Finds a literal of more than `threshold` bytes whose data has not been
+// received completely, returning the positions of its marker and data.
+fn find_streamable_literal(buf: &[u8], threshold: usize) -> Option<(usize, usize, usize)> {
+    let mut pos = 0;
+    loop {
+        let end = pos + find_crlf(&buf[pos..])?;
+        let (marker, len) = literal_marker(&buf[pos..end])?;
+        let data = end + 2;
+        if data.saturating_add(len) > buf.len() {
+            return if len > threshold {
+                Some((pos + marker, data, len))
+            } else {
+                None
+            };
+        }
+        pos = data + len;
+    }
+}
+
+// Checks that a response prefix ending right before a literal belongs to a
+// `FETCH` response, by completing it with an empty literal.
+fn is_fetch_prefix(prefix: &[u8]) -> bool {
+    let mut test = prefix.to_vec();
+    test.extend(b"{0}\r\n)\r\n");
+    match imap_proto::parse_response(&test) {
+        IResult::Done(_, Response::Fetch(..)) => true,
+        _ => false,
+    }
+}
+
+// Finds the end of the first complete response in `buf`, if any.
+fn find_response_end(buf: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        let end = pos + find_crlf(&buf[pos..])?;
+        match literal_marker(&buf[pos..end]) {
+            Some((_, len)) => pos = end.saturating_add(2).saturating_add(len),
+            None => return Some(end + 2),
+        }
+        if pos > buf.len() {
+            return None;
         }
     }
 }
 
 impl<'a> Decoder for ImapCodec {
     type Item = ResponseData;
-    type Error = io::Error;
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, io::Error> {
+    type Error = Error;
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Error> {
+        if !self.discard_line_rest(buf) {
+            return Ok(None);
+        }
+        if self.streaming.is_some() {
+            return self.decode_literal_chunk(buf);
+        }
         if self.decode_need_message_bytes > buf.len() {
             return Ok(None);
         }
+        if let Some(threshold) = self.stream_literals {
+            if let Some((marker, data, len)) = find_streamable_literal(buf, threshold) {
+                if is_fetch_prefix(&buf[..marker]) {
+                    let prefix = buf.split_to(marker).to_vec();
+                    buf.split_to(data - marker);
+                    self.streaming = Some(StreamedLiteral {
+                        prefix,
+                        offset: 0,
+                        total: len,
+                    });
+                    self.decode_need_message_bytes = 0;
+                    return self.decode_literal_chunk(buf);
+                }
+            }
+        }
+        if let Some(max) = self.max_line_length {
+            if let Some(start) = find_overlong_line(buf, max) {
+                self.truncate_at = Some(start);
+                if !self.discard_line_rest(buf) {
+                    return Ok(None);
+                }
+            }
+        }
+        self.check_limits(buf)?;
         let (response, rsp_len) = match imap_proto::parse_response(buf) {
             IResult::Done(remaining, response) => {
                 // This SHOULD be acceptable/safe: BytesMut storage memory is
@@ -49,34 +420,129 @@ impl<'a> Decoder for ImapCodec {
             IResult::Incomplete(_) => {
                 return Ok(None);
             },
+            IResult::Error(_) if self.truncated_response => {
+                // Truncation made the response unparseable. Skip it rather
+                // than failing the whole connection.
+                if let Some(end) = find_response_end(buf) {
+                    warn!("skipping truncated response that could not be parsed");
+                    let skipped = buf.split_to(end);
+                    self.warn(Warning::new(WarningKind::SkippedResponse, &skipped));
+                    self.truncated_response = false;
+                    return self.decode(buf);
+                }
+                return Ok(None);
+            },
             IResult::Error(err) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("{} during parsing of {:?}", err, buf),
-                ));
+                debug!("{} during parsing of {:?}", err, buf);
+                return Err(Error::Parse(buf.to_vec()));
             },
         };
         let raw = buf.split_to(rsp_len).freeze();
         self.decode_need_message_bytes = 0;
-        Ok(Some(ResponseData { raw, response }))
+        self.truncated_response = false;
+        let rsp = ResponseData {
+            raw,
+            chunk: None,
+            response,
+        };
+        if log_enabled!(target: WIRE_LOG_TARGET, Level::Debug) {
+            debug!(target: WIRE_LOG_TARGET, "S: {}", self.response_log_line(&rsp));
+        }
+        if let Response::Done { ref tag, .. } = rsp.response {
+            if self.redacting.as_ref() == Some(tag) {
+                self.redacting = None;
+            }
+            self.in_flight.retain(|sent| sent != tag);
+        }
+        if let Some(warning) = diagnostics::check(&rsp) {
+            self.warn(warning);
+        }
+        Ok(Some(rsp))
     }
 }
 
 impl Encoder for ImapCodec {
     type Item = Request;
-    type Error = io::Error;
-    fn encode(&mut self, msg: Self::Item, dst: &mut BytesMut) -> Result<(), io::Error> {
-        dst.put(msg.0.as_bytes());
-        dst.put(b' ');
-        dst.put(&msg.1);
-        dst.put("\r\n");
+    type Error = Error;
+    fn encode(&mut self, msg: Self::Item, dst: &mut BytesMut) -> Result<(), Error> {
+        // Continuation data is sent as a request without a tag.
+        let tag = msg.0.as_bytes();
+        if !tag.is_empty() {
+            match &*command_verb(&msg.1) {
+                "AUTHENTICATE" | "LOGIN" => self.redacting = Some(msg.0.clone()),
+                _ => {},
+            }
+            self.in_flight.push(msg.0.clone());
+        }
+        if log_enabled!(target: WIRE_LOG_TARGET, Level::Debug) {
+            debug!(target: WIRE_LOG_TARGET, "C: {}", self.request_log_line(&msg));
+        }
+        // Grow the buffer at most once, as it may hold other commands.
+        dst.reserve(tag.len() + 1 + msg.1.len() + 2);
+        if !tag.is_empty() {
+            dst.put_slice(tag);
+            dst.put_u8(b' ');
+        }
+        dst.put_slice(&msg.1);
+        dst.put_slice(b"\r\n");
         Ok(())
     }
 }
 
+impl ImapCodec {
+    fn request_log_line(&self, msg: &Request) -> String {
+        let tag = &(msg.0).0;
+        if tag.is_empty() {
+            let data = if self.redacting.is_some() {
+                "[redacted]".to_string()
+            } else {
+                summarize(&msg.1)
+            };
+            return format!("{}{}", self.context(), data);
+        }
+        let line = match self.redacting {
+            Some(ref redacting) if redacting == &msg.0 => {
+                format!("{} [redacted]", command_verb(&msg.1))
+            },
+            _ => summarize(&msg.1),
+        };
+        format!("{} {}", tag, line)
+    }
+
+    fn response_log_line(&self, rsp: &ResponseData) -> String {
+        match rsp.response {
+            Response::Done { .. } => summarize(&rsp.raw),
+            _ => format!("{}{}", self.context(), summarize(&rsp.raw)),
+        }
+    }
+
+    // The tags of the commands in flight, as a prefix for untagged lines.
+    fn context(&self) -> String {
+        if self.in_flight.is_empty() {
+            return String::new();
+        }
+        let tags = self.in_flight.iter().map(|tag| &tag.0[..]).collect::<Vec<_>>();
+        format!("[{}] ", tags.join(" "))
+    }
+}
+
+/// Position of a streamed chunk within its literal.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LiteralChunk {
+    pub offset: usize,
+    pub total: usize,
+}
+
+impl LiteralChunk {
+    pub fn is_last(&self, len: usize) -> bool {
+        self.offset + len == self.total
+    }
+}
+
 #[derive(Debug)]
 pub struct ResponseData {
     raw: Bytes,
+    chunk: Option<LiteralChunk>,
     // This reference is really scoped to the lifetime of the `raw`
     // member, but unfortunately Rust does not allow that yet. It
     // is transmuted to `'static` by the `Decoder`, instead, and
@@ -86,22 +552,331 @@ pub struct ResponseData {
 }
 
 impl ResponseData {
+    pub(crate) fn parse(raw: Vec<u8>, chunk: Option<LiteralChunk>) -> Result<Self, Error> {
+        // Converting from a `Vec` keeps the data on the heap, so that the
+        // references in `response` stay valid when the `Bytes` is moved.
+        let raw = Bytes::from(raw);
+        let response = match imap_proto::parse_response(&raw) {
+            IResult::Done(_, response) => unsafe { mem::transmute(response) },
+            _ => return Err(Error::Parse(raw.to_vec())),
+        };
+        Ok(ResponseData {
+            raw,
+            chunk,
+            response,
+        })
+    }
+
+    /// For chunks of a streamed literal, their position within the literal.
+    pub fn chunk(&self) -> Option<LiteralChunk> {
+        self.chunk
+    }
+
+    pub(crate) fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The response as received, sharing the buffer it was decoded from.
+    pub fn bytes(&self) -> Bytes {
+        self.raw.clone()
+    }
+
+    /// `part`, which must borrow from this response (such as the data of a
+    /// body section), as `Bytes` sharing the response's buffer. This keeps
+    /// the data without copying it, after the `ResponseData` is dropped.
+    ///
+    /// Returns `None` if `part` does not point into the response.
+    pub fn slice_bytes(&self, part: &[u8]) -> Option<Bytes> {
+        let start = (part.as_ptr() as usize).checked_sub(self.raw.as_ptr() as usize)?;
+        let end = start + part.len();
+        if end > self.raw.len() {
+            return None;
+        }
+        Some(self.raw.slice(start, end))
+    }
+
     pub fn request_id(&self) -> Option<&RequestId> {
         match self.response {
             Response::Done { ref tag, .. } => Some(tag),
             _ => None,
         }
     }
+    /// The text of a continuation request (`+`), if this is one.
+    pub fn continuation_text(&self) -> Option<&str> {
+        match *self.parsed() {
+            Response::Continue { information, .. } => Some(information.unwrap_or("")),
+            _ => None,
+        }
+    }
+
+    /// The base64-decoded challenge of a continuation request, as sent
+    /// during `AUTHENTICATE`. `None` if this is not a continuation request
+    /// or its text is not valid base64.
+    pub fn continuation_data(&self) -> Option<Vec<u8>> {
+        self.continuation_text()
+            .and_then(|text| base64::decode(text.trim()).ok())
+    }
+
     pub fn parsed(&self) -> &Response {
         unsafe { mem::transmute(&self.response) }
     }
 }
 
-pub type ImapTls = Framed<TlsStream<TcpStream>, ImapCodec>;
+pub type ImapTls = Framed<TlsStream, ImapCodec>;
 
-impl ImapTransport for ImapTls {}
+impl<T> ImapTransport for Framed<T, ImapCodec>
+where
+    T: AsyncRead + AsyncWrite,
+{
+}
 
 pub trait ImapTransport
-    : futures::Stream<Item = ResponseData, Error = io::Error>
-    + futures::Sink<SinkItem = Request, SinkError = io::Error> {
+    : futures::Stream<Item = ResponseData, Error = Error>
+    + futures::Sink<SinkItem = Request, SinkError = Error> {
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::Stream;
+
+    use imap_proto::types::{AttributeValue, MailboxDatum, Status};
+
+    use tokio_codec::Encoder;
+
+    // Appends `data` to `buf`, as if just read, and decodes all the
+    // responses it completes.
+    fn feed(codec: &mut ImapCodec, buf: &mut BytesMut, data: &[u8]) -> Vec<ResponseData> {
+        buf.extend_from_slice(data);
+        let mut decoded = Vec::new();
+        while let Some(rsp) = codec.decode(buf).unwrap() {
+            decoded.push(rsp);
+        }
+        decoded
+    }
+
+    fn is_exists(rsp: &ResponseData, num: u32) -> bool {
+        *rsp.parsed() == Response::MailboxData(MailboxDatum::Exists(num))
+    }
+
+    #[test]
+    fn overlong_line_across_reads() {
+        let mut codec = ImapCodec::default();
+        codec.set_max_line_length(Some(20));
+        let warnings = codec.warnings();
+        let mut buf = BytesMut::new();
+        assert!(feed(&mut codec, &mut buf, b"* OK this line keeps going").is_empty());
+        assert!(feed(&mut codec, &mut buf, b" on and on and on").is_empty());
+        // Only the start of the line and what might be a literal marker are
+        // kept while waiting for the end of the line.
+        assert!(buf.len() <= 20 + MAX_LITERAL_MARKER);
+        let decoded = feed(&mut codec, &mut buf, b" and on\r\n* 3 EXISTS\r\n");
+        assert_eq!(decoded.len(), 2);
+        match *decoded[0].parsed() {
+            Response::Data {
+                status: Status::Ok,
+                information,
+                ..
+            } => assert_eq!(information, Some("this line keeps")),
+            ref other => panic!("unexpected response {:?}", other),
+        }
+        assert!(is_exists(&decoded[1], 3));
+        assert!(buf.is_empty());
+
+        drop(codec);
+        let warnings = warnings.wait().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, WarningKind::TruncatedLine { dropped: 30 });
+    }
+
+    #[test]
+    fn overlong_line_with_literal() {
+        let mut codec = ImapCodec::default();
+        codec.set_max_line_length(Some(24));
+        let warnings = codec.warnings();
+        let mut buf = BytesMut::new();
+        let flags = b"* 1 FETCH (FLAGS (\\Seen \\Answered \\Flagged $Junk";
+        assert!(feed(&mut codec, &mut buf, flags).is_empty());
+        assert!(feed(&mut codec, &mut buf, b" $Important) BODY[] {1").is_empty());
+        // The literal data looks like a response, but must not be taken for
+        // one.
+        assert!(feed(&mut codec, &mut buf, b"2}\r\n* 9 EXISTS\r\n").is_empty());
+        let decoded = feed(&mut codec, &mut buf, b")\r\n* 2 EXISTS\r\n");
+        assert_eq!(decoded.len(), 1);
+        assert!(is_exists(&decoded[0], 2));
+        assert!(buf.is_empty());
+
+        drop(codec);
+        let warnings = warnings.wait().collect::<Result<Vec<_>, _>>().unwrap();
+        let kinds = warnings.into_iter().map(|w| w.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![WarningKind::TruncatedLine { dropped: 44 }, WarningKind::SkippedResponse]
+        );
+    }
+
+    #[test]
+    fn line_length_limit_across_reads() {
+        let mut codec = ImapCodec::default();
+        codec.set_limits(Limits {
+            line_length: Some(16),
+            literal_size: None,
+        });
+        let mut buf = BytesMut::from(&b"* OK short\r\n* OK this is"[..]);
+        assert!(codec.decode(&mut buf).unwrap().is_some());
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(b" far too long\r\n");
+        match codec.decode(&mut buf) {
+            Err(Error::LimitExceeded(Limit::LineLength(16))) => {},
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn line_length_limit_skips_literals() {
+        let mut codec = ImapCodec::default();
+        codec.set_limits(Limits {
+            line_length: Some(24),
+            literal_size: None,
+        });
+        let mut buf = BytesMut::new();
+        assert!(feed(&mut codec, &mut buf, b"* 1 FETCH (BODY[] {30}\r\n").is_empty());
+        assert!(feed(&mut codec, &mut buf, b"a literal is not a line").is_empty());
+        let decoded = feed(&mut codec, &mut buf, b", ok...)\r\n");
+        assert_eq!(decoded.len(), 1);
+    }
+
+    // The body section data of a `FETCH` response, and its UID.
+    fn fetch_body(rsp: &ResponseData) -> (Option<u32>, &[u8]) {
+        let attrs = match *rsp.parsed() {
+            Response::Fetch(_, ref attrs) => attrs,
+            ref other => panic!("unexpected response {:?}", other),
+        };
+        let uid = attrs
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::Uid(uid) => Some(uid),
+                _ => None,
+            })
+            .next();
+        let body = attrs
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::BodySection { data, .. } => Some(data.unwrap_or(b"")),
+                _ => None,
+            })
+            .next();
+        (uid, body.expect("no body section"))
+    }
+
+    #[test]
+    fn streamed_literal() {
+        let mut codec = ImapCodec::default();
+        codec.set_literal_streaming(Some(4));
+        let mut buf = BytesMut::new();
+        assert!(feed(&mut codec, &mut buf, b"* 1 FETCH (UID 7 BODY[] {1").is_empty());
+        assert!(feed(&mut codec, &mut buf, b"0}\r\n").is_empty());
+
+        let chunks = feed(&mut codec, &mut buf, b"0123");
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(fetch_body(&chunks[0]), (Some(7), &b"0123"[..]));
+        let chunk = chunks[0].chunk().unwrap();
+        assert_eq!(chunk, LiteralChunk { offset: 0, total: 10 });
+        assert!(!chunk.is_last(4));
+
+        let chunks = feed(&mut codec, &mut buf, b"456");
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(fetch_body(&chunks[0]).1, b"456");
+        assert_eq!(chunks[0].chunk(), Some(LiteralChunk { offset: 4, total: 10 }));
+
+        // The last chunk arrives together with the rest of the response and
+        // the next one.
+        let decoded = feed(&mut codec, &mut buf, b"789 FLAGS (\\Seen))\r\n* 2 EXISTS\r\n");
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(fetch_body(&decoded[0]).1, b"789");
+        let chunk = decoded[0].chunk().unwrap();
+        assert_eq!(chunk, LiteralChunk { offset: 7, total: 10 });
+        assert!(chunk.is_last(3));
+
+        // The complete response follows, with an empty body section.
+        assert_eq!(decoded[1].chunk(), None);
+        assert_eq!(fetch_body(&decoded[1]), (Some(7), &b""[..]));
+        match *decoded[1].parsed() {
+            Response::Fetch(1, ref attrs) => {
+                assert!(attrs.contains(&AttributeValue::Flags(vec!["\\Seen"])))
+            },
+            ref other => panic!("unexpected response {:?}", other),
+        }
+        assert!(is_exists(&decoded[2], 2));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn literal_below_threshold() {
+        let mut codec = ImapCodec::default();
+        codec.set_literal_streaming(Some(16));
+        let mut buf = BytesMut::new();
+        assert!(feed(&mut codec, &mut buf, b"* 1 FETCH (BODY[] {10}\r\n0123").is_empty());
+        let decoded = feed(&mut codec, &mut buf, b"456789)\r\n");
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].chunk(), None);
+        assert_eq!(fetch_body(&decoded[0]).1, b"0123456789");
+    }
+
+    #[test]
+    fn non_fetch_literal_is_not_streamed() {
+        let mut codec = ImapCodec::default();
+        codec.set_literal_streaming(Some(4));
+        let mut buf = BytesMut::new();
+        assert!(feed(&mut codec, &mut buf, b"* LIST () \"/\" {9}\r\nIN").is_empty());
+        let decoded = feed(&mut codec, &mut buf, b"BOX/abc\r\n");
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].chunk(), None);
+    }
+
+    fn request(tag: &str, data: &str) -> Request {
+        Request(RequestId(tag.to_string()), data.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn redaction_ends_with_its_own_command() {
+        let mut codec = ImapCodec::default();
+        let mut out = BytesMut::new();
+        let mut buf = BytesMut::new();
+        codec.encode(request("A1", "NOOP"), &mut out).unwrap();
+        let login = "LOGIN \"me\" \"secret\"";
+        codec.encode(request("A2", login), &mut out).unwrap();
+        assert_eq!(codec.request_log_line(&request("A2", login)), "A2 LOGIN [redacted]");
+
+        // The completion of an earlier command does not end redaction.
+        feed(&mut codec, &mut buf, b"A1 OK done\r\n");
+        assert_eq!(codec.redacting, Some(RequestId("A2".to_string())));
+        assert_eq!(codec.request_log_line(&request("", "c2VjcmV0")), "[A2] [redacted]");
+        codec.encode(request("A3", "NOOP"), &mut out).unwrap();
+        assert_eq!(codec.request_log_line(&request("A3", "NOOP")), "A3 NOOP");
+
+        feed(&mut codec, &mut buf, b"A2 OK logged in\r\n");
+        assert_eq!(codec.redacting, None);
+        assert_eq!(codec.request_log_line(&request("", "data")), "[A3] data");
+    }
+
+    #[test]
+    fn log_context() {
+        let mut codec = ImapCodec::default();
+        let mut out = BytesMut::new();
+        let mut buf = BytesMut::new();
+        let rsp = feed(&mut codec, &mut buf, b"* OK ready\r\n");
+        assert_eq!(codec.response_log_line(&rsp[0]), "* OK ready");
+
+        codec.encode(request("A1", "SELECT INBOX"), &mut out).unwrap();
+        codec.encode(request("A2", "NOOP"), &mut out).unwrap();
+        let rsp = feed(&mut codec, &mut buf, b"* 3 EXISTS\r\nA1 OK done\r\n* 4 EXISTS\r\n");
+        assert_eq!(codec.in_flight, vec![RequestId("A2".to_string())]);
+        assert_eq!(codec.response_log_line(&rsp[1]), "A1 OK done");
+        assert_eq!(codec.response_log_line(&rsp[2]), "[A2] * 4 EXISTS");
+        let mut codec = ImapCodec::default();
+        codec.in_flight = vec![RequestId("A1".to_string()), RequestId("A2".to_string())];
+        assert_eq!(codec.response_log_line(&rsp[0]), "[A1 A2] * 3 EXISTS");
+    }
 }
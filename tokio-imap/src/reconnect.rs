@@ -0,0 +1,373 @@
+//! A client that reconnects when its connection is lost, for long-running
+//! programs such as sync daemons.
+
+use futures::{future, Future};
+use futures::future::Loop;
+use futures_state_stream::StateStream;
+
+use std::cmp;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use imap_proto::builders::command::Command;
+
+use client::{command_verb, Client, Greeting, ImapClient};
+use clock::{Clock, SystemClock};
+use error::Error;
+use proto::ResponseData;
+use session::{Mailbox, Session};
+use tls::TlsStream;
+
+pub type ReconnectFuture<T, S = TlsStream> =
+    Box<Future<Item = (T, ReconnectingClient<S>), Error = Error> + Send>;
+
+type AuthFuture<T> = Box<Future<Item = Client<T>, Error = Error> + Send>;
+
+pub(crate) type ConnectFuture<T> = Box<Future<Item = (Greeting, Client<T>), Error = Error> + Send>;
+
+// A new connection, and the outcome of selecting the previously selected
+// mailbox again: its new UIDVALIDITY, or the error selecting it failed with.
+type ConnectOnce<T> =
+    Box<Future<Item = (Client<T>, Result<Option<u32>, Error>), Error = Error> + Send>;
+
+/// Logs in on the connections made by a `ReconnectingClient`.
+pub trait Authenticator<T = TlsStream>: Send + Sync {
+    fn authenticate(&self, client: Client<T>) -> AuthFuture<T>;
+}
+
+/// Authenticates with `LOGIN`.
+pub struct Login {
+    user_name: String,
+    password: String,
+}
+
+impl Login {
+    pub fn new(user_name: &str, password: &str) -> Self {
+        Self {
+            user_name: user_name.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+impl<T> Authenticator<T> for Login
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    fn authenticate(&self, client: Client<T>) -> AuthFuture<T> {
+        Box::new(
+            Session::new(client)
+                .login(&self.user_name, &self.password)
+                .map(|((), session)| session.into_client()),
+        )
+    }
+}
+
+/// How long a `ReconnectingClient` waits between connection attempts.
+///
+/// The first retry waits `initial`, and each further one twice as long as
+/// the one before, up to `max`. After `max_attempts` failed attempts, if
+/// set, the client gives up.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    fn delay(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry).unwrap_or(u32::max_value());
+        cmp::min(self.initial.checked_mul(factor).unwrap_or(self.max), self.max)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(300),
+            max_attempts: None,
+        }
+    }
+}
+
+/// Sends commands over a connection that is re-established when it fails.
+///
+/// A new connection is authenticated with the `Authenticator` (unless the
+/// server greets with `PREAUTH`), and the mailbox that was selected before
+/// is selected again, with the same command.
+///
+/// Connections are usually made with a `ClientBuilder`, but any future that
+/// resolves to a greeting and a `Client` will do.
+///
+/// ```ignore
+/// let client = ReconnectingClient::new(
+///     || ClientBuilder::new("imap.example.com").connect(),
+///     Login::new("me@example.com", "secret"),
+/// );
+/// let fetch = client.call(CommandBuilder::select("INBOX"))
+///     .and_then(|(_, client)| client.call(fetch_command));
+/// ```
+pub struct ReconnectingClient<T = TlsStream> {
+    connect: Arc<Fn() -> Result<ConnectFuture<T>, Error> + Send + Sync>,
+    authenticator: Arc<Authenticator<T>>,
+    backoff: Backoff,
+    clock: Arc<Clock>,
+    client: Option<Client<T>>,
+    // The command that selected the current mailbox, and the mailbox's
+    // UIDVALIDITY.
+    selected: Option<(Command, Option<u32>)>,
+}
+
+impl<T> ReconnectingClient<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    /// `connect` is called for every connection attempt. Nothing happens
+    /// until the first command is sent.
+    pub fn new<F, R, A>(connect: F, authenticator: A) -> Self
+    where
+        F: Fn() -> Result<R, Error> + Send + Sync + 'static,
+        R: Future<Item = (Greeting, Client<T>), Error = Error> + Send + 'static,
+        A: Authenticator<T> + 'static, {
+        let connect = move || connect().map(|connecting| Box::new(connecting) as ConnectFuture<T>);
+        Self {
+            connect: Arc::new(connect),
+            authenticator: Arc::new(authenticator),
+            backoff: Backoff::default(),
+            clock: Arc::new(SystemClock),
+            client: None,
+            selected: None,
+        }
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Waits between connection attempts with `clock`.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// The current connection, if there is one.
+    pub fn client(&self) -> Option<&Client<T>> {
+        self.client.as_ref()
+    }
+
+    /// The current connection, for example to get its `mailbox_watch()`.
+    /// Settings made on it are lost when it is replaced.
+    pub fn client_mut(&mut self) -> Option<&mut Client<T>> {
+        self.client.as_mut()
+    }
+
+    /// Drops the current connection, for when the network has changed and
+    /// it may hang instead of failing. The next command reconnects, and
+    /// selects the mailbox that was selected again.
+    pub fn network_changed(&mut self) {
+        self.client = None;
+    }
+
+    /// Sends `cmd`, connecting first if there is no connection.
+    ///
+    /// Resolves to the responses to the command, ending with its tagged
+    /// response, or to the error it failed with. If the connection is lost
+    /// while the command is in flight, only `UID FETCH` and `UID SEARCH` are
+    /// sent again once reconnected, unless they refer to a saved search
+    /// result. Other commands fail with `Error::Interrupted`, as they may
+    /// already have taken effect, or, like `FETCH` and `SEARCH` by sequence
+    /// number, may refer to other messages on the new connection.
+    /// If the mailbox selected again after reconnecting has a different
+    /// UIDVALIDITY, the next command fails with `Error::UidValidityChanged`
+    /// without being sent.
+    ///
+    /// The future itself only fails when reconnecting gives up, after a
+    /// permanent error such as failed authentication or when the backoff's
+    /// `max_attempts` are used up.
+    pub fn call(self, cmd: Command) -> ReconnectFuture<Result<Vec<ResponseData>, Error>, T> {
+        let args = cmd.clone().into_parts().0;
+        let verb = command_verb(&args);
+        // A saved search result does not survive reconnecting (RFC 5182).
+        let replayable = is_replayable(&verb) && !uses_saved_result(&args);
+        self.call_verb(cmd, verb, replayable)
+    }
+
+    fn call_verb(
+        self, cmd: Command, verb: String, replayable: bool
+    ) -> ReconnectFuture<Result<Vec<ResponseData>, Error>, T> {
+        let connected = match self.client {
+            Some(_) => Box::new(future::ok((Ok(()), self))),
+            None => self.reconnect(),
+        };
+        Box::new(connected.and_then(move |(reselected, mut this)| -> ReconnectFuture<_, T> {
+            if let Err(e) = reselected {
+                return Box::new(future::ok((Err(e), this)));
+            }
+            let client = this.client.take().unwrap(); // safe: just connected
+            let sent = client.call(cmd.clone()).collect();
+            Box::new(sent.then(move |result| -> ReconnectFuture<_, T> {
+                match result {
+                    Ok((responses, client)) => {
+                        this.client = Some(client);
+                        this.track(&verb, cmd, &responses);
+                        Box::new(future::ok((Ok(responses), this)))
+                    },
+                    Err(Error::Io(_)) | Err(Error::ConnectionClosed) if replayable => {
+                        this.call_verb(cmd, verb, false)
+                    },
+                    Err(Error::Io(_)) | Err(Error::ConnectionClosed) => {
+                        Box::new(future::ok((Err(Error::Interrupted), this)))
+                    },
+                    // The client is gone, so the next command reconnects.
+                    Err(e) => Box::new(future::ok((Err(e), this))),
+                }
+            }))
+        }))
+    }
+
+    // Keeps track of the selected mailbox.
+    fn track(&mut self, verb: &str, cmd: Command, responses: &[ResponseData]) {
+        let ok = Error::from_responses(responses).is_ok();
+        match verb {
+            "SELECT" | "EXAMINE" if ok => {
+                let uid_validity = Mailbox::from_responses(responses).uid_validity;
+                self.selected = Some((cmd, uid_validity));
+            },
+            "SELECT" | "EXAMINE" => self.selected = None,
+            "CLOSE" | "UNSELECT" | "LOGOUT" if ok => self.selected = None,
+            _ => {},
+        }
+    }
+
+    // Connects, retrying with backoff. Resolves to an error for the next
+    // command if the selected mailbox could not be selected again or has
+    // changed.
+    fn reconnect(self) -> ReconnectFuture<Result<(), Error>, T> {
+        Box::new(future::loop_fn((self, 0), |(mut this, failed)| {
+            let selected = this.selected.as_ref().map(|&(ref cmd, _)| cmd.clone());
+            let connected = connect_once(&*this.connect, this.authenticator.clone(), selected);
+            connected.then(move |result| match result {
+                Ok((client, reselected)) => {
+                    this.client = Some(client);
+                    let checked = this.reselected(reselected);
+                    future::Either::A(future::ok(Loop::Break((checked, this))))
+                },
+                Err(e) => {
+                    let failed = failed + 1;
+                    let exhausted = this.backoff
+                        .max_attempts
+                        .map(|max| failed >= max)
+                        .unwrap_or(false);
+                    if !e.is_temporary() || exhausted {
+                        return future::Either::A(future::err(e));
+                    }
+                    let delay = this.backoff.delay(failed - 1);
+                    warn!("reconnecting in {:?} after error: {}", delay, e);
+                    future::Either::B(
+                        this.clock
+                            .delay_for(delay)
+                            .map_err(Error::Io)
+                            .map(move |()| Loop::Continue((this, failed))),
+                    )
+                },
+            })
+        }))
+    }
+
+    fn reselected(&mut self, reselected: Result<Option<u32>, Error>) -> Result<(), Error> {
+        let uid_validity = match reselected {
+            Ok(uid_validity) => uid_validity,
+            Err(e) => {
+                self.selected = None;
+                return Err(e);
+            },
+        };
+        let known = match self.selected {
+            Some((_, ref mut known)) => known,
+            None => return Ok(()),
+        };
+        let result = match (*known, uid_validity) {
+            (Some(old), Some(new)) if old != new => Err(Error::UidValidityChanged { old, new }),
+            _ => Ok(()),
+        };
+        *known = uid_validity;
+        result
+    }
+}
+
+pub(crate) fn connect_once<T, R>(
+    connect: &Fn() -> Result<R, Error>, authenticator: Arc<Authenticator<T>>,
+    selected: Option<Command>,
+) -> ConnectOnce<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+    R: Future<Item = (Greeting, Client<T>), Error = Error> + Send + 'static,
+{
+    let connecting = match connect() {
+        Ok(connecting) => connecting,
+        Err(e) => return Box::new(future::err(e)),
+    };
+    let authenticated = connecting.and_then(move |(greeting, client)| -> AuthFuture<T> {
+        if greeting.preauth {
+            Box::new(future::ok(client))
+        } else {
+            authenticator.authenticate(client)
+        }
+    });
+    Box::new(authenticated.and_then(move |client| -> ConnectOnce<T> {
+        let cmd = match selected {
+            Some(cmd) => cmd,
+            None => return Box::new(future::ok((client, Ok(None)))),
+        };
+        Box::new(client.call(cmd).collect().map(|(responses, client)| {
+            let reselected = Error::from_responses(&responses)
+                .map(|()| Mailbox::from_responses(&responses).uid_validity);
+            (client, reselected)
+        }))
+    }))
+}
+
+// Commands that only read and can be sent again without changing the
+// outcome. Sequence numbers are not stable across connections, so only the
+// UID forms of FETCH and SEARCH qualify.
+fn is_replayable(verb: &str) -> bool {
+    match verb {
+        "UID FETCH" | "UID SEARCH" => true,
+        _ => false,
+    }
+}
+
+// Whether `args` refer to `$`, the result of an earlier `SEARCH RETURN
+// (SAVE)`.
+fn uses_saved_result(args: &[u8]) -> bool {
+    args.split(|&b| b == b' ').any(|word| word == b"$")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replayable() {
+        assert!(is_replayable("UID FETCH"));
+        assert!(is_replayable("UID SEARCH"));
+        assert!(!is_replayable("SELECT"));
+        assert!(!is_replayable("NOTIFY"));
+        assert!(!is_replayable("FETCH"));
+        assert!(!is_replayable("SEARCH"));
+        assert!(!is_replayable("UID STORE"));
+        assert!(!is_replayable("APPEND"));
+    }
+
+    #[test]
+    fn saved_result() {
+        assert!(uses_saved_result(b"UID FETCH $ (FLAGS)"));
+        assert!(!uses_saved_result(b"UID FETCH 1:* (FLAGS)"));
+        assert!(!uses_saved_result(b"SEARCH SUBJECT \"$5\""));
+    }
+}
@@ -0,0 +1,2515 @@
+use bytes::Bytes;
+
+use futures::{future, stream, Async, Future, Poll, Stream};
+use futures::future::Loop;
+use futures_state_stream::{StateStream, StreamEvent};
+
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Cursor, Write};
+use std::ops::RangeInclusive;
+use std::slice;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use imap_proto::builders::command::{Command, CommandBuilder, FetchBuilderAttributes,
+                                    FetchBuilderMessages, FetchBuilderModifiers};
+use imap_proto::SequenceSet;
+use imap_proto::builders::search::{SearchCriteria, SearchDate, SearchReturnOption};
+use imap_proto::types::{Attribute, AttributeValue, BodyStructure, Envelope, ListReturn,
+                        MailboxDatum, MessageSection, MetadataCode, MetadataDepth, Namespace,
+                        Response, ResponseCode, SearchReturn, SectionPath, StatusAttribute,
+                        StoreType};
+use imap_proto::utf7;
+
+use backup::{self, Backup, BackupDate, Checkpoint, CollisionPolicy, ManifestEntry, MboxWriter,
+             RestoreOutcome, RestoreResult};
+use client::{AppendMessage, Client, ImapClient, ResponseStream, Schedule};
+use downgrade;
+use error::Error;
+use features::Features;
+use headers::Headers;
+use proto::ResponseData;
+use set::UidSet;
+use store::FlagChanges;
+
+// The number of messages fetched at a time by `Session::backup_since()`.
+const BACKUP_CHUNK_SIZE: usize = 100;
+
+// What `Session::backup_since()` has written so far, and the UID it has
+// got to.
+struct BackupProgress<W> {
+    mbox: MboxWriter<W>,
+    manifest: Vec<ManifestEntry>,
+    last_uid: u32,
+}
+
+// The number of messages searched at a time by `Session::flag_stats()` if
+// the server does not support ESEARCH.
+const SEARCH_CHUNK_SIZE: u32 = 10_000;
+
+pub type SessionFuture<C, T> = Box<Future<Item = (T, Session<C>), Error = Error> + Send>;
+
+/// The result of a `try_` method of `Session`: the command's future, or the
+/// error and the session, still usable, if the command was not sent.
+pub type TrySessionFuture<C, T> = Result<SessionFuture<C, T>, (Error, Session<C>)>;
+
+/// High-level wrapper around an `ImapClient`.
+///
+/// Each method issues a single command, consumes the resulting `ResponseStream`
+/// and resolves to a typed result together with the `Session`, so that further
+/// commands can be chained. A tagged `NO` or `BAD` response fails the future
+/// with `Error::No`, `Error::Bad` or, if the server gives a reason,
+/// `Error::Condition`.
+///
+/// Methods that check before sending anything that the command can be sent,
+/// for example that the server has the capabilities it needs, drop the
+/// session if it cannot. Their `try_` variants hand it back with the error
+/// instead, like `ImapClient::try_call()`.
+pub struct Session<C> {
+    client: C,
+}
+
+impl<C> Session<C>
+where
+    C: ImapClient + Send + 'static,
+    C::Transport: Send + 'static,
+{
+    pub fn new(client: C) -> Self {
+        Self { client }
+    }
+
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+
+    pub fn into_client(self) -> C {
+        self.client
+    }
+
+    /// Asks the server for its capabilities and derives the supported
+    /// features from them.
+    pub fn features(self) -> SessionFuture<C, Features> {
+        self.run(CommandBuilder::capability(), |responses| {
+            let mut capabilities = Vec::new();
+            for rsp in &responses {
+                if let Response::Capabilities(ref caps) = *rsp.parsed() {
+                    capabilities.extend(caps.iter().cloned());
+                }
+            }
+            Features::from_capabilities(&capabilities)
+        })
+    }
+
+    /// Enables the given extensions, resolving to those the server enabled.
+    pub fn enable(self, capabilities: &[&str]) -> SessionFuture<C, Vec<String>> {
+        self.run(CommandBuilder::enable(capabilities), |responses| {
+            let mut enabled = Vec::new();
+            for rsp in &responses {
+                if let Response::Enabled(ref caps) = *rsp.parsed() {
+                    enabled.extend(caps.iter().map(|cap| cap.to_string()));
+                }
+            }
+            enabled
+        })
+    }
+
+    /// Identifies the client to the server with fields such as `name` and
+    /// `version` (RFC 2971), resolving to the fields the server sends back.
+    /// Fields the server sends without a value are left out.
+    pub fn id(self, params: &[(&str, &str)]) -> SessionFuture<C, HashMap<String, String>> {
+        self.run(CommandBuilder::id(params), |responses| {
+            let mut fields = HashMap::new();
+            for rsp in &responses {
+                if let Response::Id(Some(ref params)) = *rsp.parsed() {
+                    for &(key, value) in params {
+                        if let Some(value) = value {
+                            fields.insert(key.to_string(), value.to_string());
+                        }
+                    }
+                }
+            }
+            fields
+        })
+    }
+
+    /// Logs in, then asks for the capabilities again if the server did not
+    /// send the updated list along.
+    pub fn login(self, user_name: &str, password: &str) -> SessionFuture<C, ()> {
+        Box::new(
+            self.run(CommandBuilder::login(user_name, password), |_| ())
+                .and_then(|((), session)| session.refresh_capabilities()),
+        )
+    }
+
+    // Issues `CAPABILITY` if the client does not know the capabilities.
+    fn refresh_capabilities(self) -> SessionFuture<C, ()> {
+        let (transport, state) = self.client.into_parts();
+        let known = state.capabilities().is_some();
+        let session = Session {
+            client: C::rebuild(transport, state),
+        };
+        if known {
+            return Box::new(future::ok(((), session)));
+        }
+        session.run(CommandBuilder::capability(), |_| ())
+    }
+
+    /// Lists the mailboxes matching `pattern` relative to `reference`.
+    pub fn list(self, reference: &str, pattern: &str) -> SessionFuture<C, Vec<MailboxInfo>> {
+        self.run_utf8(CommandBuilder::list(reference, pattern), |responses, utf8| {
+            MailboxInfo::from_responses(&responses, utf8)
+        })
+    }
+
+    /// Lists the subscribed mailboxes matching `pattern` relative to
+    /// `reference`.
+    pub fn lsub(self, reference: &str, pattern: &str) -> SessionFuture<C, Vec<MailboxInfo>> {
+        self.run_utf8(CommandBuilder::lsub(reference, pattern), |responses, utf8| {
+            MailboxInfo::from_responses(&responses, utf8)
+        })
+    }
+
+    /// Requests the given status items, such as `MESSAGES` or `UNSEEN`, for
+    /// `mailbox` without selecting it. `HIGHESTMODSEQ` requires CONDSTORE.
+    pub fn status(self, mailbox: &str, items: &[&str]) -> SessionFuture<C, MailboxStatus> {
+        self.run(CommandBuilder::status(mailbox, items), |responses| {
+            let mut status = MailboxStatus::default();
+            for rsp in &responses {
+                if let Response::MailboxData(MailboxDatum::Status { status: ref attrs, .. }) =
+                    *rsp.parsed()
+                {
+                    status.extend(attrs);
+                }
+            }
+            status
+        })
+    }
+
+    /// The rights the user has on `mailbox`, such as `lrswi` (RFC 4314).
+    pub fn my_rights(self, mailbox: &str) -> SessionFuture<C, String> {
+        self.run(CommandBuilder::my_rights(mailbox), |responses| {
+            for rsp in &responses {
+                if let Response::MyRights { rights, .. } = *rsp.parsed() {
+                    return rights.to_string();
+                }
+            }
+            String::new()
+        })
+    }
+
+    /// Asks for the server's mailbox namespaces, which tell where personal
+    /// and shared mailboxes are and which hierarchy delimiter they use.
+    pub fn namespaces(self) -> SessionFuture<C, Namespaces> {
+        self.run_utf8(CommandBuilder::namespace(), |responses, utf8| {
+            Namespaces::from_responses(&responses, utf8)
+        })
+    }
+
+    pub fn select(self, mailbox: &str) -> SessionFuture<C, Mailbox> {
+        self.run(CommandBuilder::select(mailbox), |responses| {
+            Mailbox::from_responses(&responses)
+        })
+    }
+
+    /// Like `select()`, but opens `mailbox` read-only.
+    pub fn examine(self, mailbox: &str) -> SessionFuture<C, Mailbox> {
+        self.run(CommandBuilder::examine(mailbox), |responses| {
+            Mailbox::from_responses(&responses)
+        })
+    }
+
+    /// Selects `mailbox` and resynchronizes with it (RFC 7162, QRESYNC).
+    ///
+    /// `uid_validity` and `mod_seq` are the `UIDVALIDITY` and `HIGHESTMODSEQ`
+    /// values from the last time the mailbox was synchronized; `known_uids`
+    /// optionally limits the changes reported to the given UID set. QRESYNC
+    /// must have been enabled with `enable()` first.
+    pub fn select_qresync(
+        self, mailbox: &str, uid_validity: u32, mod_seq: u64, known_uids: Option<&str>
+    ) -> SessionFuture<C, Resync> {
+        let cmd = CommandBuilder::select_qresync(mailbox, uid_validity, mod_seq, known_uids);
+        self.run_utf8(cmd, |responses, utf8| {
+            let mailbox = Mailbox::from_responses(&responses);
+            let vanished = vanished_uids(&responses);
+            Resync {
+                mailbox,
+                vanished,
+                changed: Fetch::from_responses(responses, utf8),
+            }
+        })
+    }
+
+    pub fn fetch<F>(self, cmd: F) -> SessionFuture<C, Vec<Fetch>>
+    where
+        F: FetchBuilderModifiers, {
+        self.run_utf8(cmd.build(), Fetch::from_responses)
+    }
+
+    /// Fetches `attrs` for the messages with the given UIDs.
+    ///
+    /// Unlike `fetch()`, the results are returned in the order in which the
+    /// UIDs were given, and UIDs for which the server did not return any data
+    /// (for example because the message has been expunged) are reported
+    /// explicitly. The `UID` attribute is always requested.
+    pub fn uid_fetch_ordered(
+        self, uids: &[u32], attrs: &[Attribute]
+    ) -> SessionFuture<C, OrderedFetch> {
+        let mut seen = HashSet::with_capacity(uids.len());
+        let requested: Vec<u32> = uids.iter().cloned().filter(|&uid| seen.insert(uid)).collect();
+        if requested.is_empty() {
+            return Box::new(future::ok((OrderedFetch::default(), self)));
+        }
+        let set: SequenceSet = requested.iter().cloned().collect();
+        let messages = CommandBuilder::uid_fetch().set(&set);
+        let cmd = attrs
+            .iter()
+            .filter(|&&attr| attr != Attribute::Uid)
+            .fold(messages.attr(Attribute::Uid), |cmd, &attr| cmd.attr(attr));
+        self.run_utf8(cmd.build(), move |responses, utf8| {
+            OrderedFetch::from_responses(&requested, responses, utf8)
+        })
+    }
+
+    /// Fetches the header fields named in `fields` (all of them, if it is
+    /// empty) of the messages in `set`, for example to list them, without
+    /// marking them as seen.
+    ///
+    /// Encoded words in the values are decoded only in the UTF-8, US-ASCII
+    /// and ISO-8859-1 charsets; words in other charsets, such as
+    /// Windows-1252 or ISO-2022-JP, are left encoded (see
+    /// `headers::decode_words()`).
+    pub fn fetch_headers(
+        self, set: &SequenceSet, fields: &[&str]
+    ) -> SessionFuture<C, Vec<MessageHeaders>> {
+        let cmd = CommandBuilder::fetch()
+            .set(set)
+            .attr(Attribute::Uid)
+            .header_fields(fields);
+        self.run_utf8(cmd.build(), |responses, utf8| {
+            Fetch::from_responses(responses, utf8)
+                .iter()
+                .map(MessageHeaders::from_fetch)
+                .collect()
+        })
+    }
+
+    /// Fetches body sections, yielding each one separately as it arrives.
+    ///
+    /// `cmd` would typically ask for several `BODY.PEEK[part]` sections of
+    /// the same message. Instead of waiting for the complete `FETCH`
+    /// response, the returned stream yields a `PartChunk` for each section,
+    /// keyed by its section spec. With literal streaming enabled on the
+    /// codec (see `ImapCodec::set_literal_streaming()`), large sections are
+    /// yielded piece by piece while they are being received, so that text
+    /// parts can be processed while attachments are still arriving.
+    pub fn fetch_parts<F>(self, cmd: F) -> FetchParts<C>
+    where
+        F: FetchBuilderModifiers, {
+        FetchParts {
+            responses: self.client.call(cmd.build()),
+            streamed: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Writes the messages in `mailbox` dated `since` or later to `writer`
+    /// in mbox format.
+    ///
+    /// IMAP searches by day, so messages from all of the `since` day are
+    /// included, in the server's time zone. If `checkpoint` is the
+    /// checkpoint from a previous run on the same mailbox, only messages
+    /// added since then are written. The result holds the writer, a manifest
+    /// of the messages written and the checkpoint for the next run.
+    ///
+    /// The mailbox is opened with `EXAMINE` and the messages are fetched
+    /// with `BODY.PEEK[]`, so the backup leaves their flags alone. They are
+    /// fetched a hundred at a time, and each one is written as soon as it
+    /// arrives instead of being held in memory.
+    pub fn backup_since<W>(
+        self, mailbox: &str, since: SearchDate, date: BackupDate, checkpoint: Option<Checkpoint>,
+        writer: W,
+    ) -> SessionFuture<C, Backup<W>>
+    where
+        W: Write + Send + 'static, {
+        Box::new(
+            self.examine(mailbox)
+                .and_then(move |(mailbox, session)| {
+                    let uid_validity = mailbox.uid_validity;
+                    let last_uid = match (checkpoint, uid_validity) {
+                        (Some(checkpoint), Some(validity))
+                            if checkpoint.uid_validity == validity =>
+                        {
+                            checkpoint.last_uid
+                        },
+                        _ => 0,
+                    };
+                    let criteria = backup_criteria(date, since, last_uid);
+                    let searched: SessionFuture<C, Vec<u32>> =
+                        match CommandBuilder::uid_search_criteria(&criteria) {
+                            Ok(cmd) => session.run(cmd, search_results),
+                            Err(e) => {
+                                let e = io::Error::new(io::ErrorKind::InvalidInput, e);
+                                Box::new(future::err(Error::Io(e)))
+                            },
+                        };
+                    searched.map(move |(uids, session)| (uids, session, uid_validity, last_uid))
+                })
+                .and_then(move |(uids, session, uid_validity, last_uid)| {
+                    // `UID n:*` always matches the highest UID, even if below n.
+                    let mut uids: Vec<u32> =
+                        uids.into_iter().filter(|&uid| uid > last_uid).collect();
+                    uids.sort();
+                    let chunks = uids.chunks(BACKUP_CHUNK_SIZE)
+                        .map(|chunk| chunk.to_vec())
+                        .collect::<Vec<_>>();
+                    let progress = BackupProgress {
+                        mbox: MboxWriter::new(writer),
+                        manifest: Vec::new(),
+                        last_uid,
+                    };
+                    stream::iter_ok::<_, Error>(chunks)
+                        .fold((progress, session), |(progress, session), chunk| {
+                            session.backup_chunk(&chunk, progress)
+                        })
+                        .and_then(move |(progress, session)| {
+                            let checkpoint = uid_validity.map(|uid_validity| Checkpoint {
+                                uid_validity,
+                                last_uid: progress.last_uid,
+                            });
+                            let backup = Backup {
+                                writer: progress.mbox.into_inner()?,
+                                manifest: progress.manifest,
+                                checkpoint,
+                            };
+                            Ok((backup, session))
+                        })
+                }),
+        )
+    }
+
+    // Fetches the messages with the given UIDs, in ascending order, and
+    // writes each one to the backup as its FETCH response arrives.
+    fn backup_chunk<W>(
+        self, uids: &[u32], progress: BackupProgress<W>
+    ) -> SessionFuture<C, BackupProgress<W>>
+    where
+        W: Write + Send + 'static, {
+        let set = uids.iter().cloned().collect::<SequenceSet>();
+        let chunk_end = uids.last().cloned().unwrap_or(progress.last_uid);
+        let cmd = CommandBuilder::uid_fetch()
+            .set(&set)
+            .attr(Attribute::Uid)
+            .attr(Attribute::Flags)
+            .attr(Attribute::InternalDate)
+            .body_peek()
+            .build();
+        let (transport, state) = self.client.into_parts();
+        let utf8 = state.utf8_enabled();
+        let client = C::rebuild(transport, state);
+        // The fold ends with the connection, which comes with the end of the
+        // response stream.
+        let written = client.call(cmd).fold(
+            (progress, None),
+            move |(mut progress, client), rsp| -> Result<_, Error> {
+                match *rsp.parsed() {
+                    Response::Fetch(..) => {},
+                    Response::Done { .. } => {
+                        Error::from_responses(slice::from_ref(&rsp))?;
+                        return Ok((progress, client));
+                    },
+                    _ => return Ok((progress, client)),
+                }
+                let fetch = Fetch::from_response(rsp, utf8);
+                // Unsolicited FETCH responses, such as flag changes by other
+                // clients, carry no message.
+                let (uid, body) = match (fetch.uid(), fetch.body()) {
+                    (Some(uid), Some(body)) => (uid, body),
+                    _ => return Ok((progress, client)),
+                };
+                let internal_date = fetch.internal_date();
+                let (offset, len) = progress.mbox.write_message(internal_date, body)?;
+                let flags = fetch.flags().unwrap_or(&[]);
+                progress.manifest.push(ManifestEntry {
+                    uid,
+                    flags: flags.iter().map(|f| f.to_string()).collect(),
+                    internal_date: internal_date.map(|s| s.to_string()),
+                    offset,
+                    len,
+                });
+                Ok((progress, client))
+            },
+            // Messages expunged in the meantime are skipped.
+            move |(mut progress, _), client| -> Result<_, Error> {
+                progress.last_uid = chunk_end;
+                Ok((progress, Some(client)))
+            },
+        );
+        Box::new(written.and_then(|(progress, client)| match client {
+            Some(client) => Ok((progress, Session { client })),
+            None => Err(Error::ConnectionClosed),
+        }))
+    }
+
+    /// Copies the messages in `set` to `mailbox`.
+    ///
+    /// Resolves to the UIDs of the copies if the server supports UIDPLUS.
+    pub fn copy(self, set: &SequenceSet, mailbox: &str) -> SessionFuture<C, Option<CopyResult>> {
+        self.run(CommandBuilder::copy(set, mailbox), |responses| {
+            CopyResult::from_responses(&responses)
+        })
+    }
+
+    pub fn uid_copy(
+        self, set: &SequenceSet, mailbox: &str
+    ) -> SessionFuture<C, Option<CopyResult>> {
+        self.run(CommandBuilder::uid_copy(set, mailbox), |responses| {
+            CopyResult::from_responses(&responses)
+        })
+    }
+
+    pub fn search(self, criteria: &str) -> SessionFuture<C, Vec<u32>> {
+        self.run(CommandBuilder::search(criteria), search_results)
+    }
+
+    pub fn uid_search(self, criteria: &str) -> SessionFuture<C, Vec<u32>> {
+        self.run(CommandBuilder::uid_search(criteria), search_results)
+    }
+
+    pub fn store(
+        self, set: &SequenceSet, kind: StoreType, flags: &[&str]
+    ) -> SessionFuture<C, Vec<Fetch>> {
+        self.run_utf8(CommandBuilder::store(set, kind, flags), Fetch::from_responses)
+    }
+
+    pub fn uid_store(
+        self, set: &SequenceSet, kind: StoreType, flags: &[&str]
+    ) -> SessionFuture<C, Vec<Fetch>> {
+        self.run_utf8(
+            CommandBuilder::uid_store(set, kind, flags),
+            Fetch::from_responses,
+        )
+    }
+
+    /// Logs out. A server that closes the connection without confirming is
+    /// taken to have logged out as well.
+    pub fn logout(self) -> SessionFuture<C, ()> {
+        Box::new(
+            self.client
+                .call(CommandBuilder::logout())
+                .collect()
+                .and_then(|(responses, client)| {
+                    let confirmed = responses
+                        .last()
+                        .map(|rsp| rsp.request_id().is_some())
+                        .unwrap_or(false);
+                    if confirmed {
+                        Error::from_responses(&responses)?;
+                    }
+                    Ok(((), Session { client }))
+                }),
+        )
+    }
+
+    fn run<T, F>(self, cmd: Command, extract: F) -> SessionFuture<C, T>
+    where
+        T: Send + 'static,
+        F: FnOnce(Vec<ResponseData>) -> T + Send + 'static, {
+        Box::new(
+            self.client
+                .call(cmd)
+                .collect()
+                .and_then(|(responses, client)| {
+                    Error::from_responses(&responses)?;
+                    Ok((extract(responses), Session { client }))
+                }),
+        )
+    }
+
+    // Like `run()`, but also tells `extract` whether UTF8=ACCEPT is enabled,
+    // in which case mailbox names are sent as UTF-8 rather than in modified
+    // UTF-7.
+    fn run_utf8<T, F>(self, cmd: Command, extract: F) -> SessionFuture<C, T>
+    where
+        T: Send + 'static,
+        F: FnOnce(Vec<ResponseData>, bool) -> T + Send + 'static, {
+        Box::new(
+            self.client
+                .call(cmd)
+                .collect()
+                .and_then(|(responses, client)| {
+                    Error::from_responses(&responses)?;
+                    let (transport, state) = client.into_parts();
+                    let utf8 = state.utf8_enabled();
+                    let client = C::rebuild(transport, state);
+                    Ok((extract(responses, utf8), Session { client }))
+                }),
+        )
+    }
+}
+
+impl<T> Session<Client<T>>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    /// Limits how fast the session's commands are sent; see
+    /// `Client::set_schedule()`.
+    pub fn set_schedule(&mut self, schedule: Option<Schedule>) {
+        self.client.set_schedule(schedule);
+    }
+
+    /// Appends a message of `len` bytes, read from `body`, to `mailbox`.
+    ///
+    /// Resolves to the UID assigned to the message if the server supports
+    /// UIDPLUS.
+    pub fn append<R>(
+        self, mailbox: &str, flags: &[&str], date: Option<&str>, body: R, len: u64
+    ) -> SessionFuture<Client<T>, Option<AppendResult>>
+    where
+        R: AsyncRead + Send + 'static, {
+        or_failed(self.try_append(mailbox, flags, date, body, len))
+    }
+
+    pub fn try_append<R>(
+        self, mailbox: &str, flags: &[&str], date: Option<&str>, body: R, len: u64
+    ) -> TrySessionFuture<Client<T>, Option<AppendResult>>
+    where
+        R: AsyncRead + Send + 'static, {
+        let appending = self.client
+            .try_append(mailbox, flags, date, body, len)
+            .map_err(|(e, client)| (e, Session { client }))?;
+        Ok(Box::new(appending.and_then(|(responses, client)| {
+            Error::from_responses(&responses)?;
+            Ok((AppendResult::from_responses(&responses), Session { client }))
+        })))
+    }
+
+    /// Appends `messages` to `mailbox` in a single command, which requires
+    /// MULTIAPPEND (RFC 3502); see `Client::multi_append()`.
+    ///
+    /// Resolves to the UIDs assigned to the messages if the server supports
+    /// UIDPLUS.
+    pub fn multi_append<R>(
+        self, mailbox: &str, messages: Vec<AppendMessage<R>>
+    ) -> SessionFuture<Client<T>, Option<MultiAppendResult>>
+    where
+        R: AsyncRead + Send + 'static, {
+        or_failed(self.try_multi_append(mailbox, messages))
+    }
+
+    pub fn try_multi_append<R>(
+        self, mailbox: &str, messages: Vec<AppendMessage<R>>
+    ) -> TrySessionFuture<Client<T>, Option<MultiAppendResult>>
+    where
+        R: AsyncRead + Send + 'static, {
+        let appending = self.client
+            .try_multi_append(mailbox, messages)
+            .map_err(|(e, client)| (e, Session { client }))?;
+        Ok(Box::new(appending.and_then(|(responses, client)| {
+            Error::from_responses(&responses)?;
+            Ok((MultiAppendResult::from_responses(&responses), Session { client }))
+        })))
+    }
+
+    // Checks that the server has `capability`, for the `try_` methods.
+    fn try_require(self, capability: &str) -> Result<Self, (Error, Self)> {
+        match self.client.require(capability) {
+            Ok(()) => Ok(self),
+            Err(e) => Err((e, self)),
+        }
+    }
+
+    /// Makes the flag `changes`, pipelining the `UID STORE` commands.
+    ///
+    /// Resolves to the `FETCH` responses the server sent for the changed
+    /// messages. All commands are sent; the first that failed is reported as
+    /// the error.
+    pub fn store_changes(self, changes: &FlagChanges) -> SessionFuture<Client<T>, Vec<Fetch>> {
+        let utf8 = self.client.utf8_enabled();
+        Box::new(
+            self.client
+                .batch(|batch| {
+                    batch.store_changes(changes);
+                })
+                .and_then(move |(client, results)| {
+                    let mut fetches = Vec::new();
+                    for responses in results {
+                        let responses = responses?;
+                        Error::from_responses(&responses)?;
+                        fetches.extend(Fetch::from_responses(responses, utf8));
+                    }
+                    Ok((fetches, Session { client }))
+                }),
+        )
+    }
+
+    /// Changes the Gmail labels of the messages in `set`, resolving to the
+    /// `FETCH` responses the server sends back.
+    ///
+    /// This requires the X-GM-EXT-1 capability.
+    pub fn store_labels(
+        self, set: &SequenceSet, kind: StoreType, labels: &[&str]
+    ) -> SessionFuture<Client<T>, Vec<Fetch>> {
+        or_failed(self.try_store_labels(set, kind, labels))
+    }
+
+    pub fn try_store_labels(
+        self, set: &SequenceSet, kind: StoreType, labels: &[&str]
+    ) -> TrySessionFuture<Client<T>, Vec<Fetch>> {
+        let cmd = CommandBuilder::store_labels(set, kind, labels);
+        self.try_require("X-GM-EXT-1")
+            .map(|session| session.run_utf8(cmd, Fetch::from_responses))
+    }
+
+    pub fn uid_store_labels(
+        self, set: &SequenceSet, kind: StoreType, labels: &[&str]
+    ) -> SessionFuture<Client<T>, Vec<Fetch>> {
+        or_failed(self.try_uid_store_labels(set, kind, labels))
+    }
+
+    pub fn try_uid_store_labels(
+        self, set: &SequenceSet, kind: StoreType, labels: &[&str]
+    ) -> TrySessionFuture<Client<T>, Vec<Fetch>> {
+        let cmd = CommandBuilder::uid_store_labels(set, kind, labels);
+        self.try_require("X-GM-EXT-1")
+            .map(|session| session.run_utf8(cmd, Fetch::from_responses))
+    }
+
+    /// Searches the selected mailbox for messages matching `criteria`.
+    ///
+    /// Strings that are not ASCII are sent as literals, which requires
+    /// LITERAL+ (RFC 7888), unless UTF8=ACCEPT is enabled; so are strings
+    /// with line breaks. Fails without sending anything for criteria that
+    /// cannot be sent (see `SearchCriteria::to_args()`).
+    pub fn search_criteria(
+        self, criteria: &SearchCriteria
+    ) -> SessionFuture<Client<T>, Vec<u32>> {
+        or_failed(self.try_search_criteria(criteria))
+    }
+
+    pub fn try_search_criteria(
+        self, criteria: &SearchCriteria
+    ) -> TrySessionFuture<Client<T>, Vec<u32>> {
+        self.criteria_command(criteria, CommandBuilder::search_criteria)
+            .map(|(cmd, session)| session.run(cmd, search_results))
+    }
+
+    /// Like `search_criteria()`, but finding UIDs.
+    pub fn uid_search_criteria(
+        self, criteria: &SearchCriteria
+    ) -> SessionFuture<Client<T>, UidSet> {
+        or_failed(self.try_uid_search_criteria(criteria))
+    }
+
+    pub fn try_uid_search_criteria(
+        self, criteria: &SearchCriteria
+    ) -> TrySessionFuture<Client<T>, UidSet> {
+        let extract = |responses| UidSet::from(search_results(responses));
+        self.criteria_command(criteria, CommandBuilder::uid_search_criteria)
+            .map(|(cmd, session)| session.run(cmd, extract))
+    }
+
+    // Builds a search with `criteria`, checking that the server accepts the
+    // literals it needs.
+    fn criteria_command<F>(
+        self, criteria: &SearchCriteria, build: F
+    ) -> Result<(Command, Self), (Error, Self)>
+    where
+        F: FnOnce(&SearchCriteria) -> Result<Command, &'static str>, {
+        let session = if criteria.has_literals(self.client.utf8_enabled()) {
+            self.try_require("LITERAL+")?
+        } else {
+            self
+        };
+        match build(criteria) {
+            Ok(cmd) => Ok((cmd, session)),
+            Err(e) => Err((Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)), session)),
+        }
+    }
+
+    /// Searches the selected mailbox, with the server only reporting what
+    /// `returns` asks for, such as the number of messages found, instead of
+    /// listing all of them.
+    ///
+    /// With `SearchReturnOption::Save`, later commands can refer to the
+    /// messages found as `SequenceSet::saved()`, until the next search that
+    /// saves or the mailbox is closed.
+    ///
+    /// This requires the ESEARCH capability (RFC 4731), and SEARCHRES (RFC
+    /// 5182) to save the result.
+    pub fn esearch(
+        self, returns: &[SearchReturnOption], criteria: &SearchCriteria
+    ) -> SessionFuture<Client<T>, SearchResult> {
+        or_failed(self.try_esearch(returns, criteria))
+    }
+
+    pub fn try_esearch(
+        self, returns: &[SearchReturnOption], criteria: &SearchCriteria
+    ) -> TrySessionFuture<Client<T>, SearchResult> {
+        let build = |criteria: &_| CommandBuilder::search_returning(returns, criteria);
+        let (cmd, session) = self.criteria_command(criteria, build)?;
+        session.esearch_with(cmd, returns)
+    }
+
+    /// Like `esearch()`, but with UIDs instead of message sequence numbers.
+    pub fn uid_esearch(
+        self, returns: &[SearchReturnOption], criteria: &SearchCriteria
+    ) -> SessionFuture<Client<T>, SearchResult> {
+        or_failed(self.try_uid_esearch(returns, criteria))
+    }
+
+    pub fn try_uid_esearch(
+        self, returns: &[SearchReturnOption], criteria: &SearchCriteria
+    ) -> TrySessionFuture<Client<T>, SearchResult> {
+        let build = |criteria: &_| CommandBuilder::uid_search_returning(returns, criteria);
+        let (cmd, session) = self.criteria_command(criteria, build)?;
+        session.esearch_with(cmd, returns)
+    }
+
+    fn esearch_with(
+        self, cmd: Command, returns: &[SearchReturnOption]
+    ) -> TrySessionFuture<Client<T>, SearchResult> {
+        let required = if returns.contains(&SearchReturnOption::Save) {
+            "SEARCHRES"
+        } else {
+            "ESEARCH"
+        };
+        let extract = |responses: Vec<_>| SearchResult::from_responses(&responses);
+        self.try_require(required).map(|session| session.run(cmd, extract))
+    }
+
+    /// Counts the messages in `mailbox` with each of its flags and keywords,
+    /// and those without `\Seen`, for overviews like "42 flagged, 980
+    /// unseen, 17 $Todo".
+    ///
+    /// The mailbox is opened with `EXAMINE` and stays selected. If the server
+    /// supports ESEARCH (RFC 4731), each count takes a single `SEARCH RETURN
+    /// (COUNT)`; otherwise, the messages are searched in chunks, so that the
+    /// lists of matching messages the server sends back stay short.
+    pub fn flag_stats(self, mailbox: &str) -> SessionFuture<Client<T>, FlagStats> {
+        let esearch = self.client.has_capability("ESEARCH");
+        Box::new(self.examine(mailbox).and_then(move |(mailbox, session)| {
+            let mut flags = mailbox.flags;
+            for flag in mailbox.permanent_flags {
+                if flag != "\\*" && !flags.contains(&flag) {
+                    flags.push(flag);
+                }
+            }
+            let stats = FlagStats {
+                exists: mailbox.exists,
+                ..FlagStats::default()
+            };
+            let exists = mailbox.exists;
+            let counted = stream::iter_ok::<_, Error>(flags).fold(
+                (stats, session),
+                move |(mut stats, session), flag| -> SessionFuture<_, _> {
+                    let criteria = match flag_criteria(&flag) {
+                        Some(criteria) => criteria,
+                        None => return Box::new(future::ok((stats, session))),
+                    };
+                    Box::new(session.count_matching(&criteria, exists, esearch).map(
+                        move |(count, session)| {
+                            stats.flags.insert(flag, count);
+                            (stats, session)
+                        },
+                    ))
+                },
+            );
+            counted.and_then(move |(mut stats, session)| {
+                session
+                    .count_matching("UNSEEN", exists, esearch)
+                    .map(move |(unseen, session)| {
+                        stats.unseen = unseen;
+                        (stats, session)
+                    })
+            })
+        }))
+    }
+
+    // Counts the messages in the selected mailbox, which has `exists`
+    // messages, that match `criteria`.
+    fn count_matching(
+        self, criteria: &str, exists: u32, esearch: bool
+    ) -> SessionFuture<Client<T>, u32> {
+        if exists == 0 {
+            return Box::new(future::ok((0, self)));
+        }
+        if esearch {
+            let cmd = CommandBuilder::search(&format!("RETURN (COUNT) {}", criteria));
+            return self.run(cmd, search_count);
+        }
+        let chunks = (1..exists + 1)
+            .step_by(SEARCH_CHUNK_SIZE as usize)
+            .map(|start| {
+                let end = cmp::min(start + SEARCH_CHUNK_SIZE - 1, exists);
+                format!("{}:{} {}", start, end, criteria)
+            })
+            .collect::<Vec<_>>();
+        Box::new(
+            stream::iter_ok::<_, Error>(chunks).fold((0, self), |(count, session), criteria| {
+                session
+                    .search(&criteria)
+                    .map(move |(found, session)| (count + found.len() as u32, session))
+            }),
+        )
+    }
+
+    /// Appends `message` to `mailbox`, downgrading UTF-8 header fields to
+    /// encoded words (see `downgrade::downgrade_headers()`) unless
+    /// UTF8=ACCEPT is enabled.
+    pub fn append_message(
+        self, mailbox: &str, flags: &[&str], date: Option<&str>, message: &[u8]
+    ) -> SessionFuture<Client<T>, Option<AppendResult>> {
+        let message = if self.client.utf8_enabled() {
+            message.to_vec()
+        } else {
+            downgrade::downgrade_headers(message).into_owned()
+        };
+        let len = message.len() as u64;
+        self.append(mailbox, flags, date, Cursor::new(message), len)
+    }
+
+    /// Lists the mailboxes matching `pattern` together with the given
+    /// `STATUS` items, such as `MESSAGES` or `UNSEEN`, for each of them.
+    ///
+    /// This requires the LIST-STATUS capability (RFC 5819).
+    pub fn list_status(
+        self, reference: &str, pattern: &str, items: &[&str]
+    ) -> SessionFuture<Client<T>, Vec<MailboxInfo>> {
+        or_failed(self.try_list_status(reference, pattern, items))
+    }
+
+    pub fn try_list_status(
+        self, reference: &str, pattern: &str, items: &[&str]
+    ) -> TrySessionFuture<Client<T>, Vec<MailboxInfo>> {
+        self.try_list_returning(reference, pattern, &[ListReturn::Status(items)])
+    }
+
+    /// Lists the mailboxes matching `pattern` with the given return options,
+    /// such as `ListReturn::Children` to fill in `MailboxInfo::has_children()`
+    /// when building a mailbox tree one level at a time.
+    ///
+    /// This requires the LIST-EXTENDED capability (RFC 5258), and
+    /// LIST-STATUS (RFC 5819) for `ListReturn::Status`.
+    pub fn list_returning(
+        self, reference: &str, pattern: &str, returns: &[ListReturn]
+    ) -> SessionFuture<Client<T>, Vec<MailboxInfo>> {
+        or_failed(self.try_list_returning(reference, pattern, returns))
+    }
+
+    pub fn try_list_returning(
+        self, reference: &str, pattern: &str, returns: &[ListReturn]
+    ) -> TrySessionFuture<Client<T>, Vec<MailboxInfo>> {
+        let status = returns.iter().any(|ret| match *ret {
+            ListReturn::Status(_) => true,
+            _ => false,
+        });
+        let required = if status { "LIST-STATUS" } else { "LIST-EXTENDED" };
+        let session = if returns.is_empty() {
+            self
+        } else {
+            self.try_require(required)?
+        };
+        let cmd = CommandBuilder::list_returning(reference, pattern, returns);
+        let extract = |responses: Vec<_>, utf8| MailboxInfo::from_responses(&responses, utf8);
+        Ok(session.run_utf8(cmd, extract))
+    }
+
+    /// Lists the mailboxes in the other users' and shared namespaces, with
+    /// the rights the user has on each if the server supports ACL.
+    ///
+    /// The names include the namespace prefix, so they can be passed to
+    /// `select()` and the other methods as they are.
+    pub fn shared_mailboxes(self) -> SessionFuture<Client<T>, Vec<SharedMailbox>> {
+        let acl = self.client.has_capability("ACL");
+        let listed = self.namespaces().and_then(|(namespaces, session)| {
+            let other_users = namespaces.other_users.into_iter().map(|ns| (ns, false));
+            let shared = namespaces.shared.into_iter().map(|ns| (ns, true));
+            // An empty prefix would list the personal mailboxes as well.
+            let namespaces = other_users
+                .chain(shared)
+                .filter(|&(ref ns, _)| !ns.prefix.is_empty())
+                .collect::<Vec<_>>();
+            stream::iter_ok::<_, Error>(namespaces).fold(
+                (session, Vec::new()),
+                |(session, mut found), (namespace, shared)| {
+                    let pattern = format!("{}*", namespace.prefix);
+                    session.list("", &pattern).map(move |(mailboxes, session)| {
+                        found.extend(mailboxes.into_iter().map(|info| SharedMailbox {
+                            info,
+                            namespace: namespace.clone(),
+                            shared,
+                            rights: None,
+                        }));
+                        (session, found)
+                    })
+                },
+            )
+        });
+        Box::new(listed.and_then(move |(session, found)| -> SessionFuture<_, _> {
+            if !acl {
+                return Box::new(future::ok((found, session)));
+            }
+            let with_rights = stream::iter_ok::<_, Error>(found).fold(
+                (session, Vec::new()),
+                |(session, mut done), mut mailbox| {
+                    if !mailbox.info.is_selectable() {
+                        done.push(mailbox);
+                        return future::Either::A(future::ok((session, done)));
+                    }
+                    let rights = session.my_rights(&mailbox.info.name);
+                    future::Either::B(rights.map(move |(rights, session)| {
+                        mailbox.rights = Some(rights);
+                        done.push(mailbox);
+                        (session, done)
+                    }))
+                },
+            );
+            Box::new(with_rights.map(|(session, done)| (done, session)))
+        }))
+    }
+
+    /// Asks for the values of the annotation `entries`, like
+    /// `/private/comment`, of `mailbox`, or of the server if `mailbox` is
+    /// empty (RFC 5464). `depth` also asks for the entries below the given
+    /// ones; values larger than `max_size` bytes are left out.
+    pub fn get_metadata(
+        self, mailbox: &str, entries: &[&str], depth: Option<MetadataDepth>, max_size: Option<u32>
+    ) -> SessionFuture<Client<T>, Metadata> {
+        or_failed(self.try_get_metadata(mailbox, entries, depth, max_size))
+    }
+
+    pub fn try_get_metadata(
+        self, mailbox: &str, entries: &[&str], depth: Option<MetadataDepth>, max_size: Option<u32>
+    ) -> TrySessionFuture<Client<T>, Metadata> {
+        let cmd = CommandBuilder::get_metadata(mailbox, entries, depth, max_size);
+        let extract = |responses: Vec<_>| Metadata::from_responses(&responses);
+        self.require_metadata(mailbox).map(|session| session.run(cmd, extract))
+    }
+
+    /// Sets annotation entries of `mailbox`, or of the server if `mailbox`
+    /// is empty (RFC 5464), removing those without a value. Values that are
+    /// not ASCII text require LITERAL+ (see `CommandBuilder::set_metadata()`).
+    pub fn set_metadata(
+        self, mailbox: &str, values: &[(&str, Option<&[u8]>)]
+    ) -> SessionFuture<Client<T>, ()> {
+        or_failed(self.try_set_metadata(mailbox, values))
+    }
+
+    pub fn try_set_metadata(
+        self, mailbox: &str, values: &[(&str, Option<&[u8]>)]
+    ) -> TrySessionFuture<Client<T>, ()> {
+        let cmd = CommandBuilder::set_metadata(mailbox, values);
+        self.require_metadata(mailbox).map(|session| session.run(cmd, |_| ()))
+    }
+
+    // Server annotations are also available with just METADATA-SERVER.
+    fn require_metadata(self, mailbox: &str) -> Result<Self, (Error, Self)> {
+        if mailbox.is_empty() && self.client.has_capability("METADATA-SERVER") {
+            return Ok(self);
+        }
+        self.try_require("METADATA")
+    }
+
+    /// Moves the messages in `set` to `mailbox` (RFC 6851), which requires
+    /// the MOVE capability.
+    pub fn move_messages(
+        self, set: &SequenceSet, mailbox: &str
+    ) -> SessionFuture<Client<T>, MoveResult> {
+        or_failed(self.try_move_messages(set, mailbox))
+    }
+
+    pub fn try_move_messages(
+        self, set: &SequenceSet, mailbox: &str
+    ) -> TrySessionFuture<Client<T>, MoveResult> {
+        self.move_with(CommandBuilder::move_messages(set, mailbox))
+    }
+
+    pub fn uid_move(
+        self, set: &SequenceSet, mailbox: &str
+    ) -> SessionFuture<Client<T>, MoveResult> {
+        or_failed(self.try_uid_move(set, mailbox))
+    }
+
+    pub fn try_uid_move(
+        self, set: &SequenceSet, mailbox: &str
+    ) -> TrySessionFuture<Client<T>, MoveResult> {
+        self.move_with(CommandBuilder::uid_move(set, mailbox))
+    }
+
+    /// Enables UTF8=ACCEPT (RFC 6855), after which mailbox names and other
+    /// strings are sent as UTF-8. Resolves to whether the server enabled it.
+    pub fn enable_utf8(self) -> SessionFuture<Client<T>, bool> {
+        or_failed(self.try_enable_utf8())
+    }
+
+    pub fn try_enable_utf8(self) -> TrySessionFuture<Client<T>, bool> {
+        let session = if self.client.has_capability("UTF8=ONLY") {
+            self
+        } else {
+            self.try_require("UTF8=ACCEPT")?
+        };
+        Ok(Box::new(
+            session
+                .enable(&["UTF8=ACCEPT"])
+                .map(|(_, session)| (session.client.utf8_enabled(), session)),
+        ))
+    }
+
+    fn move_with(self, cmd: Command) -> TrySessionFuture<Client<T>, MoveResult> {
+        let extract = |responses: Vec<_>| MoveResult::from_responses(&responses);
+        self.try_require("MOVE").map(|session| session.run(cmd, extract))
+    }
+
+    /// Deletes and expunges the messages in `uids` from the selected mailbox,
+    /// `chunk_size` messages at a time.
+    ///
+    /// Each chunk is marked `\Deleted` and removed with `UID EXPUNGE`, so
+    /// other messages marked for deletion are left alone; this requires the
+    /// UIDPLUS capability. The returned stream yields the progress after each
+    /// chunk, and the next chunk is only sent once it is polled again, so
+    /// callers can pause between chunks by delaying that.
+    pub fn purge(self, uids: &[u32], chunk_size: usize) -> Purge<T> {
+        let mut uids = uids.to_vec();
+        uids.sort();
+        uids.dedup();
+        Purge {
+            error: self.client.require("UIDPLUS").err(),
+            uids,
+            next: 0,
+            chunk_size: cmp::max(chunk_size, 1),
+            session: Some(self),
+            future: None,
+            progress: ExpungeProgress::default(),
+        }
+    }
+
+    // Expunges one chunk of `purge()`, resolving to the number of messages
+    // the server reported as expunged.
+    fn purge_chunk(self, uids: &[u32]) -> SessionFuture<Client<T>, usize> {
+        let set = UidSet::from(uids.to_vec()).to_sequence_set();
+        let expunge = CommandBuilder::uid_expunge(&set);
+        Box::new(
+            self.uid_store(&set, StoreType::Add, &["\\Deleted"])
+                .and_then(move |(_, session)| session.run(expunge, count_expunged)),
+        )
+    }
+
+    /// Restores the messages in `manifest` from a backup `archive` (as
+    /// written by `backup_since()`) to `mailbox`, preserving their flags and
+    /// internal dates.
+    ///
+    /// Each message is appended separately; messages that the server refuses
+    /// are reported as failed without aborting the restore. Restored messages
+    /// that have a `Message-ID` are verified by searching for it afterwards.
+    pub fn restore(
+        self, archive: &[u8], manifest: Vec<ManifestEntry>, mailbox: &str, policy: CollisionPolicy
+    ) -> SessionFuture<Client<T>, Vec<RestoreResult>> {
+        let messages: Vec<_> = manifest
+            .into_iter()
+            .map(|entry| (backup::read_message(archive, &entry), entry))
+            .collect();
+        let mailbox = mailbox.to_string();
+        Box::new(self.select(&mailbox).and_then(move |(_, session)| {
+            let state = (session, messages.into_iter(), Vec::new());
+            future::loop_fn(state, move |(session, mut messages, mut report)| {
+                let (message, entry) = match messages.next() {
+                    Some(next) => next,
+                    None => return future::Either::A(future::ok(Loop::Break((report, session)))),
+                };
+                let uid = entry.uid;
+                let restored = match message {
+                    Some(message) => session.restore_message(&mailbox, message, entry, policy),
+                    None => {
+                        report.push(RestoreResult {
+                            uid,
+                            message_id: None,
+                            outcome: RestoreOutcome::Failed("not found in archive".to_string()),
+                        });
+                        return future::Either::A(future::ok(Loop::Continue((
+                            session,
+                            messages,
+                            report,
+                        ))));
+                    },
+                };
+                future::Either::B(restored.map(move |(result, session)| {
+                    report.push(result);
+                    Loop::Continue((session, messages, report))
+                }))
+            })
+        }))
+    }
+
+    fn restore_message(
+        self, mailbox: &str, message: Vec<u8>, entry: ManifestEntry, policy: CollisionPolicy
+    ) -> SessionFuture<Client<T>, RestoreResult> {
+        let message_id = backup::message_id(&message);
+        let mailbox = mailbox.to_string();
+        let uid = entry.uid;
+        let result = move |message_id, outcome| RestoreResult {
+            uid,
+            message_id,
+            outcome,
+        };
+        let present = match (policy, message_id.clone()) {
+            (CollisionPolicy::Skip, Some(id)) => self.contains_message_id(&id),
+            _ => Box::new(future::ok((false, self))),
+        };
+        Box::new(present.and_then(move |(present, session)| {
+            if present {
+                let skipped = result(message_id, RestoreOutcome::Skipped);
+                return future::Either::A(future::ok((skipped, session)));
+            }
+            let flags: Vec<&str> = entry
+                .flags
+                .iter()
+                .map(|f| &f[..])
+                .filter(|f| !f.eq_ignore_ascii_case("\\Recent"))
+                .collect();
+            let date = entry.internal_date.as_ref().map(|d| &d[..]);
+            let len = message.len() as u64;
+            let body = Cursor::new(message);
+            let appended = session
+                .client
+                .append(&mailbox, &flags, date, body, len)
+                .and_then(move |(responses, client)| {
+                    let session = Session { client };
+                    let message_id = match Error::from_responses(&responses) {
+                        Ok(()) => message_id,
+                        Err(e) => {
+                            let failed = result(message_id, RestoreOutcome::Failed(e.to_string()));
+                            return future::Either::A(future::ok((failed, session)));
+                        },
+                    };
+                    let verified: SessionFuture<Client<T>, bool> = match message_id.clone() {
+                        Some(id) => session.contains_message_id(&id),
+                        None => Box::new(future::ok((true, session))),
+                    };
+                    future::Either::B(verified.map(move |(verified, session)| {
+                        let restored = RestoreOutcome::Restored { verified };
+                        (result(message_id, restored), session)
+                    }))
+                });
+            future::Either::B(appended)
+        }))
+    }
+
+    fn contains_message_id(self, message_id: &str) -> SessionFuture<Client<T>, bool> {
+        let criteria = SearchCriteria::Header("Message-ID".to_string(), message_id.to_string());
+        Box::new(
+            self.uid_search_criteria(&criteria)
+                .map(|(uids, session)| (!uids.is_empty(), session)),
+        )
+    }
+}
+
+// The messages `backup_since()` writes: those dated `since` or later, and
+// above `last_uid` if an earlier run got that far.
+fn backup_criteria(date: BackupDate, since: SearchDate, last_uid: u32) -> SearchCriteria {
+    let criteria = date.since(since);
+    if last_uid > 0 {
+        criteria.and(SearchCriteria::Uid(SequenceSet::from(last_uid + 1..)))
+    } else {
+        criteria
+    }
+}
+
+// Counts the messages reported by `EXPUNGE` and `VANISHED` responses.
+fn count_expunged(responses: Vec<ResponseData>) -> usize {
+    let mut count = 0;
+    for rsp in &responses {
+        if let Response::Expunge(_) = *rsp.parsed() {
+            count += 1;
+        }
+    }
+    // Collecting into a set counts UIDs reported in overlapping ranges once.
+    let vanished: SequenceSet = vanished_uids(&responses).into_iter().collect();
+    count + vanished.len()
+}
+
+// Collects the UIDs reported by `VANISHED` responses.
+fn vanished_uids(responses: &[ResponseData]) -> Vec<RangeInclusive<u32>> {
+    let mut vanished = Vec::new();
+    for rsp in responses {
+        if let Response::Vanished { ref uids, .. } = *rsp.parsed() {
+            vanished.extend(uids.iter().cloned());
+        }
+    }
+    vanished
+}
+
+// The `SEARCH` criteria for messages with `flag`, unless it is a system
+// flag that cannot be searched for.
+fn flag_criteria(flag: &str) -> Option<String> {
+    if !flag.starts_with('\\') {
+        return Some(format!("KEYWORD {}", flag));
+    }
+    let criteria = match &flag.to_uppercase()[..] {
+        "\\ANSWERED" => "ANSWERED",
+        "\\DELETED" => "DELETED",
+        "\\DRAFT" => "DRAFT",
+        "\\FLAGGED" => "FLAGGED",
+        "\\RECENT" => "RECENT",
+        "\\SEEN" => "SEEN",
+        _ => return None,
+    };
+    Some(criteria.to_string())
+}
+
+// Fails the way the methods without `try_` do when the command cannot be
+// sent, dropping the session.
+fn or_failed<C, T>(tried: TrySessionFuture<C, T>) -> SessionFuture<C, T>
+where
+    C: Send + 'static,
+    T: Send + 'static, {
+    tried.unwrap_or_else(|(e, _)| Box::new(future::err(e)))
+}
+
+// The count from an `ESEARCH` response with `RETURN (COUNT)`.
+fn search_count(responses: Vec<ResponseData>) -> u32 {
+    for rsp in &responses {
+        if let Response::ESearch { ref data, .. } = *rsp.parsed() {
+            for item in data {
+                if let SearchReturn::Count(count) = *item {
+                    return count;
+                }
+            }
+        }
+    }
+    0
+}
+
+// Collects the results of `SEARCH` (RFC 3501) and `ESEARCH` (RFC 4731)
+// responses.
+fn search_results(responses: Vec<ResponseData>) -> Vec<u32> {
+    let mut ids = Vec::new();
+    for rsp in &responses {
+        match *rsp.parsed() {
+            Response::IDs(ref found) => ids.extend(found),
+            Response::ESearch { ref data, .. } => for item in data {
+                if let SearchReturn::All(ref ranges) = *item {
+                    ids.extend(ranges.iter().flat_map(|range| range.clone()));
+                }
+            },
+            _ => {},
+        }
+    }
+    ids
+}
+
+/// The result of `Session::esearch()`, with `None` for what was not asked
+/// for. When no messages match, only the count is returned.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SearchResult {
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+    pub count: Option<u32>,
+    pub all: Option<SequenceSet>,
+    /// The highest mod-sequence of the messages found, for searches that
+    /// include `MODSEQ` criteria (RFC 7162).
+    pub mod_seq: Option<u64>,
+}
+
+impl SearchResult {
+    fn from_responses(responses: &[ResponseData]) -> Self {
+        let mut result = SearchResult::default();
+        for rsp in responses {
+            if let Response::ESearch { ref data, .. } = *rsp.parsed() {
+                for item in data {
+                    match *item {
+                        SearchReturn::All(ref ranges) => {
+                            result.all = Some(SequenceSet::from(&ranges[..]))
+                        },
+                        SearchReturn::Count(count) => result.count = Some(count),
+                        SearchReturn::Max(max) => result.max = Some(max),
+                        SearchReturn::Min(min) => result.min = Some(min),
+                        SearchReturn::ModSeq(mod_seq) => result.mod_seq = Some(mod_seq),
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+/// A mailbox name attribute from a `LIST` or `LSUB` response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NameAttribute {
+    NoInferiors,
+    NoSelect,
+    Marked,
+    Unmarked,
+    // RFC 5258, LIST-EXTENDED
+    HasChildren,
+    HasNoChildren,
+    NonExistent,
+    Remote,
+    Subscribed,
+    // RFC 6154, SPECIAL-USE
+    All,
+    Archive,
+    Drafts,
+    Flagged,
+    Junk,
+    Sent,
+    Trash,
+    Extension(String),
+}
+
+impl NameAttribute {
+    fn from_flag(flag: &str) -> NameAttribute {
+        let known = [
+            ("\\Noinferiors", NameAttribute::NoInferiors),
+            ("\\Noselect", NameAttribute::NoSelect),
+            ("\\Marked", NameAttribute::Marked),
+            ("\\Unmarked", NameAttribute::Unmarked),
+            ("\\HasChildren", NameAttribute::HasChildren),
+            ("\\HasNoChildren", NameAttribute::HasNoChildren),
+            ("\\NonExistent", NameAttribute::NonExistent),
+            ("\\Remote", NameAttribute::Remote),
+            ("\\Subscribed", NameAttribute::Subscribed),
+            ("\\All", NameAttribute::All),
+            ("\\Archive", NameAttribute::Archive),
+            ("\\Drafts", NameAttribute::Drafts),
+            ("\\Flagged", NameAttribute::Flagged),
+            ("\\Junk", NameAttribute::Junk),
+            ("\\Sent", NameAttribute::Sent),
+            ("\\Trash", NameAttribute::Trash),
+        ];
+        known
+            .iter()
+            .find(|&&(name, _)| name.eq_ignore_ascii_case(flag))
+            .map(|&(_, ref attr)| attr.clone())
+            .unwrap_or_else(|| NameAttribute::Extension(flag.to_string()))
+    }
+
+    /// Whether this is one of the RFC 6154 attributes describing the use of
+    /// a mailbox.
+    pub fn is_special_use(&self) -> bool {
+        match *self {
+            NameAttribute::All
+            | NameAttribute::Archive
+            | NameAttribute::Drafts
+            | NameAttribute::Flagged
+            | NameAttribute::Junk
+            | NameAttribute::Sent
+            | NameAttribute::Trash => true,
+            _ => false,
+        }
+    }
+}
+
+/// A mailbox returned by `LIST` or `LSUB`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MailboxInfo {
+    /// The name, decoded from modified UTF-7 unless UTF8=ACCEPT is enabled.
+    pub name: String,
+    /// The hierarchy delimiter, or `None` if there is no hierarchy.
+    pub delimiter: Option<String>,
+    pub attributes: Vec<NameAttribute>,
+    /// `STATUS` items returned for the mailbox by `list_status()` or
+    /// `list_returning()`.
+    pub status: MailboxStatus,
+}
+
+impl MailboxInfo {
+    fn from_responses(responses: &[ResponseData], utf8: bool) -> Vec<Self> {
+        let mut mailboxes: Vec<MailboxInfo> = Vec::new();
+        for rsp in responses {
+            match *rsp.parsed() {
+                Response::MailboxData(MailboxDatum::List {
+                    ref flags,
+                    delimiter,
+                    name,
+                })
+                | Response::MailboxData(MailboxDatum::SubList {
+                    ref flags,
+                    delimiter,
+                    name,
+                }) => mailboxes.push(MailboxInfo {
+                    name: mailbox_name(name, utf8),
+                    delimiter: delimiter.map(|d| d.to_string()),
+                    attributes: flags.iter().map(|f| NameAttribute::from_flag(f)).collect(),
+                    status: MailboxStatus::default(),
+                }),
+                // Servers send the STATUS response right after the LIST
+                // response for the same mailbox.
+                Response::MailboxData(MailboxDatum::Status {
+                    mailbox,
+                    ref status,
+                }) => match mailboxes
+                    .iter_mut()
+                    .rev()
+                    .find(|info| info.name == mailbox_name(mailbox, utf8))
+                {
+                    Some(info) => info.status.extend(status),
+                    None => debug!("STATUS for unlisted mailbox {}", mailbox),
+                },
+                _ => {},
+            }
+        }
+        mailboxes
+    }
+
+    /// Whether the mailbox can be selected.
+    pub fn is_selectable(&self) -> bool {
+        !self.attributes.iter().any(|attr| match *attr {
+            NameAttribute::NoSelect | NameAttribute::NonExistent => true,
+            _ => false,
+        })
+    }
+
+    /// Whether the mailbox has child mailboxes, or `None` if the server did
+    /// not say. Servers with the CHILDREN capability (RFC 3348) always say,
+    /// and others do when asked with `ListReturn::Children`.
+    pub fn has_children(&self) -> Option<bool> {
+        self.attributes
+            .iter()
+            .filter_map(|attr| match *attr {
+                NameAttribute::HasChildren => Some(true),
+                // A mailbox that cannot have children has none.
+                NameAttribute::HasNoChildren | NameAttribute::NoInferiors => Some(false),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Whether the mailbox is subscribed, which servers only say when asked
+    /// with `ListReturn::Subscribed`.
+    pub fn is_subscribed(&self) -> bool {
+        self.attributes.contains(&NameAttribute::Subscribed)
+    }
+
+    /// The RFC 6154 special use of the mailbox, if any.
+    pub fn special_use(&self) -> Option<&NameAttribute> {
+        self.attributes.iter().find(|attr| attr.is_special_use())
+    }
+}
+
+// Decodes a mailbox name sent by the server from modified UTF-7, unless
+// UTF8=ACCEPT is enabled, in which case names are sent as UTF-8 (RFC 6855).
+// Names that are not valid modified UTF-7 are kept as they are.
+fn mailbox_name(name: &str, utf8: bool) -> String {
+    if utf8 {
+        return name.to_string();
+    }
+    utf7::decode(name).unwrap_or_else(|_| name.into()).into_owned()
+}
+
+/// A namespace prefix and its hierarchy delimiter, if any.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NamespaceInfo {
+    pub prefix: String,
+    pub delimiter: Option<String>,
+}
+
+impl NamespaceInfo {
+    /// The full name of `mailbox` in this namespace.
+    pub fn qualify(&self, mailbox: &str) -> String {
+        format!("{}{}", self.prefix, mailbox)
+    }
+}
+
+/// The namespaces returned by `NAMESPACE` (RFC 2342).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Namespaces {
+    pub personal: Vec<NamespaceInfo>,
+    pub other_users: Vec<NamespaceInfo>,
+    pub shared: Vec<NamespaceInfo>,
+}
+
+impl Namespaces {
+    /// The namespace `mailbox` belongs to: the one with the longest prefix
+    /// the name starts with.
+    pub fn namespace_of(&self, mailbox: &str) -> Option<&NamespaceInfo> {
+        self.personal
+            .iter()
+            .chain(&self.other_users)
+            .chain(&self.shared)
+            .filter(|ns| mailbox.starts_with(&ns.prefix[..]))
+            .max_by_key(|ns| ns.prefix.len())
+    }
+
+    fn from_responses(responses: &[ResponseData], utf8: bool) -> Self {
+        let convert = |list: &Option<Vec<Namespace>>| match *list {
+            Some(ref list) => list.iter()
+                .map(|ns| NamespaceInfo {
+                    prefix: mailbox_name(ns.prefix, utf8),
+                    delimiter: ns.delimiter.map(|d| d.to_string()),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        for rsp in responses {
+            if let Response::Namespace {
+                ref personal,
+                ref other_users,
+                ref shared,
+            } = *rsp.parsed()
+            {
+                return Namespaces {
+                    personal: convert(personal),
+                    other_users: convert(other_users),
+                    shared: convert(shared),
+                };
+            }
+        }
+        Namespaces::default()
+    }
+}
+
+/// A mailbox in another user's namespace or a shared namespace, as found by
+/// `shared_mailboxes()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SharedMailbox {
+    pub info: MailboxInfo,
+    pub namespace: NamespaceInfo,
+    /// Whether the mailbox is in a shared namespace rather than in another
+    /// user's.
+    pub shared: bool,
+    /// The rights the user has (RFC 4314, section 2.1), if the server
+    /// supports ACL and the mailbox is selectable.
+    pub rights: Option<String>,
+}
+
+impl SharedMailbox {
+    /// The name relative to the namespace prefix.
+    pub fn relative_name(&self) -> &str {
+        let name = &self.info.name;
+        if name.starts_with(&self.namespace.prefix[..]) {
+            &name[self.namespace.prefix.len()..]
+        } else {
+            name
+        }
+    }
+
+    /// For mailboxes of other users, the user they belong to, which is the
+    /// first level of the hierarchy below the namespace prefix.
+    pub fn owner(&self) -> Option<&str> {
+        if self.shared {
+            return None;
+        }
+        let name = self.relative_name();
+        match self.namespace.delimiter {
+            Some(ref delimiter) => name.split(&delimiter[..]).next(),
+            None => Some(name),
+        }
+    }
+}
+
+/// Annotations returned by `Session::get_metadata()`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metadata {
+    /// The values by entry name, with `None` for entries without a value.
+    pub entries: HashMap<String, Option<Vec<u8>>>,
+    /// If values larger than `max_size` were left out, the size of the
+    /// largest of them.
+    pub long_entries: Option<u32>,
+}
+
+impl Metadata {
+    fn from_responses(responses: &[ResponseData]) -> Self {
+        let mut metadata = Metadata::default();
+        for rsp in responses {
+            match *rsp.parsed() {
+                Response::Metadata { ref values, .. } => {
+                    for &(entry, value) in values {
+                        let value = value.map(|value| value.to_vec());
+                        metadata.entries.insert(entry.to_string(), value);
+                    }
+                },
+                Response::Done {
+                    code: Some(ResponseCode::Metadata(MetadataCode::LongEntries(size))),
+                    ..
+                } => metadata.long_entries = Some(size),
+                _ => {},
+            }
+        }
+        metadata
+    }
+}
+
+/// Mailbox counters returned by `STATUS`, with `None` for items that were
+/// not requested or not returned.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct MailboxStatus {
+    pub messages: Option<u32>,
+    pub recent: Option<u32>,
+    pub uid_next: Option<u32>,
+    pub uid_validity: Option<u32>,
+    pub unseen: Option<u32>,
+    pub highest_mod_seq: Option<u64>,
+}
+
+impl<'a> Extend<&'a StatusAttribute> for MailboxStatus {
+    fn extend<I: IntoIterator<Item = &'a StatusAttribute>>(&mut self, attrs: I) {
+        for attr in attrs {
+            match *attr {
+                StatusAttribute::HighestModSeq(num) => self.highest_mod_seq = Some(num),
+                StatusAttribute::Messages(num) => self.messages = Some(num),
+                StatusAttribute::Recent(num) => self.recent = Some(num),
+                StatusAttribute::UidNext(num) => self.uid_next = Some(num),
+                StatusAttribute::UidValidity(num) => self.uid_validity = Some(num),
+                StatusAttribute::Unseen(num) => self.unseen = Some(num),
+            }
+        }
+    }
+}
+
+/// Mailbox information collected from the responses to `SELECT` or
+/// `EXAMINE`.
+#[derive(Debug, Default)]
+pub struct Mailbox {
+    pub exists: u32,
+    pub recent: u32,
+    pub flags: Vec<String>,
+    /// The flags that can be changed permanently, possibly including `\*`
+    /// if new keywords can be created. Empty if the server did not say.
+    pub permanent_flags: Vec<String>,
+    pub uid_validity: Option<u32>,
+    pub uid_next: Option<u32>,
+    pub unseen: Option<u32>,
+    /// `None` if the server does not support CONDSTORE or the mailbox does
+    /// not support persistent mod-sequences.
+    pub highest_mod_seq: Option<u64>,
+    /// Whether the mailbox was opened read-only, as reported in the tagged
+    /// response.
+    pub read_only: bool,
+}
+
+impl Mailbox {
+    /// Collects the mailbox information from the responses to a `SELECT` or
+    /// `EXAMINE` command, as returned by its `ResponseStream`.
+    pub fn from_responses(responses: &[ResponseData]) -> Self {
+        let mut mailbox = Mailbox::default();
+        for rsp in responses {
+            let code = match *rsp.parsed() {
+                Response::MailboxData(MailboxDatum::Exists(num)) => {
+                    mailbox.exists = num;
+                    continue;
+                },
+                Response::MailboxData(MailboxDatum::Recent(num)) => {
+                    mailbox.recent = num;
+                    continue;
+                },
+                Response::MailboxData(MailboxDatum::Flags(ref flags)) => {
+                    mailbox.flags = flags.iter().map(|f| f.to_string()).collect();
+                    continue;
+                },
+                Response::Data {
+                    code: Some(ref code),
+                    ..
+                }
+                | Response::Done {
+                    code: Some(ref code),
+                    ..
+                } => code,
+                _ => continue,
+            };
+            match *code {
+                ResponseCode::PermanentFlags(ref flags) => {
+                    mailbox.permanent_flags = flags.iter().map(|f| f.to_string()).collect();
+                },
+                ResponseCode::UidValidity(num) => mailbox.uid_validity = Some(num),
+                ResponseCode::UidNext(num) => mailbox.uid_next = Some(num),
+                ResponseCode::Unseen(num) => mailbox.unseen = Some(num),
+                ResponseCode::HighestModSeq(num) => mailbox.highest_mod_seq = Some(num),
+                ResponseCode::ReadOnly => mailbox.read_only = true,
+                ResponseCode::ReadWrite => mailbox.read_only = false,
+                _ => {},
+            }
+        }
+        mailbox
+    }
+}
+
+/// The `APPENDUID` response code sent by servers supporting UIDPLUS.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AppendResult {
+    pub uid_validity: u32,
+    pub uid: u32,
+}
+
+impl AppendResult {
+    fn from_responses(responses: &[ResponseData]) -> Option<Self> {
+        match responses.last().map(|rsp| rsp.parsed()) {
+            Some(&Response::Done {
+                code: Some(ResponseCode::AppendUid(uid_validity, ref uids)),
+                ..
+            }) => uids.first().map(|range| AppendResult {
+                uid_validity,
+                uid: *range.start(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The `APPENDUID` response code for a `MULTIAPPEND`, sent by servers
+/// supporting UIDPLUS.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MultiAppendResult {
+    pub uid_validity: u32,
+    /// The UIDs of the messages, in the order they were appended.
+    pub uids: Vec<u32>,
+}
+
+impl MultiAppendResult {
+    fn from_responses(responses: &[ResponseData]) -> Option<Self> {
+        match responses.last().map(|rsp| rsp.parsed()) {
+            Some(&Response::Done {
+                code: Some(ResponseCode::AppendUid(uid_validity, ref uids)),
+                ..
+            }) => Some(MultiAppendResult {
+                uid_validity,
+                uids: uids.iter().flat_map(|range| range.clone()).collect(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The `COPYUID` response code sent by servers supporting UIDPLUS.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CopyResult {
+    /// The `UIDVALIDITY` of the destination mailbox.
+    pub uid_validity: u32,
+    pub source: Vec<RangeInclusive<u32>>,
+    pub destination: Vec<RangeInclusive<u32>>,
+}
+
+impl CopyResult {
+    // `COPY` returns the code in its tagged response, `MOVE` in an untagged
+    // `OK` response.
+    fn from_responses(responses: &[ResponseData]) -> Option<Self> {
+        responses
+            .iter()
+            .filter_map(|rsp| match *rsp.parsed() {
+                Response::Data {
+                    code: Some(ResponseCode::CopyUid(uid_validity, ref source, ref destination)),
+                    ..
+                }
+                | Response::Done {
+                    code: Some(ResponseCode::CopyUid(uid_validity, ref source, ref destination)),
+                    ..
+                } => Some(CopyResult {
+                    uid_validity,
+                    source: source.clone(),
+                    destination: destination.clone(),
+                }),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Pairs of source and destination UIDs, in the order given by the
+    /// server.
+    pub fn pairs(&self) -> Vec<(u32, u32)> {
+        let source = self.source.iter().flat_map(|range| range.clone());
+        let destination = self.destination.iter().flat_map(|range| range.clone());
+        source.zip(destination).collect()
+    }
+}
+
+/// Results of `Session::move_messages()` and `Session::uid_move()`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MoveResult {
+    /// The UIDs of the moved messages if the server supports UIDPLUS.
+    pub copied: Option<CopyResult>,
+    /// Sequence numbers from the `EXPUNGE` responses for the moved messages.
+    pub expunged: Vec<u32>,
+    /// UIDs from `VANISHED` responses, sent instead of `EXPUNGE` responses
+    /// once QRESYNC is enabled.
+    pub vanished: Vec<RangeInclusive<u32>>,
+}
+
+impl MoveResult {
+    fn from_responses(responses: &[ResponseData]) -> Self {
+        let mut expunged = Vec::new();
+        for rsp in responses {
+            if let Response::Expunge(num) = *rsp.parsed() {
+                expunged.push(num);
+            }
+        }
+        MoveResult {
+            copied: CopyResult::from_responses(responses),
+            expunged,
+            vanished: vanished_uids(responses),
+        }
+    }
+}
+
+/// Results of `Session::select_qresync()`.
+#[derive(Debug, Default)]
+pub struct Resync {
+    pub mailbox: Mailbox,
+    /// UIDs of messages expunged since the last synchronization.
+    pub vanished: Vec<RangeInclusive<u32>>,
+    /// Messages whose flags changed since the last synchronization.
+    pub changed: Vec<Fetch>,
+}
+
+/// The header fields of a message, as returned by `Session::fetch_headers()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MessageHeaders {
+    /// The message sequence number.
+    pub message: u32,
+    pub uid: Option<u32>,
+    pub headers: Headers,
+}
+
+impl MessageHeaders {
+    fn from_fetch(fetch: &Fetch) -> Self {
+        MessageHeaders {
+            message: fetch.message(),
+            uid: fetch.uid(),
+            headers: fetch.header().map(Headers::parse).unwrap_or_default(),
+        }
+    }
+}
+
+/// Results of `Session::uid_fetch_ordered()`.
+#[derive(Debug, Default)]
+pub struct OrderedFetch {
+    /// One entry per requested UID, in request order, with `None` for UIDs
+    /// the server did not return data for.
+    pub results: Vec<(u32, Option<Fetch>)>,
+    /// `FETCH` responses for messages that were not requested, such as flag
+    /// updates caused by other clients.
+    pub unsolicited: Vec<Fetch>,
+}
+
+impl OrderedFetch {
+    fn from_responses(requested: &[u32], responses: Vec<ResponseData>, utf8: bool) -> Self {
+        let wanted: HashSet<u32> = requested.iter().cloned().collect();
+        let mut found = HashMap::new();
+        let mut unsolicited = Vec::new();
+        for fetch in Fetch::from_responses(responses, utf8) {
+            match fetch.uid() {
+                Some(uid) if wanted.contains(&uid) && !found.contains_key(&uid) => {
+                    found.insert(uid, fetch);
+                },
+                _ => unsolicited.push(fetch),
+            }
+        }
+        let results = requested
+            .iter()
+            .map(|uid| (*uid, found.remove(uid)))
+            .collect();
+        OrderedFetch {
+            results,
+            unsolicited,
+        }
+    }
+
+    /// The requested UIDs for which no data was returned.
+    pub fn missing(&self) -> Vec<u32> {
+        self.results
+            .iter()
+            .filter(|&&(_, ref fetch)| fetch.is_none())
+            .map(|&(uid, _)| uid)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.results.iter().all(|&(_, ref fetch)| fetch.is_some())
+    }
+}
+
+/// Results of `Session::flag_stats()`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FlagStats {
+    /// The number of messages in the mailbox.
+    pub exists: u32,
+    /// The number of messages without `\Seen`.
+    pub unseen: u32,
+    /// The number of messages with each flag or keyword defined in the
+    /// mailbox, such as `\Flagged` or `$Todo`.
+    pub flags: HashMap<String, u32>,
+}
+
+impl FlagStats {
+    /// The number of messages with `flag`, which is compared without regard
+    /// to case.
+    pub fn count(&self, flag: &str) -> u32 {
+        self.flags
+            .iter()
+            .filter(|&(name, _)| name.eq_ignore_ascii_case(flag))
+            .map(|(_, &count)| count)
+            .next()
+            .unwrap_or(0)
+    }
+}
+
+/// Progress of a `Session::purge()`, reported after each chunk.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ExpungeProgress {
+    /// Messages the server reported as expunged so far.
+    pub expunged: usize,
+    /// The highest UID in the chunks processed so far.
+    pub last_uid: u32,
+    /// UIDs still to be processed.
+    pub remaining: usize,
+}
+
+/// Stream of `ExpungeProgress` returned by `Session::purge()`, which ends
+/// with the `Session`.
+pub struct Purge<T> {
+    error: Option<Error>,
+    uids: Vec<u32>,
+    next: usize,
+    chunk_size: usize,
+    session: Option<Session<Client<T>>>,
+    future: Option<SessionFuture<Client<T>, usize>>,
+    progress: ExpungeProgress,
+}
+
+impl<T> StateStream for Purge<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    type Item = ExpungeProgress;
+    type State = Session<Client<T>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<StreamEvent<Self::Item, Self::State>, Self::Error> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.future.is_none() {
+            let session = self.session.take().unwrap(); // safe: restored after each chunk
+            if self.next == self.uids.len() {
+                return Ok(Async::Ready(StreamEvent::Done(session)));
+            }
+            let end = cmp::min(self.next + self.chunk_size, self.uids.len());
+            self.future = Some(session.purge_chunk(&self.uids[self.next..end]));
+            self.next = end;
+        }
+        let (expunged, session) = try_ready!(self.future.as_mut().unwrap().poll());
+        self.future = None;
+        self.session = Some(session);
+        self.progress.expunged += expunged;
+        self.progress.last_uid = self.uids[self.next - 1];
+        self.progress.remaining = self.uids.len() - self.next;
+        Ok(Async::Ready(StreamEvent::Next(self.progress)))
+    }
+}
+
+/// A single untagged `FETCH` response.
+#[derive(Debug)]
+pub struct Fetch {
+    response: ResponseData,
+    // Whether UTF8=ACCEPT was enabled, for decoding Gmail labels.
+    utf8: bool,
+}
+
+impl Fetch {
+    fn from_responses(responses: Vec<ResponseData>, utf8: bool) -> Vec<Fetch> {
+        responses
+            .into_iter()
+            .filter(|rsp| match *rsp.parsed() {
+                Response::Fetch(..) => true,
+                _ => false,
+            })
+            .map(|response| Fetch { response, utf8 })
+            .collect()
+    }
+
+    // `response` must be a `FETCH` response.
+    pub(crate) fn from_response(response: ResponseData, utf8: bool) -> Fetch {
+        Fetch { response, utf8 }
+    }
+
+    pub fn message(&self) -> u32 {
+        match *self.response.parsed() {
+            Response::Fetch(num, _) => num,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn attributes(&self) -> &[AttributeValue] {
+        match *self.response.parsed() {
+            Response::Fetch(_, ref attrs) => attrs,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn uid(&self) -> Option<u32> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::Uid(uid) => Some(uid),
+                _ => None,
+            })
+            .next()
+    }
+
+    pub fn flags(&self) -> Option<&[&str]> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::Flags(ref flags) => Some(&flags[..]),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// The Gmail message ID, which stays the same across mailboxes.
+    pub fn gmail_msg_id(&self) -> Option<u64> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::GmailMsgId(id) => Some(id),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// The Gmail ID of the conversation the message belongs to.
+    pub fn gmail_thread_id(&self) -> Option<u64> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::GmailThrId(id) => Some(id),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// The Gmail labels of the message, with label names decoded from
+    /// modified UTF-7 unless UTF8=ACCEPT is enabled. System labels keep
+    /// their backslash, like `\Inbox`.
+    pub fn gmail_labels(&self) -> Option<Vec<String>> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::GmailLabels(ref labels) => {
+                    Some(labels.iter().map(|label| mailbox_name(label, self.utf8)).collect())
+                },
+                _ => None,
+            })
+            .next()
+    }
+
+    pub fn mod_seq(&self) -> Option<u64> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::ModSeq(mod_seq) => Some(mod_seq),
+                _ => None,
+            })
+            .next()
+    }
+
+    pub fn internal_date(&self) -> Option<&str> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::InternalDate(date) => Some(date),
+                _ => None,
+            })
+            .next()
+    }
+
+    pub fn envelope(&self) -> Option<&Envelope> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::Envelope(ref envelope) => Some(&**envelope),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// The MIME structure of the message, as returned for `BODYSTRUCTURE`
+    /// or `BODY`.
+    pub fn body_structure(&self) -> Option<&BodyStructure> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::BodyStructure(ref body) => Some(body),
+                _ => None,
+            })
+            .next()
+    }
+
+    /// The full message, as returned for `RFC822` or `BODY[]`.
+    pub fn body(&self) -> Option<&[u8]> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::Rfc822(data) => data,
+                AttributeValue::BodySection {
+                    section: None,
+                    data,
+                    ..
+                } => data,
+                _ => None,
+            })
+            .next()
+    }
+
+    /// The header of the message, as returned for `RFC822.HEADER` or
+    /// `BODY[HEADER]`, or some of its fields, as returned for
+    /// `BODY[HEADER.FIELDS (...)]`.
+    pub fn header(&self) -> Option<&[u8]> {
+        self.attributes()
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::Rfc822Header(data) => data,
+                AttributeValue::BodySection {
+                    section: Some(SectionPath::Full(MessageSection::Header)),
+                    data,
+                    ..
+                }
+                | AttributeValue::BodySection {
+                    section: Some(SectionPath::Full(MessageSection::HeaderFields { .. })),
+                    data,
+                    ..
+                }
+                | AttributeValue::BodySection {
+                    section: Some(SectionPath::Full(MessageSection::HeaderFieldsNot { .. })),
+                    data,
+                    ..
+                } => data,
+                _ => None,
+            })
+            .next()
+    }
+
+    /// Like `body()`, but sharing the buffer the response was read into
+    /// rather than borrowing from the `Fetch`.
+    pub fn body_bytes(&self) -> Option<Bytes> {
+        self.body()
+            .and_then(|body| self.response.slice_bytes(body))
+    }
+
+    /// Like `header()`, but sharing the buffer the response was read into.
+    pub fn header_bytes(&self) -> Option<Bytes> {
+        self.header()
+            .and_then(|header| self.response.slice_bytes(header))
+    }
+
+    pub fn response(&self) -> &ResponseData {
+        &self.response
+    }
+}
+
+/// A piece of a body section yielded by `Session::fetch_parts()`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartChunk {
+    /// The message sequence number.
+    pub message: u32,
+    pub uid: Option<u32>,
+    /// The section spec as it appears between the brackets of `BODY[]`,
+    /// such as `1.2` or `HEADER`, followed by `<origin>` for partial
+    /// fetches.
+    pub section: String,
+    /// The position of `data` within the section.
+    pub offset: usize,
+    pub data: Vec<u8>,
+    /// Whether this is the last piece of the section.
+    pub last: bool,
+}
+
+/// Stream of `PartChunk`s returned by `Session::fetch_parts()`, which ends
+/// with the `Session`.
+///
+/// Sections that are not streamed by the codec are yielded as a single,
+/// last chunk once their `FETCH` response is complete. Pieces of different
+/// sections may be interleaved.
+pub struct FetchParts<C>
+where
+    C: ImapClient,
+{
+    responses: ResponseStream<C>,
+    // Sections yielded in pieces, whose data is left out of the complete
+    // `FETCH` response.
+    streamed: Vec<(u32, String)>,
+    pending: VecDeque<PartChunk>,
+}
+
+impl<C> FetchParts<C>
+where
+    C: ImapClient,
+{
+    fn split(&mut self, rsp: &ResponseData) {
+        let (message, attrs) = match *rsp.parsed() {
+            Response::Fetch(message, ref attrs) => (message, attrs),
+            _ => return,
+        };
+        let uid = attrs
+            .iter()
+            .filter_map(|attr| match *attr {
+                AttributeValue::Uid(uid) => Some(uid),
+                _ => None,
+            })
+            .next();
+        let sections = attrs.iter().filter_map(|attr| match *attr {
+            AttributeValue::BodySection {
+                ref section,
+                index,
+                data,
+            } => Some((section_spec(section.as_ref(), index), data.unwrap_or(&[]))),
+            _ => None,
+        });
+        if let Some(chunk) = rsp.chunk() {
+            // The streamed literal is the last attribute of a chunk.
+            if let Some((section, data)) = sections.last() {
+                let last = chunk.is_last(data.len());
+                if last {
+                    self.streamed.push((message, section.clone()));
+                }
+                self.pending.push_back(PartChunk {
+                    message,
+                    uid,
+                    section,
+                    offset: chunk.offset,
+                    data: data.to_vec(),
+                    last,
+                });
+            }
+            return;
+        }
+        for (section, data) in sections {
+            let key = (message, section);
+            if let Some(pos) = self.streamed.iter().position(|streamed| *streamed == key) {
+                self.streamed.remove(pos);
+                continue;
+            }
+            self.pending.push_back(PartChunk {
+                message,
+                uid,
+                section: key.1,
+                offset: 0,
+                data: data.to_vec(),
+                last: true,
+            });
+        }
+    }
+}
+
+impl<C> StateStream for FetchParts<C>
+where
+    C: ImapClient,
+{
+    type Item = PartChunk;
+    type State = Session<C>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<StreamEvent<Self::Item, Self::State>, Self::Error> {
+        loop {
+            if let Some(part) = self.pending.pop_front() {
+                return Ok(Async::Ready(StreamEvent::Next(part)));
+            }
+            match try_ready!(self.responses.poll()) {
+                StreamEvent::Next(rsp) => match *rsp.parsed() {
+                    Response::Done { .. } => Error::from_responses(&[rsp])?,
+                    _ => self.split(&rsp),
+                },
+                StreamEvent::Done(client) => {
+                    return Ok(Async::Ready(StreamEvent::Done(Session { client })));
+                },
+            }
+        }
+    }
+}
+
+// Formats a section the way it is written in a `FETCH` command (RFC 3501,
+// section 6.4.5).
+fn section_spec(section: Option<&SectionPath>, index: Option<u32>) -> String {
+    let message_section = |section: &MessageSection| match *section {
+        MessageSection::Header => "HEADER".to_string(),
+        MessageSection::HeaderFields { ref fields } => {
+            format!("HEADER.FIELDS ({})", fields.join(" "))
+        },
+        MessageSection::HeaderFieldsNot { ref fields } => {
+            format!("HEADER.FIELDS.NOT ({})", fields.join(" "))
+        },
+        MessageSection::Mime => "MIME".to_string(),
+        MessageSection::Text => "TEXT".to_string(),
+    };
+    let mut spec = match section {
+        None => String::new(),
+        Some(&SectionPath::Full(ref section)) => message_section(section),
+        Some(&SectionPath::Part(ref part, ref section)) => {
+            let mut spec = part.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(".");
+            if let Some(ref section) = *section {
+                spec.push('.');
+                spec.push_str(&message_section(section));
+            }
+            spec
+        },
+    };
+    if let Some(index) = index {
+        spec.push_str(&format!("<{}>", index));
+    }
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn responses(lines: &[&str]) -> Vec<ResponseData> {
+        lines
+            .iter()
+            .map(|line| ResponseData::parse(format!("{}\r\n", line).into_bytes(), None).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn expunged() {
+        assert_eq!(count_expunged(responses(&["* 3 EXPUNGE", "* 3 EXPUNGE"])), 2);
+        assert_eq!(count_expunged(responses(&["* VANISHED 1:3,7", "* 2 EXISTS"])), 4);
+        let overlapping = responses(&["* VANISHED 1:5", "* VANISHED 4:6", "* 1 EXPUNGE"]);
+        assert_eq!(count_expunged(overlapping), 7);
+        assert_eq!(count_expunged(Vec::new()), 0);
+    }
+
+    #[test]
+    fn mailbox_names() {
+        assert_eq!(mailbox_name("Entw&APw-rfe", false), "Entwürfe");
+        assert_eq!(mailbox_name("INBOX", false), "INBOX");
+        // Not valid modified UTF-7, kept as it is.
+        assert_eq!(mailbox_name("R&D", false), "R&D");
+        // With UTF8=ACCEPT, `&` has no special meaning.
+        assert_eq!(mailbox_name("Entw&APw-rfe", true), "Entw&APw-rfe");
+        assert_eq!(mailbox_name("Entwürfe", true), "Entwürfe");
+    }
+
+    #[test]
+    fn ordered_fetch() {
+        let fetched = responses(&[
+            "* 4 FETCH (UID 12 FLAGS (\\Seen))",
+            "* 1 FETCH (FLAGS (\\Deleted))",
+            "* 2 FETCH (UID 7 FLAGS ())",
+            "* 2 FETCH (UID 7 FLAGS (\\Seen))",
+            "* 9 FETCH (UID 30 FLAGS ())",
+            "a1 OK done",
+        ]);
+        let ordered = OrderedFetch::from_responses(&[7, 5, 12], fetched, false);
+        let results: Vec<_> = ordered
+            .results
+            .iter()
+            .map(|&(uid, ref fetch)| (uid, fetch.as_ref().map(|fetch| fetch.message())))
+            .collect();
+        assert_eq!(results, vec![(7, Some(2)), (5, None), (12, Some(4))]);
+        assert_eq!(ordered.missing(), vec![5]);
+        // The flag update without a UID, the second response for UID 7 and
+        // the one for UID 30 were not asked for.
+        let unsolicited: Vec<_> = ordered.unsolicited.iter().map(Fetch::message).collect();
+        assert_eq!(unsolicited, vec![1, 2, 9]);
+        assert_eq!(ordered.unsolicited[1].flags(), Some(&["\\Seen"][..]));
+    }
+
+    #[test]
+    fn selected_mailbox() {
+        let mailbox = Mailbox::from_responses(&responses(&[
+            "* 172 EXISTS",
+            "* 1 RECENT",
+            "* OK [UNSEEN 12] Message 12 is first unseen",
+            "* OK [UIDVALIDITY 3857529045] UIDs valid",
+            "* OK [UIDNEXT 4392] Predicted next UID",
+            "* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)",
+            "* OK [PERMANENTFLAGS (\\Deleted \\Seen \\*)] Limited",
+            "* OK [HIGHESTMODSEQ 715194045007] Highest",
+            "A142 OK [READ-WRITE] SELECT completed",
+        ]));
+        assert_eq!(mailbox.exists, 172);
+        assert_eq!(mailbox.recent, 1);
+        assert_eq!(mailbox.unseen, Some(12));
+        assert_eq!(mailbox.uid_validity, Some(3_857_529_045));
+        assert_eq!(mailbox.uid_next, Some(4392));
+        assert_eq!(mailbox.flags.len(), 5);
+        assert_eq!(mailbox.permanent_flags, vec!["\\Deleted", "\\Seen", "\\*"]);
+        assert_eq!(mailbox.highest_mod_seq, Some(715_194_045_007));
+        assert!(!mailbox.read_only);
+    }
+
+    #[test]
+    fn examined_mailbox() {
+        let mailbox = Mailbox::from_responses(&responses(&[
+            "* 17 EXISTS",
+            "* OK [PERMANENTFLAGS ()] No permanent flags permitted",
+            "A932 OK [READ-ONLY] EXAMINE completed",
+        ]));
+        assert_eq!(mailbox.exists, 17);
+        assert!(mailbox.permanent_flags.is_empty());
+        assert_eq!(mailbox.uid_validity, None);
+        assert_eq!(mailbox.highest_mod_seq, None);
+        assert!(mailbox.read_only);
+    }
+
+    #[test]
+    fn listed_mailboxes() {
+        let mailboxes = MailboxInfo::from_responses(
+            &responses(&[
+                "* LIST (\\HasNoChildren \\Subscribed) \"/\" INBOX",
+                "* STATUS INBOX (MESSAGES 17 UNSEEN 16)",
+                "* LIST (\\HasChildren \\Sent) \"/\" \"Sent Items\"",
+                "* LIST (\\Noselect) \"/\" Archive",
+                "* LIST (\\Drafts \\HasNoChildren) \"/\" \"Entw&APw-rfe\"",
+                "* STATUS \"Entw&APw-rfe\" (MESSAGES 3)",
+                "* LIST (\\Flagged \\X-Custom) NIL Starred",
+            ]),
+            false,
+        );
+        let names: Vec<_> = mailboxes.iter().map(|info| &info.name[..]).collect();
+        assert_eq!(names, vec!["INBOX", "Sent Items", "Archive", "Entwürfe", "Starred"]);
+
+        let inbox = &mailboxes[0];
+        assert_eq!(inbox.delimiter, Some("/".to_string()));
+        assert_eq!(inbox.status.messages, Some(17));
+        assert_eq!(inbox.status.unseen, Some(16));
+        assert_eq!(inbox.has_children(), Some(false));
+        assert!(inbox.is_subscribed());
+        assert_eq!(inbox.special_use(), None);
+
+        let sent = &mailboxes[1];
+        assert_eq!(sent.has_children(), Some(true));
+        assert!(!sent.is_subscribed());
+        assert_eq!(sent.special_use(), Some(&NameAttribute::Sent));
+        assert_eq!(sent.status, MailboxStatus::default());
+
+        let archive = &mailboxes[2];
+        assert!(!archive.is_selectable());
+        assert_eq!(archive.has_children(), None);
+
+        let drafts = &mailboxes[3];
+        assert_eq!(drafts.special_use(), Some(&NameAttribute::Drafts));
+        assert_eq!(drafts.status.messages, Some(3));
+
+        let starred = &mailboxes[4];
+        assert_eq!(starred.delimiter, None);
+        assert_eq!(starred.special_use(), Some(&NameAttribute::Flagged));
+        assert!(starred.attributes.contains(&NameAttribute::Extension("\\X-Custom".to_string())));
+    }
+
+    #[test]
+    fn listed_utf8_mailboxes() {
+        let mailboxes = MailboxInfo::from_responses(
+            &responses(&[
+                "* LIST () \"/\" \"Entwürfe\"",
+                "* STATUS \"Entwürfe\" (UNSEEN 2)",
+                "* LIST () \"/\" \"R&D-Team\"",
+            ]),
+            true,
+        );
+        assert_eq!(mailboxes[0].name, "Entwürfe");
+        assert_eq!(mailboxes[0].status.unseen, Some(2));
+        assert_eq!(mailboxes[1].name, "R&D-Team");
+    }
+
+    #[test]
+    fn backup_search() {
+        let since = SearchDate::new(2018, 2, 1);
+        let args = |criteria: SearchCriteria| criteria.to_args(false).unwrap();
+        assert_eq!(args(backup_criteria(BackupDate::Received, since, 0)), "SINCE 1-Feb-2018");
+        assert_eq!(
+            args(backup_criteria(BackupDate::Sent, since, 41)),
+            "SENTSINCE 1-Feb-2018 UID 42:*"
+        );
+    }
+}
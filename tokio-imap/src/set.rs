@@ -0,0 +1,168 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::RangeInclusive;
+
+use imap_proto::SequenceSet;
+use imap_proto::sequence::{IntoIter, Iter};
+
+/// A set of UIDs, such as the results of a `UID SEARCH`.
+///
+/// This is a `SequenceSet` that only holds actual UIDs: it never stands for
+/// `$`, and no range goes up to `*`. Results of several searches can be
+/// combined with `union()`, `intersection()` and `difference()`. The
+/// `Display` implementation formats the set in IMAP sequence set syntax,
+/// with consecutive UIDs collapsed into ranges, for use in further commands.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct UidSet {
+    set: SequenceSet,
+}
+
+impl UidSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    pub fn contains(&self, uid: u32) -> bool {
+        self.set.contains(uid)
+    }
+
+    /// Adds `uid`, returning whether it was not in the set yet.
+    pub fn insert(&mut self, uid: u32) -> bool {
+        if self.set.contains(uid) {
+            return false;
+        }
+        self.set.insert(uid);
+        true
+    }
+
+    /// The UIDs in ascending order.
+    pub fn iter(&self) -> Iter {
+        self.set.iter()
+    }
+
+    /// The UIDs in either set.
+    pub fn union(&self, other: &UidSet) -> UidSet {
+        UidSet {
+            set: self.set.union(&other.set),
+        }
+    }
+
+    /// The UIDs in both sets.
+    pub fn intersection(&self, other: &UidSet) -> UidSet {
+        UidSet {
+            set: self.set.intersection(&other.set),
+        }
+    }
+
+    /// The UIDs in this set but not in `other`.
+    pub fn difference(&self, other: &UidSet) -> UidSet {
+        UidSet {
+            set: self.set.difference(&other.set),
+        }
+    }
+
+    /// The set as a `SequenceSet`, for use in commands.
+    ///
+    /// The result is empty for an empty set, which is not valid in commands.
+    pub fn to_sequence_set(&self) -> SequenceSet {
+        self.set.clone()
+    }
+
+    pub fn as_sequence_set(&self) -> &SequenceSet {
+        &self.set
+    }
+}
+
+impl fmt::Display for UidSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.set, f)
+    }
+}
+
+impl From<Vec<u32>> for UidSet {
+    fn from(uids: Vec<u32>) -> Self {
+        uids.into_iter().collect()
+    }
+}
+
+impl FromIterator<u32> for UidSet {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        UidSet {
+            set: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Builds a set from the ranges in a server response, such as those of a
+/// `VANISHED` response.
+impl FromIterator<RangeInclusive<u32>> for UidSet {
+    fn from_iter<I: IntoIterator<Item = RangeInclusive<u32>>>(iter: I) -> Self {
+        UidSet {
+            set: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<u32> for UidSet {
+    fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+        self.set.extend(iter);
+    }
+}
+
+impl IntoIterator for UidSet {
+    type Item = u32;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        self.set.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a UidSet {
+    type Item = u32;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.set.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_operations() {
+        let a = UidSet::from(vec![9, 3, 1, 2, 3]);
+        let b: UidSet = vec![2, 4, 9, 10].into_iter().collect();
+        assert_eq!(a.to_string(), "1:3,9");
+        assert_eq!(a.len(), 4);
+        assert_eq!(a.union(&b).to_string(), "1:4,9:10");
+        assert_eq!(a.intersection(&b).iter().collect::<Vec<_>>(), vec![2, 9]);
+        assert_eq!(a.difference(&b).into_iter().collect::<Vec<_>>(), vec![1, 3]);
+        assert!(UidSet::new().union(&UidSet::new()).is_empty());
+        assert_eq!(UidSet::new().to_string(), "");
+    }
+
+    #[test]
+    fn insert() {
+        let mut uids = UidSet::new();
+        assert!(uids.insert(5));
+        assert!(uids.insert(4));
+        assert!(!uids.insert(5));
+        uids.extend(vec![6, 10]);
+        assert!(uids.contains(6));
+        assert!(!uids.contains(7));
+        assert_eq!((&uids).into_iter().collect::<Vec<_>>(), vec![4, 5, 6, 10]);
+        let vanished: UidSet = vec![1..=3, 2..=5].into_iter().collect();
+        assert_eq!(vanished.to_sequence_set().to_string(), "1:5");
+    }
+}
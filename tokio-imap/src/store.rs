@@ -0,0 +1,131 @@
+//! Flag changes to many messages, coalesced into few `UID STORE` commands.
+//!
+//! Migrations and bulk operations often change the same flags on many
+//! messages. `FlagChanges` collects such changes per message, and groups the
+//! messages that get the same change so that each group is sent as a single
+//! command with a compact sequence set:
+//!
+//! ```ignore
+//! let mut changes = FlagChanges::new();
+//! for uid in 1..=1000 {
+//!     changes.add(uid, &["\\Seen"]);
+//! }
+//! changes.add(1001, &["\\Seen"]).add(1001, &["\\Flagged"]);
+//! // Sends `UID STORE 1:1001 +FLAGS (\Seen)` and
+//! // `UID STORE 1001 +FLAGS (\Flagged)`.
+//! session.store_changes(&changes)
+//! ```
+
+use imap_proto::SequenceSet;
+use imap_proto::builders::command::{Command, CommandBuilder};
+use imap_proto::types::StoreType;
+
+use set::UidSet;
+
+// The longest sequence set put in a single command. Servers should accept
+// command lines of at least 8000 octets (RFC 7162, section 4).
+const MAX_SET_LEN: usize = 4000;
+
+struct Group {
+    kind: StoreType,
+    flags: Vec<String>,
+    uids: UidSet,
+}
+
+/// Collects flag changes by UID; see the module documentation.
+///
+/// Changes to the same message are applied in the order they were made.
+#[derive(Default)]
+pub struct FlagChanges {
+    groups: Vec<Group>,
+}
+
+impl FlagChanges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `flags` to the flags of message `uid`.
+    pub fn add(&mut self, uid: u32, flags: &[&str]) -> &mut Self {
+        self.change(uid, StoreType::Add, flags)
+    }
+
+    /// Removes `flags` from the flags of message `uid`.
+    pub fn remove(&mut self, uid: u32, flags: &[&str]) -> &mut Self {
+        self.change(uid, StoreType::Remove, flags)
+    }
+
+    /// Replaces the flags of message `uid` with `flags`.
+    pub fn replace(&mut self, uid: u32, flags: &[&str]) -> &mut Self {
+        self.change(uid, StoreType::Replace, flags)
+    }
+
+    pub fn change(&mut self, uid: u32, kind: StoreType, flags: &[&str]) -> &mut Self {
+        let mut flags: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+        flags.sort();
+        flags.dedup();
+        if flags.is_empty() && kind != StoreType::Replace {
+            return self;
+        }
+        // The message can join the last group with the same change, unless
+        // a later group changes it too: it would then get the changes out of
+        // order.
+        let mut target = None;
+        for (i, group) in self.groups.iter().enumerate().rev() {
+            if group.kind == kind && group.flags == flags {
+                target = Some(i);
+                break;
+            }
+            if group.uids.contains(uid) {
+                break;
+            }
+        }
+        match target {
+            Some(i) => {
+                self.groups[i].uids.insert(uid);
+            },
+            None => {
+                let mut uids = UidSet::new();
+                uids.insert(uid);
+                self.groups.push(Group { kind, flags, uids });
+            },
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// The `UID STORE` commands that make the changes, in order.
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        for group in &self.groups {
+            let flags: Vec<_> = group.flags.iter().map(|flag| &flag[..]).collect();
+            for set in sequence_sets(&group.uids) {
+                commands.push(CommandBuilder::uid_store(&set, group.kind.clone(), &flags));
+            }
+        }
+        commands
+    }
+}
+
+// Splits `uids` into sequence sets of at most `MAX_SET_LEN` bytes each.
+fn sequence_sets(uids: &UidSet) -> Vec<SequenceSet> {
+    let mut sets = Vec::new();
+    let mut ranges = Vec::new();
+    let mut len = 0;
+    for range in uids.to_sequence_set().ranges() {
+        let range_len = SequenceSet::from(range.clone()).to_string().len();
+        if !ranges.is_empty() && len + range_len > MAX_SET_LEN {
+            sets.push(ranges.drain(..).collect());
+            len = 0;
+        }
+        len += range_len + 1;
+        ranges.push(range);
+    }
+    if !ranges.is_empty() {
+        sets.push(ranges.into_iter().collect());
+    }
+    sets
+}
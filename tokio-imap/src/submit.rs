@@ -0,0 +1,307 @@
+//! A minimal SMTP submission client (RFC 6409), for sending messages such as
+//! replies without a separate email stack.
+//!
+//! Only implicit TLS (port 465, RFC 8314) and `AUTH PLAIN` are supported.
+
+use base64;
+use bytes::{BufMut, BytesMut};
+
+use futures::{future, stream, Future, Sink, Stream};
+
+use std::io;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio_codec::{Decoder, Encoder, Framed};
+
+use error::Error;
+use timeout::TimeoutStream;
+use tls::{TlsBackend, TlsConnector, TlsStream};
+
+type Transport = Framed<TlsStream, SmtpCodec>;
+type Step = Box<Future<Item = Transport, Error = Error> + Send>;
+
+/// Sends messages through an SMTP submission server.
+///
+/// ```ignore
+/// let send = Submission::new("smtp.example.com")
+///     .credentials("me@example.com", "secret")
+///     .send("me@example.com", &["you@example.com"], &message)?;
+/// ```
+pub struct Submission {
+    server: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    connector: Option<TlsConnector>,
+    client_name: String,
+    read_timeout: Option<Duration>,
+}
+
+impl Submission {
+    pub fn new(server: &str) -> Self {
+        Self {
+            server: server.to_string(),
+            port: 465,
+            credentials: None,
+            connector: None,
+            client_name: "localhost".to_string(),
+            read_timeout: None,
+        }
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Authenticates with `AUTH PLAIN` before sending.
+    pub fn credentials(mut self, user_name: &str, password: &str) -> Self {
+        self.credentials = Some((user_name.to_string(), password.to_string()));
+        self
+    }
+
+    pub fn tls_connector(mut self, connector: TlsConnector) -> Self {
+        self.connector = Some(connector);
+        self
+    }
+
+    /// The host name to announce in `EHLO`.
+    pub fn client_name(mut self, name: &str) -> Self {
+        self.client_name = name.to_string();
+        self
+    }
+
+    /// Fails when the server does not send any data for `timeout`.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Connects and sends `message`, a complete RFC 5322 message with CRLF
+    /// line endings, from `from` to each of `to`.
+    ///
+    /// Fails without connecting if an address is empty or contains a line
+    /// break or an angle bracket.
+    pub fn send(
+        self, from: &str, to: &[&str], message: &[u8]
+    ) -> Result<Box<Future<Item = (), Error = Error> + Send>, Error> {
+        let from = format!("MAIL FROM:<{}>\r\n", check_address(from)?).into_bytes();
+        let recipients = to.iter()
+            .map(|to| Ok(format!("RCPT TO:<{}>\r\n", check_address(to)?).into_bytes()))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let addr = match (&self.server[..], self.port).to_socket_addrs()?.next() {
+            Some(addr) => addr,
+            None => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("no IP addresses found for {}", self.server),
+                )))
+            },
+        };
+        let connector = match self.connector {
+            Some(connector) => connector,
+            None => TlsConnector::new(TlsBackend::default())?,
+        };
+        let Submission {
+            server,
+            credentials,
+            client_name,
+            read_timeout,
+            ..
+        } = self;
+        let data = dot_stuff(message);
+
+        let connected = TcpStream::connect(&addr)
+            .map_err(Error::Io)
+            .and_then(move |stream| {
+                let stream = TimeoutStream::new(stream, read_timeout);
+                connector.handshake(&server, stream)
+            })
+            .and_then(|stream| reply(SmtpCodec.framed(stream), 2))
+            .and_then(move |transport| {
+                let ehlo = format!("EHLO {}\r\n", client_name).into_bytes();
+                command(transport, ehlo, 2)
+            });
+        let authenticated = connected.and_then(move |transport| -> Step {
+            match credentials {
+                Some((user_name, password)) => {
+                    let plain = format!("\0{}\0{}", user_name, password);
+                    let line = format!("AUTH PLAIN {}\r\n", base64::encode(&plain));
+                    command(transport, line.into_bytes(), 2)
+                },
+                None => Box::new(future::ok(transport)),
+            }
+        });
+        let sent = authenticated
+            .and_then(move |transport| command(transport, from, 2))
+            .and_then(move |transport| {
+                stream::iter_ok(recipients)
+                    .fold(transport, |transport, rcpt| command(transport, rcpt, 2))
+            })
+            .and_then(|transport| command(transport, b"DATA\r\n".to_vec(), 3))
+            .and_then(move |transport| command(transport, data, 2))
+            .and_then(|transport| {
+                // The message has been accepted; a failing QUIT does not
+                // change that.
+                command(transport, b"QUIT\r\n".to_vec(), 2).then(|_| Ok(()))
+            });
+        Ok(Box::new(sent))
+    }
+}
+
+// Rejects addresses that would break out of the angle brackets of a path,
+// such as a display name decoded from a header that contains a line break.
+fn check_address(address: &str) -> Result<&str, Error> {
+    if address.is_empty() || address.contains(|c| "\r\n<>".contains(c)) {
+        let e = io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid address {:?}", address),
+        );
+        return Err(Error::Io(e));
+    }
+    Ok(address)
+}
+
+// Escapes lines starting with a dot and appends the end-of-data marker.
+fn dot_stuff(message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(message.len() + 5);
+    let mut line_start = true;
+    for &b in message {
+        if line_start && b == b'.' {
+            data.push(b'.');
+        }
+        data.push(b);
+        line_start = b == b'\n';
+    }
+    if !line_start {
+        data.extend_from_slice(b"\r\n");
+    }
+    data.extend_from_slice(b".\r\n");
+    data
+}
+
+// Sends `line` and waits for a reply in the given class (2 for 2xx).
+fn command(transport: Transport, line: Vec<u8>, class: u16) -> Step {
+    Box::new(transport.send(line).and_then(move |transport| reply(transport, class)))
+}
+
+fn reply(transport: Transport, class: u16) -> Step {
+    Box::new(
+        transport
+            .into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(reply, transport)| match reply {
+                Some(ref reply) if reply.code / 100 == class => Ok(transport),
+                Some(reply) => Err(Error::Io(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("SMTP server replied {} {}", reply.code, reply.text),
+                ))),
+                None => Err(Error::ConnectionClosed),
+            }),
+    )
+}
+
+struct Reply {
+    code: u16,
+    text: String,
+}
+
+// Frames multi-line SMTP replies (RFC 5321, section 4.2.1).
+struct SmtpCodec;
+
+impl Decoder for SmtpCodec {
+    type Item = Reply;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Reply>, Error> {
+        let mut start = 0;
+        let mut lines = Vec::new();
+        loop {
+            let end = match buf[start..].windows(2).position(|w| w == b"\r\n") {
+                Some(pos) => start + pos,
+                None => return Ok(None),
+            };
+            let line = String::from_utf8_lossy(&buf[start..end]).into_owned();
+            start = end + 2;
+            let last = line.as_bytes().get(3) != Some(&b'-');
+            lines.push(line);
+            if last {
+                break;
+            }
+        }
+        buf.split_to(start);
+        let code = lines[0]
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| Error::Parse(lines[0].clone().into_bytes()))?;
+        let text = lines
+            .iter()
+            .map(|line| line.get(4..).unwrap_or(""))
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(Some(Reply { code, text }))
+    }
+}
+
+impl Encoder for SmtpCodec {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn encode(&mut self, data: Vec<u8>, dst: &mut BytesMut) -> Result<(), Error> {
+        dst.reserve(data.len());
+        dst.put_slice(&data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address() {
+        assert_eq!(check_address("me@example.com").unwrap(), "me@example.com");
+        for bad in &["", "me@example.com>\r\nRCPT TO:<x@y", "a\nb", "<me@x>", "a>b"] {
+            assert!(check_address(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn send_rejects_injection() {
+        let send = Submission::new("127.0.0.1").port(1).send(
+            "me@example.com",
+            &["you@example.com>\r\nRCPT TO:<evil@example.com"],
+            b"",
+        );
+        assert!(send.is_err());
+    }
+
+    #[test]
+    fn dot_stuffing() {
+        assert_eq!(dot_stuff(b"a\r\n.b\r\n"), b"a\r\n..b\r\n.\r\n".to_vec());
+        assert_eq!(dot_stuff(b".\r\n"), b"..\r\n.\r\n".to_vec());
+        assert_eq!(dot_stuff(b"no newline"), b"no newline\r\n.\r\n".to_vec());
+        assert_eq!(dot_stuff(b"a.b\r\n"), b"a.b\r\n.\r\n".to_vec());
+        assert_eq!(dot_stuff(b""), b".\r\n".to_vec());
+    }
+
+    #[test]
+    fn decode_replies() {
+        let mut codec = SmtpCodec;
+        let mut buf = BytesMut::from(&b"250-smtp.example.com\r\n250-AUTH PLAIN\r\n"[..]);
+        buf.extend_from_slice(b"250 8BI");
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(b"TMIME\r\n354 go ahead\r\n");
+        let reply = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(reply.code, 250);
+        assert_eq!(reply.text, "smtp.example.com AUTH PLAIN 8BITMIME");
+        let reply = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(reply.code, 354);
+        assert_eq!(reply.text, "go ahead");
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"garbage\r\n"[..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use futures::{Async, Future, Poll};
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use clock::{Clock, Sleep, SystemClock};
+
+/// A stream wrapper that fails reads when no data arrives in time.
+///
+/// The timeout only runs while a read is waiting for data: it starts when a
+/// read would block and is reset whenever data is received.
+pub struct TimeoutStream<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    clock: Arc<Clock>,
+    delay: Option<Sleep>,
+}
+
+impl<S> TimeoutStream<S> {
+    pub fn new(inner: S, timeout: Option<Duration>) -> Self {
+        Self::with_clock(inner, timeout, Arc::new(SystemClock))
+    }
+
+    /// Measures the timeout with `clock`.
+    pub fn with_clock(inner: S, timeout: Option<Duration>, clock: Arc<Clock>) -> Self {
+        Self {
+            inner,
+            timeout,
+            clock,
+            delay: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.delay = None;
+    }
+
+    // Called when a read would block, to find out whether it has timed out.
+    fn blocked(&mut self) -> io::Error {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return io::ErrorKind::WouldBlock.into(),
+        };
+        let clock = &self.clock;
+        let delay = self.delay
+            .get_or_insert_with(|| clock.delay_for(timeout));
+        match delay.poll() {
+            Ok(Async::Ready(())) => {
+                self.delay = None;
+                io::Error::new(io::ErrorKind::TimedOut, "read timed out")
+            },
+            Ok(Async::NotReady) => io::ErrorKind::WouldBlock.into(),
+            Err(e) => e,
+        }
+    }
+}
+
+impl<S: Read> Read for TimeoutStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(read) => {
+                self.delay = None;
+                Ok(read)
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Err(self.blocked()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<S: Write> Write for TimeoutStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for TimeoutStream<S> {}
+
+impl<S: AsyncWrite> AsyncWrite for TimeoutStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.inner.shutdown()
+    }
+}
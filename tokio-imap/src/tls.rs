@@ -0,0 +1,344 @@
+use futures::{Future, Poll};
+
+#[cfg(feature = "tls-native")]
+use native_tls;
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use ring::digest;
+#[cfg(feature = "tls-rustls")]
+use rustls::{Certificate, ClientConfig, ClientSession, RootCertStore, ServerCertVerified,
+             ServerCertVerifier, TLSError};
+#[cfg(feature = "tls-rustls")]
+use tokio_rustls::webpki::DNSNameRef;
+
+use error::Error;
+use timeout::TimeoutStream;
+
+#[cfg(not(any(feature = "tls-native", feature = "tls-rustls")))]
+compile_error!("at least one of the tls-native and tls-rustls features must be enabled");
+
+/// The TLS implementation used to connect.
+///
+/// Which backends are available depends on the `tls-native` (the default)
+/// and `tls-rustls` features.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TlsBackend {
+    /// The platform's TLS library, through `native-tls`.
+    #[cfg(feature = "tls-native")]
+    Native,
+    /// `rustls`, with the Mozilla root certificates from `webpki-roots`.
+    #[cfg(feature = "tls-rustls")]
+    Rustls,
+}
+
+impl Default for TlsBackend {
+    #[cfg(feature = "tls-native")]
+    fn default() -> Self {
+        TlsBackend::Native
+    }
+
+    #[cfg(not(feature = "tls-native"))]
+    fn default() -> Self {
+        TlsBackend::Rustls
+    }
+}
+
+// The name used to set up connections to servers that are not known by a
+// DNS name, such as those given by IP address, when the certificate is
+// pinned and its name does not matter.
+#[cfg(feature = "tls-rustls")]
+const PINNED_NAME: &str = "pinned.invalid";
+
+/// A configured TLS implementation, used to set up connections.
+pub enum TlsConnector {
+    #[cfg(feature = "tls-native")]
+    Native(native_tls::TlsConnector),
+    /// Accepts any certificate in the handshake, then checks that the
+    /// server's certificate is one of those pinned.
+    #[cfg(feature = "tls-native")]
+    NativePinned(native_tls::TlsConnector, Arc<Vec<Fingerprint>>),
+    #[cfg(feature = "tls-rustls")]
+    Rustls(Arc<ClientConfig>),
+    /// Verifies the server's certificate against the pinned fingerprints
+    /// during the handshake.
+    #[cfg(feature = "tls-rustls")]
+    RustlsPinned(Arc<ClientConfig>),
+}
+
+impl TlsConnector {
+    /// Builds a connector with the default configuration for `backend`.
+    pub fn new(backend: TlsBackend) -> Result<TlsConnector, Error> {
+        match backend {
+            #[cfg(feature = "tls-native")]
+            TlsBackend::Native => native_tls::TlsConnector::new()
+                .map(TlsConnector::Native)
+                .map_err(|e| Error::Tls(Box::new(e))),
+            #[cfg(feature = "tls-rustls")]
+            TlsBackend::Rustls => {
+                let mut config = ClientConfig::new();
+                config
+                    .root_store
+                    .add_server_trust_anchors(&::webpki_roots::TLS_SERVER_ROOTS);
+                Ok(TlsConnector::Rustls(Arc::new(config)))
+            },
+        }
+    }
+
+    /// Builds a connector for `backend` that accepts exactly the certificates
+    /// with the given fingerprints, for servers with self-signed
+    /// certificates or that are only known by IP address.
+    ///
+    /// The certificate chain and host name are not checked at all, so this
+    /// should only be used with fingerprints obtained from a trusted source.
+    /// The native backend completes the handshake before checking the
+    /// certificate, and fails with `Error::Tls` if it is not pinned.
+    pub fn pinned(backend: TlsBackend, fingerprints: Vec<Fingerprint>) -> Result<Self, Error> {
+        match backend {
+            #[cfg(feature = "tls-native")]
+            TlsBackend::Native => native_tls::TlsConnector::builder()
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true)
+                .build()
+                .map(|connector| TlsConnector::NativePinned(connector, Arc::new(fingerprints)))
+                .map_err(|e| Error::Tls(Box::new(e))),
+            #[cfg(feature = "tls-rustls")]
+            TlsBackend::Rustls => {
+                let mut config = ClientConfig::new();
+                config
+                    .dangerous()
+                    .set_certificate_verifier(Arc::new(PinnedVerifier { fingerprints }));
+                Ok(TlsConnector::RustlsPinned(Arc::new(config)))
+            },
+        }
+    }
+
+    pub(crate) fn handshake(&self, domain: &str, stream: TimeoutStream<TcpStream>) -> Handshake {
+        match *self {
+            #[cfg(feature = "tls-native")]
+            TlsConnector::Native(ref connector) => Box::new(
+                ::tokio_tls::TlsConnector::from(connector.clone())
+                    .connect(domain, stream)
+                    .map(TlsStream::Native)
+                    .map_err(|e| Error::Tls(Box::new(e))),
+            ),
+            #[cfg(feature = "tls-native")]
+            TlsConnector::NativePinned(ref connector, ref fingerprints) => {
+                let fingerprints = fingerprints.clone();
+                Box::new(
+                    ::tokio_tls::TlsConnector::from(connector.clone())
+                        .connect(domain, stream)
+                        .map_err(|e| Error::Tls(Box::new(e)))
+                        .and_then(move |stream| {
+                            check_pinned(stream.get_ref(), &fingerprints)?;
+                            Ok(TlsStream::Native(stream))
+                        }),
+                )
+            },
+            #[cfg(feature = "tls-rustls")]
+            TlsConnector::Rustls(ref config) => match DNSNameRef::try_from_ascii_str(domain) {
+                Ok(domain) => rustls_handshake(config.clone(), domain, stream),
+                Err(()) => {
+                    let e = io::Error::new(io::ErrorKind::InvalidInput, "invalid DNS name");
+                    Box::new(::futures::future::err(Error::Io(e)))
+                },
+            },
+            #[cfg(feature = "tls-rustls")]
+            TlsConnector::RustlsPinned(ref config) => match DNSNameRef::try_from_ascii_str(domain) {
+                Ok(domain) => rustls_handshake(config.clone(), domain, stream),
+                // The verifier ignores the name, but an IP address must not
+                // be sent for SNI (RFC 6066, section 3).
+                Err(()) => {
+                    let mut config = (**config).clone();
+                    config.enable_sni = false;
+                    let domain = DNSNameRef::try_from_ascii_str(PINNED_NAME).unwrap(); // safe
+                    rustls_handshake(Arc::new(config), domain, stream)
+                },
+            },
+        }
+    }
+}
+
+#[cfg(feature = "tls-rustls")]
+fn rustls_handshake(
+    config: Arc<ClientConfig>, domain: DNSNameRef, stream: TimeoutStream<TcpStream>
+) -> Handshake {
+    Box::new(
+        ::tokio_rustls::TlsConnector::from(config)
+            .connect(domain, stream)
+            .map(TlsStream::Rustls)
+            .map_err(|e| Error::Tls(Box::new(e))),
+    )
+}
+
+// Fails unless the server's certificate has one of the pinned fingerprints.
+#[cfg(feature = "tls-native")]
+fn check_pinned<S>(
+    stream: &native_tls::TlsStream<S>, fingerprints: &[Fingerprint]
+) -> Result<(), Error>
+where
+    S: Read + Write, {
+    let tls_error = |e| Error::Tls(Box::new(e));
+    let der = match stream.peer_certificate().map_err(tls_error)? {
+        Some(cert) => cert.to_der().map_err(tls_error)?,
+        None => {
+            let e = io::Error::new(io::ErrorKind::InvalidData, "no certificate presented");
+            return Err(Error::Tls(Box::new(e)));
+        },
+    };
+    let fingerprint = Fingerprint::of(&der);
+    if fingerprints.contains(&fingerprint) {
+        Ok(())
+    } else {
+        let msg = format!("certificate fingerprint {} is not pinned", fingerprint);
+        Err(Error::Tls(Box::new(io::Error::new(io::ErrorKind::InvalidData, msg))))
+    }
+}
+
+/// The SHA-256 digest of a DER-encoded certificate.
+///
+/// Parsed from and displayed as hexadecimal, optionally with colons between
+/// the bytes as printed by `openssl x509 -noout -fingerprint -sha256`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Fingerprint(pub [u8; 32]);
+
+impl Fingerprint {
+    pub fn of(der: &[u8]) -> Self {
+        let mut fingerprint = [0; 32];
+        fingerprint.copy_from_slice(digest::digest(&digest::SHA256, der).as_ref());
+        Fingerprint(fingerprint)
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid fingerprint");
+        let digits = s.trim().replace(':', "");
+        if digits.len() != 64 || !digits.is_ascii() {
+            return Err(invalid());
+        }
+        let mut fingerprint = [0; 32];
+        for (i, byte) in fingerprint.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Fingerprint(fingerprint))
+    }
+}
+
+// Accepts the server's certificate if its fingerprint is pinned.
+#[cfg(feature = "tls-rustls")]
+struct PinnedVerifier {
+    fingerprints: Vec<Fingerprint>,
+}
+
+#[cfg(feature = "tls-rustls")]
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self, _roots: &RootCertStore, presented_certs: &[Certificate], _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let cert = presented_certs
+            .first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        let fingerprint = Fingerprint::of(&cert.0);
+        if self.fingerprints.contains(&fingerprint) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TLSError::General(format!(
+                "certificate fingerprint {} is not pinned",
+                fingerprint
+            )))
+        }
+    }
+}
+
+/// A TLS connection established with any of the available backends.
+pub enum TlsStream {
+    #[cfg(feature = "tls-native")]
+    Native(::tokio_tls::TlsStream<TimeoutStream<TcpStream>>),
+    #[cfg(feature = "tls-rustls")]
+    Rustls(::tokio_rustls::TlsStream<TimeoutStream<TcpStream>, ClientSession>),
+}
+
+pub(crate) type Handshake = Box<Future<Item = TlsStream, Error = Error> + Send>;
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            #[cfg(feature = "tls-native")]
+            TlsStream::Native(ref mut stream) => stream.read(buf),
+            #[cfg(feature = "tls-rustls")]
+            TlsStream::Rustls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            #[cfg(feature = "tls-native")]
+            TlsStream::Native(ref mut stream) => stream.write(buf),
+            #[cfg(feature = "tls-rustls")]
+            TlsStream::Rustls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            #[cfg(feature = "tls-native")]
+            TlsStream::Native(ref mut stream) => stream.flush(),
+            #[cfg(feature = "tls-rustls")]
+            TlsStream::Rustls(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsyncRead for TlsStream {}
+
+impl AsyncWrite for TlsStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match *self {
+            #[cfg(feature = "tls-native")]
+            TlsStream::Native(ref mut stream) => stream.shutdown(),
+            #[cfg(feature = "tls-rustls")]
+            TlsStream::Rustls(ref mut stream) => stream.shutdown(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint() {
+        let fingerprint = Fingerprint::of(b"certificate");
+        let printed = fingerprint.to_string();
+        assert_eq!(printed.len(), 32 * 3 - 1);
+        assert_eq!(printed.parse::<Fingerprint>().unwrap(), fingerprint);
+        let bare = printed.replace(':', "").to_lowercase();
+        assert_eq!(bare.parse::<Fingerprint>().unwrap(), fingerprint);
+        assert!("AB:CD".parse::<Fingerprint>().is_err());
+        assert!(bare.replace('a', "g").parse::<Fingerprint>().is_err());
+    }
+}
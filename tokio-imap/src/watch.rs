@@ -0,0 +1,164 @@
+use futures::{Async, Poll, Stream};
+use futures::task::{self, Task};
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use imap_proto::{MailboxDatum, Response, ResponseCode};
+
+use proto::ResponseData;
+
+/// The counters of the selected mailbox, as last reported by the server.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MailboxSnapshot {
+    /// Whether a mailbox is selected; all other fields are empty if not.
+    pub selected: bool,
+    pub exists: u32,
+    pub recent: u32,
+    /// The sequence number of the first unseen message, if the server said.
+    pub unseen: Option<u32>,
+    pub uid_next: Option<u32>,
+    pub uid_validity: Option<u32>,
+    pub flags: Vec<String>,
+}
+
+struct Shared {
+    snapshot: RwLock<Arc<MailboxSnapshot>>,
+    version: AtomicUsize,
+    closed: AtomicBool,
+    tasks: Mutex<Vec<Task>>,
+}
+
+/// A read-only view of the selected mailbox, kept up to date by the client.
+///
+/// Clones are cheap and can be sent to other threads, for example to render
+/// the mailbox state in a user interface without issuing commands. `get()`
+/// returns the latest snapshot; as a `Stream`, the watch yields a snapshot
+/// whenever it changed since the last one yielded, starting with the current
+/// one. The stream ends when the client is dropped.
+#[derive(Clone)]
+pub struct MailboxWatch {
+    shared: Arc<Shared>,
+    seen: Option<usize>,
+}
+
+impl MailboxWatch {
+    pub(crate) fn new() -> Self {
+        let shared = Shared {
+            snapshot: RwLock::new(Arc::new(MailboxSnapshot::default())),
+            version: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            tasks: Mutex::new(Vec::new()),
+        };
+        Self {
+            shared: Arc::new(shared),
+            seen: None,
+        }
+    }
+
+    /// The latest snapshot.
+    pub fn get(&self) -> Arc<MailboxSnapshot> {
+        self.shared.snapshot.read().unwrap().clone()
+    }
+
+    // Applies `update` to a copy of the snapshot, publishing it and waking
+    // watching tasks if anything changed.
+    fn update<F>(&self, update: F)
+    where
+        F: FnOnce(&mut MailboxSnapshot), {
+        let mut current = self.shared.snapshot.write().unwrap();
+        let mut snapshot = (**current).clone();
+        update(&mut snapshot);
+        if snapshot == **current {
+            return;
+        }
+        *current = Arc::new(snapshot);
+        drop(current);
+        self.shared.version.fetch_add(1, Ordering::SeqCst);
+        self.notify();
+    }
+
+    fn notify(&self) {
+        for task in self.shared.tasks.lock().unwrap().drain(..) {
+            task.notify();
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.update(|snapshot| *snapshot = MailboxSnapshot::default());
+    }
+
+    pub(crate) fn selecting(&self) {
+        self.update(|snapshot| {
+            *snapshot = MailboxSnapshot::default();
+            snapshot.selected = true;
+        });
+    }
+
+    pub(crate) fn observe(&self, rsp: &ResponseData) {
+        match *rsp.parsed() {
+            Response::MailboxData(MailboxDatum::Exists(num)) => {
+                self.update(|snapshot| snapshot.exists = num)
+            },
+            Response::MailboxData(MailboxDatum::Recent(num)) => {
+                self.update(|snapshot| snapshot.recent = num)
+            },
+            Response::MailboxData(MailboxDatum::Flags(ref flags)) => self.update(|snapshot| {
+                snapshot.flags = flags.iter().map(|flag| flag.to_string()).collect()
+            }),
+            Response::Expunge(_) => self.update(|snapshot| {
+                snapshot.exists = snapshot.exists.saturating_sub(1);
+            }),
+            Response::Data {
+                code: Some(ref code),
+                ..
+            } => match *code {
+                ResponseCode::Unseen(num) => self.update(|snapshot| snapshot.unseen = Some(num)),
+                ResponseCode::UidNext(num) => {
+                    self.update(|snapshot| snapshot.uid_next = Some(num))
+                },
+                ResponseCode::UidValidity(num) => {
+                    self.update(|snapshot| snapshot.uid_validity = Some(num))
+                },
+                _ => {},
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Stream for MailboxWatch {
+    type Item = Arc<MailboxSnapshot>;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, ()> {
+        // Register before checking the version, so that an update racing with
+        // this poll is not missed.
+        {
+            let mut tasks = self.shared.tasks.lock().unwrap();
+            if !tasks.iter().any(|task| task.will_notify_current()) {
+                tasks.push(task::current());
+            }
+        }
+        let version = self.shared.version.load(Ordering::SeqCst);
+        if self.seen != Some(version) {
+            self.seen = Some(version);
+            return Ok(Async::Ready(Some(self.get())));
+        }
+        if self.shared.closed.load(Ordering::SeqCst) {
+            return Ok(Async::Ready(None));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+// Wakes watching tasks when the client side is dropped, so that their
+// streams end.
+pub(crate) struct WatchHandle(pub(crate) MailboxWatch);
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.0.shared.closed.store(true, Ordering::SeqCst);
+        self.0.notify();
+    }
+}
@@ -0,0 +1,403 @@
+extern crate futures;
+extern crate futures_state_stream;
+extern crate tokio;
+extern crate tokio_imap;
+
+use futures::Future;
+use futures_state_stream::StateStream;
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::runtime::current_thread::Runtime;
+
+use tokio_imap::client::builder::CommandBuilder;
+use tokio_imap::client::Schedule;
+use tokio_imap::mock::{MockFuture, MockServer};
+use tokio_imap::pool::ClientPool;
+use tokio_imap::reconnect::Login;
+use tokio_imap::types::State;
+use tokio_imap::{Client, Error, ImapClient, ReconnectingClient, SequenceSet, Session};
+
+// Runs `client` against `server`, failing if either fails.
+fn run<F>(server: MockFuture, client: F) -> F::Item
+where
+    F: Future<Error = Error>, {
+    let mut runtime = Runtime::new().unwrap();
+    runtime.block_on(client.join(server)).unwrap().0
+}
+
+// Connects to `first`, and to `second` when reconnecting.
+fn reconnecting(first: SocketAddr, second: SocketAddr) -> ReconnectingClient<TcpStream> {
+    let addrs = Mutex::new(vec![second, first]);
+    let connect = move || {
+        let addr = addrs.lock().unwrap().pop().expect("connected too often");
+        Ok(Client::connect_insecure(&addr))
+    };
+    ReconnectingClient::new(connect, Login::new("me", "secret"))
+}
+
+#[test]
+fn greeting() {
+    let (addr, server) = MockServer::new().close().bind().unwrap();
+    let (greeting, client) = run(server, Client::connect_insecure(&addr));
+    assert!(!greeting.preauth);
+    assert_eq!(greeting.capabilities, Some(vec!["IMAP4rev1".to_string()]));
+    assert_eq!(greeting.text, Some("mock server ready".to_string()));
+    assert!(client.has_capability("imap4rev1"));
+    assert_eq!(client.state(), State::NotAuthenticated);
+}
+
+#[test]
+fn login_refreshes_capabilities() {
+    let (addr, server) = MockServer::new()
+        .expect("LOGIN \"me\" \"secret\"")
+        .complete("OK logged in")
+        .expect("CAPABILITY")
+        .respond("* CAPABILITY IMAP4rev1 MOVE UIDPLUS")
+        .complete("OK done")
+        .close()
+        .bind()
+        .unwrap();
+    let session = Client::connect_insecure(&addr)
+        .and_then(|(_, client)| Session::new(client).login("me", "secret"))
+        .map(|((), session)| session.into_client());
+    let client = run(server, session);
+    assert!(client.has_capability("MOVE"));
+    assert_eq!(client.state(), State::Authenticated);
+}
+
+#[test]
+fn login_with_capabilities() {
+    let (addr, server) = MockServer::new()
+        .expect("LOGIN \"me\" \"secret\"")
+        .complete("OK [CAPABILITY IMAP4rev1 MOVE] logged in")
+        .close()
+        .bind()
+        .unwrap();
+    let session = Client::connect_insecure(&addr)
+        .and_then(|(_, client)| Session::new(client).login("me", "secret"))
+        .map(|((), session)| session.into_client());
+    assert!(run(server, session).has_capability("MOVE"));
+}
+
+#[test]
+fn select() {
+    let (addr, server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("SELECT \"INBOX\"")
+        .respond("* 3 EXISTS")
+        .respond("* 0 RECENT")
+        .respond("* FLAGS (\\Seen \\Deleted)")
+        .respond("* OK [UIDVALIDITY 42] UIDs valid")
+        .respond("* OK [UIDNEXT 4] predicted next UID")
+        .complete("OK [READ-WRITE] done")
+        .close()
+        .bind()
+        .unwrap();
+    let session = Client::connect_insecure(&addr)
+        .and_then(|(_, client)| Session::new(client).select("INBOX"));
+    let (mailbox, session) = run(server, session);
+    assert_eq!(mailbox.exists, 3);
+    assert_eq!(mailbox.recent, 0);
+    assert_eq!(mailbox.flags, vec!["\\Seen", "\\Deleted"]);
+    assert_eq!(mailbox.uid_validity, Some(42));
+    assert_eq!(mailbox.uid_next, Some(4));
+    assert!(!mailbox.read_only);
+    assert_eq!(session.client().state(), State::Selected);
+}
+
+#[test]
+fn uid_move() {
+    let (addr, server) = MockServer::new()
+        .greeting("* PREAUTH [CAPABILITY IMAP4rev1 MOVE UIDPLUS] ready")
+        .expect("SELECT \"INBOX\"")
+        .complete("OK [READ-WRITE] done")
+        .expect("UID MOVE 1:2 \"Archive\"")
+        .respond("* OK [COPYUID 7 1:2 10:11] moved")
+        .respond("* 1 EXPUNGE")
+        .respond("* 1 EXPUNGE")
+        .complete("OK done")
+        .close()
+        .bind()
+        .unwrap();
+    let session = Client::connect_insecure(&addr)
+        .and_then(|(_, client)| Session::new(client).select("INBOX"))
+        .and_then(|(_, session)| session.uid_move(&SequenceSet::from(1..=2), "Archive"));
+    let (moved, _) = run(server, session);
+    let copied = moved.copied.unwrap();
+    assert_eq!(copied.uid_validity, 7);
+    assert_eq!(copied.source, vec![1..=2]);
+    assert_eq!(copied.destination, vec![10..=11]);
+    assert_eq!(moved.expunged, vec![1, 1]);
+}
+
+#[test]
+fn logout_on_eof() {
+    let (addr, server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("LOGOUT")
+        .close()
+        .bind()
+        .unwrap();
+    let session = Client::connect_insecure(&addr)
+        .and_then(|(_, client)| Session::new(client).logout());
+    let ((), session) = run(server, session);
+    assert_eq!(session.client().state(), State::Logout);
+}
+
+#[test]
+fn timeout_and_resync() {
+    // The server never answers the first `NOOP`.
+    let (addr, server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("NOOP")
+        .expect("NOOP")
+        .complete("OK resynced")
+        .close()
+        .bind()
+        .unwrap();
+    let client = Client::connect_insecure(&addr)
+        .and_then(|(_, client)| {
+            client
+                .call_with_timeout(CommandBuilder::noop(), Duration::from_millis(50))
+                .collect()
+        })
+        .and_then(|(responses, client)| {
+            assert!(responses.is_empty());
+            assert!(client.needs_resync());
+            client.resync()
+        });
+    assert!(!run(server, client).needs_resync());
+}
+
+#[test]
+fn timeout_while_sending() {
+    let (addr, server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("NOOP")
+        .complete("OK done")
+        .expect("NOOP")
+        .complete("OK resynced")
+        .close()
+        .bind()
+        .unwrap();
+    let client = Client::connect_insecure(&addr)
+        .and_then(|(_, mut client)| {
+            // The second command has to wait a second for its turn.
+            client.set_schedule(Some(Schedule::new(1)));
+            client.call(CommandBuilder::noop()).collect()
+        })
+        .and_then(|(_, client)| {
+            client
+                .call_with_timeout(CommandBuilder::noop(), Duration::from_millis(50))
+                .collect()
+        })
+        .and_then(|(responses, mut client)| {
+            assert!(responses.is_empty());
+            assert!(client.needs_resync());
+            client.set_schedule(None);
+            client.resync()
+        });
+    assert!(!run(server, client).needs_resync());
+}
+
+#[test]
+fn rejected_command_keeps_client() {
+    let (addr, server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("NOOP")
+        .complete("OK done")
+        .close()
+        .bind()
+        .unwrap();
+    let client = Client::connect_insecure(&addr).and_then(|(_, client)| {
+        let client = match client.try_call(CommandBuilder::login("me", "secret")) {
+            Err((Error::BadState { .. }, client)) => client,
+            _ => panic!("LOGIN is not valid once authenticated"),
+        };
+        client.call(CommandBuilder::noop()).collect()
+    });
+    run(server, client);
+}
+
+#[test]
+fn reconnect_replays_uid_search() {
+    let (first, first_server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("SELECT \"INBOX\"")
+        .respond("* OK [UIDVALIDITY 42] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        .expect("UID SEARCH ALL")
+        .close()
+        .bind()
+        .unwrap();
+    let (second, second_server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("SELECT \"INBOX\"")
+        .respond("* OK [UIDVALIDITY 42] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        .expect("UID SEARCH ALL")
+        .respond("* SEARCH 1 2")
+        .complete("OK done")
+        .close()
+        .bind()
+        .unwrap();
+    let servers: MockFuture = Box::new(first_server.join(second_server).map(|_| ()));
+    let client = reconnecting(first, second)
+        .call(CommandBuilder::select("INBOX"))
+        .and_then(|(selected, client)| {
+            assert!(selected.is_ok());
+            client.call(CommandBuilder::uid_search("ALL"))
+        });
+    let (searched, client) = run(servers, client);
+    assert_eq!(searched.unwrap().len(), 2);
+    assert_eq!(client.client().unwrap().state(), State::Selected);
+}
+
+#[test]
+fn reconnect_interrupts_and_checks_uid_validity() {
+    let (first, first_server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("SELECT \"INBOX\"")
+        .respond("* OK [UIDVALIDITY 42] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        .expect("UID COPY 1 \"Archive\"")
+        .close()
+        .bind()
+        .unwrap();
+    // The mailbox was recreated while the client was away.
+    let (second, second_server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("SELECT \"INBOX\"")
+        .respond("* OK [UIDVALIDITY 43] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        .close()
+        .bind()
+        .unwrap();
+    let servers: MockFuture = Box::new(first_server.join(second_server).map(|_| ()));
+    let client = reconnecting(first, second)
+        .call(CommandBuilder::select("INBOX"))
+        .and_then(|(_, client)| {
+            client.call(CommandBuilder::uid_copy(&SequenceSet::from(1), "Archive"))
+        })
+        .and_then(|(copied, client)| {
+            match copied {
+                Err(Error::Interrupted) => {},
+                other => panic!("UID COPY must not be sent again, got {:?}", other),
+            }
+            assert!(client.client().is_none());
+            client.call(CommandBuilder::noop())
+        });
+    match run(servers, client).0 {
+        Err(Error::UidValidityChanged { old: 42, new: 43 }) => {},
+        other => panic!("expected a UIDVALIDITY change, got {:?}", other),
+    }
+}
+
+#[test]
+fn reconnect_after_network_change() {
+    let (first, first_server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("SELECT \"INBOX\"")
+        .respond("* OK [UIDVALIDITY 42] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        .close()
+        .bind()
+        .unwrap();
+    let (second, second_server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("SELECT \"INBOX\"")
+        .respond("* OK [UIDVALIDITY 42] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        .expect("NOOP")
+        .complete("OK done")
+        .close()
+        .bind()
+        .unwrap();
+    let servers: MockFuture = Box::new(first_server.join(second_server).map(|_| ()));
+    let client = reconnecting(first, second)
+        .call(CommandBuilder::select("INBOX"))
+        .and_then(|(_, mut client)| {
+            client.network_changed();
+            assert!(client.client().is_none());
+            client.call(CommandBuilder::noop())
+        });
+    let (nooped, client) = run(servers, client);
+    assert!(nooped.is_ok());
+    assert_eq!(client.client().unwrap().state(), State::Selected);
+}
+
+#[test]
+fn pool_checkout_for() {
+    let (addr, server) = MockServer::new()
+        .greeting("* PREAUTH ready")
+        .expect("SELECT \"INBOX\"")
+        .respond("* OK [UIDVALIDITY 42] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        // Checking the idle connection selects the mailbox again, and that
+        // is enough for the second checkout.
+        .expect("SELECT \"INBOX\"")
+        .respond("* OK [UIDVALIDITY 42] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        .expect("SELECT \"INBOX\"")
+        .respond("* OK [UIDVALIDITY 42] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        .expect("SELECT \"Archive\"")
+        .respond("* OK [UIDVALIDITY 8] UIDs valid")
+        .complete("OK [READ-WRITE] done")
+        .close()
+        .bind()
+        .unwrap();
+    let pool = ClientPool::new(
+        1,
+        move || Ok(Client::connect_insecure(&addr)),
+        Login::new("me", "secret"),
+    );
+    let (first, second, third) = (pool.clone(), pool.clone(), pool.clone());
+    let client = first
+        .checkout_for("INBOX", None)
+        .and_then(move |client| {
+            assert_eq!(client.state(), State::Selected);
+            drop(client);
+            second.checkout_for("INBOX", Some(42))
+        })
+        .and_then(move |client| {
+            drop(client);
+            third.checkout_for("Archive", Some(7)).then(Ok)
+        });
+    match run(server, client) {
+        Err(Error::UidValidityChanged { old: 7, new: 8 }) => {},
+        other => panic!("expected a UIDVALIDITY change, got {:?}", other.map(|_| ())),
+    }
+    assert_eq!((pool.open(), pool.idle()), (1, 1));
+}
+
+#[test]
+fn missing_capability_keeps_session() {
+    let (addr, server) = MockServer::new()
+        .greeting("* PREAUTH [CAPABILITY IMAP4rev1 UIDPLUS] ready")
+        .expect("SELECT \"INBOX\"")
+        .complete("OK [READ-WRITE] done")
+        .expect("UID COPY 1:2 \"Archive\"")
+        .complete("OK done")
+        .close()
+        .bind()
+        .unwrap();
+    let session = Client::connect_insecure(&addr)
+        .and_then(|(_, client)| Session::new(client).select("INBOX"))
+        .and_then(|(_, session)| {
+            let session = match session.try_uid_move(&SequenceSet::from(1..=2), "Archive") {
+                Err((Error::MissingCapability(ref cap), session)) if cap == "MOVE" => session,
+                Err((e, _)) => panic!("expected MOVE to be missing, got {:?}", e),
+                Ok(_) => panic!("UID MOVE must not be sent"),
+            };
+            let copy = CommandBuilder::uid_copy(&SequenceSet::from(1..=2), "Archive");
+            session.into_client().call(copy).collect()
+        });
+    let (responses, client) = run(server, session);
+    assert!(Error::from_responses(&responses).is_ok());
+    assert_eq!(client.state(), State::Selected);
+}